@@ -0,0 +1,657 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::io::{Seek, SeekFrom};
+
+use crate::{
+    api::{
+        JxlBasicInfo, JxlBitstreamInput, JxlColorProfile, JxlDecoder, JxlDecoderOptions,
+        JxlFrameIndex, JxlOutputBuffer, JxlPixelFormat, ProcessingResult,
+        states::{Initialized, WithImageInfo},
+    },
+    error::{Error, Result},
+    image::{Image, Rect},
+};
+
+/// A displayed frame of an animation, see [`JxlAnimationPlayer`].
+pub struct JxlAnimationFrame {
+    /// Index of the frame among the displayed frames of the file. Frames that are only blended
+    /// into later ones, such as zero-duration layers, are not counted.
+    pub index: usize,
+    /// Number of times the animation was played in full before this frame.
+    pub loop_index: u32,
+    /// Time at which the frame is shown, in milliseconds since the start of playback.
+    pub timestamp: f64,
+    /// Time for which the frame is shown, in milliseconds.
+    pub duration: f64,
+    /// The composited pixels of the frame, one image per output of the pixel format. Each row of
+    /// an image holds the samples of a row of the frame, as bytes in the requested data format.
+    pub buffers: Vec<Image<u8>>,
+}
+
+/// Plays the frames of a file, handling frame durations and looping.
+///
+/// The input must start at the beginning of the file, and is seeked back there whenever the
+/// animation starts over. Still images are played as a single frame with no duration.
+///
+/// If the file has a frame index (see [`JxlFrameIndex`]), seeking uses it to jump to the
+/// keyframe before the requested frame instead of decoding all the frames before it.
+///
+/// As with [`JxlDecoder`], the player cannot be used anymore once decoding fails.
+pub struct JxlAnimationPlayer<In: JxlBitstreamInput + Seek> {
+    input: In,
+    // Decoder positioned before the frame with index `next_index`.
+    decoder: Option<JxlDecoder<WithImageInfo>>,
+    // Output color profile set by the user, which has to be set again after rewinding.
+    output_color_profile: Option<JxlColorProfile>,
+    next_index: usize,
+    // Frames before this one are not needed by the frames that are decoded next, even if they are
+    // referenced, as a keyframe comes after them.
+    skip_before: usize,
+    loop_index: u32,
+    // Start times within a loop of the frames that were seen so far, in milliseconds.
+    frame_starts: Vec<f64>,
+    // Time at which the last of the frames that were seen so far ends.
+    frames_end: f64,
+    // Set once all the frames were seen.
+    loop_duration: Option<f64>,
+    frame: Option<JxlAnimationFrame>,
+}
+
+/// Calls `process` until it completes, failing if the input ends before that.
+pub(crate) fn run_to_completion<In: JxlBitstreamInput, T, U>(
+    input: &mut In,
+    mut state: U,
+    mut process: impl FnMut(U, &mut In) -> Result<ProcessingResult<T, U>>,
+) -> Result<T> {
+    loop {
+        match process(state, input)? {
+            ProcessingResult::Complete { result } => return Ok(result),
+            ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                if input.available_bytes()? == 0 {
+                    return Err(Error::TruncatedInput);
+                }
+                state = fallback;
+            }
+        }
+    }
+}
+
+impl<In: JxlBitstreamInput + Seek> JxlAnimationPlayer<In> {
+    /// Creates a player for the file in `input`, reading its headers.
+    pub fn new(mut input: In, options: JxlDecoderOptions) -> Result<Self> {
+        let decoder = JxlDecoder::<Initialized>::new(options);
+        let decoder = run_to_completion(&mut input, decoder, |d, input| d.process(input))?;
+        Ok(Self {
+            input,
+            decoder: Some(decoder),
+            output_color_profile: None,
+            next_index: 0,
+            skip_before: 0,
+            loop_index: 0,
+            frame_starts: vec![],
+            frames_end: 0.0,
+            loop_duration: None,
+            frame: None,
+        })
+    }
+
+    fn decoder(&self) -> &JxlDecoder<WithImageInfo> {
+        self.decoder.as_ref().unwrap()
+    }
+
+    /// Obtains the image's basic information.
+    pub fn basic_info(&self) -> &JxlBasicInfo {
+        self.decoder().basic_info()
+    }
+
+    pub fn current_pixel_format(&self) -> &JxlPixelFormat {
+        self.decoder().current_pixel_format()
+    }
+
+    /// Sets the pixel format of the frames that are decoded from now on.
+    pub fn set_pixel_format(&mut self, pixel_format: JxlPixelFormat) {
+        self.decoder
+            .as_mut()
+            .unwrap()
+            .set_pixel_format(pixel_format);
+        // The current frame cannot be reused in the new format.
+        self.frame = None;
+    }
+
+    /// Sets the output color profile of the frames that are decoded from now on.
+    pub fn set_output_color_profile(&mut self, profile: JxlColorProfile) -> Result<()> {
+        self.decoder
+            .as_mut()
+            .unwrap()
+            .set_output_color_profile(profile.clone())?;
+        self.output_color_profile = Some(profile);
+        self.frame = None;
+        Ok(())
+    }
+
+    /// Number of times the animation is played, or 0 if it loops forever.
+    pub fn num_loops(&self) -> u32 {
+        self.basic_info()
+            .animation
+            .as_ref()
+            .map_or(1, |animation| animation.num_loops)
+    }
+
+    /// Duration of a single loop of the animation in milliseconds, once all of its frames were
+    /// seen.
+    pub fn loop_duration(&self) -> Option<f64> {
+        self.loop_duration
+    }
+
+    /// Returns the frame that was returned last, if any.
+    pub fn current_frame(&self) -> Option<&JxlAnimationFrame> {
+        self.frame.as_ref()
+    }
+
+    /// Restarts decoding from the first frame of the file.
+    fn restart(&mut self) -> Result<()> {
+        let decoder = self.decoder.take().unwrap().rewind();
+        self.input.seek(SeekFrom::Start(0))?;
+        let mut decoder = run_to_completion(&mut self.input, decoder, |d, input| d.process(input))?;
+        if let Some(profile) = &self.output_color_profile {
+            decoder.set_output_color_profile(profile.clone())?;
+        }
+        self.decoder = Some(decoder);
+        self.next_index = 0;
+        self.skip_before = 0;
+        Ok(())
+    }
+
+    /// Returns the frame index of the file, if any.
+    fn frame_index(&mut self) -> Result<Option<&JxlFrameIndex>> {
+        self.decoder
+            .as_mut()
+            .unwrap()
+            .find_frame_index(&mut self.input)
+    }
+
+    /// Jumps to the last keyframe before the frame with index `index`, if the file has a frame
+    /// index and that is faster than decoding forward from `next_index`. Returns whether it did.
+    fn seek_to_keyframe(&mut self, index: usize) -> Result<bool> {
+        let Some(keyframe) = self
+            .frame_index()?
+            .and_then(|frame_index| frame_index.keyframe_before(index))
+            .map(|keyframe| keyframe.frame_number)
+        else {
+            return Ok(false);
+        };
+        if (keyframe..=index).contains(&self.next_index) {
+            return Ok(false);
+        }
+        let decoder = self.decoder.as_mut().unwrap();
+        let Some(next_index) = decoder.seek_to_keyframe(&mut self.input, index)? else {
+            return Ok(false);
+        };
+        self.next_index = next_index;
+        self.skip_before = 0;
+        Ok(true)
+    }
+
+    /// Restarts playback from the first frame.
+    pub fn rewind(&mut self) -> Result<()> {
+        self.restart()?;
+        self.loop_index = 0;
+        self.frame = None;
+        Ok(())
+    }
+
+    /// Returns the sizes in bytes of the images that frames are rendered into.
+    fn output_sizes(&self) -> Vec<(usize, usize)> {
+        let (width, height) = self.basic_info().size;
+        let pixel_format = self.current_pixel_format();
+        let color_bytes = pixel_format
+            .color_data_format
+            .map(|f| f.bytes_per_sample() * pixel_format.color_type.samples_per_pixel());
+        color_bytes
+            .into_iter()
+            .chain(
+                pixel_format
+                    .extra_channel_format
+                    .iter()
+                    .flatten()
+                    .map(|f| f.bytes_per_sample()),
+            )
+            .map(|bytes| (width * bytes, height))
+            .collect()
+    }
+
+    /// Returns images of the given sizes to render a frame into, reusing the ones of the current
+    /// frame if possible.
+    fn output_images(&mut self, sizes: Vec<(usize, usize)>) -> Result<Vec<Image<u8>>> {
+        if let Some(frame) = self.frame.take()
+            && frame
+                .buffers
+                .iter()
+                .map(|b| b.size())
+                .eq(sizes.iter().copied())
+        {
+            return Ok(frame.buffers);
+        }
+        sizes.into_iter().map(Image::new).collect()
+    }
+
+    /// Decodes the frame with index `next_index`, rendering it if `render` returns true for its
+    /// start time and duration. Frames that are not rendered are skipped if no later frame
+    /// depends on them.
+    fn decode_next(&mut self, render: impl Fn(f64, f64) -> bool) -> Result<()> {
+        let sizes = self.output_sizes();
+        let decoder = self.decoder.take().unwrap();
+        let decoder = run_to_completion(&mut self.input, decoder, |d, input| d.process(input))?;
+        let duration = decoder.frame_header().duration.unwrap_or(0.0);
+        let index = self.next_index;
+        let start = match self.frame_starts.get(index) {
+            Some(start) => *start,
+            None => {
+                self.frame_starts.push(self.frames_end);
+                self.frames_end += duration;
+                self.frames_end - duration
+            }
+        };
+        // Frames before `skip_before` might not be decoded correctly.
+        let shown = render(start, duration) && index >= self.skip_before;
+        // Frames that later frames depend on have to be decoded even if they are not shown.
+        let referenced = decoder.frame_is_referenced() && index >= self.skip_before;
+        let decoder = if shown || referenced {
+            let mut images = self.output_images(sizes)?;
+            let mut buffers: Vec<_> = images
+                .iter_mut()
+                .map(|image| {
+                    let rect = Rect {
+                        origin: (0, 0),
+                        size: image.size(),
+                    };
+                    JxlOutputBuffer::from_image_rect_mut(image.get_rect_mut(rect).into_raw())
+                })
+                .collect();
+            let decoder = run_to_completion(&mut self.input, decoder, |d, input| {
+                d.process(input, &mut buffers)
+            })?;
+            self.frame = shown.then(|| JxlAnimationFrame {
+                index,
+                loop_index: self.loop_index,
+                timestamp: start,
+                duration,
+                buffers: images,
+            });
+            decoder
+        } else {
+            run_to_completion(&mut self.input, decoder, |d, input| d.skip_frame(input))?
+        };
+        if !decoder.has_more_frames() {
+            self.loop_duration = Some(self.frames_end);
+        }
+        self.decoder = Some(decoder);
+        self.next_index += 1;
+        Ok(())
+    }
+
+    /// Decodes the next frame to display, starting the animation over after its last frame as
+    /// many times as [`num_loops`](Self::num_loops) says. Returns `None` once playback is over.
+    pub fn next_frame(&mut self) -> Result<Option<&JxlAnimationFrame>> {
+        if !self.decoder().has_more_frames() {
+            let num_loops = self.num_loops();
+            if num_loops != 0 && self.loop_index + 1 >= num_loops {
+                return Ok(None);
+            }
+            self.restart()?;
+            self.loop_index += 1;
+        }
+        self.decode_next(|_, _| true)?;
+        let loop_offset = self.loop_index as f64 * self.loop_duration.unwrap_or(0.0);
+        let frame = self.frame.as_mut().unwrap();
+        frame.timestamp += loop_offset;
+        Ok(Some(frame))
+    }
+
+    /// Finds the loop and the index of the frame displayed at `time`, if the frames that were
+    /// seen so far are enough to tell.
+    fn locate(&self, time: f64) -> Option<(u32, usize)> {
+        let num_frames = self.frame_starts.len();
+        let (loop_index, time) = match self.loop_duration {
+            Some(duration) if duration > 0.0 => {
+                let loop_index = (time / duration).floor();
+                let num_loops = self.num_loops();
+                if num_loops != 0 && loop_index >= num_loops as f64 {
+                    // Playback is over, and the last frame stays on display.
+                    return Some((num_loops - 1, num_frames - 1));
+                }
+                (loop_index as u32, time - loop_index * duration)
+            }
+            Some(_) => return Some((0, num_frames - 1)),
+            None if time < self.frames_end => (0, time),
+            None => return None,
+        };
+        let index = self.frame_starts.partition_point(|start| *start <= time);
+        Some((loop_index, index.saturating_sub(1)))
+    }
+
+    /// Returns the frame displayed at `time`, in milliseconds since the start of playback.
+    ///
+    /// Frames are only decoded if needed: the current frame is reused if it is still on display,
+    /// later frames are reached by decoding forward or jumping to a keyframe, and frames that are
+    /// not displayed nor used by the requested one are skipped. Times past the end of playback return the last frame.
+    pub fn frame_at(&mut self, time: f64) -> Result<&JxlAnimationFrame> {
+        let time = time.max(0.0);
+        let (loop_index, index) = loop {
+            if let Some(position) = self.locate(time) {
+                break position;
+            }
+            // The frames that were seen so far end before `time`: look for more frames, and
+            // render the one that covers `time` when it is found. The frames before the keyframe
+            // that comes before `time`, if known, are only needed for their duration.
+            if let Some(keyframe) = self
+                .frame_index()?
+                .and_then(|frame_index| frame_index.keyframe_at_time(time))
+                .map(|keyframe| keyframe.frame_number)
+            {
+                self.skip_before = self.skip_before.max(keyframe);
+            }
+            while self.next_index < self.frame_starts.len() {
+                self.decode_next(|_, _| false)?;
+            }
+            self.decode_next(|start, duration| start <= time && time < start + duration)?;
+        };
+        if self.frame.as_ref().is_none_or(|frame| frame.index != index) {
+            if !self.seek_to_keyframe(index)? && self.next_index > index {
+                self.restart()?;
+                self.seek_to_keyframe(index)?;
+            }
+            while self.next_index <= index {
+                let target = self.next_index == index;
+                self.decode_next(|_, _| target)?;
+            }
+        }
+        self.loop_index = loop_index;
+        let loop_offset = loop_index as f64 * self.loop_duration.unwrap_or(0.0);
+        let frame = self.frame.as_mut().unwrap();
+        frame.loop_index = loop_index;
+        frame.timestamp = self.frame_starts[index] + loop_offset;
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::api::{JxlColorType, JxlDataFormat, JxlSeekableReader, JxlSharedInput};
+
+    const ANIMATIONS: [&str; 3] = [
+        "resources/test/conformance_test_images/animation_icos4d.jxl",
+        "resources/test/conformance_test_images/animation_newtons_cradle.jxl",
+        "resources/test/conformance_test_images/animation_spline.jxl",
+    ];
+
+    fn rgb8(num_extra_channels: usize) -> JxlPixelFormat {
+        JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
+            color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+            extra_channel_format: vec![None; num_extra_channels],
+        }
+    }
+
+    // Decodes the displayed frames of the file to 8-bit RGB, with their durations.
+    fn decode_frames(file: &[u8]) -> Vec<(Vec<u8>, f64)> {
+        let mut input = file;
+        let decoder = JxlDecoder::<Initialized>::new(Default::default());
+        let mut decoder = run_to_completion(&mut input, decoder, |d, i| d.process(i)).unwrap();
+        decoder.set_pixel_format(rgb8(decoder.basic_info().extra_channels.len()));
+        let (width, height) = decoder.basic_info().size;
+        let mut frames = vec![];
+        while decoder.has_more_frames() {
+            let frame_decoder =
+                run_to_completion(&mut input, decoder, |d, i| d.process(i)).unwrap();
+            let duration = frame_decoder.frame_header().duration.unwrap_or(0.0);
+            let mut output = vec![0u8; width * height * 3];
+            let mut bufs = [JxlOutputBuffer::new(&mut output, height, width * 3)];
+            decoder = run_to_completion(&mut input, frame_decoder, |d, i| d.process(i, &mut bufs))
+                .unwrap();
+            frames.push((output, duration));
+        }
+        frames
+    }
+
+    fn pixels(frame: &JxlAnimationFrame) -> Vec<u8> {
+        let image = &frame.buffers[0];
+        (0..image.size().1)
+            .flat_map(|y| image.row(y).iter().copied())
+            .collect()
+    }
+
+    #[test]
+    fn test_next_frame_loops() {
+        for path in ANIMATIONS {
+            let file = std::fs::read(path).unwrap();
+            let expected = decode_frames(&file);
+            let starts: Vec<f64> = expected
+                .iter()
+                .scan(0.0, |time, (_, duration)| {
+                    *time += duration;
+                    Some(*time - duration)
+                })
+                .collect();
+            let loop_duration: f64 = expected.iter().map(|(_, duration)| duration).sum();
+
+            let mut player =
+                JxlAnimationPlayer::new(JxlSharedInput::new(file), Default::default()).unwrap();
+            player.set_pixel_format(rgb8(player.basic_info().extra_channels.len()));
+            let num_loops = player.num_loops();
+            let n = expected.len();
+            for i in 0..2 * n {
+                let Some(frame) = player.next_frame().unwrap() else {
+                    assert_eq!(num_loops, 1, "{path}");
+                    assert_eq!(i, n, "{path}");
+                    break;
+                };
+                let loop_index = i / n;
+                assert_eq!(frame.index, i % n, "{path}");
+                assert_eq!(frame.loop_index as usize, loop_index, "{path}");
+                assert_eq!(frame.duration, expected[i % n].1, "{path}");
+                let timestamp = loop_index as f64 * loop_duration + starts[i % n];
+                assert!(
+                    (frame.timestamp - timestamp).abs() < 1e-6,
+                    "{path}: frame {i}"
+                );
+                assert!(pixels(frame) == expected[i % n].0, "{path}: frame {i}");
+            }
+            assert_eq!(player.loop_duration(), Some(loop_duration), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_frame_at() {
+        for path in ANIMATIONS {
+            let file = std::fs::read(path).unwrap();
+            let expected = decode_frames(&file);
+            let n = expected.len();
+            let starts: Vec<f64> = expected
+                .iter()
+                .scan(0.0, |time, (_, duration)| {
+                    *time += duration;
+                    Some(*time - duration)
+                })
+                .collect();
+            let loop_duration: f64 = expected.iter().map(|(_, duration)| duration).sum();
+
+            let input = JxlSeekableReader::new(Cursor::new(file)).unwrap();
+            let mut player = JxlAnimationPlayer::new(input, Default::default()).unwrap();
+            player.set_pixel_format(rgb8(player.basic_info().extra_channels.len()));
+            // Jump forward, stay on the same frame, go back, and wrap around.
+            let positions = [
+                (0, n / 2),
+                (0, n / 2),
+                (0, n - 1),
+                (0, 1),
+                (0, 0),
+                (1, n - 2),
+                (0, n / 3),
+            ];
+            let num_loops = player.num_loops() as usize;
+            for (loop_index, index) in positions {
+                let time =
+                    loop_index as f64 * loop_duration + starts[index] + expected[index].1 / 2.0;
+                // Times past the end of playback show the last frame.
+                let (loop_index, index) = if num_loops != 0 && loop_index >= num_loops {
+                    (num_loops - 1, n - 1)
+                } else {
+                    (loop_index, index)
+                };
+                let frame = player.frame_at(time).unwrap();
+                assert_eq!(frame.index, index, "{path}: {time}");
+                assert_eq!(frame.loop_index as usize, loop_index, "{path}: {time}");
+                assert!(pixels(frame) == expected[index].0, "{path}: {time}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_still_image() {
+        let file = std::fs::read("resources/test/basic.jxl").unwrap();
+        let expected = decode_frames(&file);
+        let mut player =
+            JxlAnimationPlayer::new(JxlSharedInput::new(file), Default::default()).unwrap();
+        player.set_pixel_format(rgb8(player.basic_info().extra_channels.len()));
+        let frame = player.frame_at(1000.0).unwrap();
+        assert_eq!(frame.index, 0);
+        assert_eq!(frame.duration, 0.0);
+        assert!(pixels(frame) == expected[0].0);
+        player.rewind().unwrap();
+        assert!(player.next_frame().unwrap().is_some());
+        assert!(player.next_frame().unwrap().is_none());
+    }
+
+    fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    // Wraps a bare codestream in a container, with a frame index listing the given displayed
+    // frames before or after the codestream.
+    fn with_frame_index(file: &[u8], keyframes: &[usize], index_first: bool) -> Vec<u8> {
+        let mut input = file;
+        let decoder = JxlDecoder::<Initialized>::new(Default::default());
+        let mut decoder = run_to_completion(&mut input, decoder, |d, i| d.process(i)).unwrap();
+        let animation = decoder.basic_info().animation.clone().unwrap();
+        let ms_per_tick =
+            1000.0 * animation.tps_denominator as f64 / animation.tps_numerator as f64;
+        let mut frames = vec![];
+        while decoder.has_more_frames() {
+            let frame_decoder =
+                run_to_completion(&mut input, decoder, |d, i| d.process(i)).unwrap();
+            let ticks = frame_decoder.frame_header().duration.unwrap() / ms_per_tick;
+            frames.push((frame_decoder.codestream_offset(), ticks.round() as u64));
+            decoder = run_to_completion(&mut input, frame_decoder, |d, i| d.skip_frame(i)).unwrap();
+        }
+
+        let mut index = vec![];
+        push_varint(&mut index, keyframes.len() as u64);
+        index.extend_from_slice(&animation.tps_denominator.to_be_bytes());
+        index.extend_from_slice(&animation.tps_numerator.to_be_bytes());
+        let mut last_offset = 0;
+        for (i, &keyframe) in keyframes.iter().enumerate() {
+            let next = keyframes.get(i + 1).copied().unwrap_or(frames.len());
+            push_varint(&mut index, frames[keyframe].0 - last_offset);
+            push_varint(&mut index, frames[keyframe..next].iter().map(|f| f.1).sum());
+            push_varint(&mut index, (next - keyframe) as u64);
+            last_offset = frames[keyframe].0;
+        }
+
+        let mut boxes = vec![(b"ftyp", &b"jxl \0\0\0\0jxl "[..]), (b"jxlc", file)];
+        boxes.insert(if index_first { 1 } else { 2 }, (b"jxli", &index));
+        let mut container = crate::api::CONTAINER_SIGNATURE.to_vec();
+        for (ty, content) in boxes {
+            container.extend_from_slice(&(content.len() as u32 + 8).to_be_bytes());
+            container.extend_from_slice(ty);
+            container.extend_from_slice(content);
+        }
+        container
+    }
+
+    #[test]
+    fn test_seek_to_keyframe() {
+        let path = "resources/test/conformance_test_images/animation_icos4d.jxl";
+        let file = std::fs::read(path).unwrap();
+        let expected = decode_frames(&file);
+        for index_first in [true, false] {
+            let file = with_frame_index(&file, &[0, 10, 20, 30], index_first);
+            let mut input = JxlSharedInput::new(file);
+            let decoder = JxlDecoder::<Initialized>::new(Default::default());
+            let mut decoder = run_to_completion(&mut input, decoder, |d, i| d.process(i)).unwrap();
+            assert_eq!(decoder.frame_index().is_some(), index_first);
+            let frame_index = decoder.find_frame_index(&mut input).unwrap().unwrap();
+            assert_eq!(frame_index.frames.len(), 4);
+            assert_eq!(frame_index.frames[2].frame_number, 20);
+
+            decoder.set_pixel_format(rgb8(decoder.basic_info().extra_channels.len()));
+            let (width, height) = decoder.basic_info().size;
+            for (frame, keyframe) in [(25, 20), (12, 10), (45, 30), (3, 0)] {
+                let next = decoder.seek_to_keyframe(&mut input, frame).unwrap();
+                assert_eq!(next, Some(keyframe));
+                let frame_decoder =
+                    run_to_completion(&mut input, decoder, |d, i| d.process(i)).unwrap();
+                let mut output = vec![0u8; width * height * 3];
+                let mut bufs = [JxlOutputBuffer::new(&mut output, height, width * 3)];
+                decoder =
+                    run_to_completion(&mut input, frame_decoder, |d, i| d.process(i, &mut bufs))
+                        .unwrap();
+                assert!(output == expected[keyframe].0, "frame {keyframe}");
+            }
+        }
+
+        // Without a frame index, the decoder is left unchanged.
+        let mut input = file.as_slice();
+        let decoder = JxlDecoder::<Initialized>::new(Default::default());
+        let mut decoder = run_to_completion(&mut input, decoder, |d, i| d.process(i)).unwrap();
+        let mut seekable = JxlSeekableReader::new(Cursor::new(input)).unwrap();
+        assert_eq!(decoder.seek_to_keyframe(&mut seekable, 10).unwrap(), None);
+    }
+
+    #[test]
+    fn test_frame_at_with_frame_index() {
+        for (path, keyframes) in [
+            (ANIMATIONS[0], &[0, 16, 32][..]),
+            (ANIMATIONS[1], &[0]),
+            (ANIMATIONS[2], &[0, 7, 30, 31, 50]),
+        ] {
+            let file = std::fs::read(path).unwrap();
+            let expected = decode_frames(&file);
+            let n = expected.len();
+            let starts: Vec<f64> = expected
+                .iter()
+                .scan(0.0, |time, (_, duration)| {
+                    *time += duration;
+                    Some(*time - duration)
+                })
+                .collect();
+            let file = with_frame_index(&file, keyframes, false);
+            let mut player =
+                JxlAnimationPlayer::new(JxlSharedInput::new(file), Default::default()).unwrap();
+            player.set_pixel_format(rgb8(player.basic_info().extra_channels.len()));
+            for index in [n - 2, n / 2, 1, n / 2 + 2, n - 1, 0] {
+                let time = starts[index] + expected[index].1 / 2.0;
+                let decoded_before = player.decoder().decoded_frames();
+                let frame = player.frame_at(time).unwrap();
+                assert_eq!(frame.index, index, "{path}: {time}");
+                assert!(pixels(frame) == expected[index].0, "{path}: {time}");
+                // No frame before the keyframe that precedes the requested one is decoded. The
+                // count starts over if the player rewinds.
+                let decoded = player.decoder().decoded_frames();
+                let decoded = decoded.checked_sub(decoded_before).unwrap_or(decoded);
+                let keyframe = keyframes.iter().rev().find(|k| **k <= index).unwrap();
+                assert!(decoded <= index - keyframe + 1, "{path}: {index}");
+            }
+        }
+    }
+}
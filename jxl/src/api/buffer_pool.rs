@@ -0,0 +1,245 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    error::Result,
+    image::{Image, ImageDataType, OwnedRawImage},
+};
+
+/// Counters of a [`JxlBufferPool`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JxlBufferPoolStats {
+    /// Number of buffer requests that were served by a pooled buffer.
+    pub hits: usize,
+    /// Number of buffer requests that needed a new allocation.
+    pub misses: usize,
+    /// Number of buffers that were given back and kept for re-use.
+    pub recycled: usize,
+    /// Number of buffers that were given back but dropped, because the pool was full.
+    pub discarded: usize,
+    /// Total size, in bytes, of the buffers that the pool currently holds.
+    pub pooled_bytes: usize,
+}
+
+// Byte size, offset and padding of a raw image.
+type ImageShape = ((usize, usize), (usize, usize), (usize, usize));
+
+#[derive(Default)]
+struct PoolState {
+    images: HashMap<ImageShape, Vec<OwnedRawImage>>,
+    // Empty vectors, by element type and capacity.
+    vecs: HashMap<(TypeId, usize), Vec<Box<dyn Any + Send>>>,
+    stats: JxlBufferPoolStats,
+}
+
+/// Pool of the buffers that the decoder would otherwise allocate for each frame (group data,
+/// render pipeline row buffers and entropy coding tables), so that they can be re-used by later
+/// frames and by other decoders.
+///
+/// Clones of a pool share its buffers, and a pool can be shared between decoders running on
+/// different threads. Buffers are only re-used for requests of exactly the same size, so pools
+/// are most effective when decoding many images of the same dimensions, or animations.
+#[derive(Clone)]
+pub struct JxlBufferPool {
+    max_bytes: usize,
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl JxlBufferPool {
+    /// Creates a pool that holds at most `max_bytes` bytes of unused buffers.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            state: Arc::new(Mutex::new(PoolState::default())),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn stats(&self) -> JxlBufferPoolStats {
+        self.state().stats
+    }
+
+    /// Drops all the buffers in the pool. Counters are preserved.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.images.clear();
+        state.vecs.clear();
+        state.stats.pooled_bytes = 0;
+    }
+
+    // Returns true if `bytes` more bytes can be pooled, and updates the counters accordingly.
+    fn admit(&self, state: &mut PoolState, bytes: usize) -> bool {
+        if bytes == 0 || state.stats.pooled_bytes + bytes > self.max_bytes {
+            state.stats.discarded += 1;
+            return false;
+        }
+        state.stats.recycled += 1;
+        state.stats.pooled_bytes += bytes;
+        true
+    }
+
+    /// Returns a zero-filled image of the given size. Pooled images are shared between all types
+    /// with the same byte size, so an image that comes from the pool is cleared before being
+    /// returned; this costs a pass over its memory, which is still cheaper than a new
+    /// allocation.
+    pub(crate) fn take_image<T: ImageDataType>(&self, size: (usize, usize)) -> Result<Image<T>> {
+        let byte_size = (size.0 * T::DATA_TYPE_ID.size(), size.1);
+        let shape = (
+            byte_size,
+            (0, 0),
+            OwnedRawImage::actual_padding(byte_size, (0, 0)),
+        );
+        {
+            let mut state = self.state();
+            if let Some(image) = state.images.get_mut(&shape).and_then(Vec::pop) {
+                state.stats.hits += 1;
+                state.stats.pooled_bytes -= raw_image_bytes(&image);
+                drop(state);
+                let mut image = Image::from_raw(image);
+                image.fill(T::default());
+                return Ok(image);
+            }
+            state.stats.misses += 1;
+        }
+        Image::new(size)
+    }
+
+    pub(crate) fn recycle_image(&self, image: OwnedRawImage) {
+        let mut state = self.state();
+        if !self.admit(&mut state, raw_image_bytes(&image)) {
+            return;
+        }
+        let shape = (image.byte_size(), image.byte_offset(), image.byte_padding());
+        state.images.entry(shape).or_default().push(image);
+    }
+
+    /// Returns an empty vector with a capacity of exactly `capacity` elements.
+    pub(crate) fn take_vec<T: Send + 'static>(&self, capacity: usize) -> Vec<T> {
+        {
+            let mut state = self.state();
+            let pooled = state
+                .vecs
+                .get_mut(&(TypeId::of::<T>(), capacity))
+                .and_then(Vec::pop);
+            if let Some(v) = pooled {
+                state.stats.hits += 1;
+                state.stats.pooled_bytes -= capacity * std::mem::size_of::<T>();
+                return *v.downcast::<Vec<T>>().unwrap();
+            }
+            state.stats.misses += 1;
+        }
+        Vec::with_capacity(capacity)
+    }
+
+    pub(crate) fn recycle_vec<T: Send + 'static>(&self, mut v: Vec<T>) {
+        v.clear();
+        let mut state = self.state();
+        if !self.admit(&mut state, v.capacity() * std::mem::size_of::<T>()) {
+            return;
+        }
+        state
+            .vecs
+            .entry((TypeId::of::<T>(), v.capacity()))
+            .or_default()
+            .push(Box::new(v));
+    }
+}
+
+/// Returns a vector with a capacity of exactly `capacity` elements, from `pool` if present.
+pub(crate) fn pooled_vec<T: Send + 'static>(
+    pool: Option<&JxlBufferPool>,
+    capacity: usize,
+) -> Vec<T> {
+    match pool {
+        Some(pool) => pool.take_vec(capacity),
+        None => Vec::with_capacity(capacity),
+    }
+}
+
+fn raw_image_bytes(image: &OwnedRawImage) -> usize {
+    let (size, padding) = (image.byte_size(), image.byte_padding());
+    (size.0 + padding.0) * (size.1 + padding.1)
+}
+
+impl std::fmt::Debug for JxlBufferPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JxlBufferPool")
+            .field("max_bytes", &self.max_bytes)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn images_are_reused_by_shape() {
+        let pool = JxlBufferPool::new(1 << 20);
+        let mut image = pool.take_image::<f32>((100, 10)).unwrap();
+        image.row_mut(3)[7] = 1.0;
+        pool.recycle_image(image.into_raw());
+        // Same byte shape, different size.
+        let other = pool.take_image::<f32>((10, 100)).unwrap();
+        let reused = pool.take_image::<f32>((100, 10)).unwrap();
+        assert_eq!(reused.row(3)[7], 0.0);
+        drop(other);
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses, stats.recycled), (1, 2, 1));
+        assert_eq!(stats.pooled_bytes, 0);
+    }
+
+    #[test]
+    fn images_of_other_types_are_cleared() {
+        let pool = JxlBufferPool::new(1 << 20);
+        let mut image = pool.take_image::<f32>((16, 4)).unwrap();
+        image.fill(1.5);
+        pool.recycle_image(image.into_raw());
+        let reused = pool.take_image::<i32>((16, 4)).unwrap();
+        assert_eq!(pool.stats().hits, 1);
+        for y in 0..4 {
+            assert!(reused.row(y).iter().all(|v| *v == 0));
+        }
+    }
+
+    #[test]
+    fn vecs_are_reused_by_type_and_capacity() {
+        let pool = JxlBufferPool::new(1 << 20);
+        let mut v = pool.take_vec::<u32>(64);
+        v.extend(0..64);
+        pool.recycle_vec(v);
+        assert!(pool.take_vec::<u16>(64).is_empty());
+        assert!(pool.take_vec::<u32>(32).is_empty());
+        let v = pool.take_vec::<u32>(64);
+        assert!(v.is_empty());
+        assert_eq!(v.capacity(), 64);
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 3));
+    }
+
+    #[test]
+    fn full_pool_discards_buffers() {
+        let pool = JxlBufferPool::new(1000);
+        pool.recycle_vec(Vec::<u8>::with_capacity(600));
+        pool.recycle_vec(Vec::<u8>::with_capacity(600));
+        pool.recycle_vec(Vec::<u8>::with_capacity(400));
+        let stats = pool.stats();
+        assert_eq!((stats.recycled, stats.discarded), (2, 1));
+        assert_eq!(stats.pooled_bytes, 1000);
+        pool.clear();
+        assert_eq!(pool.stats().pooled_bytes, 0);
+    }
+}
@@ -279,6 +279,23 @@ pub(crate) fn primaries_to_xyz_d50(
     Ok(result_matrix)
 }
 
+/// Returns the matrix converting linear RGB samples with the given `from` primaries and white
+/// point to linear RGB samples with the `to` primaries and white point, using Bradford chromatic
+/// adaptation.
+pub(crate) fn primaries_conversion_matrix(
+    from: (&JxlPrimaries, &JxlWhitePoint),
+    to: (&JxlPrimaries, &JxlWhitePoint),
+) -> Result<Matrix3x3<f64>, Error> {
+    let to_xyz_d50 = |(primaries, white_point): (&JxlPrimaries, &JxlWhitePoint)| {
+        let [r, g, b] = primaries.to_xy_coords();
+        let w = white_point.to_xy_coords();
+        primaries_to_xyz_d50(r.0, r.1, g.0, g.1, b.0, b.1, w.0, w.1)
+    };
+    let from_to_xyzd50 = to_xyz_d50(from)?;
+    let xyzd50_to_to = inv_3x3_matrix(&to_xyz_d50(to)?)?;
+    Ok(mul_3x3_matrix(&xyzd50_to_to, &from_to_xyzd50))
+}
+
 #[allow(clippy::too_many_arguments)]
 fn create_icc_rgb_matrix(
     rx: f32,
@@ -1104,9 +1121,42 @@ impl JxlColorEncoding {
             }
         }
     }
+
+    /// Returns the same color encoding, but with a linear transfer function. XYB is mapped to
+    /// linear sRGB.
+    pub fn with_linear_tf(&self) -> Self {
+        match self.clone() {
+            JxlColorEncoding::RgbColorSpace {
+                white_point,
+                primaries,
+                transfer_function: _,
+                rendering_intent,
+            } => JxlColorEncoding::RgbColorSpace {
+                white_point,
+                primaries,
+                transfer_function: JxlTransferFunction::Linear,
+                rendering_intent,
+            },
+            JxlColorEncoding::GrayscaleColorSpace {
+                white_point,
+                transfer_function: _,
+                rendering_intent,
+            } => JxlColorEncoding::GrayscaleColorSpace {
+                white_point,
+                transfer_function: JxlTransferFunction::Linear,
+                rendering_intent,
+            },
+            JxlColorEncoding::XYB { .. } => JxlColorEncoding::RgbColorSpace {
+                transfer_function: JxlTransferFunction::Linear,
+                white_point: JxlWhitePoint::D65,
+                primaries: JxlPrimaries::SRGB,
+                rendering_intent: RenderingIntent::Relative,
+            },
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum JxlColorProfile {
     Icc(Vec<u8>),
     Simple(JxlColorEncoding),
@@ -1138,6 +1188,17 @@ impl JxlColorProfile {
                 .map(Cow::Owned),
         }
     }
+
+    /// Returns true if this is an ICC profile whose data color space is CMYK.
+    ///
+    /// For such images, the C, M and Y samples are stored in the color channels and K is
+    /// stored in the extra channel of type `Black`.
+    pub fn is_cmyk(&self) -> bool {
+        match self {
+            Self::Icc(icc) => icc.get(16..20) == Some(b"CMYK"),
+            Self::Simple(_) => false,
+        }
+    }
 }
 
 impl fmt::Display for JxlColorProfile {
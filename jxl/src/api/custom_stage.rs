@@ -0,0 +1,40 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+/// Color space of the samples that a [`JxlCustomStage`] operates on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JxlCustomStageSpace {
+    /// Linear light samples, with the primaries and white point of the output color profile.
+    /// Requires the output color profile to be described by a color encoding.
+    Linear,
+    /// Samples encoded with the output color profile, in the nominal range `0.0..=1.0`. These are
+    /// the values that are converted to the requested data format and saved.
+    Output,
+}
+
+/// A processing step that runs as part of rendering, modifying rows of floating point samples
+/// in place. Stages run after color conversion and before alpha premultiplication, conversion to
+/// the output data format, and orientation.
+pub trait JxlCustomStage {
+    /// Whether the stage reads or modifies channel `c`. Channels 0 to 2 are the color channels
+    /// (only channel 0 is meaningful for grayscale images), and channel `3 + i` is the extra
+    /// channel `i`.
+    fn uses_channel(&self, c: usize) -> bool;
+
+    /// Processes `xsize` pixels starting at `position`, in image coordinates. `rows` contains
+    /// one row for each used channel, in increasing channel order. Rows might be longer than
+    /// `xsize`; samples past `xsize` are not part of the image.
+    ///
+    /// This might be called with rows covering the same pixels more than once, and with rows in
+    /// any order, so the result for each pixel should only depend on the inputs for that pixel
+    /// and its position.
+    fn process_rows(&self, position: (usize, usize), xsize: usize, rows: &mut [&mut [f32]]);
+}
+
+impl std::fmt::Debug for dyn JxlCustomStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JxlCustomStage")
+    }
+}
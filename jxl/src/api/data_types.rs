@@ -250,10 +250,167 @@ pub struct JxlAnimation {
     pub have_timecodes: bool,
 }
 
+impl JxlAnimation {
+    /// Converts a duration in ticks to microseconds, rounding down.
+    pub fn ticks_to_microseconds(&self, ticks: u64) -> u64 {
+        let us =
+            ticks as u128 * 1_000_000 * self.tps_denominator as u128 / self.tps_numerator as u128;
+        us.min(u64::MAX as u128) as u64
+    }
+}
+
+/// SMPTE timecode of a frame, as hours, minutes, seconds and frames.
+///
+/// Timecodes are ordered chronologically.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JxlTimecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+}
+
+impl JxlTimecode {
+    /// Decodes a timecode stored as `0xHHMMSSFF`, as in the frame header.
+    pub fn from_bits(bits: u32) -> Self {
+        let [hours, minutes, seconds, frames] = bits.to_be_bytes();
+        Self {
+            hours,
+            minutes,
+            seconds,
+            frames,
+        }
+    }
+
+    /// Encodes the timecode as `0xHHMMSSFF`.
+    pub fn to_bits(&self) -> u32 {
+        u32::from_be_bytes([self.hours, self.minutes, self.seconds, self.frames])
+    }
+
+    /// Number of frames since `00:00:00:00`, for a video with `frames_per_second` frames per
+    /// second.
+    pub fn frame_number(&self, frames_per_second: u32) -> u64 {
+        let seconds = (self.hours as u64 * 60 + self.minutes as u64) * 60 + self.seconds as u64;
+        seconds * frames_per_second as u64 + self.frames as u64
+    }
+
+    /// Time since `00:00:00:00` in milliseconds, for a video with `frames_per_second` frames
+    /// per second.
+    pub fn milliseconds(&self, frames_per_second: f64) -> f64 {
+        let seconds = (self.hours as f64 * 60.0 + self.minutes as f64) * 60.0 + self.seconds as f64;
+        (seconds + self.frames as f64 / frames_per_second) * 1000.0
+    }
+}
+
+impl std::fmt::Display for JxlTimecode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}:{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct JxlFrameHeader {
     pub name: String,
     pub duration: Option<f64>,
+    /// Timecode of the frame, if [`JxlAnimation::have_timecodes`] is set.
+    pub timecode: Option<JxlTimecode>,
     /// Frame size (width, height)
     pub size: (usize, usize),
 }
+
+/// A frame found by [`JxlDecoder::scan_frames`](crate::api::JxlDecoder::scan_frames), which reads
+/// the headers of frames without decoding them.
+#[derive(Clone, Debug)]
+pub struct JxlScannedFrame {
+    pub header: JxlFrameHeader,
+    /// Duration of the frame in ticks, see [`JxlAnimation::ticks_to_microseconds`].
+    pub duration_ticks: u32,
+    /// Whether the frame is displayed, rather than only being used by later frames. The preview
+    /// frame is not displayed.
+    pub is_visible: bool,
+    /// Whether this is the preview frame of the image.
+    pub is_preview: bool,
+    /// Offset in bytes of the start of the frame in the codestream.
+    pub codestream_offset: u64,
+    /// Sizes in bytes of the sections of the frame, in the order in which they are stored.
+    pub section_sizes: Vec<usize>,
+}
+
+/// Selects the part of a frame that should be decoded, see
+/// [`JxlDecoder::plan_sections`](crate::api::JxlDecoder::plan_sections).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JxlSectionRequest {
+    /// Region of the image to decode, as ((x0, y0), (width, height)). `None` selects the whole
+    /// image.
+    pub region: Option<((usize, usize), (usize, usize))>,
+    /// Maximum number of passes to decode. `None` selects all passes.
+    pub max_passes: Option<usize>,
+    /// Factor (1, 2, 4 or 8) by which the image will be downscaled when displayed. Passes that
+    /// only add detail beyond that resolution are not decoded; at 8, VarDCT frames are rendered
+    /// from their LF image alone.
+    pub downsampling: usize,
+}
+
+impl Default for JxlSectionRequest {
+    fn default() -> Self {
+        Self {
+            region: None,
+            max_passes: None,
+            downsampling: 1,
+        }
+    }
+}
+
+/// Reports the decoding of a section of the current frame, see
+/// [`JxlDecoderOptions::progress_callback`](crate::api::JxlDecoderOptions::progress_callback).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JxlProgressEvent {
+    /// The global LF section was decoded.
+    LfGlobal,
+    /// The LF group with the given index was decoded.
+    LfGroup(usize),
+    /// The LF image of the frame is complete: all the LF groups were decoded, or the frame uses
+    /// the LF image of an earlier LF frame, in which case this is reported before any of its
+    /// sections. A preview can be rendered from this point on if
+    /// [`JxlDecoder::has_lf_preview`](crate::api::JxlDecoder::has_lf_preview) is true.
+    LfComplete,
+    /// The global HF section was decoded.
+    HfGlobal,
+    /// The given pass of the given group was decoded. Passes of a group are decoded in order,
+    /// so `pass + 1` passes of `group` are complete at this point.
+    HfGroup { group: usize, pass: usize },
+    /// The given pass was decoded for all the groups of the frame.
+    PassComplete(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timecode() {
+        let timecode = JxlTimecode::from_bits(0x01172d0c);
+        assert_eq!(
+            timecode,
+            JxlTimecode {
+                hours: 1,
+                minutes: 23,
+                seconds: 45,
+                frames: 12,
+            }
+        );
+        assert_eq!(timecode.to_bits(), 0x01172d0c);
+        assert_eq!(timecode.to_string(), "01:23:45:12");
+        assert_eq!(timecode.frame_number(25), (3600 + 23 * 60 + 45) * 25 + 12);
+        assert_eq!(
+            timecode.milliseconds(24.0),
+            (3600.0 + 23.0 * 60.0 + 45.5) * 1000.0
+        );
+        assert!(timecode < JxlTimecode::from_bits(0x01172e00));
+        assert!(timecode > JxlTimecode::from_bits(0x00ffffff));
+    }
+}
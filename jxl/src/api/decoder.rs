@@ -5,13 +5,16 @@
 
 use super::{
     JxlBasicInfo, JxlBitstreamInput, JxlColorProfile, JxlDecoderInner, JxlDecoderOptions,
-    JxlOutputBuffer, JxlPixelFormat, ProcessingResult,
+    JxlFrameIndex, JxlGainMap, JxlOutputBuffer, JxlOutputSink, JxlPixelFormat, JxlScannedFrame,
+    JxlSectionRequest, ProcessingResult,
 };
 #[cfg(test)]
 use crate::frame::Frame;
 use crate::{api::JxlFrameHeader, error::Result};
 use states::*;
+use std::io::{Seek, SeekFrom};
 use std::marker::PhantomData;
+use std::ops::Range;
 
 pub mod states {
     pub trait JxlState {}
@@ -23,6 +26,14 @@ pub mod states {
     impl JxlState for WithFrameInfo {}
 }
 
+/// The result of [`JxlDecoder::finish_input`].
+pub struct JxlFinishedFrame {
+    /// The decoder, ready to decode the next frame if there is one.
+    pub decoder: JxlDecoder<WithImageInfo>,
+    /// Whether the input ended before the end of the frame, so that some of it was filled in.
+    pub partial: bool,
+}
+
 // Q: do we plan to add support for box decoding?
 // If we do, one way is to take a callback &[u8; 4] -> Box<dyn Write>.
 
@@ -54,6 +65,31 @@ impl<S: JxlState> JxlDecoder<S> {
         self.inner.decoded_frames()
     }
 
+    /// Retrieves the gain map of the image, if the `jhgm` box containing it has been read.
+    ///
+    /// Boxes that come after the codestream are not read by the decoder; use
+    /// [`JxlGainMap::from_container`] to find those.
+    pub fn gain_map(&self) -> Option<&JxlGainMap> {
+        self.inner.gain_map()
+    }
+
+    /// Retrieves the frame index of the animation, if the `jxli` box containing it has been read.
+    ///
+    /// Boxes that come after the codestream are not read by the decoder; use
+    /// [`find_frame_index`](JxlDecoder::find_frame_index) or [`JxlFrameIndex::from_container`] to
+    /// find those.
+    pub fn frame_index(&self) -> Option<&JxlFrameIndex> {
+        self.inner.frame_index()
+    }
+
+    /// Returns the largest amount of memory, in bytes, that the buffers used to render any of the
+    /// frames decoded so far took up at the same time.
+    ///
+    /// This does not include the output buffers, nor memory used to decode the frame itself.
+    pub fn peak_render_memory_usage(&self) -> usize {
+        self.inner.peak_render_memory_usage()
+    }
+
     /// Rewinds a decoder to the start of the file, allowing past frames to be displayed again.
     pub fn rewind(mut self) -> JxlDecoder<Initialized> {
         self.inner.rewind();
@@ -110,8 +146,19 @@ impl JxlDecoder<WithImageInfo> {
         self.inner.output_color_profile().unwrap()
     }
 
+    /// Returns the luminance, in nits, corresponding to a sample value of 1.0 in the output,
+    /// if the output color profile has a linear transfer function. This is derived from the
+    /// intensity target of the image and, for HLG images, from
+    /// [`JxlDecoderOptions::hlg_rendering`].
+    pub fn linear_output_luminance(&self) -> Option<f32> {
+        self.inner.linear_output_luminance()
+    }
+
     /// Specifies the preferred color profile to be used for outputting data.
     /// Same semantics as JxlDecoderSetOutputColorProfile.
+    ///
+    /// Conversions between RGB color encodings (any combination of primaries, white point and
+    /// transfer function) do not require a CMS.
     pub fn set_output_color_profile(&mut self, profile: JxlColorProfile) -> Result<()> {
         self.inner.set_output_color_profile(profile)
     }
@@ -136,6 +183,64 @@ impl JxlDecoder<WithImageInfo> {
         self.inner.has_more_frames()
     }
 
+    /// Reads the headers and TOCs of all the remaining frames, including the ones that are not
+    /// displayed, without decoding them. Section data is skipped over with
+    /// [`JxlBitstreamInput::skip`], and no pixel buffers are allocated.
+    ///
+    /// The frames are returned once the end of the codestream is reached, at which point no frames
+    /// are left to decode.
+    pub fn scan_frames(
+        mut self,
+        input: &mut impl JxlBitstreamInput,
+    ) -> Result<ProcessingResult<Vec<JxlScannedFrame>, Self>> {
+        Ok(match self.inner.scan_frames(input)? {
+            ProcessingResult::Complete { result } => ProcessingResult::Complete { result },
+            ProcessingResult::NeedsMoreInput { size_hint, .. } => {
+                ProcessingResult::NeedsMoreInput {
+                    size_hint,
+                    fallback: self,
+                }
+            }
+        })
+    }
+
+    /// Returns the frame index of the file. If no `jxli` box was read so far and `input` supports
+    /// [`as_seekable`](JxlBitstreamInput::as_seekable), the boxes after the codestream are searched
+    /// for one first.
+    pub fn find_frame_index(
+        &mut self,
+        input: &mut impl JxlBitstreamInput,
+    ) -> Result<Option<&JxlFrameIndex>> {
+        if let Some(input) = input.as_seekable() {
+            self.inner.find_frame_index(input)?;
+        }
+        Ok(self.inner.frame_index())
+    }
+
+    /// Moves the decoder to the last keyframe listed in the frame index of the file that is
+    /// displayed no later than the displayed frame with index `frame`, and seeks `input` to it.
+    /// The frames before a keyframe are not needed to decode it and the frames after it.
+    ///
+    /// The frame index is found as in [`find_frame_index`](Self::find_frame_index).
+    ///
+    /// Returns the index of the displayed frame that is decoded next, or `None` if the decoder was
+    /// left unchanged because there is no suitable keyframe, or because the last frame of the file
+    /// was decoded already. In that case, use [`rewind`](Self::rewind) and decode frames from the
+    /// start of the file instead.
+    pub fn seek_to_keyframe<In: JxlBitstreamInput + Seek>(
+        &mut self,
+        input: &mut In,
+        frame: usize,
+    ) -> Result<Option<usize>> {
+        let Some((frame_number, file_offset)) =
+            self.inner.seek_to_keyframe(input.as_seekable(), frame)?
+        else {
+            return Ok(None);
+        };
+        input.seek(SeekFrom::Start(file_offset))?;
+        Ok(Some(frame_number))
+    }
+
     #[cfg(test)]
     pub(crate) fn set_use_simple_pipeline(&mut self, u: bool) {
         self.inner.set_use_simple_pipeline(u);
@@ -144,6 +249,9 @@ impl JxlDecoder<WithImageInfo> {
 
 impl JxlDecoder<WithFrameInfo> {
     /// Skip the current frame.
+    ///
+    /// The sections of the frame are not read, so later frames that depend on it (see
+    /// [`frame_is_referenced`](Self::frame_is_referenced)) are not decoded correctly.
     pub fn skip_frame(
         mut self,
         input: &mut impl JxlBitstreamInput,
@@ -156,11 +264,63 @@ impl JxlDecoder<WithFrameInfo> {
         self.inner.frame_header().unwrap()
     }
 
+    /// Offset in bytes of the start of the current frame in the codestream, as used by frame
+    /// indices. For frames that are composited from several layers, this is the offset of the
+    /// last layer.
+    pub fn codestream_offset(&self) -> u64 {
+        self.inner.frame_codestream_offset().unwrap()
+    }
+
+    /// Whether later frames depend on the current one, so that it cannot be skipped with
+    /// [`skip_frame`](Self::skip_frame) without affecting them.
+    pub fn frame_is_referenced(&self) -> bool {
+        self.inner.frame_is_referenced().unwrap()
+    }
+
     /// Number of passes we have full data for.
     pub fn num_completed_passes(&self) -> usize {
         self.inner.num_completed_passes().unwrap()
     }
 
+    /// Whether a low resolution preview of the current frame can be rendered with
+    /// [`render_lf_preview`](Self::render_lf_preview).
+    ///
+    /// This becomes true for visible VarDCT frames once the LF global and LF group sections
+    /// have been decoded, which typically happens well before the HF groups arrive. Frames that
+    /// take their LF image from an earlier LF frame have a preview as soon as their header is
+    /// read. Frames that use blending or chroma subsampling are not supported.
+    pub fn has_lf_preview(&self) -> bool {
+        self.inner.has_lf_preview()
+    }
+
+    /// Renders the LF image of the current frame, upsampled to the full frame size, into
+    /// `buffers`. The buffers are the same as the ones passed to `process`, and can be passed to
+    /// it afterwards to continue decoding.
+    ///
+    /// Returns [`Error::NoLfPreview`](crate::error::Error::NoLfPreview) if
+    /// [`has_lf_preview`](Self::has_lf_preview) is false.
+    pub fn render_lf_preview(&mut self, buffers: &mut [JxlOutputBuffer<'_>]) -> Result<()> {
+        let mut sinks: Vec<_> = buffers
+            .iter_mut()
+            .map(|buf| JxlOutputSink::Buffer(JxlOutputBuffer::reborrow(buf)))
+            .collect();
+        self.inner.render_lf_preview(&mut sinks)
+    }
+
+    /// Restricts decoding of the current frame to the sections needed for `request`, and returns
+    /// the byte ranges of the file that hold the ones that were not read yet, in file order.
+    ///
+    /// This is meant for inputs that are fetched piecewise, such as
+    /// [`JxlRangeInput`](crate::api::JxlRangeInput): once the returned ranges are available,
+    /// `process` skips over the bytes of the other sections without reading them, and renders the
+    /// frame as [`finish_input`](Self::finish_input) does when they are missing.
+    ///
+    /// Returns [`Error::SectionsNotContiguous`](crate::error::Error::SectionsNotContiguous) if the
+    /// sections of the frame are split across several codestream boxes.
+    pub fn plan_sections(&mut self, request: &JxlSectionRequest) -> Result<Vec<Range<u64>>> {
+        self.inner.plan_sections(request)
+    }
+
     /// Draws all the pixels we have data for.
     ///
     /// Note: see `process` for alignment requirements for the buffer data.
@@ -176,25 +336,149 @@ impl JxlDecoder<WithFrameInfo> {
     /// u16/f16 and f32 respectively), each row in the provided buffers must be aligned to 2 or 4
     /// bytes respectively. If that is not the case, the library may panic.
     pub fn process<In: JxlBitstreamInput>(
-        mut self,
+        self,
         input: &mut In,
         buffers: &mut [JxlOutputBuffer<'_>],
     ) -> Result<ProcessingResult<JxlDecoder<WithImageInfo>, Self>> {
-        let inner_result = self.inner.process(input, Some(buffers))?;
+        let mut sinks: Vec<_> = buffers
+            .iter_mut()
+            .map(|buf| JxlOutputSink::Buffer(JxlOutputBuffer::reborrow(buf)))
+            .collect();
+        self.process_to_sinks(input, &mut sinks)
+    }
+
+    /// Like `process`, but each output can either be written to a buffer or passed to a callback
+    /// row by row, as it is rendered. See [`JxlOutputSink`].
+    pub fn process_to_sinks<In: JxlBitstreamInput>(
+        mut self,
+        input: &mut In,
+        sinks: &mut [JxlOutputSink<'_>],
+    ) -> Result<ProcessingResult<JxlDecoder<WithImageInfo>, Self>> {
+        let inner_result = self.inner.process(input, Some(sinks))?;
         Ok(self.map_inner_processing_result(inner_result))
     }
+
+    /// Like `process`, but declares that `input` holds all the remaining data of the file.
+    ///
+    /// If the input ends before the end of the frame, the frame is rendered anyway from the
+    /// sections that were received in full: missing VarDCT groups are filled in from the LF
+    /// image, and missing modular data from the lower resolution data of progressive images where
+    /// it is available. Anything else is filled with mid-gray, or with opaque alpha. In that case,
+    /// [`JxlFinishedFrame::partial`] is set, and no further frames are decoded.
+    ///
+    /// Returns [`Error::NotEnoughFrameData`](crate::error::Error::NotEnoughFrameData) if not even
+    /// the global data of the frame was received.
+    pub fn finish_input<In: JxlBitstreamInput>(
+        self,
+        input: &mut In,
+        buffers: &mut [JxlOutputBuffer<'_>],
+    ) -> Result<JxlFinishedFrame> {
+        let mut decoder = match self.process(input, buffers)? {
+            ProcessingResult::Complete { result } => {
+                return Ok(JxlFinishedFrame {
+                    decoder: result,
+                    partial: false,
+                });
+            }
+            ProcessingResult::NeedsMoreInput { fallback, .. } => fallback,
+        };
+        let mut sinks: Vec<_> = buffers
+            .iter_mut()
+            .map(|buf| JxlOutputSink::Buffer(JxlOutputBuffer::reborrow(buf)))
+            .collect();
+        decoder.inner.finish_input(&mut sinks)?;
+        Ok(JxlFinishedFrame {
+            decoder: JxlDecoder::wrap_inner(decoder.inner),
+            partial: true,
+        })
+    }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    use crate::api::{JxlDataFormat, JxlDecoderOptions};
+    use crate::api::{JxlDataFormat, JxlDecoderOptions, SimdLevel};
     use crate::error::Error;
     use crate::image::{Image, Rect};
     use crate::util::test::assert_almost_abs_eq_coords;
     use jxl_macros::for_each_test_file;
     use std::path::Path;
 
+    pub(crate) use crate::api::animation::run_to_completion;
+
+    /// An 8-bit RGB image to decode a frame into.
+    pub(crate) struct Rgb8Image {
+        pub(crate) data: Vec<u8>,
+        pub(crate) width: usize,
+        pub(crate) height: usize,
+    }
+
+    impl Rgb8Image {
+        pub(crate) fn new((width, height): (usize, usize)) -> Self {
+            Self {
+                data: vec![0; width * height * 3],
+                width,
+                height,
+            }
+        }
+
+        pub(crate) fn buffers(&mut self) -> [JxlOutputBuffer<'_>; 1] {
+            [JxlOutputBuffer::new(
+                &mut self.data,
+                self.height,
+                self.width * 3,
+            )]
+        }
+    }
+
+    /// Requests 8-bit RGB output, without extra channels.
+    pub(crate) fn set_rgb8_pixel_format(decoder: &mut JxlDecoder<WithImageInfo>) {
+        use crate::api::{JxlColorType, JxlPixelFormat};
+
+        let num_extra_channels = decoder.basic_info().extra_channels.len();
+        decoder.set_pixel_format(JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
+            color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+            extra_channel_format: vec![None; num_extra_channels],
+        });
+    }
+
+    /// Reads the headers of the image and of its first frame, with 8-bit RGB output, and returns
+    /// the decoder with an image to decode the frame into.
+    pub(crate) fn decode_frame_header_rgb8(
+        input: &mut impl JxlBitstreamInput,
+    ) -> Result<(JxlDecoder<WithFrameInfo>, Rgb8Image)> {
+        let decoder = JxlDecoder::<states::Initialized>::new(Default::default());
+        let mut decoder = run_to_completion(input, decoder, |d, input| d.process(input))?;
+        set_rgb8_pixel_format(&mut decoder);
+        let image = Rgb8Image::new(decoder.basic_info().size);
+        let decoder = run_to_completion(input, decoder, |d, input| d.process(input))?;
+        Ok((decoder, image))
+    }
+
+    /// Decodes all the frames of the input to 8-bit RGB, returning their headers and pixels.
+    pub(crate) fn decode_frames_rgb8(
+        input: &mut impl JxlBitstreamInput,
+    ) -> Result<Vec<(JxlFrameHeader, Vec<u8>)>> {
+        let decoder = JxlDecoder::<states::Initialized>::new(Default::default());
+        let mut decoder = run_to_completion(input, decoder, |d, input| d.process(input))?;
+        set_rgb8_pixel_format(&mut decoder);
+        let size = decoder.basic_info().size;
+        let mut frames = vec![];
+        loop {
+            let frame_decoder = run_to_completion(input, decoder, |d, input| d.process(input))?;
+            let header = frame_decoder.frame_header();
+            let mut output = Rgb8Image::new(size);
+            let mut bufs = output.buffers();
+            decoder =
+                run_to_completion(input, frame_decoder, |d, input| d.process(input, &mut bufs))?;
+            frames.push((header, output.data));
+            if !decoder.has_more_frames() {
+                return Ok(frames);
+            }
+        }
+    }
+
     #[test]
     fn decode_small_chunks() {
         arbtest::arbtest(|u| {
@@ -211,12 +495,28 @@ pub(crate) mod tests {
 
     #[allow(clippy::type_complexity)]
     pub fn decode(
+        input: &[u8],
+        chunk_size: usize,
+        use_simple_pipeline: bool,
+        callback: Option<Box<dyn FnMut(&Frame, usize) -> Result<(), Error>>>,
+    ) -> Result<(usize, Vec<Vec<Image<f32>>>), Error> {
+        decode_with_options(
+            input,
+            chunk_size,
+            use_simple_pipeline,
+            callback,
+            JxlDecoderOptions::default(),
+        )
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn decode_with_options(
         mut input: &[u8],
         chunk_size: usize,
         use_simple_pipeline: bool,
         callback: Option<Box<dyn FnMut(&Frame, usize) -> Result<(), Error>>>,
+        options: JxlDecoderOptions,
     ) -> Result<(usize, Vec<Vec<Image<f32>>>), Error> {
-        let options = JxlDecoderOptions::default();
         let mut initialized_decoder = JxlDecoder::<states::Initialized>::new(options);
 
         if let Some(callback) = callback {
@@ -359,17 +659,13 @@ pub(crate) mod tests {
         let simple_frames = decode(&file, usize::MAX, true, None)?.1;
         let frames = decode(&file, usize::MAX, false, None)?.1;
         assert_eq!(frames.len(), simple_frames.len());
-        for (fc, (f, sf)) in frames
-            .into_iter()
-            .zip(simple_frames.into_iter())
-            .enumerate()
-        {
+        for (fc, (f, sf)) in frames.into_iter().zip(simple_frames).enumerate() {
             assert_eq!(
                 f.len(),
                 sf.len(),
                 "Frame {fc} has different channels counts",
             );
-            for (c, (b, sb)) in f.into_iter().zip(sf.into_iter()).enumerate() {
+            for (c, (b, sb)) in f.into_iter().zip(sf).enumerate() {
                 assert_eq!(
                     b.size(),
                     sb.size(),
@@ -411,6 +707,1184 @@ pub(crate) mod tests {
 
     for_each_test_file!(compare_pipelines);
 
+    fn compare_simd_levels(path: &Path) -> Result<(), Error> {
+        let file = std::fs::read(path)?;
+        let decode_with_level = |level| {
+            let options = JxlDecoderOptions {
+                max_simd_level: Some(level),
+                ..JxlDecoderOptions::default()
+            };
+            decode_with_options(&file, usize::MAX, false, None, options).map(|x| x.1)
+        };
+        let scalar_frames = decode_with_level(SimdLevel::Scalar)?;
+        for level in SimdLevel::available() {
+            if level == SimdLevel::Scalar {
+                continue;
+            }
+            let frames = decode_with_level(level)?;
+            assert_eq!(frames.len(), scalar_frames.len());
+            // The EPF stages only skip a vector of pixels if none of them need filtering, so with
+            // wider vectors, a few pixels next to filtered blocks are filtered too.
+            let (mut num_samples, mut num_different) = (0, 0);
+            for (fc, (f, sf)) in frames.iter().zip(scalar_frames.iter()).enumerate() {
+                for (c, (b, sb)) in f.iter().zip(sf).enumerate() {
+                    assert_eq!(b.size(), sb.size());
+                    let sz = b.size();
+                    for y in 0..sz.1 {
+                        for x in 0..sz.0 {
+                            let (v, sv) = (b.row(y)[x], sb.row(y)[x]);
+                            // Relative error for HDR images.
+                            let error = (v - sv).abs() / sv.abs().max(1.0);
+                            assert!(
+                                error <= 1e-2,
+                                "{level:?} differs from scalar at {x} {y} in channel {c} of \
+                                 frame {fc}: {v} vs {sv}",
+                            );
+                            num_samples += 1;
+                            num_different += (error > 1e-4) as usize;
+                        }
+                    }
+                }
+            }
+            assert!(
+                num_different * 1000 <= num_samples,
+                "{level:?}: {num_different} of {num_samples} samples differ from scalar",
+            );
+        }
+        Ok(())
+    }
+
+    for_each_test_file!(compare_simd_levels);
+
+    #[test]
+    fn compare_pipelines_small_tiles() -> Result<(), Error> {
+        // Images with upsampling, chroma subsampling, EPF, patches and noise, and orientation.
+        for file in [
+            "oddsize_ups.jxl",
+            "multiple_lf_420.jxl",
+            "green_queen_vardct_e3.jxl",
+            "multiple_layers_noise_spline.jxl",
+            "stp2_520x260_d25_e6.jxl",
+            "orientation6_rotate_90_cw.jxl",
+        ] {
+            let path = Path::new("resources/test").join(file);
+            crate::render::with_smallest_tiles(|| compare_pipelines(&path))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_peak_render_memory_usage() {
+        use crate::api::{JxlColorType, JxlPixelFormat};
+
+        let file = std::fs::read("resources/test/stp2_520x260_d25_e6.jxl").unwrap();
+        let decode = || {
+            let mut input = file.as_slice();
+            let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            assert_eq!(decoder.peak_render_memory_usage(), 0);
+            decoder.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Rgb,
+                color_data_format: Some(JxlDataFormat::f32()),
+                extra_channel_format: vec![],
+            });
+            let (width, height) = decoder.basic_info().size;
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let mut output = Image::<f32>::new((width * 3, height)).unwrap();
+            let rect = Rect {
+                size: output.size(),
+                origin: (0, 0),
+            };
+            let mut bufs = [JxlOutputBuffer::from_image_rect_mut(
+                output.get_rect_mut(rect).into_raw(),
+            )];
+            loop {
+                match decoder.process(&mut input, &mut bufs).unwrap() {
+                    ProcessingResult::Complete { result } => {
+                        break result.peak_render_memory_usage();
+                    }
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            }
+        };
+        let default_peak = decode();
+        let small_tiles_peak = crate::render::with_smallest_tiles(decode);
+        assert!(default_peak > 0);
+        assert!(small_tiles_peak < default_peak);
+    }
+
+    #[test]
+    fn test_custom_stages() {
+        use crate::api::{JxlColorType, JxlCustomStage, JxlCustomStageSpace, JxlPixelFormat};
+        use std::sync::Arc;
+
+        struct Fill {
+            value: f32,
+            // Only pixels with both coordinates below this are modified.
+            limit: usize,
+        }
+
+        impl JxlCustomStage for Fill {
+            fn uses_channel(&self, c: usize) -> bool {
+                c < 3
+            }
+
+            fn process_rows(
+                &self,
+                position: (usize, usize),
+                xsize: usize,
+                rows: &mut [&mut [f32]],
+            ) {
+                if position.1 >= self.limit {
+                    return;
+                }
+                let end = xsize.min(self.limit.saturating_sub(position.0));
+                for row in rows.iter_mut() {
+                    row[..end].fill(self.value);
+                }
+            }
+        }
+
+        struct Invert;
+
+        impl JxlCustomStage for Invert {
+            fn uses_channel(&self, c: usize) -> bool {
+                c < 3
+            }
+
+            fn process_rows(
+                &self,
+                _position: (usize, usize),
+                xsize: usize,
+                rows: &mut [&mut [f32]],
+            ) {
+                for row in rows.iter_mut() {
+                    for v in row[..xsize].iter_mut() {
+                        *v = 1.0 - *v;
+                    }
+                }
+            }
+        }
+
+        let decode = |file: &[u8],
+                      custom_stages: Vec<(JxlCustomStageSpace, Arc<dyn JxlCustomStage>)>,
+                      use_simple: bool| {
+            let options = JxlDecoderOptions {
+                custom_stages,
+                ..Default::default()
+            };
+            let mut input = file;
+            let decoder = JxlDecoder::<states::Initialized>::new(options);
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            decoder.set_use_simple_pipeline(use_simple);
+            decoder.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Rgb,
+                color_data_format: Some(JxlDataFormat::f32()),
+                extra_channel_format: vec![None; decoder.basic_info().extra_channels.len()],
+            });
+            let (width, height) = decoder.basic_info().size;
+            let decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let mut output = Image::<f32>::new((width * 3, height)).unwrap();
+            let rect = Rect {
+                size: output.size(),
+                origin: (0, 0),
+            };
+            let mut bufs = [JxlOutputBuffer::from_image_rect_mut(
+                output.get_rect_mut(rect).into_raw(),
+            )];
+            run_to_completion(&mut input, decoder, |d, input| d.process(input, &mut bufs)).unwrap();
+            output
+        };
+
+        // An XYB image and an image that is not linear after decoding.
+        for path in [
+            "resources/test/stp2_520x260_d25_e6.jxl",
+            "resources/test/green_queen_modular_e3.jxl",
+        ] {
+            let file = std::fs::read(path).unwrap();
+            for use_simple in [false, true] {
+                let baseline = decode(&file, vec![], use_simple);
+                let (width, height) = baseline.size();
+
+                let inverted = decode(
+                    &file,
+                    vec![(JxlCustomStageSpace::Output, Arc::new(Invert))],
+                    use_simple,
+                );
+                for y in 0..height {
+                    for x in 0..width {
+                        assert_eq!(inverted.row(y)[x], 1.0 - baseline.row(y)[x]);
+                    }
+                }
+
+                // Linear stages see the same pixels as output stages, and black stays black.
+                let limit = 20;
+                let filled = decode(
+                    &file,
+                    vec![(
+                        JxlCustomStageSpace::Linear,
+                        Arc::new(Fill { value: 0.0, limit }),
+                    )],
+                    use_simple,
+                );
+                for y in 0..height {
+                    for x in 0..width {
+                        let expected = if x < limit * 3 && y < limit {
+                            0.0
+                        } else {
+                            baseline.row(y)[x]
+                        };
+                        assert!(
+                            (filled.row(y)[x] - expected).abs() < 1e-4,
+                            "{path} at ({x}, {y}): {} vs {expected}",
+                            filled.row(y)[x]
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_resampling() {
+        use crate::api::{JxlColorType, JxlPixelFormat, JxlResampling, JxlResamplingFilter};
+
+        let decode = |file: &[u8], resampling: Option<JxlResampling>, use_simple: bool| {
+            let size = resampling.as_ref().map(|r| r.size);
+            let options = JxlDecoderOptions {
+                resampling,
+                ..Default::default()
+            };
+            let mut input = file;
+            let decoder = JxlDecoder::<states::Initialized>::new(options);
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            decoder.set_use_simple_pipeline(use_simple);
+            decoder.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Rgb,
+                color_data_format: Some(JxlDataFormat::f32()),
+                extra_channel_format: vec![None; decoder.basic_info().extra_channels.len()],
+            });
+            let (width, height) = size.unwrap_or(decoder.basic_info().size);
+            let decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let mut output = Image::<f32>::new((width * 3, height)).unwrap();
+            let rect = Rect {
+                size: output.size(),
+                origin: (0, 0),
+            };
+            let mut bufs = [JxlOutputBuffer::from_image_rect_mut(
+                output.get_rect_mut(rect).into_raw(),
+            )];
+            run_to_completion(&mut input, decoder, |d, input| d.process(input, &mut bufs)).unwrap();
+            output
+        };
+        let channel_means = |image: &Image<f32>| {
+            let mut sums = [0.0f64; 3];
+            for y in 0..image.size().1 {
+                for (x, v) in image.row(y).iter().enumerate() {
+                    sums[x % 3] += *v as f64;
+                }
+            }
+            sums.map(|s| s * 3.0 / (image.size().0 * image.size().1) as f64)
+        };
+
+        // An XYB image, an image that is not linear after decoding, and a progressive image.
+        for (path, use_simple_pipeline) in [
+            (
+                "resources/test/stp2_520x260_d25_e6.jxl",
+                [false, true].as_slice(),
+            ),
+            ("resources/test/green_queen_modular_e3.jxl", &[false, true]),
+            (
+                "resources/test/conformance_test_images/progressive.jxl",
+                &[false],
+            ),
+        ] {
+            let file = std::fs::read(path).unwrap();
+            for &use_simple in use_simple_pipeline {
+                let baseline = decode(&file, None, use_simple);
+                let size = (baseline.size().0 / 3, baseline.size().1);
+
+                // Lanczos3 resampling to the same size does not change the image.
+                let same_size = decode(
+                    &file,
+                    Some(JxlResampling {
+                        size,
+                        filter: JxlResamplingFilter::Lanczos3,
+                    }),
+                    use_simple,
+                );
+                for y in 0..size.1 {
+                    for x in 0..size.0 * 3 {
+                        assert_almost_abs_eq_coords(
+                            same_size.row(y)[x],
+                            baseline.row(y)[x],
+                            1e-4,
+                            (x, y),
+                            0,
+                        );
+                    }
+                }
+
+                // Downsampling approximately preserves the average color.
+                for filter in [JxlResamplingFilter::Lanczos3, JxlResamplingFilter::Mitchell] {
+                    let smaller = decode(
+                        &file,
+                        Some(JxlResampling {
+                            size: (size.0 * 2 / 7, size.1 / 3),
+                            filter,
+                        }),
+                        use_simple,
+                    );
+                    assert_eq!(smaller.size(), (size.0 * 2 / 7 * 3, size.1 / 3));
+                    for (mean, expected) in channel_means(&smaller)
+                        .into_iter()
+                        .zip(channel_means(&baseline))
+                    {
+                        assert!(
+                            (mean - expected).abs() < 0.02,
+                            "{path}: {mean} vs {expected}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_sinks() {
+        use crate::api::{JxlColorType, JxlOutputSink, JxlPixelFormat};
+
+        // Decodes the first frame, either into buffers or through callbacks, and returns the
+        // contents of each output.
+        let decode = |file: &[u8], use_callbacks: bool, use_simple: bool| {
+            let mut input = file;
+            let decoder = JxlDecoder::<states::Initialized>::new(Default::default());
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            decoder.set_use_simple_pipeline(use_simple);
+            let num_extra_channels = decoder.basic_info().extra_channels.len();
+            decoder.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Rgb,
+                color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+                extra_channel_format: vec![
+                    Some(JxlDataFormat::U8 { bit_depth: 8 });
+                    num_extra_channels
+                ],
+            });
+            let (width, height) = decoder.basic_info().size;
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let row_bytes: Vec<_> = std::iter::once(width * 3)
+                .chain(std::iter::repeat_n(width, num_extra_channels))
+                .collect();
+            let mut outputs: Vec<_> = row_bytes.iter().map(|b| vec![0u8; b * height]).collect();
+            if use_callbacks {
+                let mut callbacks: Vec<_> = outputs
+                    .iter_mut()
+                    .zip(row_bytes.iter())
+                    .map(|(output, &bytes)| {
+                        let channels = bytes / width;
+                        move |x: usize, y: usize, num_pixels: usize, pixels: &[u8]| {
+                            assert_eq!(pixels.len(), num_pixels * channels);
+                            output[(y * width + x) * channels..][..pixels.len()]
+                                .copy_from_slice(pixels);
+                        }
+                    })
+                    .collect();
+                let mut sinks: Vec<_> = callbacks
+                    .iter_mut()
+                    .map(|callback| JxlOutputSink::Callback(callback))
+                    .collect();
+                loop {
+                    match decoder.process_to_sinks(&mut input, &mut sinks).unwrap() {
+                        ProcessingResult::Complete { .. } => break,
+                        ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                    }
+                }
+            } else {
+                let mut bufs: Vec<_> = outputs
+                    .iter_mut()
+                    .zip(row_bytes.iter())
+                    .map(|(output, &bytes)| JxlOutputBuffer::new(output, height, bytes))
+                    .collect();
+                run_to_completion(&mut input, decoder, |d, input| d.process(input, &mut bufs))
+                    .unwrap();
+            }
+            outputs
+        };
+
+        for path in [
+            "resources/test/stp2_520x260_d25_e6.jxl",
+            "resources/test/orientation6_rotate_90_cw.jxl",
+            "resources/test/extra_channels.jxl",
+            "resources/test/multiple_layers_noise_spline.jxl",
+        ] {
+            let file = std::fs::read(path).unwrap();
+            for use_simple in [false, true] {
+                let expected = decode(&file, false, use_simple);
+                let outputs = decode(&file, true, use_simple);
+                assert!(outputs == expected, "{path}, simple pipeline: {use_simple}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_lf_preview() {
+        for (path, uses_lf_frame) in [
+            ("resources/test/green_queen_vardct_e3.jxl", false),
+            (
+                "resources/test/conformance_test_images/progressive.jxl",
+                true,
+            ),
+        ] {
+            let file = std::fs::read(path).unwrap();
+            let mut input = &file[..];
+            let (mut decoder, mut output) = decode_frame_header_rgb8(&mut input).unwrap();
+            let (width, height) = (output.width, output.height);
+            let mut preview = Rgb8Image::new((width, height));
+            let mut preview_buf = preview.buffers();
+            let mut output_buf = output.buffers();
+            // The LF image of frames that use an LF frame is known before their sections.
+            assert_eq!(decoder.has_lf_preview(), uses_lf_frame, "{path}");
+            if !uses_lf_frame {
+                assert!(matches!(
+                    decoder.render_lf_preview(&mut preview_buf),
+                    Err(Error::NoLfPreview)
+                ));
+            }
+
+            // Feed the rest of the file in small chunks, and render the preview as soon as the
+            // LF image is available.
+            let mut available = 0;
+            let mut rendered_preview = false;
+            loop {
+                if !rendered_preview && decoder.has_lf_preview() {
+                    assert!(!input.is_empty());
+                    decoder.render_lf_preview(&mut preview_buf).unwrap();
+                    rendered_preview = true;
+                }
+                available = (available + 256).min(input.len());
+                let mut chunk = &input[..available];
+                let result = decoder.process(&mut chunk, &mut output_buf).unwrap();
+                let consumed = available - chunk.len();
+                input = &input[consumed..];
+                available -= consumed;
+                match result {
+                    ProcessingResult::Complete { .. } => break,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            }
+            assert!(rendered_preview, "{path}");
+
+            // The preview is a blurry version of the final image, so their averages over blocks
+            // of pixels are close, except near edges.
+            const BLOCK: usize = 16;
+            let block_mean = |image: &[u8], bx: usize, by: usize, c: usize| {
+                let mut sum = 0.0;
+                for y in by * BLOCK..(by + 1) * BLOCK {
+                    for x in bx * BLOCK..(bx + 1) * BLOCK {
+                        sum += image[(y * width + x) * 3 + c] as f64;
+                    }
+                }
+                sum / (BLOCK * BLOCK) as f64
+            };
+            let mut total_diff = 0.0;
+            let num_blocks = (width / BLOCK) * (height / BLOCK);
+            for by in 0..height / BLOCK {
+                for bx in 0..width / BLOCK {
+                    for c in 0..3 {
+                        let expected = block_mean(&output.data, bx, by, c);
+                        total_diff += (block_mean(&preview.data, bx, by, c) - expected).abs();
+                    }
+                }
+            }
+            let mean_diff = total_diff / (num_blocks * 3) as f64;
+            assert!(mean_diff < 6.0, "{path}: mean difference {mean_diff}");
+        }
+    }
+
+    #[test]
+    fn test_finish_truncated_input() {
+        // Decodes the first frame from the first `len` bytes of `file`, as 8-bit RGB, and returns
+        // whether the frame was partial. Returns `None` if the headers are not complete.
+        let decode = |file: &[u8], len: usize| -> Result<Option<(Vec<u8>, bool)>> {
+            let mut input = &file[..len];
+            let (decoder, mut output) = match decode_frame_header_rgb8(&mut input) {
+                Err(Error::TruncatedInput) => return Ok(None),
+                result => result?,
+            };
+            let finished = decoder.finish_input(&mut input, &mut output.buffers())?;
+            assert!(!finished.partial || !finished.decoder.has_more_frames());
+            Ok(Some((output.data, finished.partial)))
+        };
+        let mean_diff = |a: &[u8], b: &[u8]| {
+            let total: f64 = a
+                .iter()
+                .zip(b.iter())
+                .map(|(a, b)| (*a as f64 - *b as f64).abs())
+                .sum();
+            total / a.len() as f64
+        };
+
+        for path in [
+            "resources/test/green_queen_vardct_e3.jxl",
+            "resources/test/green_queen_modular_e3.jxl",
+            "resources/test/conformance_test_images/progressive.jxl",
+        ] {
+            let file = std::fs::read(path).unwrap();
+            let (expected, partial) = decode(&file, file.len()).unwrap().unwrap();
+            assert!(!partial);
+
+            // Missing data is filled in, with less error as more of the file is available.
+            let mut previous_diff = f64::INFINITY;
+            for fraction in [0.1, 0.2, 0.5, 0.8, 0.95] {
+                let len = (file.len() as f64 * fraction) as usize;
+                let Some((output, partial)) = decode(&file, len).unwrap() else {
+                    // The frame header, or a frame that comes before it, is not complete yet.
+                    assert!(previous_diff.is_infinite(), "{path}: at {fraction}");
+                    continue;
+                };
+                assert!(partial);
+                if path.contains("modular") {
+                    // Groups that were not reached, like the bottom-right one, are mid-gray.
+                    for &v in &output[output.len() - 3..] {
+                        assert!(v.abs_diff(128) <= 2, "{path}: at {fraction}, got {v}");
+                    }
+                }
+                let diff = mean_diff(&output, &expected);
+                assert!(diff <= previous_diff, "{path}: {diff} at {fraction}");
+                previous_diff = diff;
+            }
+            assert!(previous_diff < 5.0, "{path}: {previous_diff}");
+        }
+
+        // Without the global sections of the frame, nothing can be rendered.
+        let file = std::fs::read("resources/test/green_queen_vardct_e3.jxl").unwrap();
+        assert!(matches!(decode(&file, 200), Err(Error::NotEnoughFrameData)));
+    }
+
+    #[test]
+    fn test_plan_sections_range_fetch() {
+        use crate::api::JxlRangeInput;
+
+        // Decodes `file` to 8-bit RGB, fetching the headers in small chunks and then only the
+        // ranges planned for `request`, in reverse order. Returns the image, its width and the
+        // number of fetched bytes.
+        let decode = |file: &[u8], request: JxlSectionRequest| -> (Vec<u8>, usize, usize) {
+            let mut fetched = 0;
+            let mut input = JxlRangeInput::new(file.len() as u64);
+            let mut fetch = |input: &mut JxlRangeInput, range: Range<u64>| {
+                let range = range.start as usize..(range.end as usize).min(file.len());
+                fetched += range.len();
+                input.fill(range.start as u64, &file[range]);
+            };
+            let mut decoder = JxlDecoder::<states::Initialized>::new(Default::default());
+            let mut decoder = loop {
+                match decoder.process(&mut input).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                        let position = input.position();
+                        fetch(&mut input, position..position + 256);
+                        decoder = fallback;
+                    }
+                }
+            };
+            set_rgb8_pixel_format(&mut decoder);
+            let mut output = Rgb8Image::new(decoder.basic_info().size);
+            let mut decoder = loop {
+                match decoder.process(&mut input).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                        let position = input.position();
+                        fetch(&mut input, position..position + 256);
+                        decoder = fallback;
+                    }
+                }
+            };
+            for range in decoder.plan_sections(&request).unwrap().into_iter().rev() {
+                fetch(&mut input, range);
+            }
+            let ProcessingResult::Complete { .. } =
+                decoder.process(&mut input, &mut output.buffers()).unwrap()
+            else {
+                panic!("frame not complete after fetching the planned ranges");
+            };
+            (output.data, output.width, fetched)
+        };
+
+        for path in [
+            "resources/test/green_queen_vardct_e3.jxl",
+            "resources/test/conformance_test_images/cafe.jxl",
+        ] {
+            let file = std::fs::read(path).unwrap();
+            let (expected, width, _) = decode(&file, JxlSectionRequest::default());
+
+            // A region within the first group decodes exactly from a fraction of the file.
+            let (x0, y0, size) = (16, 32, 200);
+            let request = JxlSectionRequest {
+                region: Some(((x0, y0), (size, size))),
+                ..Default::default()
+            };
+            let (region_output, _, region_fetched) = decode(&file, request);
+            assert!(region_fetched < file.len() / 2, "{path}: {region_fetched}");
+            for y in y0..y0 + size {
+                let row = (y * width + x0) * 3..(y * width + x0 + size) * 3;
+                assert_eq!(region_output[row.clone()], expected[row], "{path}: row {y}");
+            }
+
+            // At 1:8, only the LF image is needed.
+            let request = JxlSectionRequest {
+                downsampling: 8,
+                ..Default::default()
+            };
+            let (lf_output, _, lf_fetched) = decode(&file, request);
+            assert!(lf_fetched < region_fetched, "{path}: {lf_fetched}");
+            // Compare the 8x8 block averages, as that is the resolution the output is good for.
+            let height = expected.len() / (width * 3);
+            let block_means = |image: &[u8]| {
+                let mut means = vec![0.0; width.div_ceil(8) * height.div_ceil(8) * 3];
+                for (i, v) in image.iter().enumerate() {
+                    let (x, y, c) = ((i / 3) % width, i / 3 / width, i % 3);
+                    means[((y / 8) * width.div_ceil(8) + x / 8) * 3 + c] += *v as f64 / 64.0;
+                }
+                means
+            };
+            let mean_diff = block_means(&lf_output)
+                .iter()
+                .zip(block_means(&expected).iter())
+                .map(|(a, b)| (a - b).abs())
+                .sum::<f64>()
+                / (expected.len() / 64) as f64;
+            assert!(mean_diff < 8.0, "{path}: {mean_diff}");
+        }
+    }
+
+    #[test]
+    fn test_progress_events() {
+        use crate::api::JxlProgressEvent;
+        use std::{cell::RefCell, rc::Rc};
+
+        for (path, multiple_passes, uses_lf_frame) in [
+            ("resources/test/green_queen_vardct_e3.jxl", false, false),
+            // The sections of the skipped preview frame come first.
+            ("resources/test/with_preview.jxl", false, false),
+            ("resources/test/green_queen_modular_e3.jxl", false, false),
+            (
+                "resources/test/conformance_test_images/progressive.jxl",
+                true,
+                true,
+            ),
+        ] {
+            let file = std::fs::read(path).unwrap();
+            let events = Rc::new(RefCell::new(vec![]));
+            let mut options = JxlDecoderOptions::default();
+            let events_clone = events.clone();
+            options.progress_callback = Some(Box::new(move |event| {
+                events_clone.borrow_mut().push(event);
+            }));
+            decode_with_options(&file, 4096, false, None, options).unwrap();
+            let events = events.take();
+
+            // Each section is reported once, after the global sections it depends on, and
+            // passes are reported in order. The LF image is complete once all the LF groups are
+            // decoded, or before any section if it comes from an LF frame.
+            let position = |event| events.iter().position(|e| *e == event).unwrap();
+            let lf_global = position(JxlProgressEvent::LfGlobal);
+            let lf_complete = position(JxlProgressEvent::LfComplete);
+            let hf_global = position(JxlProgressEvent::HfGlobal);
+            assert_eq!(lf_global, uses_lf_frame as usize, "{path}");
+            if uses_lf_frame {
+                assert_eq!(lf_complete, 0, "{path}");
+            } else {
+                assert!(lf_complete < hf_global, "{path}");
+            }
+            let mut lf_groups = vec![];
+            let mut group_passes: Vec<usize> = vec![];
+            let mut completed_passes = 0;
+            for (i, event) in events.iter().enumerate().skip(lf_global + 1) {
+                match *event {
+                    JxlProgressEvent::LfGlobal => panic!("{path}: LF global reported twice"),
+                    JxlProgressEvent::LfGroup(group) => {
+                        assert!(i < hf_global, "{path}");
+                        assert!(uses_lf_frame || i < lf_complete, "{path}");
+                        assert!(!lf_groups.contains(&group), "{path}");
+                        lf_groups.push(group);
+                    }
+                    JxlProgressEvent::LfComplete => assert_eq!(i, lf_complete, "{path}"),
+                    JxlProgressEvent::HfGlobal => assert_eq!(i, hf_global, "{path}"),
+                    JxlProgressEvent::HfGroup { group, pass } => {
+                        assert!(i > hf_global, "{path}");
+                        if group >= group_passes.len() {
+                            group_passes.resize(group + 1, 0);
+                        }
+                        assert_eq!(group_passes[group], pass, "{path}: group {group}");
+                        group_passes[group] += 1;
+                    }
+                    JxlProgressEvent::PassComplete(pass) => {
+                        assert_eq!(pass, completed_passes, "{path}");
+                        assert!(group_passes.iter().all(|p| *p > pass), "{path}");
+                        completed_passes += 1;
+                    }
+                }
+            }
+            assert!(!lf_groups.is_empty());
+            assert!(
+                group_passes.iter().all(|p| *p == completed_passes),
+                "{path}"
+            );
+            assert_eq!(completed_passes > 1, multiple_passes, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_seekable_input() {
+        use crate::api::JxlSeekableReader;
+        use std::cell::Cell;
+        use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+        use std::rc::Rc;
+
+        // Counts the bytes that are read from the file, but not the ones that are seeked over,
+        // and the number of seeks.
+        struct CountingReader<'a> {
+            data: Cursor<&'a [u8]>,
+            bytes_read: Rc<Cell<usize>>,
+            seeks: Rc<Cell<usize>>,
+        }
+        impl Read for CountingReader<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let num = self.data.read(buf)?;
+                self.bytes_read.set(self.bytes_read.get() + num);
+                Ok(num)
+            }
+        }
+        impl Seek for CountingReader<'_> {
+            fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+                self.seeks.set(self.seeks.get() + 1);
+                self.data.seek(pos)
+            }
+        }
+
+        // Decodes the first frame of the input to 8-bit RGB.
+        fn decode_rgb(
+            input: &mut impl JxlBitstreamInput,
+            request: &JxlSectionRequest,
+        ) -> (Vec<u8>, usize) {
+            let (mut decoder, mut output) = decode_frame_header_rgb8(input).unwrap();
+            decoder.plan_sections(request).unwrap();
+            let mut bufs = output.buffers();
+            run_to_completion(input, decoder, |d, input| d.process(input, &mut bufs)).unwrap();
+            (output.data, output.width)
+        }
+
+        // The region covers most of the groups of the smaller images, so only the larger ones
+        // check the number of bytes read.
+        for (path, check_bytes_read) in [
+            ("resources/test/has_permutation.jxl", false),
+            ("resources/test/has_permutation_with_container.jxl", false),
+            // The codestream is split across several boxes.
+            ("resources/test/zoltan_tasi_unsplash.jxl", true),
+            ("resources/test/green_queen_vardct_e3.jxl", false),
+            // The sections of the skipped preview frame come first.
+            ("resources/test/with_preview.jxl", false),
+        ] {
+            let file = std::fs::read(path).unwrap();
+            let request = JxlSectionRequest::default();
+            let (expected, width) = decode_rgb(&mut &file[..], &request);
+
+            // The file does not need to start at the beginning of the reader.
+            const PREFIX: usize = 100;
+            let mut data = vec![0xff; PREFIX];
+            data.extend_from_slice(&file);
+            let bytes_read = Rc::new(Cell::new(0));
+            let seeks = Rc::new(Cell::new(0));
+            let reader = |start| {
+                let mut data = Cursor::new(&data[..]);
+                data.set_position(start);
+                CountingReader {
+                    data,
+                    bytes_read: bytes_read.clone(),
+                    seeks: seeks.clone(),
+                }
+            };
+            let mut input = JxlSeekableReader::new(reader(PREFIX as u64)).unwrap();
+            let (output, _) = decode_rgb(&mut input, &request);
+            assert!(output == expected, "{path}");
+            // The file is read once, without seeking around every read.
+            assert_eq!(bytes_read.get(), file.len(), "{path}");
+            assert!(seeks.get() <= 4, "{path}: {}", seeks.get());
+
+            // Buffered readers are read sequentially.
+            let mut input = BufReader::new(Cursor::new(&file[..]));
+            let (output, _) = decode_rgb(&mut input, &request);
+            assert!(output == expected, "{path}");
+
+            // Sections that are not needed are not read at all.
+            bytes_read.set(0);
+            let (x0, y0, size) = (16, 32, 200);
+            let request = JxlSectionRequest {
+                region: Some(((x0, y0), (size, size))),
+                ..Default::default()
+            };
+            let mut input = JxlSeekableReader::new(reader(PREFIX as u64)).unwrap();
+            let (output, _) = decode_rgb(&mut input, &request);
+            assert!(
+                !check_bytes_read || bytes_read.get() < file.len() / 2,
+                "{path}: {}",
+                bytes_read.get()
+            );
+            let height = expected.len() / (width * 3);
+            for y in y0..(y0 + size).min(height) {
+                let row = (y * width + x0) * 3..(y * width + (x0 + size).min(width)) * 3;
+                assert_eq!(output[row.clone()], expected[row], "{path}: row {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_shared_input() {
+        use crate::api::JxlSharedInput;
+
+        // Decodes all the frames of the input to 8-bit RGB.
+        fn decode_frames(input: &mut impl JxlBitstreamInput) -> Vec<Vec<u8>> {
+            let frames = decode_frames_rgb8(input).unwrap();
+            frames.into_iter().map(|(_, pixels)| pixels).collect()
+        }
+
+        for path in [
+            "resources/test/has_permutation_with_container.jxl",
+            // The codestream is split across several boxes.
+            "resources/test/zoltan_tasi_unsplash.jxl",
+            "resources/test/green_queen_modular_e3.jxl",
+            "resources/test/multiple_layers_noise_spline.jxl",
+            "resources/test/with_preview.jxl",
+            "resources/test/basic.jxl",
+        ] {
+            let file = std::fs::read(path).unwrap();
+            let expected = decode_frames(&mut &file[..]);
+            let output = decode_frames(&mut JxlSharedInput::new(file));
+            assert_eq!(output.len(), expected.len(), "{path}");
+            assert!(output == expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_timecodes() {
+        use crate::api::JxlTimecode;
+
+        // The same animation, with a timecode added to each frame.
+        let file = std::fs::read("resources/test/cropped_traffic_light.jxl").unwrap();
+        let expected = decode_frames_rgb8(&mut &file[..]).unwrap();
+        let file = std::fs::read("resources/test/timecodes.jxl").unwrap();
+        let frames = decode_frames_rgb8(&mut &file[..]).unwrap();
+        let timecodes =
+            [0x0a000000, 0x0a000300, 0x0a000400, 0x0a000700].map(JxlTimecode::from_bits);
+        assert_eq!(frames.len(), timecodes.len());
+        assert_eq!(expected.len(), timecodes.len());
+        for (((header, pixels), (expected_header, expected_pixels)), timecode) in
+            frames.iter().zip(&expected).zip(timecodes)
+        {
+            assert_eq!(header.timecode, Some(timecode));
+            assert_eq!(expected_header.timecode, None);
+            assert!(pixels == expected_pixels);
+        }
+        assert_eq!(timecodes[1].to_string(), "10:00:03:00");
+
+        // Scanning the frames finds the same timecodes.
+        let mut input = &file[..];
+        let decoder = JxlDecoder::<states::Initialized>::new(Default::default());
+        let decoder = run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+        assert!(
+            decoder
+                .basic_info()
+                .animation
+                .as_ref()
+                .unwrap()
+                .have_timecodes
+        );
+        let ProcessingResult::Complete { result: scanned } =
+            decoder.scan_frames(&mut input).unwrap()
+        else {
+            panic!("incomplete frames");
+        };
+        let scanned_timecodes: Vec<_> = scanned.iter().map(|frame| frame.header.timecode).collect();
+        assert_eq!(scanned_timecodes, timecodes.map(Some));
+    }
+
+    #[test]
+    fn test_scan_frames() {
+        fn headers(input: &mut &[u8]) -> JxlDecoder<WithImageInfo> {
+            let decoder = JxlDecoder::<states::Initialized>::new(Default::default());
+            let ProcessingResult::Complete { result } = decoder.process(input).unwrap() else {
+                panic!("incomplete headers");
+            };
+            result
+        }
+
+        for path in [
+            "resources/test/conformance_test_images/animation_icos4d.jxl",
+            "resources/test/conformance_test_images/animation_spline.jxl",
+            "resources/test/multiple_layers_noise_spline.jxl",
+            "resources/test/with_preview.jxl",
+            // The codestream is split across several boxes.
+            "resources/test/zoltan_tasi_unsplash.jxl",
+        ] {
+            let file = std::fs::read(path).unwrap();
+
+            // Displayed frames, as found by going through them with `skip_frame`.
+            let mut input = &file[..];
+            let mut decoder = headers(&mut input);
+            let animation = decoder.basic_info().animation.clone();
+            let mut expected = vec![];
+            while decoder.has_more_frames() {
+                let ProcessingResult::Complete { result } = decoder.process(&mut input).unwrap()
+                else {
+                    panic!("incomplete frame header");
+                };
+                expected.push((result.frame_header(), result.codestream_offset()));
+                let ProcessingResult::Complete { result } = result.skip_frame(&mut input).unwrap()
+                else {
+                    panic!("incomplete frame");
+                };
+                decoder = result;
+            }
+
+            let mut input = &file[..];
+            let decoder = headers(&mut input);
+            let ProcessingResult::Complete { result: frames } =
+                decoder.scan_frames(&mut input).unwrap()
+            else {
+                panic!("incomplete scan");
+            };
+            assert!(input.is_empty(), "{path}");
+            let visible: Vec<_> = frames.iter().filter(|f| f.is_visible).collect();
+            assert_eq!(visible.len(), expected.len(), "{path}");
+            for (frame, (header, offset)) in visible.iter().zip(&expected) {
+                assert_eq!(frame.codestream_offset, *offset, "{path}");
+                assert_eq!(frame.header.name, header.name, "{path}");
+                assert_eq!(frame.header.duration, header.duration, "{path}");
+            }
+            // Frames are stored back to back.
+            for pair in frames.windows(2) {
+                let size: usize = pair[0].section_sizes.iter().sum();
+                assert!(pair[0].codestream_offset + size as u64 <= pair[1].codestream_offset);
+            }
+            assert_eq!(
+                frames.iter().filter(|f| f.is_preview).count(),
+                path.contains("preview") as usize
+            );
+            if let Some(animation) = &animation {
+                let ticks: u64 = frames.iter().map(|f| f.duration_ticks as u64).sum();
+                let total_ms: f64 = expected.iter().map(|(h, _)| h.duration.unwrap()).sum();
+                let total_us = animation.ticks_to_microseconds(ticks);
+                assert!((total_us as f64 - total_ms * 1000.0).abs() < 1.0, "{path}");
+            }
+
+            // Scanning can be resumed when more input arrives.
+            let mut input = &file[..];
+            let mut decoder = headers(&mut input);
+            let mut available = 0;
+            let scanned = loop {
+                available = (available + 7).min(input.len());
+                let mut chunk = &input[..available];
+                let result = decoder.scan_frames(&mut chunk).unwrap();
+                input = &input[available - chunk.len()..];
+                available = chunk.len();
+                match result {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            };
+            assert_eq!(scanned.len(), frames.len(), "{path}");
+            for (a, b) in scanned.iter().zip(&frames) {
+                assert_eq!(a.codestream_offset, b.codestream_offset, "{path}");
+                assert_eq!(a.section_sizes, b.section_sizes, "{path}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer_output_matches_f32() {
+        use crate::api::JxlPixelFormat;
+
+        // Decodes the first frame to 8-bit integers.
+        let decode_u8 = |file: &[u8], use_simple: bool| {
+            let mut input = file;
+            let decoder = JxlDecoder::<states::Initialized>::new(Default::default());
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            decoder.set_use_simple_pipeline(use_simple);
+            let default_format = decoder.current_pixel_format().clone();
+            let format = JxlDataFormat::U8 { bit_depth: 8 };
+            decoder.set_pixel_format(JxlPixelFormat {
+                color_type: default_format.color_type,
+                color_data_format: Some(format),
+                extra_channel_format: vec![Some(format); default_format.extra_channel_format.len()],
+            });
+            let (width, height) = decoder.basic_info().size;
+            let decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let samples = default_format.color_type.samples_per_pixel();
+            let row_bytes: Vec<_> = std::iter::once(width * samples)
+                .chain(std::iter::repeat_n(
+                    width,
+                    default_format.extra_channel_format.len(),
+                ))
+                .collect();
+            let mut outputs: Vec<_> = row_bytes.iter().map(|b| vec![0u8; b * height]).collect();
+            let mut bufs: Vec<_> = outputs
+                .iter_mut()
+                .zip(row_bytes.iter())
+                .map(|(output, &bytes)| JxlOutputBuffer::new(output, height, bytes))
+                .collect();
+            run_to_completion(&mut input, decoder, |d, input| d.process(input, &mut bufs)).unwrap();
+            outputs
+        };
+
+        // Lossless modular images, with and without extra channels.
+        for path in [
+            "resources/test/green_queen_modular_e3.jxl",
+            "resources/test/extra_channels.jxl",
+            "resources/test/squeeze_alpha.jxl",
+        ] {
+            let file = std::fs::read(path).unwrap();
+            for use_simple in [false, true] {
+                let (_, frames) = decode(&file, usize::MAX, use_simple, None).unwrap();
+                let outputs = decode_u8(&file, use_simple);
+                assert_eq!(outputs.len(), frames[0].len());
+                for (c, (output, image)) in outputs.iter().zip(frames[0].iter()).enumerate() {
+                    let xsize = image.size().0;
+                    for y in 0..image.size().1 {
+                        for (x, v) in image.row(y).iter().enumerate() {
+                            let expected = (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+                            assert_eq!(
+                                output[y * xsize + x],
+                                expected,
+                                "{path}, simple pipeline: {use_simple}, {x} {y} in buffer {c}",
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stage_taps() {
+        use crate::api::{JxlColorType, JxlPixelFormat, JxlStageTap};
+
+        let file = std::fs::read("resources/test/stp2_520x260_d25_e6.jxl").unwrap();
+        for use_simple in [false, true] {
+            let taps = ["xyb", "epf", "2x2 upsampling", "nonexistent"].map(JxlStageTap::new);
+            let options = JxlDecoderOptions {
+                stage_taps: taps.to_vec(),
+                ..Default::default()
+            };
+            let mut input = file.as_slice();
+            let decoder = JxlDecoder::<states::Initialized>::new(options);
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            decoder.set_use_simple_pipeline(use_simple);
+            decoder.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Rgb,
+                color_data_format: Some(JxlDataFormat::f32()),
+                extra_channel_format: vec![],
+            });
+            let (width, height) = decoder.basic_info().size;
+            let decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let mut output = Image::<f32>::new((width * 3, height)).unwrap();
+            let rect = Rect {
+                size: output.size(),
+                origin: (0, 0),
+            };
+            let mut bufs = [JxlOutputBuffer::from_image_rect_mut(
+                output.get_rect_mut(rect).into_raw(),
+            )];
+            run_to_completion(&mut input, decoder, |d, input| d.process(input, &mut bufs)).unwrap();
+
+            let [xyb, epf, upsampling, nonexistent] = taps.map(|tap| tap.take_captures());
+            assert!(nonexistent.is_empty());
+
+            // The conversion from XYB is the last stage before saving the f32 output, so it
+            // produces exactly the output.
+            let [xyb] = &xyb[..] else {
+                panic!("expected one capture, got {}", xyb.len());
+            };
+            assert_eq!(xyb.stages.len(), 1);
+            for (c, channel) in xyb.channels.iter().enumerate() {
+                let Some(channel) = channel else {
+                    assert!(c >= 3);
+                    continue;
+                };
+                assert_eq!(channel.size(), (width, height));
+                for y in 0..height {
+                    for x in 0..width {
+                        assert_eq!(channel.row(y)[x], output.row(y)[x * 3 + c], "{c} {x} {y}");
+                    }
+                }
+            }
+
+            // All EPF stages match, and they run before the image is upsampled.
+            assert_eq!(epf[0].stages.len(), 3);
+            assert!(epf[0].stages[2].starts_with("EPF stage 2"));
+            for c in 0..3 {
+                let size = epf[0].channels[c].as_ref().unwrap().size();
+                assert_eq!(size, (width.div_ceil(2), height.div_ceil(2)));
+            }
+
+            // Each channel is captured by the upsampling stage that produces it.
+            assert_eq!(upsampling[0].stages.len(), 3);
+            for c in 0..3 {
+                let size = upsampling[0].channels[c].as_ref().unwrap().size();
+                assert_eq!(size, (width, height));
+            }
+        }
+    }
+
+    #[test]
+    fn test_buffer_pool() {
+        use crate::api::JxlBufferPool;
+
+        for file in [
+            "resources/test/stp2_520x260_d25_e6.jxl",
+            "resources/test/green_queen_modular_e3.jxl",
+            "resources/test/multiple_layers_noise_spline.jxl",
+        ] {
+            let data = std::fs::read(file).unwrap();
+            let (_, expected) = decode(&data, usize::MAX, false, None).unwrap();
+            let pool = JxlBufferPool::new(1 << 30);
+            let mut misses = vec![];
+            for _ in 0..2 {
+                let options = JxlDecoderOptions {
+                    buffer_pool: Some(pool.clone()),
+                    ..Default::default()
+                };
+                let (_, frames) =
+                    decode_with_options(&data, usize::MAX, false, None, options).unwrap();
+                for (frame, expected) in frames.iter().zip(expected.iter()) {
+                    for (image, expected) in frame.iter().zip(expected.iter()) {
+                        let (_, height) = image.size();
+                        for y in 0..height {
+                            assert_eq!(image.row(y), expected.row(y), "{file}");
+                        }
+                    }
+                }
+                misses.push(pool.stats().misses);
+            }
+            // Decoding the same image again re-uses all the buffers of the first decode.
+            let stats = pool.stats();
+            assert!(stats.hits > 0, "{file}: {stats:?}");
+            assert_eq!(misses[0], misses[1], "{file}: {stats:?}");
+            assert!(stats.pooled_bytes > 0, "{file}: {stats:?}");
+        }
+    }
+
     #[test]
     fn test_preview_size_none_for_regular_files() {
         let file = std::fs::read("resources/test/basic.jxl").unwrap();
@@ -928,6 +2402,342 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_hlg_rendering_linear_output() {
+        use crate::api::{
+            JxlColorEncoding, JxlColorProfile, JxlColorType, JxlDataFormat, JxlHlgRendering,
+            JxlPixelFormat, JxlTransferFunction,
+        };
+        use crate::image::{Image, Rect};
+
+        let file = std::fs::read("resources/test/hdr_hlg_test.jxl").unwrap();
+
+        // Decodes the image to linear RGB for the given HLG rendering parameters.
+        let decode_linear = |hlg_rendering: JxlHlgRendering| -> (Image<f32>, f32) {
+            let options = JxlDecoderOptions {
+                hlg_rendering,
+                ..Default::default()
+            };
+            let decoder = JxlDecoder::<states::Initialized>::new(options);
+            let mut input = file.as_slice();
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let JxlColorProfile::Simple(JxlColorEncoding::RgbColorSpace {
+                white_point,
+                primaries,
+                rendering_intent,
+                ..
+            }) = decoder.embedded_color_profile().clone()
+            else {
+                panic!("expected an RGB color encoding");
+            };
+            decoder
+                .set_output_color_profile(JxlColorProfile::Simple(
+                    JxlColorEncoding::RgbColorSpace {
+                        white_point,
+                        primaries,
+                        transfer_function: JxlTransferFunction::Linear,
+                        rendering_intent,
+                    },
+                ))
+                .unwrap();
+            decoder.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Rgb,
+                color_data_format: Some(JxlDataFormat::f32()),
+                extra_channel_format: vec![],
+            });
+            let info = decoder.basic_info().clone();
+            let (width, height) = info.size;
+            let decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let mut output = Image::<f32>::new((width * 3, height)).unwrap();
+            let rect = Rect {
+                size: output.size(),
+                origin: (0, 0),
+            };
+            let mut bufs = [JxlOutputBuffer::from_image_rect_mut(
+                output.get_rect_mut(rect).into_raw(),
+            )];
+            run_to_completion(&mut input, decoder, |d, input| d.process(input, &mut bufs)).unwrap();
+            (output, info.tone_mapping.intensity_target)
+        };
+
+        let (default, intensity_target) = decode_linear(JxlHlgRendering::default());
+        let (mastering, _) = decode_linear(JxlHlgRendering {
+            display_peak_luminance: Some(intensity_target),
+            ..Default::default()
+        });
+        let (half_white, _) = decode_linear(JxlHlgRendering {
+            reference_white: Some(intensity_target / 2.0),
+            ..Default::default()
+        });
+        let (dim_display, _) = decode_linear(JxlHlgRendering {
+            display_peak_luminance: Some(400.0),
+            ..Default::default()
+        });
+
+        let mut max_diff_dim = 0.0f32;
+        for y in 0..default.size().1 {
+            for (x, &v) in default.row(y).iter().enumerate() {
+                assert_eq!(v, mastering.row(y)[x]);
+                assert_almost_abs_eq_coords(half_white.row(y)[x], v * 2.0, 1e-5, (x, y), 0);
+                max_diff_dim = max_diff_dim.max((dim_display.row(y)[x] - v).abs());
+            }
+        }
+        // A 400-nit display uses a lower system gamma than the mastering display.
+        assert!(max_diff_dim > 1e-3);
+    }
+
+    #[test]
+    fn test_output_primaries_conversion() {
+        use crate::api::{
+            JxlColorEncoding, JxlColorProfile, JxlColorType, JxlPixelFormat, JxlPrimaries,
+            JxlTransferFunction, JxlWhitePoint, primaries_conversion_matrix,
+        };
+        use crate::headers::color_encoding::RenderingIntent;
+
+        // Decodes the image to RGB in the color encoding returned by `output`, given the
+        // embedded one.
+        let decode_to = |file: &[u8],
+                         output: &dyn Fn(&JxlColorEncoding) -> JxlColorEncoding|
+         -> (Image<f32>, JxlColorEncoding) {
+            let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+            let mut input = file;
+            let mut decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let JxlColorProfile::Simple(embedded) = decoder.embedded_color_profile().clone() else {
+                panic!("expected a color encoding");
+            };
+            decoder
+                .set_output_color_profile(JxlColorProfile::Simple(output(&embedded)))
+                .unwrap();
+            decoder.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Rgb,
+                color_data_format: Some(JxlDataFormat::f32()),
+                extra_channel_format: vec![],
+            });
+            let (width, height) = decoder.basic_info().size;
+            let decoder =
+                run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+            let mut output = Image::<f32>::new((width * 3, height)).unwrap();
+            let rect = Rect {
+                size: output.size(),
+                origin: (0, 0),
+            };
+            let mut bufs = [JxlOutputBuffer::from_image_rect_mut(
+                output.get_rect_mut(rect).into_raw(),
+            )];
+            run_to_completion(&mut input, decoder, |d, input| d.process(input, &mut bufs)).unwrap();
+            (output, embedded)
+        };
+        let with = |encoding: &JxlColorEncoding,
+                    primaries: Option<JxlPrimaries>,
+                    white_point: Option<JxlWhitePoint>,
+                    transfer_function: JxlTransferFunction| {
+            let JxlColorEncoding::RgbColorSpace {
+                white_point: embedded_white_point,
+                primaries: embedded_primaries,
+                ..
+            } = encoding
+            else {
+                panic!("expected an RGB color encoding");
+            };
+            JxlColorEncoding::RgbColorSpace {
+                white_point: white_point.unwrap_or(embedded_white_point.clone()),
+                primaries: primaries.unwrap_or(embedded_primaries.clone()),
+                transfer_function,
+                rendering_intent: RenderingIntent::Relative,
+            }
+        };
+
+        // XYB and non-XYB images.
+        for name in ["green_queen_vardct_e3.jxl", "hdr_pq_test.jxl"] {
+            let file = std::fs::read(Path::new("resources/test").join(name)).unwrap();
+            let (linear, embedded) =
+                decode_to(&file, &|e| with(e, None, None, JxlTransferFunction::Linear));
+            let JxlColorEncoding::RgbColorSpace {
+                white_point,
+                primaries,
+                transfer_function,
+                ..
+            } = embedded
+            else {
+                unreachable!()
+            };
+            let target = (JxlPrimaries::P3, JxlWhitePoint::DCI);
+            let (converted_linear, _) = decode_to(&file, &|e| {
+                with(
+                    e,
+                    Some(target.0.clone()),
+                    Some(target.1.clone()),
+                    JxlTransferFunction::Linear,
+                )
+            });
+            // Going back to the original transfer function must be (almost) lossless.
+            let (converted, _) = decode_to(&file, &|e| {
+                with(
+                    e,
+                    Some(target.0.clone()),
+                    Some(target.1.clone()),
+                    transfer_function.clone(),
+                )
+            });
+            let (roundtrip, _) =
+                decode_to(&file, &|e| with(e, None, None, transfer_function.clone()));
+            let (default, _) = decode_to(&file, &|e| e.clone());
+
+            let matrix =
+                primaries_conversion_matrix((&primaries, &white_point), (&target.0, &target.1))
+                    .unwrap();
+            let (width, height) = (linear.size().0 / 3, linear.size().1);
+            let mut max_diff = 0.0f32;
+            for y in 0..height {
+                for x in 0..width {
+                    let rgb = &linear.row(y)[3 * x..3 * x + 3];
+                    for (c, matrix_row) in matrix.iter().enumerate() {
+                        let expected: f64 = (0..3).map(|i| matrix_row[i] * rgb[i] as f64).sum();
+                        assert_almost_abs_eq_coords(
+                            converted_linear.row(y)[3 * x + c],
+                            expected as f32,
+                            1e-4,
+                            (x, y),
+                            c,
+                        );
+                        max_diff = max_diff
+                            .max((converted.row(y)[3 * x + c] - default.row(y)[3 * x + c]).abs());
+                    }
+                }
+                for (x, &v) in roundtrip.row(y).iter().enumerate() {
+                    assert_almost_abs_eq_coords(v, default.row(y)[x], 1e-4, (x, y), 0);
+                }
+            }
+            // The conversion actually changed the image.
+            assert!(max_diff > 1e-3, "{name}: {max_diff}");
+        }
+    }
+
+    #[test]
+    fn test_linear_output_luminance() {
+        use crate::api::{JxlColorProfile, JxlHlgRendering};
+
+        let header = |name: &str, options: JxlDecoderOptions| {
+            let file = std::fs::read(Path::new("resources/test").join(name)).unwrap();
+            let mut input = file.as_slice();
+            let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+            loop {
+                match decoder.process(&mut input).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            }
+        };
+        let set_linear = |decoder: &mut JxlDecoder<states::WithImageInfo>| {
+            let JxlColorProfile::Simple(embedded) = decoder.embedded_color_profile().clone() else {
+                panic!("expected a color encoding");
+            };
+            decoder
+                .set_output_color_profile(JxlColorProfile::Simple(embedded.with_linear_tf()))
+                .unwrap();
+        };
+
+        let mut decoder = header("hdr_pq_test.jxl", JxlDecoderOptions::default());
+        assert_eq!(decoder.linear_output_luminance(), None);
+        set_linear(&mut decoder);
+        let intensity_target = decoder.basic_info().tone_mapping.intensity_target;
+        assert_eq!(decoder.linear_output_luminance(), Some(intensity_target));
+
+        // XYB images are rendered to linear output by default.
+        let decoder = header("basic.jxl", JxlDecoderOptions::default());
+        assert_eq!(
+            decoder.linear_output_luminance(),
+            Some(decoder.basic_info().tone_mapping.intensity_target)
+        );
+
+        let mut decoder = header(
+            "hdr_hlg_test.jxl",
+            JxlDecoderOptions {
+                hlg_rendering: JxlHlgRendering {
+                    reference_white: Some(203.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        set_linear(&mut decoder);
+        assert_eq!(decoder.linear_output_luminance(), Some(203.0));
+    }
+
+    #[test]
+    fn test_cmyk_rendering() {
+        use crate::render::stages::NaiveCmykCms;
+
+        let file = std::fs::read("resources/test/conformance_test_images/cmyk_layers.jxl").unwrap();
+
+        // Without a CMS, CMY and K are returned as-is.
+        let (_, cmyk_frames) = decode(&file, usize::MAX, false, None).unwrap();
+        for use_simple_pipeline in [false, true] {
+            let options = JxlDecoderOptions {
+                cms: Some(Box::new(NaiveCmykCms)),
+                ..Default::default()
+            };
+            let (_, rgb_frames) =
+                decode_with_options(&file, usize::MAX, use_simple_pipeline, None, options).unwrap();
+            assert_eq!(cmyk_frames.len(), rgb_frames.len());
+            for (cmyk, rgb) in cmyk_frames.iter().zip(&rgb_frames) {
+                // Channel 0 is the interleaved color, the black channel is the first extra
+                // channel and is returned unchanged.
+                let (xsize, ysize) = cmyk[1].size();
+                for y in 0..ysize {
+                    for x in 0..xsize {
+                        let k = cmyk[1].row(y)[x];
+                        assert_eq!(rgb[1].row(y)[x], k);
+                        for c in 0..3 {
+                            assert_almost_abs_eq_coords(
+                                rgb[0].row(y)[3 * x + c],
+                                cmyk[0].row(y)[3 * x + c] * k,
+                                1e-6,
+                                (x, y),
+                                c,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_skipped_extra_channel_buffers() {
+        use crate::api::{JxlColorType, JxlPixelFormat};
+
+        let file = std::fs::read("resources/test/conformance_test_images/cmyk_layers.jxl").unwrap();
+        let (_, all_frames) = decode(&file, usize::MAX, false, None).unwrap();
+
+        // Request only the alpha channel, which comes after the black channel.
+        let mut input = file.as_slice();
+        let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+        let mut decoder =
+            run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+        decoder.set_pixel_format(JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
+            color_data_format: None,
+            extra_channel_format: vec![None, Some(JxlDataFormat::f32())],
+        });
+        let decoder = run_to_completion(&mut input, decoder, |d, input| d.process(input)).unwrap();
+        let mut alpha = Image::<f32>::new(decoder.frame_header().size).unwrap();
+        let rect = Rect {
+            size: alpha.size(),
+            origin: (0, 0),
+        };
+        let mut bufs = [JxlOutputBuffer::from_image_rect_mut(
+            alpha.get_rect_mut(rect).into_raw(),
+        )];
+        run_to_completion(&mut input, decoder, |d, input| d.process(input, &mut bufs)).unwrap();
+        for y in 0..alpha.size().1 {
+            assert_eq!(alpha.row(y), all_frames[0][2].row(y));
+        }
+    }
+
     /// Test that animations with reference frames work correctly.
     /// This exercises the buffer index calculation fix where reference frame
     /// save stages use indices beyond the API-provided buffer array.
@@ -979,6 +2789,8 @@ pub(crate) mod tests {
                     }
                 }
             };
+            // The animation has no timecodes.
+            assert_eq!(decoder_frame.frame_header().timecode, None);
 
             // Prepare buffer for RGB (3 channels interleaved)
             let mut color_buffer = Image::<f32>::new((width * 3, height)).unwrap();
@@ -0,0 +1,173 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api::find_container_box,
+    error::{Error, Result},
+};
+
+/// A keyframe listed in a frame index: decoding can start from it without decoding any of the
+/// frames that come before it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JxlIndexedFrame {
+    /// Offset in bytes of the start of the frame in the codestream.
+    pub codestream_offset: u64,
+    /// Number of displayed frames that come before the frame.
+    pub frame_number: usize,
+    /// Time at which the frame is displayed, in ticks since the start of the animation.
+    pub start_ticks: u64,
+}
+
+/// Frame index of an animation, as stored in a `jxli` box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JxlFrameIndex {
+    /// A tick lasts `tick_numerator / tick_denominator` seconds.
+    pub tick_numerator: u32,
+    pub tick_denominator: u32,
+    /// The indexed frames, in codestream order.
+    pub frames: Vec<JxlIndexedFrame>,
+}
+
+struct IndexReader<'a> {
+    data: &'a [u8],
+}
+
+impl IndexReader<'_> {
+    fn u8(&mut self) -> Result<u8> {
+        let (byte, rest) = self.data.split_first().ok_or(Error::InvalidFrameIndex)?;
+        self.data = rest;
+        Ok(*byte)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let (bytes, rest) = self
+            .data
+            .split_first_chunk::<4>()
+            .ok_or(Error::InvalidFrameIndex)?;
+        self.data = rest;
+        Ok(u32::from_be_bytes(*bytes))
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            if shift == 63 && byte > 1 {
+                return Err(Error::InvalidFrameIndex);
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidFrameIndex)
+    }
+}
+
+impl JxlFrameIndex {
+    /// Parses the contents of a `jxli` box.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut r = IndexReader { data };
+        let num_frames = r.varint()?;
+        let tick_numerator = r.u32()?;
+        let tick_denominator = r.u32()?;
+        if tick_numerator == 0 || tick_denominator == 0 {
+            return Err(Error::InvalidFrameIndex);
+        }
+        let mut frames = vec![];
+        let (mut codestream_offset, mut frame_number, mut start_ticks) = (0u64, 0usize, 0u64);
+        for i in 0..num_frames {
+            // Offsets are relative to the previous indexed frame, while durations and frame
+            // counts are those of the span up to the next indexed frame.
+            let offset = r.varint()?;
+            if i != 0 && offset == 0 {
+                return Err(Error::InvalidFrameIndex);
+            }
+            codestream_offset = codestream_offset
+                .checked_add(offset)
+                .ok_or(Error::InvalidFrameIndex)?;
+            frames.push(JxlIndexedFrame {
+                codestream_offset,
+                frame_number,
+                start_ticks,
+            });
+            start_ticks = start_ticks
+                .checked_add(r.varint()?)
+                .ok_or(Error::InvalidFrameIndex)?;
+            frame_number = usize::try_from(r.varint()?)
+                .ok()
+                .and_then(|n| frame_number.checked_add(n))
+                .ok_or(Error::InvalidFrameIndex)?;
+        }
+        Ok(Self {
+            tick_numerator,
+            tick_denominator,
+            frames,
+        })
+    }
+
+    /// Finds and parses the `jxli` box of a complete JPEG XL container, if any.
+    pub fn from_container(data: &[u8]) -> Result<Option<Self>> {
+        find_container_box(data, b"jxli")?
+            .map(Self::parse)
+            .transpose()
+    }
+
+    /// Duration of a tick in milliseconds.
+    pub fn tick_duration(&self) -> f64 {
+        1000.0 * self.tick_numerator as f64 / self.tick_denominator as f64
+    }
+
+    /// Returns the last keyframe that is displayed no later than the displayed frame with index
+    /// `frame`, if any.
+    pub fn keyframe_before(&self, frame: usize) -> Option<&JxlIndexedFrame> {
+        let num = self.frames.partition_point(|f| f.frame_number <= frame);
+        num.checked_sub(1).map(|i| &self.frames[i])
+    }
+
+    /// Returns the last keyframe that is displayed no later than `time`, in milliseconds since
+    /// the start of the animation, if any.
+    pub fn keyframe_at_time(&self, time: f64) -> Option<&JxlIndexedFrame> {
+        let num = self
+            .frames
+            .partition_point(|f| f.start_ticks as f64 * self.tick_duration() <= time);
+        num.checked_sub(1).map(|i| &self.frames[i])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let data = [
+            3, // Number of frames.
+            0, 0, 0, 1, 0, 0, 0, 10, // 1/10 seconds per tick.
+            100, 5, 2, // First frame.
+            0x80, 0x01, 7, 3, // Second frame, 128 bytes later.
+            20, 1, 1, // Third frame.
+        ];
+        let index = JxlFrameIndex::parse(&data)?;
+        assert_eq!(index.tick_duration(), 100.0);
+        let frames: Vec<_> = index
+            .frames
+            .iter()
+            .map(|f| (f.codestream_offset, f.frame_number, f.start_ticks))
+            .collect();
+        assert_eq!(frames, [(100, 0, 0), (228, 2, 5), (248, 5, 12)]);
+
+        assert_eq!(index.keyframe_before(1).unwrap().frame_number, 0);
+        assert_eq!(index.keyframe_before(4).unwrap().frame_number, 2);
+        assert_eq!(index.keyframe_before(9).unwrap().frame_number, 5);
+        assert_eq!(index.keyframe_at_time(499.0).unwrap().frame_number, 0);
+        assert_eq!(index.keyframe_at_time(500.0).unwrap().frame_number, 2);
+
+        assert!(JxlFrameIndex::parse(&data[..data.len() - 1]).is_err());
+        // Overlong varints.
+        assert!(JxlFrameIndex::parse(&[0xff; 11]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,635 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api::{
+        JxlColorEncoding, JxlColorType, JxlDataFormat, JxlDecoder, JxlDecoderOptions,
+        JxlOutputBuffer, JxlPixelFormat, ProcessingResult, find_container_box, states,
+    },
+    bit_reader::BitReader,
+    error::{Error, Result},
+    headers::{color_encoding::ColorEncoding, encodings::*},
+    icc::IncrementalIccReader,
+    image::{Image, Rect},
+};
+
+/// Per-channel parameters of a gain map, as specified in ISO 21496-1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JxlGainMapChannel {
+    /// log2 of the gain corresponding to a gain map value of 0.
+    pub gain_map_min: f32,
+    /// log2 of the gain corresponding to a gain map value of 1.
+    pub gain_map_max: f32,
+    /// Gamma that was applied to the gain map values when encoding them.
+    pub gamma: f32,
+    /// Offset added to the base rendition before computing the gain.
+    pub base_offset: f32,
+    /// Offset added to the alternate rendition before computing the gain.
+    pub alternate_offset: f32,
+}
+
+/// Gain map metadata, as specified in ISO 21496-1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JxlGainMapMetadata {
+    /// Whether the gain map is applied in the color space of the base image (as opposed to
+    /// the color space of the alternate rendition).
+    pub use_base_color_space: bool,
+    /// Whether the base rendition is the HDR one.
+    pub backward_direction: bool,
+    /// log2 of the HDR headroom of the base rendition.
+    pub base_hdr_headroom: f32,
+    /// log2 of the HDR headroom of the alternate rendition.
+    pub alternate_hdr_headroom: f32,
+    /// One entry for single-channel gain maps, three for multi-channel ones.
+    pub channels: Vec<JxlGainMapChannel>,
+}
+
+struct MetadataReader<'a> {
+    data: &'a [u8],
+}
+
+impl MetadataReader<'_> {
+    fn read<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let (bytes, rest) = self
+            .data
+            .split_first_chunk::<N>()
+            .ok_or(Error::InvalidGainMap)?;
+        self.data = rest;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.read()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.read()?))
+    }
+}
+
+fn fraction(numerator: f64, denominator: u32) -> Result<f32> {
+    if denominator == 0 {
+        return Err(Error::InvalidGainMap);
+    }
+    Ok((numerator / denominator as f64) as f32)
+}
+
+impl JxlGainMapMetadata {
+    /// Parses the binary representation of ISO 21496-1 gain map metadata.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut r = MetadataReader { data };
+        let minimum_version = r.u16()?;
+        if minimum_version != 0 {
+            return Err(Error::UnsupportedGainMapVersion(minimum_version as u32));
+        }
+        let _writer_version = r.u16()?;
+        let flags = r.u8()?;
+        let num_channels = if flags & 0x80 != 0 { 3 } else { 1 };
+        let use_base_color_space = flags & 0x40 != 0;
+        let use_common_denominator = flags & 0x08 != 0;
+        let backward_direction = flags & 0x04 != 0;
+
+        let mut channels = Vec::with_capacity(num_channels);
+        let (base_hdr_headroom, alternate_hdr_headroom);
+        if use_common_denominator {
+            let d = r.u32()?;
+            base_hdr_headroom = fraction(r.u32()? as f64, d)?;
+            alternate_hdr_headroom = fraction(r.u32()? as f64, d)?;
+            for _ in 0..num_channels {
+                channels.push(JxlGainMapChannel {
+                    gain_map_min: fraction(r.i32()? as f64, d)?,
+                    gain_map_max: fraction(r.i32()? as f64, d)?,
+                    gamma: fraction(r.u32()? as f64, d)?,
+                    base_offset: fraction(r.i32()? as f64, d)?,
+                    alternate_offset: fraction(r.i32()? as f64, d)?,
+                });
+            }
+        } else {
+            base_hdr_headroom = fraction(r.u32()? as f64, r.u32()?)?;
+            alternate_hdr_headroom = fraction(r.u32()? as f64, r.u32()?)?;
+            for _ in 0..num_channels {
+                channels.push(JxlGainMapChannel {
+                    gain_map_min: fraction(r.i32()? as f64, r.u32()?)?,
+                    gain_map_max: fraction(r.i32()? as f64, r.u32()?)?,
+                    gamma: fraction(r.u32()? as f64, r.u32()?)?,
+                    base_offset: fraction(r.i32()? as f64, r.u32()?)?,
+                    alternate_offset: fraction(r.i32()? as f64, r.u32()?)?,
+                });
+            }
+        }
+        if channels.iter().any(|c| c.gamma <= 0.0) {
+            return Err(Error::InvalidGainMap);
+        }
+        Ok(Self {
+            use_base_color_space,
+            backward_direction,
+            base_hdr_headroom,
+            alternate_hdr_headroom,
+            channels,
+        })
+    }
+
+    /// Returns the weight with which the gain map should be applied to produce a rendition for
+    /// a display with the given headroom (log2 of the ratio between peak and SDR white). This
+    /// holds for both directions: for backward gain maps, the weight grows as the headroom
+    /// decreases from the one of the (HDR) base rendition.
+    pub fn weight(&self, hdr_headroom: f32) -> f32 {
+        if self.alternate_hdr_headroom == self.base_hdr_headroom {
+            return 0.0;
+        }
+        ((hdr_headroom - self.base_hdr_headroom)
+            / (self.alternate_hdr_headroom - self.base_hdr_headroom))
+            .clamp(0.0, 1.0)
+    }
+}
+
+/// A gain map, as stored in a `jhgm` box: an alternate rendition of the image, in the form of a
+/// JPEG XL codestream, plus the metadata needed to apply it.
+#[derive(Clone, Debug)]
+pub struct JxlGainMap {
+    /// Parameters describing how to apply the gain map.
+    pub metadata: JxlGainMapMetadata,
+    /// Color encoding of the alternate rendition, if specified.
+    pub color_encoding: Option<JxlColorEncoding>,
+    /// ICC profile of the alternate rendition, if specified.
+    pub alt_icc: Option<Vec<u8>>,
+    /// Codestream of the gain map image. It can be decoded with a separate
+    /// [`JxlDecoder`](crate::api::JxlDecoder).
+    pub codestream: Vec<u8>,
+}
+
+impl JxlGainMap {
+    const JHGM_VERSION: u8 = 0;
+
+    /// Parses the contents of a `jhgm` box.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut r = MetadataReader { data };
+        let version = r.u8()?;
+        if version != Self::JHGM_VERSION {
+            return Err(Error::UnsupportedGainMapVersion(version as u32));
+        }
+        let metadata_size = r.u16()? as usize;
+        if r.data.len() < metadata_size {
+            return Err(Error::InvalidGainMap);
+        }
+        let (metadata, rest) = r.data.split_at(metadata_size);
+        let metadata = JxlGainMapMetadata::parse(metadata)?;
+        r.data = rest;
+
+        let color_encoding_size = r.u8()? as usize;
+        let color_encoding = if color_encoding_size != 0 {
+            if r.data.len() < color_encoding_size {
+                return Err(Error::InvalidGainMap);
+            }
+            let (encoding, rest) = r.data.split_at(color_encoding_size);
+            r.data = rest;
+            let mut br = BitReader::new(encoding);
+            let encoding = ColorEncoding::read_unconditional(&(), &mut br, &Empty {})?;
+            Some(JxlColorEncoding::from_internal(&encoding)?)
+        } else {
+            None
+        };
+
+        let alt_icc_size = r.u32()? as usize;
+        let alt_icc = if alt_icc_size != 0 {
+            if r.data.len() < alt_icc_size {
+                return Err(Error::InvalidGainMap);
+            }
+            let (icc, rest) = r.data.split_at(alt_icc_size);
+            r.data = rest;
+            let mut br = BitReader::new(icc);
+            let mut reader = IncrementalIccReader::new(&mut br)?;
+            reader.read_all(&mut br)?;
+            Some(reader.finalize(&mut br)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            metadata,
+            color_encoding,
+            alt_icc,
+            codestream: r.data.to_vec(),
+        })
+    }
+
+    /// Finds and parses the `jhgm` box of a complete JPEG XL container, if any.
+    pub fn from_container(data: &[u8]) -> Result<Option<Self>> {
+        find_container_box(data, b"jhgm")?
+            .map(Self::parse)
+            .transpose()
+    }
+
+    /// Decodes the gain map image, returning its samples (1 or 3 interleaved channels) as they
+    /// are stored, without applying its orientation.
+    pub fn decode_image(&self) -> Result<(Image<f32>, usize)> {
+        let mut input = self.codestream.as_slice();
+        let options = JxlDecoderOptions {
+            adjust_orientation: false,
+            xyb_output_linear: false,
+            ..JxlDecoderOptions::default()
+        };
+        let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+        let mut decoder = loop {
+            match decoder.process(&mut input)? {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } if !input.is_empty() => {
+                    decoder = fallback
+                }
+                ProcessingResult::NeedsMoreInput { .. } => return Err(Error::InvalidGainMap),
+            }
+        };
+        let color_type = if decoder.current_pixel_format().color_type.is_grayscale() {
+            JxlColorType::Grayscale
+        } else {
+            JxlColorType::Rgb
+        };
+        decoder.set_pixel_format(JxlPixelFormat {
+            color_type,
+            color_data_format: Some(JxlDataFormat::f32()),
+            extra_channel_format: vec![None; decoder.basic_info().extra_channels.len()],
+        });
+        let num_channels = color_type.samples_per_pixel();
+        let (xsize, ysize) = decoder.basic_info().size;
+        let mut decoder = loop {
+            match decoder.process(&mut input)? {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } if !input.is_empty() => {
+                    decoder = fallback
+                }
+                ProcessingResult::NeedsMoreInput { .. } => return Err(Error::InvalidGainMap),
+            }
+        };
+        let mut image = Image::<f32>::new((xsize * num_channels, ysize))?;
+        let rect = Rect {
+            size: image.size(),
+            origin: (0, 0),
+        };
+        let mut bufs = [JxlOutputBuffer::from_image_rect_mut(
+            image.get_rect_mut(rect).into_raw(),
+        )];
+        loop {
+            match decoder.process(&mut input, &mut bufs)? {
+                ProcessingResult::Complete { .. } => break,
+                ProcessingResult::NeedsMoreInput { fallback, .. } if !input.is_empty() => {
+                    decoder = fallback
+                }
+                ProcessingResult::NeedsMoreInput { .. } => return Err(Error::InvalidGainMap),
+            }
+        }
+        Ok((image, num_channels))
+    }
+
+    /// Applies the gain map to `base`, producing the rendition for a display with the given
+    /// headroom (log2 of the ratio between peak and SDR white).
+    ///
+    /// `base` must contain interleaved RGB samples in linear light, in the color space in
+    /// which the gain map is applied (see [`JxlGainMapMetadata::use_base_color_space`]).
+    /// `gain_map` must contain the decoded gain map image, with `num_gain_map_channels`
+    /// (1 or 3) interleaved channels; it is bilinearly upsampled if smaller than `base`.
+    pub fn apply(
+        &self,
+        base: &mut Image<f32>,
+        gain_map: &Image<f32>,
+        num_gain_map_channels: usize,
+        hdr_headroom: f32,
+    ) -> Result<()> {
+        let (xsize, ysize) = (base.size().0 / 3, base.size().1);
+        let applier = GainMapApplier::new(
+            &self.metadata,
+            gain_map,
+            num_gain_map_channels,
+            (xsize, ysize),
+            hdr_headroom,
+        )?;
+        if applier.is_identity() {
+            return Ok(());
+        }
+        for y in 0..ysize {
+            let row_coords = applier.coords(y, ysize, applier.map_size.1);
+            let row = base.row_mut(y);
+            for x in 0..xsize {
+                for c in 0..3 {
+                    row[3 * x + c] =
+                        applier.apply_sample(gain_map, row_coords, x, c, row[3 * x + c]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A gain map prepared for rendering the frames of an image for a given display headroom.
+#[derive(Debug)]
+pub(crate) struct GainMapRendering {
+    pub(crate) image: Image<f32>,
+    pub(crate) applier: GainMapApplier,
+    /// Color encoding of the color space in which the gain map is applied, or `None` if it is
+    /// applied in the color space of the base rendition.
+    pub(crate) color_encoding: Option<JxlColorEncoding>,
+}
+
+impl GainMapRendering {
+    pub(crate) fn new(
+        gain_map: &JxlGainMap,
+        base_size: (usize, usize),
+        hdr_headroom: f32,
+    ) -> Result<Self> {
+        let metadata = &gain_map.metadata;
+        let color_encoding = if metadata.use_base_color_space {
+            None
+        } else if let Some(encoding) = &gain_map.color_encoding {
+            Some(encoding.clone())
+        } else if gain_map.alt_icc.is_some() {
+            return Err(Error::UnsupportedGainMapColorSpace);
+        } else {
+            // Without a description of the alternate color space, assume it is the base one.
+            None
+        };
+        let (image, num_channels) = gain_map.decode_image()?;
+        let applier = GainMapApplier::new(metadata, &image, num_channels, base_size, hdr_headroom)?;
+        Ok(Self {
+            image,
+            applier,
+            color_encoding,
+        })
+    }
+}
+
+/// Coordinates of the two gain map samples that contribute to a pixel, and the weight of the
+/// second one.
+type MapCoords = (usize, usize, f32);
+
+/// Applies a decoded gain map, stretched to the size of a base rendition, with the weight for a
+/// given display headroom.
+#[derive(Debug)]
+pub(crate) struct GainMapApplier {
+    num_channels: usize,
+    map_size: (usize, usize),
+    base_size: (usize, usize),
+    channels: [JxlGainMapChannel; 3],
+    weight: f32,
+}
+
+impl GainMapApplier {
+    pub(crate) fn new(
+        metadata: &JxlGainMapMetadata,
+        image: &Image<f32>,
+        num_channels: usize,
+        base_size: (usize, usize),
+        hdr_headroom: f32,
+    ) -> Result<Self> {
+        if !matches!(num_channels, 1 | 3) {
+            return Err(Error::InvalidGainMap);
+        }
+        let map_size = (image.size().0 / num_channels, image.size().1);
+        if map_size.0 == 0 || map_size.1 == 0 {
+            return Err(Error::InvalidGainMap);
+        }
+        let (base, alternate) = (metadata.base_hdr_headroom, metadata.alternate_hdr_headroom);
+        if base != alternate && metadata.backward_direction != (base > alternate) {
+            // The base rendition must be the HDR one exactly for backward gain maps.
+            return Err(Error::InvalidGainMap);
+        }
+        let params = &metadata.channels;
+        Ok(Self {
+            num_channels,
+            map_size,
+            base_size,
+            channels: std::array::from_fn(|c| params[c.min(params.len() - 1)].clone()),
+            weight: metadata.weight(hdr_headroom),
+        })
+    }
+
+    /// Returns whether applying the gain map leaves the base rendition unchanged.
+    pub(crate) fn is_identity(&self) -> bool {
+        self.weight == 0.0
+    }
+
+    /// Position of the gain map sample corresponding to pixel `x` of an axis of `size` pixels.
+    fn coords(&self, x: usize, size: usize, map_size: usize) -> MapCoords {
+        let pos = ((x as f32 + 0.5) * map_size as f32 / size as f32 - 0.5).max(0.0);
+        let x0 = (pos as usize).min(map_size - 1);
+        let x1 = (x0 + 1).min(map_size - 1);
+        (x0, x1, pos - x0 as f32)
+    }
+
+    /// Applies channel `c` of the gain map to the base sample `v` at column `x` of the row with
+    /// coordinates `row_coords`.
+    fn apply_sample(
+        &self,
+        image: &Image<f32>,
+        row_coords: MapCoords,
+        x: usize,
+        c: usize,
+        v: f32,
+    ) -> f32 {
+        let (gy0, gy1, fy) = row_coords;
+        let (gx0, gx1, fx) = self.coords(x, self.base_size.0, self.map_size.0);
+        let gc = c.min(self.num_channels - 1);
+        let nc = self.num_channels;
+        let sample = |gy: usize, gx: usize| image.row(gy)[gx * nc + gc];
+        let top = sample(gy0, gx0) * (1.0 - fx) + sample(gy0, gx1) * fx;
+        let bottom = sample(gy1, gx0) * (1.0 - fx) + sample(gy1, gx1) * fx;
+        let g = (top * (1.0 - fy) + bottom * fy).clamp(0.0, 1.0);
+        let p = &self.channels[c];
+        let log_recovery = g.powf(1.0 / p.gamma);
+        let log_gain = p.gain_map_min + (p.gain_map_max - p.gain_map_min) * log_recovery;
+        (v + p.base_offset) * (log_gain * self.weight).exp2() - p.alternate_offset
+    }
+
+    /// Applies the gain map `image` to `xsize` pixels of the planar color `rows` (1 or 3) of the
+    /// base rendition, starting at `position`.
+    pub(crate) fn apply_rows(
+        &self,
+        image: &Image<f32>,
+        position: (usize, usize),
+        xsize: usize,
+        rows: &mut [&mut [f32]],
+    ) {
+        let (x0, y) = position;
+        let row_coords = self.coords(y, self.base_size.1, self.map_size.1);
+        for (c, row) in rows.iter_mut().enumerate() {
+            for (x, v) in row[..xsize].iter_mut().enumerate() {
+                *v = self.apply_sample(image, row_coords, x0 + x, c, *v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::decoder::tests::{decode, decode_with_options, run_to_completion};
+    use crate::util::test::{assert_all_almost_abs_eq, check_equal_images};
+
+    /// Metadata with a common denominator of 4, a single channel, base headroom 0 and alternate
+    /// headroom 2, mapping gain map values to gains between 0 and 2 stops.
+    fn test_metadata() -> Vec<u8> {
+        let mut m = vec![0, 0, 0, 0, 0x08];
+        for v in [4u32, 0, 8, 0, 8, 4, 0, 0] {
+            m.extend_from_slice(&v.to_be_bytes());
+        }
+        m
+    }
+
+    fn test_bundle(codestream: &[u8]) -> Vec<u8> {
+        let metadata = test_metadata();
+        let mut bundle = vec![0];
+        bundle.extend_from_slice(&(metadata.len() as u16).to_be_bytes());
+        bundle.extend_from_slice(&metadata);
+        // Color encoding: all_default (sRGB).
+        bundle.extend_from_slice(&[1, 1]);
+        bundle.extend_from_slice(&0u32.to_be_bytes());
+        bundle.extend_from_slice(codestream);
+        bundle
+    }
+
+    #[test]
+    fn parse_metadata() -> Result<()> {
+        let metadata = JxlGainMapMetadata::parse(&test_metadata())?;
+        assert_eq!(
+            metadata,
+            JxlGainMapMetadata {
+                use_base_color_space: false,
+                backward_direction: false,
+                base_hdr_headroom: 0.0,
+                alternate_hdr_headroom: 2.0,
+                channels: vec![JxlGainMapChannel {
+                    gain_map_min: 0.0,
+                    gain_map_max: 2.0,
+                    gamma: 1.0,
+                    base_offset: 0.0,
+                    alternate_offset: 0.0,
+                }],
+            }
+        );
+        assert_eq!(metadata.weight(-1.0), 0.0);
+        assert_eq!(metadata.weight(1.0), 0.5);
+        assert_eq!(metadata.weight(3.0), 1.0);
+        assert!(JxlGainMapMetadata::parse(&test_metadata()[..20]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bundle() -> Result<()> {
+        let gain_map = JxlGainMap::parse(&test_bundle(b"codestream"))?;
+        assert_eq!(gain_map.metadata.channels.len(), 1);
+        assert_eq!(gain_map.color_encoding, Some(JxlColorEncoding::srgb(false)));
+        assert_eq!(gain_map.alt_icc, None);
+        assert_eq!(gain_map.codestream, b"codestream");
+        Ok(())
+    }
+
+    #[test]
+    fn apply() -> Result<()> {
+        let gain_map = JxlGainMap::parse(&test_bundle(&[]))?;
+        let mut base = Image::new_with_value((3 * 4, 2), 0.5)?;
+        // 2x1 gain map: no gain on the left, maximum gain on the right.
+        let mut map = Image::new((2, 1))?;
+        map.row_mut(0).copy_from_slice(&[0.0, 1.0]);
+
+        let mut sdr = base.try_clone()?;
+        gain_map.apply(&mut sdr, &map, 1, 0.0)?;
+        assert_eq!(sdr.row(0), base.row(0));
+
+        gain_map.apply(&mut base, &map, 1, 2.0)?;
+        for y in 0..2 {
+            let row = base.row(y);
+            assert_eq!(&row[..3], &[0.5; 3]);
+            assert_eq!(&row[9..], &[2.0; 3]);
+            assert!(row[3] > 0.5 && row[3] < row[6] && row[6] < 2.0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn direction_must_match_headrooms() -> Result<()> {
+        let map = Image::new_with_value((1, 1), 0.5)?;
+        let metadata = JxlGainMapMetadata::parse(&test_metadata())?;
+        assert!(GainMapApplier::new(&metadata, &map, 1, (4, 4), 1.0).is_ok());
+        // The alternate headroom is larger than the base one, so the base is the SDR rendition.
+        let backward = JxlGainMapMetadata {
+            backward_direction: true,
+            ..metadata
+        };
+        assert!(GainMapApplier::new(&backward, &map, 1, (4, 4), 1.0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_from_container() -> Result<()> {
+        let gain_map_file = crate::container::ContainerParser::collect_codestream(&std::fs::read(
+            "resources/test/basic.jxl",
+        )?)?;
+        let base_file = std::fs::read("resources/test/green_queen_vardct_e3.jxl")?;
+        let base_codestream = crate::container::ContainerParser::collect_codestream(&base_file)?;
+
+        let mut file = crate::api::CONTAINER_SIGNATURE.to_vec();
+        for (ty, content) in [
+            (b"ftyp", &b"jxl \0\0\0\0jxl "[..]),
+            (b"jhgm", &test_bundle(&gain_map_file)),
+            (b"jxlc", &base_codestream),
+        ] {
+            file.extend_from_slice(&(content.len() as u32 + 8).to_be_bytes());
+            file.extend_from_slice(ty);
+            file.extend_from_slice(content);
+        }
+
+        let from_container = JxlGainMap::from_container(&file)?.unwrap();
+        assert_eq!(from_container.codestream, gain_map_file);
+
+        let mut input = file.as_slice();
+        let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+        let decoder = run_to_completion(&mut input, decoder, |d, input| d.process(input))?;
+        let gain_map = decoder.gain_map().unwrap();
+        assert_eq!(gain_map.codestream, gain_map_file);
+
+        // The gain map image, decoded with a nested decoder, matches the standalone file.
+        let (map, num_map_channels) = gain_map.decode_image()?;
+        let options = JxlDecoderOptions {
+            adjust_orientation: false,
+            xyb_output_linear: false,
+            ..JxlDecoderOptions::default()
+        };
+        let (_, standalone) = decode_with_options(
+            &std::fs::read("resources/test/basic.jxl")?,
+            usize::MAX,
+            false,
+            None,
+            options,
+        )?;
+        assert_eq!(map.size(), standalone[0][0].size());
+        check_equal_images(&map, &standalone[0][0]);
+
+        // Rendering with the gain map matches applying it to the linear base rendition.
+        let (_, base) = decode(&file, usize::MAX, false, None)?;
+        let mut expected = base[0][0].try_clone()?;
+        gain_map.apply(&mut expected, &map, num_map_channels, 1.0)?;
+        for use_simple_pipeline in [false, true] {
+            let options = JxlDecoderOptions {
+                gain_map_headroom: Some(1.0),
+                ..JxlDecoderOptions::default()
+            };
+            let (_, rendered) =
+                decode_with_options(&file, usize::MAX, use_simple_pipeline, None, options)?;
+            let rendered = &rendered[0][0];
+            assert_eq!(rendered.size(), expected.size());
+            for y in 0..rendered.size().1 {
+                assert_all_almost_abs_eq(rendered.row(y), expected.row(y), 1e-5);
+            }
+        }
+        assert!((0..base[0][0].size().1).any(|y| base[0][0].row(y) != expected.row(y)));
+        Ok(())
+    }
+}
@@ -4,9 +4,11 @@
 // license that can be found in the LICENSE file.
 
 use crate::error::{Error, Result};
+use crate::util::tracing_wrappers::*;
 
 use crate::api::{
-    JxlBitstreamInput, JxlSignatureType, check_signature_internal, inner::process::SmallBuffer,
+    JxlBitstreamInput, JxlFrameIndex, JxlGainMap, JxlSeekableInput, JxlSignatureType,
+    check_signature_internal, inner::process::SmallBuffer,
 };
 
 #[derive(Clone)]
@@ -15,6 +17,54 @@ enum ParseState {
     BoxNeeded,
     CodestreamBox(u64),
     SkippableBox(u64),
+    GainMapBox(u64),
+    FrameIndexBox(u64),
+}
+
+struct BoxHeader {
+    ty: [u8; 4],
+    header_len: usize,
+    content_len: u64,
+    jxlp_index: Option<u32>,
+}
+
+impl BoxHeader {
+    /// Parses the header of the box at the start of `buf`.
+    fn parse(buf: &[u8]) -> Result<Self> {
+        let min_len = match buf {
+            [0, 0, 0, 1, ..] => 16,
+            _ => 8,
+        };
+        if buf.len() <= min_len {
+            return Err(Error::OutOfBounds(min_len - buf.len()));
+        }
+        let ty: [_; 4] = buf[4..8].try_into().unwrap();
+        let extra_len = if &ty == b"jxlp" { 4 } else { 0 };
+        if buf.len() <= min_len + extra_len {
+            return Err(Error::OutOfBounds(min_len + extra_len - buf.len()));
+        }
+        let box_len = match buf {
+            [0, 0, 0, 1, ..] => u64::from_be_bytes(buf[8..16].try_into().unwrap()),
+            _ => u32::from_be_bytes(buf[0..4].try_into().unwrap()) as u64,
+        };
+        // Per JXL spec: jxlc box with length 0 has special meaning "extends to EOF"
+        let content_len = if box_len == 0 && (&ty == b"jxlp" || &ty == b"jxlc") {
+            u64::MAX
+        } else {
+            if box_len <= (min_len + extra_len) as u64 {
+                return Err(Error::InvalidBox);
+            }
+            box_len - min_len as u64 - extra_len as u64
+        };
+        let jxlp_index = (extra_len != 0)
+            .then(|| u32::from_be_bytes(buf[min_len..min_len + 4].try_into().unwrap()));
+        Ok(Self {
+            ty,
+            header_len: min_len + extra_len,
+            content_len,
+            jxlp_index,
+        })
+    }
 }
 
 enum CodestreamBoxType {
@@ -28,6 +78,23 @@ pub(super) struct BoxParser {
     pub(super) box_buffer: SmallBuffer,
     state: ParseState,
     box_type: CodestreamBoxType,
+    gain_map_data: Vec<u8>,
+    pub(super) gain_map: Option<JxlGainMap>,
+    frame_index_data: Vec<u8>,
+    pub(super) frame_index: Option<JxlFrameIndex>,
+    // Set once the boxes after the codestream were searched for a frame index.
+    frame_index_searched: bool,
+    // File offset of the first byte that was not consumed yet.
+    pub(super) position: u64,
+    // File offsets of the start and end of the contents of the current codestream box.
+    pub(super) codestream_box_start: u64,
+    pub(super) codestream_box_end: u64,
+    // Codestream offset of the first byte that was not consumed yet.
+    pub(super) codestream_position: u64,
+    // Codestream boxes found so far, as (codestream offset, file offset, length) of their
+    // contents, plus the index of `jxlp` boxes. Might include boxes that the parser did not
+    // reach yet, see `file_offset`.
+    codestream_map: Vec<(u64, u64, u64, Option<u32>)>,
 }
 
 impl BoxParser {
@@ -36,6 +103,16 @@ impl BoxParser {
             box_buffer: SmallBuffer::new(128),
             state: ParseState::SignatureNeeded,
             box_type: CodestreamBoxType::None,
+            gain_map_data: Vec::new(),
+            gain_map: None,
+            frame_index_data: Vec::new(),
+            frame_index: None,
+            frame_index_searched: false,
+            position: 0,
+            codestream_box_start: 0,
+            codestream_box_end: u64::MAX,
+            codestream_position: 0,
+            codestream_map: vec![],
         }
     }
 
@@ -53,12 +130,14 @@ impl BoxParser {
                     match check_signature_internal(&self.box_buffer)? {
                         None => return Err(Error::InvalidSignature),
                         Some(JxlSignatureType::Codestream) => {
+                            self.add_codestream_box(0, u64::MAX, None);
                             self.state = ParseState::CodestreamBox(u64::MAX);
                             return Ok(u64::MAX);
                         }
                         Some(JxlSignatureType::Container) => {
-                            self.box_buffer
-                                .consume(JxlSignatureType::Container.signature().len());
+                            let signature_len = JxlSignatureType::Container.signature().len();
+                            self.box_buffer.consume(signature_len);
+                            self.position += signature_len as u64;
                             self.state = ParseState::BoxNeeded;
                         }
                     }
@@ -77,43 +156,67 @@ impl BoxParser {
                         return Err(Error::OutOfBounds(num));
                     }
                     s -= skipped as u64;
+                    self.position += skipped as u64;
                     if s == 0 {
                         self.state = ParseState::BoxNeeded;
                     } else {
                         self.state = ParseState::SkippableBox(s);
                     }
                 }
-                ParseState::BoxNeeded => {
-                    self.box_buffer.refill(|b| input.read(b), None)?;
-                    let min_len = match &self.box_buffer[..] {
-                        [0, 0, 0, 1, ..] => 16,
-                        _ => 8,
-                    };
-                    if self.box_buffer.len() <= min_len {
-                        return Err(Error::OutOfBounds(min_len - self.box_buffer.len()));
+                ParseState::GainMapBox(mut s) => {
+                    if self.box_buffer.is_empty() {
+                        self.box_buffer.refill(|b| input.read(b), None)?;
                     }
-                    let ty: [_; 4] = self.box_buffer[4..8].try_into().unwrap();
-                    let extra_len = if &ty == b"jxlp" { 4 } else { 0 };
-                    if self.box_buffer.len() <= min_len + extra_len {
-                        return Err(Error::OutOfBounds(
-                            min_len + extra_len - self.box_buffer.len(),
-                        ));
+                    let num = s.min(self.box_buffer.len() as u64) as usize;
+                    if num == 0 {
+                        return Err(Error::OutOfBounds(s.min(usize::MAX as u64) as usize));
                     }
-                    let box_len = match &self.box_buffer[..] {
-                        [0, 0, 0, 1, ..] => {
-                            u64::from_be_bytes(self.box_buffer[8..16].try_into().unwrap())
+                    self.gain_map_data
+                        .extend_from_slice(&self.box_buffer[..num]);
+                    self.box_buffer.consume(num);
+                    self.position += num as u64;
+                    s -= num as u64;
+                    if s == 0 {
+                        // A broken gain map should not prevent decoding the main image.
+                        self.gain_map = JxlGainMap::parse(&self.gain_map_data).ok();
+                        if self.gain_map.is_none() {
+                            warn!("Invalid gain map box");
                         }
-                        _ => u32::from_be_bytes(self.box_buffer[0..4].try_into().unwrap()) as u64,
-                    };
-                    // Per JXL spec: jxlc box with length 0 has special meaning "extends to EOF"
-                    let content_len = if box_len == 0 && (&ty == b"jxlp" || &ty == b"jxlc") {
-                        u64::MAX
+                        self.gain_map_data = Vec::new();
+                        self.state = ParseState::BoxNeeded;
                     } else {
-                        if box_len <= (min_len + extra_len) as u64 {
-                            return Err(Error::InvalidBox);
-                        }
-                        box_len - min_len as u64 - extra_len as u64
-                    };
+                        self.state = ParseState::GainMapBox(s);
+                    }
+                }
+                ParseState::FrameIndexBox(mut s) => {
+                    if self.box_buffer.is_empty() {
+                        self.box_buffer.refill(|b| input.read(b), None)?;
+                    }
+                    let num = s.min(self.box_buffer.len() as u64) as usize;
+                    if num == 0 {
+                        return Err(Error::OutOfBounds(s.min(usize::MAX as u64) as usize));
+                    }
+                    self.frame_index_data
+                        .extend_from_slice(&self.box_buffer[..num]);
+                    self.box_buffer.consume(num);
+                    self.position += num as u64;
+                    s -= num as u64;
+                    if s == 0 {
+                        let data = std::mem::take(&mut self.frame_index_data);
+                        self.set_frame_index(&data);
+                        self.state = ParseState::BoxNeeded;
+                    } else {
+                        self.state = ParseState::FrameIndexBox(s);
+                    }
+                }
+                ParseState::BoxNeeded => {
+                    self.box_buffer.refill(|b| input.read(b), None)?;
+                    let BoxHeader {
+                        ty,
+                        header_len,
+                        content_len,
+                        jxlp_index,
+                    } = BoxHeader::parse(&self.box_buffer)?;
                     match &ty {
                         b"jxlc" => {
                             if matches!(
@@ -126,9 +229,7 @@ impl BoxParser {
                             self.state = ParseState::CodestreamBox(content_len);
                         }
                         b"jxlp" => {
-                            let index = u32::from_be_bytes(
-                                self.box_buffer[min_len..min_len + 4].try_into().unwrap(),
-                            );
+                            let index = jxlp_index.unwrap();
                             let wanted_idx = match self.box_type {
                                 CodestreamBoxType::Jxlc | CodestreamBoxType::LastJxlp => {
                                     return Err(Error::InvalidBox);
@@ -148,17 +249,31 @@ impl BoxParser {
                             };
                             self.state = ParseState::CodestreamBox(content_len);
                         }
+                        b"jhgm" => {
+                            self.state = ParseState::GainMapBox(content_len);
+                        }
+                        b"jxli" if self.frame_index.is_none() => {
+                            self.state = ParseState::FrameIndexBox(content_len);
+                        }
                         _ => {
                             self.state = ParseState::SkippableBox(content_len);
                         }
                     }
-                    self.box_buffer.consume(min_len + extra_len);
+                    self.box_buffer.consume(header_len);
+                    self.position += header_len as u64;
+                    if let ParseState::CodestreamBox(len) = self.state {
+                        self.codestream_box_start = self.position;
+                        self.codestream_box_end = self.position.saturating_add(len);
+                        self.add_codestream_box(self.position, len, jxlp_index);
+                    }
                 }
             }
         }
     }
 
     pub(super) fn consume_codestream(&mut self, amount: u64) {
+        self.position += amount;
+        self.codestream_position += amount;
         if let ParseState::CodestreamBox(cb) = &mut self.state {
             *cb = cb.checked_sub(amount).unwrap();
             if *cb == 0 {
@@ -168,4 +283,162 @@ impl BoxParser {
             unreachable!()
         }
     }
+
+    fn add_codestream_box(&mut self, file_offset: u64, len: u64, jxlp_index: Option<u32>) {
+        let codestream_offset = match self.codestream_map.last() {
+            // Already found by `file_offset`.
+            Some(&(_, last_offset, _, _)) if last_offset >= file_offset => return,
+            Some(&(start, _, len, _)) => start + len,
+            None => 0,
+        };
+        self.codestream_map
+            .push((codestream_offset, file_offset, len, jxlp_index));
+    }
+
+    fn set_frame_index(&mut self, data: &[u8]) {
+        // A broken frame index should not prevent decoding the image.
+        self.frame_index = JxlFrameIndex::parse(data).ok();
+        if self.frame_index.is_none() {
+            warn!("Invalid frame index box");
+        }
+    }
+
+    /// Reads the header of the box at `box_offset` with `read_at`, recording codestream boxes
+    /// and reading frame index boxes. Returns `None` if there is no box at `box_offset`.
+    fn read_box_at(
+        &mut self,
+        input: &mut dyn JxlSeekableInput,
+        box_offset: u64,
+    ) -> Result<Option<BoxHeader>> {
+        // Offsets saturate past boxes that extend to the end of the file.
+        if box_offset == u64::MAX {
+            return Ok(None);
+        }
+        let mut buf = [0; 20];
+        let num = input.read_at(box_offset, &mut buf)?;
+        let header = match BoxHeader::parse(&buf[..num]) {
+            Err(Error::OutOfBounds(_)) => return Ok(None),
+            header => header?,
+        };
+        let content_offset = box_offset + header.header_len as u64;
+        match &header.ty {
+            b"jxlp" | b"jxlc" => {
+                self.add_codestream_box(content_offset, header.content_len, header.jxlp_index);
+            }
+            b"jxli" if self.frame_index.is_none() => {
+                let mut data = vec![];
+                let mut chunk = [0; 4096];
+                while (data.len() as u64) < header.content_len {
+                    let len = (header.content_len - data.len() as u64).min(chunk.len() as u64);
+                    let read = input.read_at(
+                        content_offset + data.len() as u64,
+                        &mut chunk[..len as usize],
+                    )?;
+                    if read == 0 {
+                        break;
+                    }
+                    data.extend_from_slice(&chunk[..read]);
+                }
+                self.set_frame_index(&data);
+            }
+            _ => {}
+        }
+        Ok(Some(header))
+    }
+
+    /// Returns the codestream box that contains the byte at `codestream_offset`, among the ones
+    /// found so far.
+    fn find_codestream_box(&self, codestream_offset: u64) -> Option<(u64, u64, u64, Option<u32>)> {
+        self.codestream_map
+            .iter()
+            .find(|(start, _, len, _)| {
+                codestream_offset >= *start && codestream_offset - start < *len
+            })
+            .copied()
+    }
+
+    /// Returns the file offset of the byte at `codestream_offset`, and the number of codestream
+    /// bytes that follow it in the same box. Box headers that come after the ones the parser
+    /// reached so far are read with `read_at`.
+    /// Returns `None` if the position of the byte is not known yet.
+    pub(super) fn file_offset(
+        &mut self,
+        input: &mut dyn JxlSeekableInput,
+        codestream_offset: u64,
+    ) -> Result<Option<(u64, u64)>> {
+        loop {
+            if let Some((start, file_offset, len, _)) = self.find_codestream_box(codestream_offset)
+            {
+                let offset = codestream_offset - start;
+                return Ok(Some((file_offset + offset, len - offset)));
+            }
+            // Look for the next codestream box after the last known one.
+            let Some(&(_, last_offset, last_len, _)) = self.codestream_map.last() else {
+                return Ok(None);
+            };
+            let mut box_offset = last_offset.saturating_add(last_len);
+            loop {
+                let Some(header) = self.read_box_at(input, box_offset)? else {
+                    return Ok(None);
+                };
+                if &header.ty == b"jxlp" || &header.ty == b"jxlc" {
+                    break;
+                }
+                box_offset =
+                    (box_offset + header.header_len as u64).saturating_add(header.content_len);
+            }
+        }
+    }
+
+    /// Looks for a frame index box among the boxes that come after the last known codestream
+    /// box, if no frame index was found yet.
+    pub(super) fn find_frame_index(&mut self, input: &mut dyn JxlSeekableInput) -> Result<()> {
+        if self.frame_index_searched || self.frame_index.is_some() {
+            return Ok(());
+        }
+        self.frame_index_searched = true;
+        let Some(&(_, last_offset, last_len, _)) = self.codestream_map.last() else {
+            return Ok(());
+        };
+        let mut box_offset = last_offset.saturating_add(last_len);
+        while self.frame_index.is_none()
+            && let Some(header) = self.read_box_at(input, box_offset)?
+        {
+            box_offset = (box_offset + header.header_len as u64).saturating_add(header.content_len);
+        }
+        Ok(())
+    }
+
+    /// Continues parsing from the byte at `codestream_offset`, discarding any buffered input.
+    /// Returns the file offset from which input has to be provided next, or `None` if the
+    /// position of the byte is not known, in which case the parser is left unchanged.
+    pub(super) fn jump_to(
+        &mut self,
+        input: Option<&mut dyn JxlSeekableInput>,
+        codestream_offset: u64,
+    ) -> Result<Option<u64>> {
+        if let Some(input) = input {
+            self.file_offset(input, codestream_offset)?;
+        }
+        let Some((start, file_offset, len, jxlp_index)) =
+            self.find_codestream_box(codestream_offset)
+        else {
+            return Ok(None);
+        };
+        let offset = codestream_offset - start;
+        self.box_buffer.consume(self.box_buffer.len());
+        self.gain_map_data.clear();
+        self.frame_index_data.clear();
+        self.box_type = match jxlp_index {
+            None => CodestreamBoxType::Jxlc,
+            Some(index) if index & 0x80000000 != 0 => CodestreamBoxType::LastJxlp,
+            Some(index) => CodestreamBoxType::Jxlp(index),
+        };
+        self.state = ParseState::CodestreamBox(len - offset);
+        self.position = file_offset + offset;
+        self.codestream_position = codestream_offset;
+        self.codestream_box_start = file_offset;
+        self.codestream_box_end = file_offset.saturating_add(len);
+        Ok(Some(self.position))
+    }
 }
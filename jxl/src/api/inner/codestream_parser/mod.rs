@@ -6,6 +6,7 @@
 use std::{
     collections::{HashSet, VecDeque},
     io::IoSliceMut,
+    sync::Arc,
 };
 
 use sections::SectionState;
@@ -14,8 +15,9 @@ use sections::SectionState;
 use crate::api::FrameCallback;
 use crate::{
     api::{
-        JxlBasicInfo, JxlBitstreamInput, JxlColorProfile, JxlDecoderOptions, JxlOutputBuffer,
-        JxlPixelFormat,
+        GainMapRendering, JxlBasicInfo, JxlBitstreamInput, JxlColorProfile, JxlDecoderOptions,
+        JxlFrameHeader, JxlOutputSink, JxlPixelFormat, JxlProgressEvent, JxlScannedFrame,
+        JxlSeekableInput, JxlTimecode, SharedBytes,
         inner::{box_parser::BoxParser, process::SmallBuffer},
     },
     error::{Error, Result},
@@ -25,12 +27,26 @@ use crate::{
 };
 
 mod non_section;
+mod scan;
 mod sections;
 
 struct SectionBuffer {
     len: usize,
     data: Vec<u8>,
     section: Section,
+    // Set for sections that are not going to be read, and are treated as missing instead.
+    skip: bool,
+    // Set for sections that were read directly from their file offset, and that are only
+    // skipped over by sequential reading.
+    loaded: bool,
+    // Bytes of the section borrowed from the input, used instead of `data` if present.
+    shared: Option<SharedBytes>,
+}
+
+impl SectionBuffer {
+    fn bytes(&self) -> &[u8] {
+        self.shared.as_deref().unwrap_or(&self.data)
+    }
 }
 
 pub(super) struct CodestreamParser {
@@ -50,12 +66,19 @@ pub(super) struct CodestreamParser {
     frame_header: Option<FrameHeader>,
     toc_parser: Option<IncrementalTocReader>,
     pub(super) frame: Option<Frame>,
+    // Codestream offset of the start of the current frame.
+    pub(super) frame_codestream_offset: u64,
 
     // Buffers.
     non_section_buf: SmallBuffer,
     non_section_bit_offset: u8,
     sections: VecDeque<SectionBuffer>,
     ready_section_data: usize,
+    // File offset of the first section in `sections`, if the remaining sections of the frame
+    // are stored contiguously in the file.
+    sections_file_offset: Option<u64>,
+    // Codestream offset of the first section in `sections`.
+    sections_codestream_offset: u64,
     skip_sections: bool,
     // True when we need to process frames without copying them to output buffers, e.g. reference frames
     process_without_output: bool,
@@ -79,6 +102,13 @@ pub(super) struct CodestreamParser {
 
     header_needed_bytes: Option<u64>,
 
+    // Bytes left to skip before the next frame header when scanning frames.
+    scan_skip_bytes: u64,
+    pub(super) scanned_frames: Vec<JxlScannedFrame>,
+
+    // Largest memory usage of the render pipeline of any frame decoded so far.
+    pub(super) peak_render_memory: usize,
+
     #[cfg(test)]
     pub frame_callback: Option<Box<FrameCallback>>,
     #[cfg(test)]
@@ -99,10 +129,13 @@ impl CodestreamParser {
             frame_header: None,
             toc_parser: None,
             frame: None,
+            frame_codestream_offset: 0,
             non_section_buf: SmallBuffer::new(4096),
             non_section_bit_offset: 0,
             sections: VecDeque::new(),
             ready_section_data: 0,
+            sections_file_offset: None,
+            sections_codestream_offset: 0,
             skip_sections: false,
             process_without_output: false,
             preview_done: false,
@@ -115,6 +148,9 @@ impl CodestreamParser {
             candidate_hf_sections: HashSet::new(),
             has_more_frames: true,
             header_needed_bytes: None,
+            scan_skip_bytes: 0,
+            scanned_frames: vec![],
+            peak_render_memory: 0,
             #[cfg(test)]
             frame_callback: None,
             #[cfg(test)]
@@ -135,6 +171,166 @@ impl CodestreamParser {
         self.section_state.num_completed_passes()
     }
 
+    /// Returns whether a preview of the current frame can be rendered from its LF image, which
+    /// is either decoded from the LF groups or taken from an earlier LF frame.
+    pub(super) fn has_lf_preview(&self) -> bool {
+        self.frame.as_ref().is_some_and(|frame| {
+            (self.section_state.lf_done() || frame.header().has_lf_frame())
+                && frame.supports_lf_preview()
+        })
+    }
+
+    pub(super) fn render_lf_preview(
+        &mut self,
+        decode_options: &JxlDecoderOptions,
+        output_buffers: &mut [JxlOutputSink],
+    ) -> Result<()> {
+        if !self.has_lf_preview() {
+            return Err(Error::NoLfPreview);
+        }
+        self.check_output_buffer_count(output_buffers)?;
+        self.frame.as_mut().unwrap().render_lf_preview(
+            output_buffers,
+            self.pixel_format.as_ref().unwrap(),
+            self.output_color_profile.as_ref().unwrap(),
+            decode_options.cms.as_deref(),
+        )
+    }
+
+    /// Renders the current frame from the sections that were received so far, and ends decoding.
+    pub(super) fn finish_input(
+        &mut self,
+        decode_options: &JxlDecoderOptions,
+        output_buffers: &mut [JxlOutputSink],
+    ) -> Result<()> {
+        self.check_output_buffer_count(output_buffers)?;
+        self.finish_sections(decode_options, output_buffers)?;
+        self.has_more_frames = false;
+        Ok(())
+    }
+
+    /// Reads the remaining needed sections of the frame directly from their file offsets, in the
+    /// order in which they are decoded, and hands them over for decoding. Stops at the first
+    /// section that is not fully available.
+    fn load_sections_at(
+        &mut self,
+        box_parser: &mut BoxParser,
+        input: &mut dyn JxlSeekableInput,
+    ) -> Result<()> {
+        let offsets: Vec<u64> = self
+            .sections
+            .iter()
+            .scan(self.sections_codestream_offset, |offset, s| {
+                let start = *offset;
+                *offset += s.len as u64;
+                Some(start)
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..self.sections.len())
+            .filter(|&i| !self.sections[i].skip && !self.sections[i].loaded)
+            .collect();
+        order.sort_by_key(|&i| match self.sections[i].section {
+            Section::LfGlobal => (0, 0, 0),
+            Section::Lf { group } => (1, 0, group),
+            Section::HfGlobal => (2, 0, 0),
+            Section::Hf { group, pass } => (3, pass, group),
+        });
+        for i in order {
+            let len = self.sections[i].len;
+            // Sections that are stored contiguously in a shared input are not copied.
+            let shared = match box_parser.file_offset(input, offsets[i])? {
+                Some((file_offset, available)) if available >= len as u64 => input
+                    .as_shared()
+                    .and_then(|input| input.bytes_at(file_offset, len)),
+                _ => None,
+            };
+            let (mut data, mut pos) = match shared {
+                Some(_) => (vec![], len),
+                None => (vec![0; len], 0),
+            };
+            while pos < len {
+                let Some((file_offset, available)) =
+                    box_parser.file_offset(input, offsets[i] + pos as u64)?
+                else {
+                    return Ok(());
+                };
+                let num = (len - pos).min(available.min(usize::MAX as u64) as usize);
+                let read = input.read_at(file_offset, &mut data[pos..pos + num])?;
+                pos += read;
+                if read < num {
+                    return Ok(());
+                }
+            }
+            let s = &mut self.sections[i];
+            s.loaded = true;
+            s.data = vec![];
+            let section = s.section;
+            self.queue_section(SectionBuffer {
+                len,
+                data,
+                section,
+                skip: false,
+                loaded: false,
+                shared,
+            });
+        }
+        Ok(())
+    }
+
+    /// Skips over the input bytes of the sections that are not going to be decoded, up to the
+    /// next section that is.
+    fn skip_unneeded_sections(
+        &mut self,
+        box_parser: &mut BoxParser,
+        input: &mut dyn JxlBitstreamInput,
+    ) -> Result<()> {
+        let mut ready = self.ready_section_data;
+        for i in 0..self.sections.len() {
+            let len = self.sections[i].len;
+            if ready >= len {
+                ready -= len;
+                continue;
+            }
+            if !self.sections[i].skip && !self.sections[i].loaded {
+                break;
+            }
+            let mut to_skip = len - ready;
+            while to_skip > 0 {
+                let available_codestream = match box_parser.get_more_codestream(input) {
+                    Err(Error::OutOfBounds(_)) => 0,
+                    Ok(c) => c as usize,
+                    Err(e) => return Err(e),
+                };
+                let num = to_skip.min(available_codestream);
+                let skipped = if !box_parser.box_buffer.is_empty() {
+                    box_parser.box_buffer.consume(num)
+                } else {
+                    input.skip(num)?
+                };
+                box_parser.consume_codestream(skipped as u64);
+                self.ready_section_data += skipped;
+                to_skip -= skipped;
+                if skipped == 0 {
+                    return Ok(());
+                }
+            }
+            ready = 0;
+        }
+        Ok(())
+    }
+
+    fn check_output_buffer_count(&self, output_buffers: &[JxlOutputSink]) -> Result<()> {
+        let px = self.pixel_format.as_ref().unwrap();
+        let expected_len = std::iter::once(&px.color_data_format)
+            .chain(px.extra_channel_format.iter())
+            .filter(|x| x.is_some())
+            .count();
+        if output_buffers.len() != expected_len {
+            return Err(Error::WrongBufferCount(output_buffers.len(), expected_len));
+        }
+        Ok(())
+    }
+
     #[cfg(test)]
     pub(crate) fn set_use_simple_pipeline(&mut self, u: bool) {
         self.decoder_state
@@ -143,6 +339,51 @@ impl CodestreamParser {
             .set_use_simple_pipeline(u);
     }
 
+    /// Converts the header of a frame to its API representation.
+    pub(super) fn api_frame_header(&self, frame_header: &FrameHeader) -> Option<JxlFrameHeader> {
+        // The render pipeline always adds ExtendToImageDimensionsStage which extends
+        // frames to the full image size. So the output size is always the image size,
+        // not the frame's upsampled size.
+        let size = self.basic_info.as_ref()?.size;
+        Some(JxlFrameHeader {
+            name: frame_header.name.clone(),
+            duration: self
+                .animation
+                .as_ref()
+                .map(|anim| frame_header.duration(anim)),
+            timecode: self
+                .animation
+                .as_ref()
+                .filter(|anim| anim.have_timecodes)
+                .map(|_| JxlTimecode::from_bits(frame_header.timecode)),
+            size,
+        })
+    }
+
+    /// Returns whether the parser is between frames, after the file headers and before the last
+    /// frame.
+    pub(super) fn can_jump_to_keyframe(&self) -> bool {
+        self.decoder_state.is_some() && self.frame.is_none()
+    }
+
+    /// Prepares for reading a keyframe that comes after `frame_number` displayed frames,
+    /// discarding any partially read frame header.
+    pub(super) fn jump_to_keyframe(&mut self, frame_number: usize) {
+        assert!(self.can_jump_to_keyframe());
+        let decoder_state = self.decoder_state.as_mut().unwrap();
+        // Frame indices are used to seed noise generation.
+        decoder_state.visible_frame_index = frame_number;
+        decoder_state.nonvisible_frame_index = 0;
+        self.frame_header = None;
+        self.toc_parser = None;
+        self.non_section_buf.consume(self.non_section_buf.len());
+        self.non_section_bit_offset = 0;
+        self.header_needed_bytes = None;
+        self.process_without_output = false;
+        // Indexed frames are never the preview frame.
+        self.preview_done = true;
+    }
+
     /// Rewinds for animation loop replay, keeping pixel_format setting.
     pub(super) fn rewind(&mut self) -> Option<JxlPixelFormat> {
         let pixel_format = self.pixel_format.take();
@@ -151,22 +392,91 @@ impl CodestreamParser {
         pixel_format
     }
 
+    /// Reads input into the non-section buffer until `parse` succeeds in parsing non-section data
+    /// from it.
+    fn read_non_section(
+        &mut self,
+        box_parser: &mut BoxParser,
+        input: &mut dyn JxlBitstreamInput,
+        mut parse: impl FnMut(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        // Loop to handle incremental parsing (e.g. large ICC profiles) that may need
+        // multiple buffer refills to complete.
+        loop {
+            let available_codestream = match box_parser.get_more_codestream(input) {
+                Err(Error::OutOfBounds(_)) => 0,
+                Ok(c) => c as usize,
+                Err(e) => return Err(e),
+            };
+            let c = self.non_section_buf.refill(
+                |buf| {
+                    if !box_parser.box_buffer.is_empty() {
+                        Ok(box_parser.box_buffer.take(buf))
+                    } else {
+                        input.read(buf)
+                    }
+                },
+                Some(available_codestream),
+            )? as u64;
+            box_parser.consume_codestream(c);
+
+            // If we know that non-section parsing will require more bytes than what
+            // we added to the codestream, don't even try to parse non-section data.
+            if let Some(needed) = self.header_needed_bytes.as_mut() {
+                *needed = needed.saturating_sub(c);
+                if *needed > 0 {
+                    if !self.non_section_buf.can_read_more() {
+                        self.non_section_buf.enlarge();
+                    }
+                    // Check if input still has data - if so, refill and retry
+                    if input.available_bytes().unwrap_or(0) > 0 {
+                        continue;
+                    } else {
+                        return Err(Error::OutOfBounds(*needed as usize));
+                    }
+                }
+            }
+
+            if self.decoder_state.is_some() && self.frame_header.is_none() {
+                self.frame_codestream_offset =
+                    box_parser.codestream_position - self.non_section_buf.len() as u64;
+            }
+            let range = self.non_section_buf.range();
+
+            match parse(self) {
+                Ok(()) => {
+                    self.header_needed_bytes = None;
+                    return Ok(());
+                }
+                Err(Error::OutOfBounds(n)) => {
+                    let new_range = self.non_section_buf.range();
+                    // If non-section parsing consumed no bytes, and the non-section buffer
+                    // cannot accept more bytes, enlarge the buffer to allow to make progress.
+                    if new_range == range && !self.non_section_buf.can_read_more() {
+                        self.non_section_buf.enlarge();
+                    }
+                    self.header_needed_bytes = Some(n as u64);
+                    // Check if input still has data - if so, refill and retry
+                    if input.available_bytes().unwrap_or(0) > 0 {
+                        continue;
+                    } else {
+                        return Err(Error::OutOfBounds(n));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
     pub(super) fn process(
         &mut self,
         box_parser: &mut BoxParser,
         input: &mut dyn JxlBitstreamInput,
         decode_options: &JxlDecoderOptions,
-        mut output_buffers: Option<&mut [JxlOutputBuffer]>,
+        mut output_buffers: Option<&mut [JxlOutputSink]>,
     ) -> Result<()> {
         if let Some(output_buffers) = &output_buffers {
-            let px = self.pixel_format.as_ref().unwrap();
-            let expected_len = std::iter::once(&px.color_data_format)
-                .chain(px.extra_channel_format.iter())
-                .filter(|x| x.is_some())
-                .count();
-            if output_buffers.len() != expected_len {
-                return Err(Error::WrongBufferCount(output_buffers.len(), expected_len));
-            }
+            self.check_output_buffer_count(output_buffers)?;
         }
         // If we have sections to read, read into sections; otherwise, read into the local buffer.
         loop {
@@ -177,6 +487,10 @@ impl CodestreamParser {
                 }
 
                 if !self.skip_sections {
+                    if let Some(input) = input.as_seekable() {
+                        self.load_sections_at(box_parser, input)?;
+                    }
+                    self.skip_unneeded_sections(box_parser, input)?;
                     // This is just an estimate as there could be box bytes in the middle.
                     let mut readable_section_data = (self.non_section_buf.len()
                         + input.available_bytes()?
@@ -184,6 +498,9 @@ impl CodestreamParser {
                         .max(1);
                     // Ensure enough section buffers are available for reading available data.
                     for buf in self.sections.iter_mut() {
+                        if buf.skip || buf.loaded {
+                            continue;
+                        }
                         if buf.data.is_empty() {
                             buf.data.resize(buf.len, 0);
                         }
@@ -202,7 +519,7 @@ impl CodestreamParser {
                     let mut section_buffers = vec![];
                     let mut ready = self.ready_section_data;
                     for buf in self.sections.iter_mut() {
-                        if buf.data.is_empty() {
+                        if buf.len > ready && buf.data.is_empty() {
                             break;
                         }
                         let len = buf.data.len();
@@ -215,7 +532,7 @@ impl CodestreamParser {
                                 break;
                             }
                         }
-                        ready = ready.saturating_sub(len);
+                        ready = ready.saturating_sub(buf.len);
                     }
                     let mut buffers = &mut section_buffers[..];
                     loop {
@@ -231,6 +548,7 @@ impl CodestreamParser {
                             break;
                         }
                     }
+                    self.skip_unneeded_sections(box_parser, input)?;
                     match self.process_sections(decode_options, &mut output_buffers) {
                         Ok(None) => Ok(()),
                         Ok(Some(missing)) => Err(Error::OutOfBounds(missing)),
@@ -261,6 +579,11 @@ impl CodestreamParser {
                         return Err(Error::OutOfBounds(total_size - self.ready_section_data));
                     } else {
                         self.sections.clear();
+                        self.skip_sections = false;
+                        match self.frame.take().unwrap().skip() {
+                            Some(state) => self.decoder_state = Some(state),
+                            None => self.has_more_frames = false,
+                        }
                     }
                 }
                 if self.sections.is_empty() {
@@ -279,74 +602,35 @@ impl CodestreamParser {
                 assert!(self.frame.is_none());
                 assert!(self.has_more_frames);
 
-                // Loop to handle incremental parsing (e.g. large ICC profiles) that may need
-                // multiple buffer refills to complete.
-                loop {
-                    let available_codestream = match box_parser.get_more_codestream(input) {
-                        Err(Error::OutOfBounds(_)) => 0,
-                        Ok(c) => c as usize,
-                        Err(e) => return Err(e),
-                    };
-                    let c = self.non_section_buf.refill(
-                        |buf| {
-                            if !box_parser.box_buffer.is_empty() {
-                                Ok(box_parser.box_buffer.take(buf))
-                            } else {
-                                input.read(buf)
-                            }
-                        },
-                        Some(available_codestream),
-                    )? as u64;
-                    box_parser.consume_codestream(c);
-
-                    // If we know that non-section parsing will require more bytes than what
-                    // we added to the codestream, don't even try to parse non-section data.
-                    if let Some(needed) = self.header_needed_bytes.as_mut() {
-                        *needed = needed.saturating_sub(c);
-                        if *needed > 0 {
-                            if !self.non_section_buf.can_read_more() {
-                                self.non_section_buf.enlarge();
-                            }
-                            // Check if input still has data - if so, refill and retry
-                            if input.available_bytes().unwrap_or(0) > 0 {
-                                continue;
-                            } else {
-                                return Err(Error::OutOfBounds(*needed as usize));
-                            }
-                        }
-                    }
-
-                    let range = self.non_section_buf.range();
-
-                    match self.process_non_section(decode_options) {
-                        Ok(()) => {
-                            self.header_needed_bytes = None;
-                            break;
-                        }
-                        Err(Error::OutOfBounds(n)) => {
-                            let new_range = self.non_section_buf.range();
-                            // If non-section parsing consumed no bytes, and the non-section buffer
-                            // cannot accept more bytes, enlarge the buffer to allow to make progress.
-                            if new_range == range && !self.non_section_buf.can_read_more() {
-                                self.non_section_buf.enlarge();
-                            }
-                            self.header_needed_bytes = Some(n as u64);
-                            // Check if input still has data - if so, refill and retry
-                            if input.available_bytes().unwrap_or(0) > 0 {
-                                continue;
-                            } else {
-                                return Err(Error::OutOfBounds(n));
-                            }
-                        }
-                        Err(e) => return Err(e),
-                    }
+                if let (Some(state), Some(headroom), Some(gain_map)) = (
+                    self.decoder_state.as_mut(),
+                    decode_options.gain_map_headroom,
+                    box_parser.gain_map.as_ref(),
+                ) && state.gain_map.is_none()
+                {
+                    let rendering = GainMapRendering::new(gain_map, state.image_size(), headroom)?;
+                    state.gain_map = Some(Arc::new(rendering));
                 }
 
+                self.read_non_section(box_parser, input, |parser| {
+                    parser.process_non_section(decode_options)
+                })?;
+
                 if self.decoder_state.is_some() && self.frame_header.is_none() {
                     // Return to caller if we found image info.
                     return Ok(());
                 }
                 if self.frame.is_some() {
+                    let sections_offset =
+                        (self.ready_section_data + self.non_section_buf.len()) as u64;
+                    self.sections_codestream_offset =
+                        box_parser.codestream_position - sections_offset;
+                    let sections_start = box_parser.position - sections_offset;
+                    let sections_len = self.sections.iter().map(|s| s.len as u64).sum::<u64>();
+                    self.sections_file_offset = (sections_start >= box_parser.codestream_box_start
+                        && sections_start + sections_len <= box_parser.codestream_box_end)
+                        .then_some(sections_start);
+
                     // Check if this is a preview frame that should be skipped
                     let is_preview_frame = !self.preview_done
                         && self
@@ -362,6 +646,16 @@ impl CodestreamParser {
                     }
 
                     if self.has_visible_frame() {
+                        // The LF image of frames that use an LF frame is already complete.
+                        if let Some(callback) = decode_options.progress_callback.as_ref()
+                            && !self.process_without_output
+                            && self
+                                .frame
+                                .as_ref()
+                                .is_some_and(|frame| frame.header().has_lf_frame())
+                        {
+                            callback(JxlProgressEvent::LfComplete);
+                        }
                         // Return to caller if we found visible frame info.
                         return Ok(());
                     } else {
@@ -8,18 +8,18 @@ use std::io::IoSliceMut;
 use crate::{
     api::{
         Endianness, JxlBasicInfo, JxlBitDepth, JxlColorEncoding, JxlColorProfile, JxlColorType,
-        JxlDataFormat, JxlDecoderOptions, JxlExtraChannel, JxlPixelFormat, JxlPrimaries,
-        JxlTransferFunction, JxlWhitePoint, inner::codestream_parser::SectionState,
+        JxlDataFormat, JxlDecoderOptions, JxlExtraChannel, JxlPixelFormat,
+        inner::codestream_parser::SectionState,
     },
     bit_reader::BitReader,
     error::{Error, Result},
     frame::{DecoderState, Frame, Section},
     headers::{
         FileHeader, JxlHeader,
-        color_encoding::{ColorSpace, RenderingIntent},
+        color_encoding::ColorSpace,
         encodings::UnconditionalCoder,
         frame_header::FrameHeader,
-        toc::IncrementalTocReader,
+        toc::{IncrementalTocReader, Toc},
     },
     icc::IncrementalIccReader,
 };
@@ -165,37 +165,13 @@ impl CodestreamParser {
                     JxlColorProfile::Simple(encoding) => encoding.clone(),
                 };
                 JxlColorProfile::Simple(if decode_options.xyb_output_linear {
-                    match nonlinear_output_color_profile {
-                        JxlColorEncoding::RgbColorSpace {
-                            white_point,
-                            primaries,
-                            transfer_function: _,
-                            rendering_intent,
-                        } => JxlColorEncoding::RgbColorSpace {
-                            white_point,
-                            primaries,
-                            transfer_function: JxlTransferFunction::Linear,
-                            rendering_intent,
-                        },
-                        JxlColorEncoding::GrayscaleColorSpace {
-                            white_point,
-                            transfer_function: _,
-                            rendering_intent,
-                        } => JxlColorEncoding::GrayscaleColorSpace {
-                            white_point,
-                            transfer_function: JxlTransferFunction::Linear,
-                            rendering_intent,
-                        },
-                        JxlColorEncoding::XYB { .. } => JxlColorEncoding::RgbColorSpace {
-                            transfer_function: JxlTransferFunction::Linear,
-                            white_point: JxlWhitePoint::D65,
-                            primaries: JxlPrimaries::SRGB,
-                            rendering_intent: RenderingIntent::Relative,
-                        },
-                    }
+                    nonlinear_output_color_profile.with_linear_tf()
                 } else {
                     nonlinear_output_color_profile
                 })
+            } else if embedded_color_profile.is_cmyk() && decode_options.cms.is_some() {
+                // Render CMYK images to sRGB when we are able to.
+                JxlColorProfile::Simple(JxlColorEncoding::srgb(false))
             } else {
                 embedded_color_profile.clone()
             };
@@ -234,92 +210,35 @@ impl CodestreamParser {
             decoder_state.render_spotcolors = decode_options.render_spot_colors;
             decoder_state.high_precision = decode_options.high_precision;
             decoder_state.premultiply_output = decode_options.premultiply_output;
+            decoder_state.hlg_rendering = decode_options.hlg_rendering.clone();
+            decoder_state.custom_stages = decode_options.custom_stages.clone();
+            decoder_state.resampling = decode_options.resampling.clone();
+            decoder_state.stage_taps = decode_options.stage_taps.clone();
+            decoder_state.buffer_pool = decode_options.buffer_pool.clone();
+            decoder_state.simd_level = decode_options.simd_level();
+            decoder_state.embedded_color_profile = self.embedded_color_profile.clone();
             self.decoder_state = Some(decoder_state);
             // Reset bit offset to 0 since we've consumed everything up to a byte boundary
             self.non_section_bit_offset = 0;
             return Ok(());
         }
 
-        let decoder_state = self.decoder_state.as_mut().unwrap();
-
-        if self.frame_header.is_none() {
-            // We don't have a frame header yet. Try parsing that.
-            let mut br = BitReader::new(&self.non_section_buf);
-            br.skip_bits(self.non_section_bit_offset as usize)?;
-
-            // For preview frames, use the preview dimensions instead of main image dimensions
-            let nonserialized = if !self.preview_done {
-                decoder_state
-                    .file_header
-                    .preview_frame_header_nonserialized()
-                    .unwrap_or_else(|| decoder_state.file_header.frame_header_nonserialized())
-            } else {
-                decoder_state.file_header.frame_header_nonserialized()
-            };
-
-            let mut frame_header = FrameHeader::read_unconditional(&(), &mut br, &nonserialized)?;
-            frame_header.postprocess(&nonserialized);
-            check_size_limit(
-                decode_options.pixel_limit,
-                frame_header.size(),
-                frame_header.num_extra_channels as usize,
-            )?;
-
-            // Initialize storage buffers for available sections.
-            self.lf_global_section = None;
-            self.lf_sections.clear();
-            self.hf_global_section = None;
-            self.hf_sections = (0..frame_header.num_groups())
-                .map(|_| (0..frame_header.passes.num_passes).map(|_| None).collect())
-                .collect();
-            self.candidate_hf_sections.clear();
-
-            self.frame_header = Some(frame_header);
-            let bits = br.total_bits_read();
-            self.non_section_buf.consume(bits / 8);
-            self.non_section_bit_offset = (bits % 8) as u8;
-        }
+        let (frame_header, toc) = self.read_frame_header_and_toc(decode_options)?;
 
-        let toc = {
-            let mut br = BitReader::new(&self.non_section_buf);
-            br.skip_bits(self.non_section_bit_offset as usize)?;
-            if self.toc_parser.is_none() {
-                let num_toc_entries = self.frame_header.as_ref().unwrap().num_toc_entries();
-                self.toc_parser = Some(IncrementalTocReader::new(num_toc_entries as u32, &mut br)?);
-            }
-
-            let toc_parser = self.toc_parser.as_mut().unwrap();
-            let mut bits = br.total_bits_read();
-            while !toc_parser.is_complete() {
-                match toc_parser.read_step(&mut br) {
-                    Ok(()) => bits = br.total_bits_read(),
-                    Err(Error::OutOfBounds(c)) => {
-                        self.non_section_buf.consume(bits / 8);
-                        self.non_section_bit_offset = (bits % 8) as u8;
-                        // Estimate >= 16 bits per remaining entry to read.
-                        return Err(Error::OutOfBounds(
-                            c + toc_parser.remaining_entries() as usize * 2,
-                        ));
-                    }
-                    Err(e) => return Err(e),
-                }
-            }
-            br.jump_to_byte_boundary()?;
-
-            bits = br.total_bits_read();
-            self.non_section_buf.consume(bits / 8);
-            self.non_section_bit_offset = (bits % 8) as u8;
-            self.toc_parser.take().unwrap().finalize()
-        };
+        // Initialize storage buffers for available sections.
+        self.lf_global_section = None;
+        self.lf_sections.clear();
+        self.hf_global_section = None;
+        self.hf_sections = (0..frame_header.num_groups())
+            .map(|_| (0..frame_header.passes.num_passes).map(|_| None).collect())
+            .collect();
+        self.candidate_hf_sections.clear();
 
         // Save file_header before creating frame (for preview frame recovery)
         self.saved_file_header = self.decoder_state.as_ref().map(|ds| ds.file_header.clone());
 
-        let frame = Frame::from_header_and_toc(
-            self.frame_header.take().unwrap(),
-            toc,
-            self.decoder_state.take().unwrap(),
-        )?;
+        let frame =
+            Frame::from_header_and_toc(frame_header, toc, self.decoder_state.take().unwrap())?;
 
         let mut sections: Vec<_> = frame
             .toc()
@@ -329,6 +248,9 @@ impl CodestreamParser {
                 len: *x as usize,
                 data: vec![],
                 section: Section::LfGlobal, // will be fixed later
+                skip: false,
+                loaded: false,
+                shared: None,
             })
             .collect();
 
@@ -378,4 +300,74 @@ impl CodestreamParser {
 
         Ok(())
     }
+
+    /// Reads the header and the TOC of the next frame.
+    pub(super) fn read_frame_header_and_toc(
+        &mut self,
+        decode_options: &JxlDecoderOptions,
+    ) -> Result<(FrameHeader, Toc)> {
+        let decoder_state = self.decoder_state.as_ref().unwrap();
+
+        if self.frame_header.is_none() {
+            // We don't have a frame header yet. Try parsing that.
+            let mut br = BitReader::new(&self.non_section_buf);
+            br.skip_bits(self.non_section_bit_offset as usize)?;
+
+            // For preview frames, use the preview dimensions instead of main image dimensions
+            let nonserialized = if !self.preview_done {
+                decoder_state
+                    .file_header
+                    .preview_frame_header_nonserialized()
+                    .unwrap_or_else(|| decoder_state.file_header.frame_header_nonserialized())
+            } else {
+                decoder_state.file_header.frame_header_nonserialized()
+            };
+
+            let mut frame_header = FrameHeader::read_unconditional(&(), &mut br, &nonserialized)?;
+            frame_header.postprocess(&nonserialized);
+            check_size_limit(
+                decode_options.pixel_limit,
+                frame_header.size(),
+                frame_header.num_extra_channels as usize,
+            )?;
+
+            self.frame_header = Some(frame_header);
+            let bits = br.total_bits_read();
+            self.non_section_buf.consume(bits / 8);
+            self.non_section_bit_offset = (bits % 8) as u8;
+        }
+
+        let toc = {
+            let mut br = BitReader::new(&self.non_section_buf);
+            br.skip_bits(self.non_section_bit_offset as usize)?;
+            if self.toc_parser.is_none() {
+                let num_toc_entries = self.frame_header.as_ref().unwrap().num_toc_entries();
+                self.toc_parser = Some(IncrementalTocReader::new(num_toc_entries as u32, &mut br)?);
+            }
+
+            let toc_parser = self.toc_parser.as_mut().unwrap();
+            let mut bits = br.total_bits_read();
+            while !toc_parser.is_complete() {
+                match toc_parser.read_step(&mut br) {
+                    Ok(()) => bits = br.total_bits_read(),
+                    Err(Error::OutOfBounds(c)) => {
+                        self.non_section_buf.consume(bits / 8);
+                        self.non_section_bit_offset = (bits % 8) as u8;
+                        // Estimate >= 16 bits per remaining entry to read.
+                        return Err(Error::OutOfBounds(
+                            c + toc_parser.remaining_entries() as usize * 2,
+                        ));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            br.jump_to_byte_boundary()?;
+
+            bits = br.total_bits_read();
+            self.non_section_buf.consume(bits / 8);
+            self.non_section_bit_offset = (bits % 8) as u8;
+            self.toc_parser.take().unwrap().finalize()
+        };
+        Ok((self.frame_header.take().unwrap(), toc))
+    }
 }
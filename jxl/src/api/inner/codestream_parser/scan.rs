@@ -0,0 +1,92 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api::{JxlBitstreamInput, JxlDecoderOptions, JxlScannedFrame, inner::box_parser::BoxParser},
+    error::{Error, Result},
+};
+
+use super::CodestreamParser;
+
+impl CodestreamParser {
+    /// Reads the headers and TOCs of all the remaining frames into `scanned_frames`, skipping over
+    /// their sections without decoding them.
+    pub(in crate::api::inner) fn scan_frames(
+        &mut self,
+        box_parser: &mut BoxParser,
+        input: &mut dyn JxlBitstreamInput,
+        decode_options: &JxlDecoderOptions,
+    ) -> Result<()> {
+        // A frame that `process` started reading without output is skipped over.
+        if let Some(frame) = self.frame.take() {
+            let total_size = self.sections.iter().map(|x| x.len).sum::<usize>();
+            self.scan_skip_bytes = (total_size - self.ready_section_data) as u64;
+            self.sections.clear();
+            self.process_without_output = false;
+            match frame.skip() {
+                Some(state) => self.decoder_state = Some(state),
+                None => self.has_more_frames = false,
+            }
+        }
+        loop {
+            while self.scan_skip_bytes > 0 {
+                let available_codestream = match box_parser.get_more_codestream(input) {
+                    Err(Error::OutOfBounds(_)) => 0,
+                    Ok(c) => c,
+                    Err(e) => return Err(e),
+                };
+                let num = self.scan_skip_bytes.min(available_codestream) as usize;
+                let skipped = if !box_parser.box_buffer.is_empty() {
+                    box_parser.box_buffer.consume(num)
+                } else {
+                    input.skip(num)?
+                };
+                box_parser.consume_codestream(skipped as u64);
+                self.scan_skip_bytes -= skipped as u64;
+                if skipped == 0 {
+                    let remaining = self.scan_skip_bytes.min(usize::MAX as u64) as usize;
+                    return Err(Error::OutOfBounds(remaining));
+                }
+            }
+            if !self.has_more_frames {
+                return Ok(());
+            }
+
+            let mut frame = None;
+            self.read_non_section(box_parser, input, |parser| {
+                frame = Some(parser.read_frame_header_and_toc(decode_options)?);
+                Ok(())
+            })?;
+            let (frame_header, toc) = frame.unwrap();
+
+            let is_preview = !self.preview_done
+                && self
+                    .basic_info
+                    .as_ref()
+                    .is_some_and(|info| info.preview_size.is_some());
+            self.preview_done = true;
+            self.scanned_frames.push(JxlScannedFrame {
+                header: self.api_frame_header(&frame_header).unwrap(),
+                duration_ticks: frame_header.duration,
+                is_visible: frame_header.is_visible() && !is_preview,
+                is_preview,
+                codestream_offset: self.frame_codestream_offset,
+                section_sizes: toc.entries.iter().map(|x| *x as usize).collect(),
+            });
+
+            // Section data that was already read along with the TOC is dropped.
+            let total_size = toc.entries.iter().map(|x| *x as u64).sum::<u64>();
+            let buffered = self
+                .non_section_buf
+                .consume(total_size.min(usize::MAX as u64) as usize);
+            self.scan_skip_bytes = total_size - buffered as u64;
+            // The preview frame is the last one of its own sequence of frames.
+            if frame_header.is_last && !is_preview {
+                self.has_more_frames = false;
+                self.decoder_state = None;
+            }
+        }
+    }
+}
@@ -3,14 +3,18 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use std::ops::Range;
+
 use crate::{
-    api::{JxlDecoderOptions, JxlOutputBuffer},
+    BLOCK_DIM,
+    api::{JxlDecoderOptions, JxlOutputSink, JxlProgressEvent, JxlSectionRequest},
     bit_reader::BitReader,
-    error::Result,
+    error::{Error, Result},
     frame::Section,
+    headers::frame_header::{Encoding, FrameHeader},
 };
 
-use super::CodestreamParser;
+use super::{CodestreamParser, SectionBuffer};
 
 pub(super) struct SectionState {
     lf_global_done: bool,
@@ -34,16 +38,160 @@ impl SectionState {
     pub(super) fn num_completed_passes(&self) -> usize {
         self.completed_passes.iter().copied().min().unwrap_or(0) as usize
     }
+
+    /// Returns whether LF global and all the LF groups have been decoded.
+    pub(super) fn lf_done(&self) -> bool {
+        self.lf_global_done && self.remaining_lf == 0
+    }
+}
+
+/// The sections of a frame that are needed to render the part of it selected by a
+/// [`JxlSectionRequest`].
+struct SectionSelection {
+    num_passes: usize,
+    // Ranges of group (resp. LF group) coordinates, as (x, y).
+    groups: (Range<usize>, Range<usize>),
+    lf_groups: (Range<usize>, Range<usize>),
+    groups_per_row: usize,
+    lf_groups_per_row: usize,
+}
+
+impl SectionSelection {
+    fn new(header: &FrameHeader, request: &JxlSectionRequest) -> Self {
+        let passes = &header.passes;
+        let shift = request.downsampling.max(1).ilog2() as usize;
+        let mut num_passes = if shift >= 3 && header.encoding == Encoding::VarDCT {
+            0
+        } else {
+            (0..passes.num_passes as usize)
+                .find(|&p| passes.downsampling_bracket(p).0 <= shift)
+                .map_or(passes.num_passes as usize, |p| p + 1)
+        };
+        if let Some(max_passes) = request.max_passes {
+            num_passes = num_passes.min(max_passes);
+        }
+
+        // Region in frame coordinates before upsampling, extended by one block on each side so
+        // that the restoration filters see the same neighbours as in a full decode.
+        let (xsize, ysize) = header.size();
+        let ((x0, y0), (width, height)) =
+            request.region.unwrap_or(((0, 0), (usize::MAX, usize::MAX)));
+        let upsampling = header.upsampling as i64;
+        let to_frame = |begin: usize, len: usize, origin: i32, size: usize| {
+            let begin = begin as i64 - origin as i64;
+            let end = begin.saturating_add(len.min(i64::MAX as usize) as i64);
+            let begin = begin.div_euclid(upsampling) - BLOCK_DIM as i64;
+            let end = end
+                .saturating_add(upsampling - 1)
+                .div_euclid(upsampling)
+                .saturating_add(BLOCK_DIM as i64);
+            begin.clamp(0, size as i64) as usize..end.clamp(0, size as i64) as usize
+        };
+        let xrange = to_frame(x0, width, header.x0, xsize);
+        let yrange = to_frame(y0, height, header.y0, ysize);
+        let to_groups = |range: &Range<usize>, dim: usize| {
+            if range.is_empty() {
+                0..0
+            } else {
+                range.start / dim..range.end.div_ceil(dim)
+            }
+        };
+        Self {
+            num_passes,
+            groups: (
+                to_groups(&xrange, header.group_dim()),
+                to_groups(&yrange, header.group_dim()),
+            ),
+            lf_groups: (
+                to_groups(&xrange, header.lf_group_dim()),
+                to_groups(&yrange, header.lf_group_dim()),
+            ),
+            groups_per_row: header.size_groups().0,
+            lf_groups_per_row: header.size_lf_groups().0,
+        }
+    }
+
+    fn contains(&self, section: Section) -> bool {
+        let in_range = |group: usize, per_row: usize, (xs, ys): &(Range<usize>, Range<usize>)| {
+            xs.contains(&(group % per_row)) && ys.contains(&(group / per_row))
+        };
+        match section {
+            Section::LfGlobal => true,
+            Section::Lf { group } => in_range(group, self.lf_groups_per_row, &self.lf_groups),
+            Section::HfGlobal => self.num_passes > 0,
+            Section::Hf { group, pass } => {
+                pass < self.num_passes && in_range(group, self.groups_per_row, &self.groups)
+            }
+        }
+    }
 }
 
 impl CodestreamParser {
+    /// Marks the sections of the current frame that are not needed for `request` as skipped, and
+    /// returns the file byte ranges of the needed ones that were not read yet.
+    pub(in crate::api::inner) fn plan_sections(
+        &mut self,
+        request: &JxlSectionRequest,
+    ) -> Result<Vec<Range<u64>>> {
+        let Some(mut offset) = self.sections_file_offset else {
+            return Err(Error::SectionsNotContiguous);
+        };
+        let selection = SectionSelection::new(self.frame.as_ref().unwrap().header(), request);
+        let mut ready = self.ready_section_data;
+        let mut ranges: Vec<Range<u64>> = vec![];
+        for s in self.sections.iter_mut() {
+            let received = ready.min(s.len);
+            ready -= received;
+            let (start, end) = (offset + received as u64, offset + s.len as u64);
+            offset = end;
+            s.skip = !selection.contains(s.section);
+            if s.skip || start == end {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Hands over a section that was fully read for decoding.
+    pub(super) fn queue_section(&mut self, s: SectionBuffer) {
+        match s.section {
+            Section::LfGlobal => {
+                self.lf_global_section = Some(s);
+            }
+            Section::HfGlobal => {
+                self.hf_global_section = Some(s);
+            }
+            Section::Lf { .. } => {
+                self.lf_sections.push(s);
+            }
+            Section::Hf { group, pass } => {
+                self.hf_sections[group][pass] = Some(s);
+                self.candidate_hf_sections.insert(group);
+            }
+        }
+    }
+
     pub(super) fn process_sections(
         &mut self,
         decode_options: &JxlDecoderOptions,
-        output_buffers: &mut Option<&mut [JxlOutputBuffer<'_>]>,
+        output_buffers: &mut Option<&mut [JxlOutputSink<'_>]>,
     ) -> Result<Option<usize>> {
-        let frame = self.frame.as_mut().unwrap();
-        let frame_header = frame.header();
+        // Progress is only reported for the frames that are returned to the user.
+        let report_progress = !self.process_without_output;
+        let progress = |event| {
+            if let Some(callback) = decode_options.progress_callback.as_ref()
+                && report_progress
+            {
+                callback(event);
+            }
+        };
+        let completed_passes = self.section_state.num_completed_passes();
+
+        let mut processed_section = false;
 
         // Dequeue ready sections.
         while self
@@ -53,25 +201,27 @@ impl CodestreamParser {
         {
             let s = self.sections.pop_front().unwrap();
             self.ready_section_data -= s.len;
-
+            self.sections_codestream_offset += s.len as u64;
+            if let Some(offset) = self.sections_file_offset.as_mut() {
+                *offset += s.len as u64;
+            }
+            if s.loaded {
+                processed_section = true;
+                continue;
+            }
             match s.section {
-                Section::LfGlobal => {
-                    self.lf_global_section = Some(s);
-                }
-                Section::HfGlobal => {
-                    self.hf_global_section = Some(s);
-                }
-                Section::Lf { .. } => {
-                    self.lf_sections.push(s);
-                }
-                Section::Hf { group, pass } => {
-                    self.hf_sections[group][pass] = Some(s);
-                    self.candidate_hf_sections.insert(group);
+                Section::Hf { .. } if s.skip => {
+                    // Rendered from the previous passes once all the sections were seen.
+                    processed_section = true;
                 }
+                _ => self.queue_section(s),
             }
         }
 
-        let mut processed_section = false;
+        let frame = self.frame.as_mut().unwrap();
+        let frame_header = frame.header();
+        // Frames that use an LF frame report their LF as complete when they start.
+        let report_lf_complete = !frame_header.has_lf_frame();
         let pixel_format = self.pixel_format.as_ref().unwrap();
         'process: {
             if frame_header.num_groups() == 1 && frame_header.passes.num_passes == 1 {
@@ -80,26 +230,36 @@ impl CodestreamParser {
                     break 'process;
                 };
                 assert!(self.sections.is_empty());
-                let mut br = BitReader::new(&sec.data);
+                let mut br = BitReader::new(sec.bytes());
                 frame.decode_lf_global(&mut br)?;
-                frame.decode_lf_group(0, &mut br)?;
-                frame.decode_hf_global(&mut br)?;
+                frame.decode_lf_group(0, Some(&mut br))?;
+                frame.decode_hf_global(Some(&mut br))?;
                 frame.prepare_render_pipeline(
                     self.pixel_format.as_ref().unwrap(),
+                    self.output_color_profile.as_ref().unwrap(),
                     decode_options.cms.as_deref(),
                 )?;
                 frame.finalize_lf()?;
                 frame.decode_and_render_hf_groups(
                     output_buffers,
                     pixel_format,
-                    vec![(0, vec![(0, br)])],
+                    vec![(0, vec![(0, Some(br))])],
                 )?;
+                self.section_state.completed_passes[0] = 1;
                 processed_section = true;
+                progress(JxlProgressEvent::LfGlobal);
+                progress(JxlProgressEvent::LfGroup(0));
+                if report_lf_complete {
+                    progress(JxlProgressEvent::LfComplete);
+                }
+                progress(JxlProgressEvent::HfGlobal);
+                progress(JxlProgressEvent::HfGroup { group: 0, pass: 0 });
             } else {
                 if let Some(lf_global) = self.lf_global_section.take() {
-                    frame.decode_lf_global(&mut BitReader::new(&lf_global.data))?;
+                    frame.decode_lf_global(&mut BitReader::new(lf_global.bytes()))?;
                     self.section_state.lf_global_done = true;
                     processed_section = true;
+                    progress(JxlProgressEvent::LfGlobal);
                 }
 
                 if !self.section_state.lf_global_done {
@@ -110,9 +270,19 @@ impl CodestreamParser {
                     let Section::Lf { group } = lf_section.section else {
                         unreachable!()
                     };
-                    frame.decode_lf_group(group, &mut BitReader::new(&lf_section.data))?;
+                    let mut br = BitReader::new(lf_section.bytes());
+                    frame.decode_lf_group(group, (!lf_section.skip).then_some(&mut br))?;
                     processed_section = true;
+                    if !lf_section.skip {
+                        progress(JxlProgressEvent::LfGroup(group));
+                    }
                     self.section_state.remaining_lf -= 1;
+                    if self.section_state.remaining_lf == 0 {
+                        frame.finalize_lf()?;
+                        if report_lf_complete {
+                            progress(JxlProgressEvent::LfComplete);
+                        }
+                    }
                 }
 
                 if self.section_state.remaining_lf != 0 {
@@ -120,14 +290,18 @@ impl CodestreamParser {
                 }
 
                 if let Some(hf_global) = self.hf_global_section.take() {
-                    frame.decode_hf_global(&mut BitReader::new(&hf_global.data))?;
+                    let mut br = BitReader::new(hf_global.bytes());
+                    frame.decode_hf_global((!hf_global.skip).then_some(&mut br))?;
                     frame.prepare_render_pipeline(
                         self.pixel_format.as_ref().unwrap(),
+                        self.output_color_profile.as_ref().unwrap(),
                         decode_options.cms.as_deref(),
                     )?;
-                    frame.finalize_lf()?;
                     self.section_state.hf_global_done = true;
                     processed_section = true;
+                    if !hf_global.skip {
+                        progress(JxlProgressEvent::HfGlobal);
+                    }
                 }
 
                 if !self.section_state.hf_global_done {
@@ -138,6 +312,7 @@ impl CodestreamParser {
                 let mut processed_groups = vec![];
 
                 let mut check_group = |g: usize| {
+                    let first_pass = self.section_state.completed_passes[g] as usize;
                     let mut sections = vec![];
                     for (pass, grp) in self.hf_sections[g]
                         .iter()
@@ -148,11 +323,12 @@ impl CodestreamParser {
                             break;
                         };
                         self.section_state.completed_passes[g] += 1;
-                        sections.push((pass, BitReader::new(&s.data)));
+                        sections.push((pass, Some(BitReader::new(s.bytes()))));
                     }
                     if !sections.is_empty() {
                         group_readers.push((g, sections));
-                        processed_groups.push(g);
+                        let last_pass = self.section_state.completed_passes[g] as usize;
+                        processed_groups.push((g, first_pass..last_pass));
                     }
                 };
 
@@ -174,18 +350,24 @@ impl CodestreamParser {
 
                 frame.decode_and_render_hf_groups(output_buffers, pixel_format, group_readers)?;
 
-                for g in processed_groups.into_iter() {
+                for (g, passes) in processed_groups.into_iter() {
                     for i in 0..self.section_state.completed_passes[g] {
                         self.hf_sections[g][i as usize] = None;
                     }
                     processed_section = true;
+                    for pass in passes {
+                        progress(JxlProgressEvent::HfGroup { group: g, pass });
+                    }
                 }
             }
         }
 
-        if !processed_section {
-            let data_for_next_section =
-                self.sections.front().unwrap().len - self.ready_section_data;
+        for pass in completed_passes..self.section_state.num_completed_passes() {
+            progress(JxlProgressEvent::PassComplete(pass));
+        }
+
+        if !processed_section && let Some(next_section) = self.sections.front() {
+            let data_for_next_section = next_section.len - self.ready_section_data;
             return Ok(Some(data_for_next_section));
         }
 
@@ -194,6 +376,61 @@ impl CodestreamParser {
             return Ok(None);
         }
 
+        self.render_missing_groups(output_buffers)?;
+        self.finalize_frame(decode_options)?;
+        Ok(None)
+    }
+
+    /// Renders the current frame with the sections that were fully received, treating all the
+    /// others as missing.
+    pub(super) fn finish_sections(
+        &mut self,
+        decode_options: &JxlDecoderOptions,
+        output_buffers: &mut [JxlOutputSink<'_>],
+    ) -> Result<()> {
+        // Without LF global, nothing in the frame can be decoded.
+        if self.frame.is_none() || !self.section_state.lf_global_done {
+            return Err(Error::NotEnoughFrameData);
+        }
+        for s in self.sections.iter_mut() {
+            s.skip = true;
+        }
+        self.ready_section_data = self.sections.iter().map(|s| s.len).sum();
+        self.process_sections(decode_options, &mut Some(output_buffers))?;
+        Ok(())
+    }
+
+    /// Renders the groups that are missing some passes from the passes that were decoded.
+    fn render_missing_groups(
+        &mut self,
+        output_buffers: &mut Option<&mut [JxlOutputSink<'_>]>,
+    ) -> Result<()> {
+        let frame = self.frame.as_mut().unwrap();
+        let num_passes = frame.header().passes.num_passes as usize;
+        // Sections of passes that come after a missing one are not used either.
+        let missing_groups: Vec<_> = self
+            .section_state
+            .completed_passes
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, completed)| (**completed as usize) < num_passes)
+            .map(|(g, completed)| {
+                let missing_passes = (*completed as usize..num_passes).map(|p| (p, None));
+                *completed = num_passes as u8;
+                (g, missing_passes.collect())
+            })
+            .collect();
+        if missing_groups.is_empty() {
+            return Ok(());
+        }
+        frame.decode_and_render_hf_groups(
+            output_buffers,
+            self.pixel_format.as_ref().unwrap(),
+            missing_groups,
+        )
+    }
+
+    fn finalize_frame(&mut self, decode_options: &JxlDecoderOptions) -> Result<()> {
         #[cfg(test)]
         {
             self.frame_callback.as_mut().map_or(Ok(()), |cb| {
@@ -209,7 +446,11 @@ impl CodestreamParser {
             .is_some_and(|info| info.preview_size.is_some());
         let might_be_preview = self.process_without_output && has_preview;
 
-        let decoder_state = self.frame.take().unwrap().finalize()?;
+        let mut frame = self.frame.take().unwrap();
+        if let Some(peak) = frame.peak_render_memory_usage() {
+            self.peak_render_memory = self.peak_render_memory.max(peak);
+        }
+        let decoder_state = frame.finalize()?;
         if let Some(state) = decoder_state {
             self.decoder_state = Some(state);
         } else if might_be_preview {
@@ -220,11 +461,18 @@ impl CodestreamParser {
                 new_state.xyb_output_linear = decode_options.xyb_output_linear;
                 new_state.render_spotcolors = decode_options.render_spot_colors;
                 new_state.enable_output = decode_options.enable_output;
+                new_state.hlg_rendering = decode_options.hlg_rendering.clone();
+                new_state.custom_stages = decode_options.custom_stages.clone();
+                new_state.resampling = decode_options.resampling.clone();
+                new_state.stage_taps = decode_options.stage_taps.clone();
+                new_state.buffer_pool = decode_options.buffer_pool.clone();
+                new_state.simd_level = decode_options.simd_level();
+                new_state.embedded_color_profile = self.embedded_color_profile.clone();
                 self.decoder_state = Some(new_state);
             }
         } else {
             self.has_more_frames = false;
         }
-        Ok(None)
+        Ok(())
     }
 }
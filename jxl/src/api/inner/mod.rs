@@ -3,6 +3,8 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use std::ops::Range;
+
 #[cfg(test)]
 use crate::api::FrameCallback;
 use crate::{
@@ -10,7 +12,11 @@ use crate::{
     error::{Error, Result},
 };
 
-use super::{JxlBasicInfo, JxlColorProfile, JxlDecoderOptions, JxlPixelFormat};
+use super::{
+    JxlBasicInfo, JxlBitstreamInput, JxlColorEncoding, JxlColorProfile, JxlDecoderOptions,
+    JxlFrameIndex, JxlGainMap, JxlPixelFormat, JxlScannedFrame, JxlSectionRequest,
+    JxlSeekableInput, JxlTransferFunction, ProcessingResult,
+};
 use box_parser::BoxParser;
 use codestream_parser::CodestreamParser;
 
@@ -50,6 +56,26 @@ impl JxlDecoderInner {
         self.codestream_parser.basic_info.as_ref()
     }
 
+    /// Retrieves the gain map, if a `jhgm` box was found so far.
+    pub fn gain_map(&self) -> Option<&JxlGainMap> {
+        self.box_parser.gain_map.as_ref()
+    }
+
+    /// Retrieves the frame index, if a `jxli` box was found so far.
+    pub fn frame_index(&self) -> Option<&JxlFrameIndex> {
+        self.box_parser.frame_index.as_ref()
+    }
+
+    /// Searches the boxes after the codestream for a frame index, if none was found so far.
+    pub fn find_frame_index(&mut self, input: &mut dyn JxlSeekableInput) -> Result<()> {
+        self.box_parser.find_frame_index(input)
+    }
+
+    /// Returns the peak memory usage, in bytes, of the render pipeline of any frame decoded so far.
+    pub fn peak_render_memory_usage(&self) -> usize {
+        self.codestream_parser.peak_render_memory
+    }
+
     /// Retrieves the file's color profile, if available.
     pub fn embedded_color_profile(&self) -> Option<&JxlColorProfile> {
         self.codestream_parser.embedded_color_profile.as_ref()
@@ -60,6 +86,33 @@ impl JxlDecoderInner {
         self.codestream_parser.output_color_profile.as_ref()
     }
 
+    /// Returns the luminance, in nits, corresponding to a sample value of 1.0 in the output, if
+    /// the output color profile has a linear transfer function.
+    pub fn linear_output_luminance(&self) -> Option<f32> {
+        let has_tf = |profile: &JxlColorProfile, tf: JxlTransferFunction| {
+            matches!(
+                profile,
+                JxlColorProfile::Simple(
+                    JxlColorEncoding::RgbColorSpace { transfer_function, .. }
+                    | JxlColorEncoding::GrayscaleColorSpace { transfer_function, .. }
+                ) if *transfer_function == tf
+            )
+        };
+        if !has_tf(self.output_color_profile()?, JxlTransferFunction::Linear) {
+            return None;
+        }
+        let intensity_target = self.basic_info()?.tone_mapping.intensity_target;
+        if has_tf(self.embedded_color_profile()?, JxlTransferFunction::HLG) {
+            // HLG images are rendered for the display described by the HLG rendering options.
+            let hlg_rendering = &self.options.hlg_rendering;
+            let display_peak = hlg_rendering
+                .display_peak_luminance
+                .unwrap_or(intensity_target);
+            return Some(hlg_rendering.reference_white.unwrap_or(display_peak));
+        }
+        Some(intensity_target)
+    }
+
     /// Specifies the preferred color profile to be used for outputting data.
     /// Same semantics as JxlDecoderSetOutputColorProfile.
     pub fn set_output_color_profile(&mut self, profile: JxlColorProfile) -> Result<()> {
@@ -80,19 +133,75 @@ impl JxlDecoderInner {
 
     pub fn frame_header(&self) -> Option<JxlFrameHeader> {
         let frame_header = self.codestream_parser.frame.as_ref()?.header();
-        // The render pipeline always adds ExtendToImageDimensionsStage which extends
-        // frames to the full image size. So the output size is always the image size,
-        // not the frame's upsampled size.
-        let size = self.codestream_parser.basic_info.as_ref()?.size;
-        Some(JxlFrameHeader {
-            name: frame_header.name.clone(),
-            duration: self
-                .codestream_parser
-                .animation
-                .as_ref()
-                .map(|anim| frame_header.duration(anim)),
-            size,
-        })
+        self.codestream_parser.api_frame_header(frame_header)
+    }
+
+    /// Returns whether the current frame is saved for use by later frames, in which case it has
+    /// to be decoded even if it is not displayed.
+    pub fn frame_is_referenced(&self) -> Option<bool> {
+        Some(
+            self.codestream_parser
+                .frame
+                .as_ref()?
+                .header()
+                .can_be_referenced,
+        )
+    }
+
+    /// Returns the offset of the start of the current frame in the codestream.
+    pub fn frame_codestream_offset(&self) -> Option<u64> {
+        self.codestream_parser
+            .frame
+            .is_some()
+            .then_some(self.codestream_parser.frame_codestream_offset)
+    }
+
+    /// Reads the headers of all the remaining frames without decoding them, and returns them once
+    /// the end of the codestream is reached.
+    pub fn scan_frames(
+        &mut self,
+        input: &mut dyn JxlBitstreamInput,
+    ) -> Result<ProcessingResult<Vec<JxlScannedFrame>, ()>> {
+        let result = self
+            .codestream_parser
+            .scan_frames(&mut self.box_parser, input, &self.options);
+        ProcessingResult::new(
+            result.map(|()| std::mem::take(&mut self.codestream_parser.scanned_frames)),
+        )
+    }
+
+    /// Moves to the last keyframe of the frame index that is displayed no later than the
+    /// displayed frame with index `frame`. If `input` is given, boxes after the codestream are
+    /// searched for a frame index too.
+    ///
+    /// Returns the number of displayed frames before the keyframe, and the file offset from which
+    /// input has to be provided next. Returns `None`, leaving the decoder unchanged, if there is no
+    /// such keyframe or the decoder cannot move to it, i.e. if a frame is being decoded or the last
+    /// frame was reached.
+    pub fn seek_to_keyframe(
+        &mut self,
+        mut input: Option<&mut dyn JxlSeekableInput>,
+        frame: usize,
+    ) -> Result<Option<(usize, u64)>> {
+        if !self.codestream_parser.can_jump_to_keyframe() {
+            return Ok(None);
+        }
+        if let Some(input) = input.as_deref_mut() {
+            self.find_frame_index(input)?;
+        }
+        let Some(keyframe) = self
+            .frame_index()
+            .and_then(|index| index.keyframe_before(frame))
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        let Some(file_offset) = self.box_parser.jump_to(input, keyframe.codestream_offset)? else {
+            return Ok(None);
+        };
+        self.codestream_parser
+            .jump_to_keyframe(keyframe.frame_number);
+        Ok(Some((keyframe.frame_number, file_offset)))
     }
 
     /// Number of passes we have full data for.
@@ -101,6 +210,17 @@ impl JxlDecoderInner {
         Some(self.codestream_parser.num_completed_passes())
     }
 
+    /// Returns whether a preview of the current frame can be rendered from its LF image.
+    pub fn has_lf_preview(&self) -> bool {
+        self.codestream_parser.has_lf_preview()
+    }
+
+    /// Restricts decoding of the current frame to the sections needed for `request`, and returns
+    /// the byte ranges of the file that hold them.
+    pub fn plan_sections(&mut self, request: &JxlSectionRequest) -> Result<Vec<Range<u64>>> {
+        self.codestream_parser.plan_sections(request)
+    }
+
     /// Fully resets the decoder to its initial state.
     ///
     /// This clears all state including pixel_format. For animation loop playback,
@@ -10,7 +10,9 @@ use std::{
 
 use crate::error::Result;
 
-use crate::api::{JxlBitstreamInput, JxlDecoderInner, JxlOutputBuffer, ProcessingResult};
+use crate::api::{
+    JxlBitstreamInput, JxlDecoderInner, JxlOutputBuffer, JxlOutputSink, ProcessingResult,
+};
 
 // General implementation strategy:
 // - Anything that is not a section is read into a small buffer.
@@ -117,7 +119,7 @@ impl JxlDecoderInner {
     pub fn process(
         &mut self,
         input: &mut dyn JxlBitstreamInput,
-        buffers: Option<&mut [JxlOutputBuffer]>,
+        buffers: Option<&mut [JxlOutputSink]>,
     ) -> Result<ProcessingResult<(), ()>> {
         ProcessingResult::new(self.codestream_parser.process(
             &mut self.box_parser,
@@ -127,6 +129,17 @@ impl JxlDecoderInner {
         ))
     }
 
+    /// Renders a preview of the current frame from its LF image.
+    pub fn render_lf_preview(&mut self, buffers: &mut [JxlOutputSink]) -> Result<()> {
+        self.codestream_parser
+            .render_lf_preview(&self.options, buffers)
+    }
+
+    /// Renders the current frame from the sections received so far, and stops decoding.
+    pub fn finish_input(&mut self, buffers: &mut [JxlOutputSink]) -> Result<()> {
+        self.codestream_parser.finish_input(&self.options, buffers)
+    }
+
     /// Draws all the pixels we have data for.
     pub fn flush_pixels(&mut self, _buffers: &mut [JxlOutputBuffer]) -> Result<()> {
         todo!()
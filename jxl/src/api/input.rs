@@ -3,7 +3,10 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use std::io::{BufRead, BufReader, Error, IoSliceMut, Read, Seek, SeekFrom};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, IoSliceMut, Read, Seek, SeekFrom};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
 
 pub trait JxlBitstreamInput {
     /// Returns an estimate bound of the total number of bytes that can be read via `read`.
@@ -40,6 +43,32 @@ pub trait JxlBitstreamInput {
     fn unconsume(&mut self, _count: usize) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Returns this input as a [`JxlSeekableInput`], if it supports reading at arbitrary
+    /// offsets. The provided implementation returns `None`.
+    fn as_seekable(&mut self) -> Option<&mut dyn JxlSeekableInput> {
+        None
+    }
+
+    /// Returns this input as a [`JxlSharedInput`], if the decoder can keep references to its
+    /// bytes instead of copying them. The provided implementation returns `None`.
+    fn as_shared(&mut self) -> Option<&mut JxlSharedInput> {
+        None
+    }
+}
+
+/// Input that can also be read at arbitrary offsets of the file.
+///
+/// With such an input, the decoder reads the sections of a frame directly from their offsets in
+/// the order in which they are decoded, instead of buffering the sections that the file stores
+/// before the ones they depend on. Sections that are not needed (see
+/// [`JxlDecoder::plan_sections`](crate::api::JxlDecoder::plan_sections)) are not read at all.
+pub trait JxlSeekableInput: JxlBitstreamInput {
+    /// Fills `buf` with the bytes of the file starting at `offset`, without changing the
+    /// position of `read`. Offsets are relative to the first byte that the decoder read from this
+    /// input. Returns the number of bytes written, which is less than the size of `buf` only if
+    /// the following bytes are not available (yet).
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, Error>;
 }
 
 impl JxlBitstreamInput for &[u8] {
@@ -80,3 +109,403 @@ impl<R: Read + Seek> JxlBitstreamInput for BufReader<R> {
         self.seek_relative(-(count as i64))
     }
 }
+
+/// Input for files that are read from a seekable reader, such as a [`File`](std::fs::File).
+///
+/// Unlike a [`BufReader`], which is read sequentially, this input also lets the decoder read
+/// sections at their offsets (see [`JxlSeekableInput`]). The file starts at the position of the
+/// reader when the input is created, and all offsets, including the ones passed to
+/// [`Seek`], are relative to it.
+pub struct JxlSeekableReader<R: Read + Seek> {
+    inner: R,
+    // Position of the start of the file in `inner`.
+    base: u64,
+    // File offset of the next byte that will be read.
+    position: u64,
+    // File offset that `inner` is at, if known.
+    inner_position: Option<u64>,
+    buf: Box<[u8]>,
+    // File offset of the first byte of `buf`, and number of valid bytes in it.
+    buf_start: u64,
+    buf_len: usize,
+}
+
+impl<R: Read + Seek> JxlSeekableReader<R> {
+    const BUF_SIZE: usize = 8 * 1024;
+
+    /// Creates an input for the file that starts at the current position of `inner`.
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let base = inner.stream_position()?;
+        Ok(Self {
+            inner,
+            base,
+            position: 0,
+            inner_position: Some(0),
+            buf: vec![0; Self::BUF_SIZE].into_boxed_slice(),
+            buf_start: 0,
+            buf_len: 0,
+        })
+    }
+
+    /// Returns the file offset of the next byte that will be read.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the underlying reader, at an unspecified position.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Returns the buffered bytes from `offset` up to the end of the buffer.
+    fn buffered_at(&self, offset: u64) -> &[u8] {
+        match offset.checked_sub(self.buf_start) {
+            Some(pos) if pos < self.buf_len as u64 => &self.buf[pos as usize..self.buf_len],
+            _ => &[],
+        }
+    }
+
+    /// Returns the size of the file, leaving `inner` at its end.
+    fn len(&mut self) -> Result<u64, Error> {
+        self.inner_position = None;
+        let len = self.inner.seek(SeekFrom::End(0))?.saturating_sub(self.base);
+        self.inner_position = Some(len);
+        Ok(len)
+    }
+
+    /// Reads the bytes of the file at `offset` into `buf` with a single read of `inner`, which is
+    /// only moved if it is not at `offset` already.
+    fn read_inner(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.inner_position != Some(offset) {
+            self.inner_position = None;
+            self.inner.seek(SeekFrom::Start(self.base + offset))?;
+            self.inner_position = Some(offset);
+        }
+        loop {
+            match self.inner.read(buf) {
+                Ok(num) => {
+                    self.inner_position = Some(offset + num as u64);
+                    return Ok(num);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.inner_position = None;
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Reads the bytes at the current position into `buf`, returning the number of bytes that were
+    /// read, or 0 at the end of the file.
+    fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.buffered_at(self.position).is_empty() {
+            if buf.len() >= self.buf.len() {
+                // Large reads bypass the buffer.
+                let num = self.read_inner(self.position, buf)?;
+                self.position += num as u64;
+                return Ok(num);
+            }
+            let mut own_buf = std::mem::take(&mut self.buf);
+            let num = self.read_inner(self.position, &mut own_buf);
+            self.buf = own_buf;
+            // At the end of the file, the buffer is kept for `read_at`.
+            match num? {
+                0 => return Ok(0),
+                num => (self.buf_start, self.buf_len) = (self.position, num),
+            }
+        }
+        let num = Read::read(&mut self.buffered_at(self.position), buf)?;
+        self.position += num as u64;
+        Ok(num)
+    }
+}
+
+impl<R: Read + Seek> JxlBitstreamInput for JxlSeekableReader<R> {
+    fn available_bytes(&mut self) -> Result<usize, Error> {
+        Ok(self.len()?.saturating_sub(self.position) as usize)
+    }
+
+    fn read(&mut self, bufs: &mut [IoSliceMut]) -> Result<usize, Error> {
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            let mut buf = &mut buf[..];
+            while !buf.is_empty() {
+                let num = self.read_some(buf)?;
+                if num == 0 {
+                    return Ok(total);
+                }
+                total += num;
+                buf = &mut buf[num..];
+            }
+        }
+        Ok(total)
+    }
+
+    fn skip(&mut self, bytes: usize) -> Result<usize, Error> {
+        // Like seeking, skipping past the end of the file is allowed.
+        self.position += bytes as u64;
+        Ok(bytes)
+    }
+
+    fn unconsume(&mut self, count: usize) -> Result<(), Error> {
+        self.position -= count as u64;
+        Ok(())
+    }
+
+    fn as_seekable(&mut self) -> Option<&mut dyn JxlSeekableInput> {
+        Some(self)
+    }
+}
+
+impl<R: Read + Seek> Seek for JxlSeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let len = match pos {
+            SeekFrom::End(_) => self.len()?,
+            _ => 0,
+        };
+        self.position = seek_position(pos, self.position, len)?;
+        Ok(self.position)
+    }
+}
+
+impl<R: Read + Seek> JxlSeekableInput for JxlSeekableReader<R> {
+    fn read_at(&mut self, offset: u64, mut buf: &mut [u8]) -> Result<usize, Error> {
+        // Buffered bytes are used as they are, and the buffer is kept for `read`.
+        let mut num = Read::read(&mut self.buffered_at(offset), buf)?;
+        buf = &mut buf[num..];
+        while !buf.is_empty() {
+            let read = self.read_inner(offset + num as u64, buf)?;
+            if read == 0 {
+                break;
+            }
+            num += read;
+            buf = &mut buf[read..];
+        }
+        Ok(num)
+    }
+}
+
+/// Input for files that are fetched piecewise, e.g. with HTTP range requests.
+///
+/// Byte ranges of the file can be filled in any order with [`fill`](Self::fill). Reads stop at
+/// the first byte that was not filled yet, while skips can move past missing data, so that the
+/// decoder can jump over the sections that
+/// [`JxlDecoder::plan_sections`](crate::api::JxlDecoder::plan_sections) left out.
+pub struct JxlRangeInput {
+    file_size: u64,
+    position: u64,
+    // Filled ranges by file offset. Overlapping or adjacent ranges are merged.
+    chunks: BTreeMap<u64, Vec<u8>>,
+}
+
+impl JxlRangeInput {
+    /// Creates an input for a file of `file_size` bytes, none of which are available yet.
+    pub fn new(file_size: u64) -> Self {
+        Self {
+            file_size,
+            position: 0,
+            chunks: BTreeMap::new(),
+        }
+    }
+
+    /// Makes `data` available as the contents of the file starting at `offset`.
+    pub fn fill(&mut self, offset: u64, data: &[u8]) {
+        let end = offset + data.len() as u64;
+        let mut start = offset;
+        let mut merged_end = end;
+        let overlapping: Vec<u64> = self
+            .chunks
+            .range(..=end)
+            .filter(|(s, d)| *s + d.len() as u64 >= offset)
+            .map(|(s, _)| *s)
+            .collect();
+        let mut merged = vec![];
+        for s in overlapping.iter() {
+            start = start.min(*s);
+            merged_end = merged_end.max(s + self.chunks[s].len() as u64);
+        }
+        merged.resize((merged_end - start) as usize, 0);
+        for s in overlapping {
+            let chunk = self.chunks.remove(&s).unwrap();
+            let pos = (s - start) as usize;
+            merged[pos..pos + chunk.len()].copy_from_slice(&chunk);
+        }
+        let pos = (offset - start) as usize;
+        merged[pos..pos + data.len()].copy_from_slice(data);
+        self.chunks.insert(start, merged);
+    }
+
+    /// Returns the file offset of the next byte that will be read.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the filled bytes from the current position up to the next missing byte.
+    fn available(&self) -> &[u8] {
+        match self.chunks.range(..=self.position).next_back() {
+            Some((start, data)) if start + data.len() as u64 > self.position => {
+                &data[(self.position - start) as usize..]
+            }
+            _ => &[],
+        }
+    }
+}
+
+impl JxlBitstreamInput for JxlRangeInput {
+    fn available_bytes(&mut self) -> Result<usize, Error> {
+        Ok(self.available().len())
+    }
+
+    fn read(&mut self, bufs: &mut [IoSliceMut]) -> Result<usize, Error> {
+        let num = self.available().read_vectored(bufs)?;
+        self.position += num as u64;
+        Ok(num)
+    }
+
+    fn skip(&mut self, bytes: usize) -> Result<usize, Error> {
+        let num = (bytes as u64).min(self.file_size.saturating_sub(self.position));
+        self.position += num;
+        Ok(num as usize)
+    }
+
+    fn unconsume(&mut self, count: usize) -> Result<(), Error> {
+        self.position -= count as u64;
+        Ok(())
+    }
+
+    fn as_seekable(&mut self) -> Option<&mut dyn JxlSeekableInput> {
+        Some(self)
+    }
+}
+
+impl Seek for JxlRangeInput {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        self.position = seek_position(pos, self.position, self.file_size)?;
+        Ok(self.position)
+    }
+}
+
+impl JxlSeekableInput for JxlRangeInput {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let position = std::mem::replace(&mut self.position, offset);
+        let num = Read::read(&mut self.available(), buf)?;
+        self.position = position;
+        Ok(num)
+    }
+}
+
+/// Input for files that are entirely in memory, e.g. memory-mapped files or downloaded blobs.
+///
+/// The decoder keeps references to the sections of the file instead of copying them into its
+/// own buffers, so the pixel data of a frame is only ever stored once.
+pub struct JxlSharedInput {
+    data: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    position: usize,
+}
+
+impl JxlSharedInput {
+    /// Creates an input over the bytes of `data`, which holds the whole file.
+    pub fn new(data: impl AsRef<[u8]> + Send + Sync + 'static) -> Self {
+        Self {
+            data: Arc::new(data),
+            position: 0,
+        }
+    }
+
+    /// Returns the file offset of the next byte that will be read.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns a reference to the `len` bytes at `offset`, or `None` if they are past the end of
+    /// the file.
+    pub(crate) fn bytes_at(&self, offset: u64, len: usize) -> Option<SharedBytes> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(len)?;
+        (end <= self.bytes().len()).then(|| SharedBytes {
+            data: self.data.clone(),
+            range: start..end,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        (*self.data).as_ref()
+    }
+
+    fn remaining(&self) -> &[u8] {
+        &self.bytes()[self.position.min(self.bytes().len())..]
+    }
+}
+
+impl JxlBitstreamInput for JxlSharedInput {
+    fn available_bytes(&mut self) -> Result<usize, Error> {
+        Ok(self.remaining().len())
+    }
+
+    fn read(&mut self, bufs: &mut [IoSliceMut]) -> Result<usize, Error> {
+        let num = self.remaining().read_vectored(bufs)?;
+        self.position += num;
+        Ok(num)
+    }
+
+    fn skip(&mut self, bytes: usize) -> Result<usize, Error> {
+        let num = bytes.min(self.remaining().len());
+        self.position += num;
+        Ok(num)
+    }
+
+    fn unconsume(&mut self, count: usize) -> Result<(), Error> {
+        self.position -= count;
+        Ok(())
+    }
+
+    fn as_seekable(&mut self) -> Option<&mut dyn JxlSeekableInput> {
+        Some(self)
+    }
+
+    fn as_shared(&mut self) -> Option<&mut JxlSharedInput> {
+        Some(self)
+    }
+}
+
+impl Seek for JxlSharedInput {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let position = seek_position(pos, self.position as u64, self.bytes().len() as u64)?;
+        self.position = usize::try_from(position)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid seek position"))?;
+        Ok(position)
+    }
+}
+
+impl JxlSeekableInput for JxlSharedInput {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = self.bytes().len();
+        let start = usize::try_from(offset).map_or(len, |x| x.min(len));
+        Read::read(&mut &self.bytes()[start..], buf)
+    }
+}
+
+/// Bytes of a [`JxlSharedInput`] that the decoder holds on to.
+pub(crate) struct SharedBytes {
+    data: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    range: Range<usize>,
+}
+
+impl Deref for SharedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &(*self.data).as_ref()[self.range.clone()]
+    }
+}
+
+/// Computes the position that `pos` refers to in a file of `len` bytes.
+fn seek_position(pos: SeekFrom, current: u64, len: u64) -> Result<u64, Error> {
+    match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(delta) => len.checked_add_signed(delta),
+        SeekFrom::Current(delta) => current.checked_add_signed(delta),
+    }
+    .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid seek position"))
+}
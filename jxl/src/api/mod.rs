@@ -5,22 +5,37 @@
 
 // #![warn(missing_docs)]
 
+mod animation;
+mod buffer_pool;
 mod color;
+mod custom_stage;
 mod data_types;
 mod decoder;
+mod frame_index;
+mod gain_map;
 mod inner;
 mod input;
 mod options;
+mod output;
 mod signature;
+mod stage_tap;
 
 pub use crate::image::JxlOutputBuffer;
+pub use animation::*;
+pub use buffer_pool::*;
 pub use color::*;
+pub use custom_stage::*;
 pub use data_types::*;
 pub use decoder::*;
+pub use frame_index::*;
+pub use gain_map::*;
 pub use inner::*;
 pub use input::*;
+pub use jxl_simd::SimdLevel;
 pub use options::*;
+pub use output::*;
 pub use signature::*;
+pub use stage_tap::*;
 
 use crate::headers::image_metadata::Orientation;
 
@@ -3,7 +3,12 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::api::JxlCms;
+use std::sync::Arc;
+
+use crate::api::{
+    JxlBufferPool, JxlCms, JxlCustomStage, JxlCustomStageSpace, JxlProgressEvent, JxlStageTap,
+    SimdLevel,
+};
 
 pub enum JxlProgressiveMode {
     /// Renders all pixels in every call to Process.
@@ -14,6 +19,55 @@ pub enum JxlProgressiveMode {
     FullFrame,
 }
 
+/// How the system gamma of the HLG OOTF is derived from the display peak luminance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum JxlHlgSystemGamma {
+    /// `1.2 * 1.111^log2(Lw / 1000)`, the extended range formula of BT.2390. This is what
+    /// libjxl uses.
+    #[default]
+    Extended,
+    /// `1.2 + 0.42 * log10(Lw / 1000)`, the variable system gamma formula of BT.2100, adjusted
+    /// for a viewing environment with an ambient luminance of `surround_luminance` nits (5 nits
+    /// being the reference environment).
+    Bt2100 { surround_luminance: f32 },
+    /// Always use the given system gamma, regardless of the display peak luminance.
+    Fixed(f32),
+}
+
+/// Parameters for rendering images with a HLG transfer function to display-referred linear
+/// output. These have no effect on images with other transfer functions, or if the output is
+/// not linear.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JxlHlgRendering {
+    /// Nominal peak luminance of the target display, in nits. If `None`, the image's
+    /// `intensity_target` is used.
+    pub display_peak_luminance: Option<f32>,
+    pub system_gamma: JxlHlgSystemGamma,
+    /// Luminance, in nits, represented by a linear sample value of 1.0. If `None`, 1.0 represents
+    /// the display peak luminance. For example, 203 maps the HLG reference white of BT.2408 on a
+    /// 1000-nit display to 1.0.
+    pub reference_white: Option<f32>,
+}
+
+/// Filter used to resample decoded images.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JxlResamplingFilter {
+    /// Lanczos filter with 3 lobes. Sharp, but can cause some ringing around edges.
+    #[default]
+    Lanczos3,
+    /// Mitchell-Netravali cubic filter with `B = C = 1/3`. Softer than `Lanczos3`, with less
+    /// ringing.
+    Mitchell,
+}
+
+/// Resampling of decoded images to an arbitrary size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JxlResampling {
+    /// Size of the output, after orientation is applied.
+    pub size: (usize, usize),
+    pub filter: JxlResamplingFilter,
+}
+
 #[non_exhaustive]
 pub struct JxlDecoderOptions {
     pub adjust_orientation: bool,
@@ -24,6 +78,9 @@ pub struct JxlDecoderOptions {
     pub progressive_mode: JxlProgressiveMode,
     pub xyb_output_linear: bool,
     pub enable_output: bool,
+    /// Color management system used for conversions that cannot be done internally. If
+    /// set, CMYK images are rendered to sRGB by default (using the `Black` extra channel); set
+    /// the output color profile to the embedded one to get CMYK data instead.
     pub cms: Option<Box<dyn JxlCms>>,
     /// Fail decoding images with more than this number of pixels, or with frames with
     /// more than this number of pixels. The limit counts the product of pixels and
@@ -41,6 +98,32 @@ pub struct JxlDecoderOptions {
     /// This produces premultiplied alpha output, which is useful for compositing.
     /// Default: false (output straight alpha)
     pub premultiply_output: bool,
+    /// Target display parameters used when producing linear output for HLG images.
+    pub hlg_rendering: JxlHlgRendering,
+    /// Stages that are run on the color and extra channels of visible frames, in order, in the
+    /// given color space.
+    pub custom_stages: Vec<(JxlCustomStageSpace, Arc<dyn JxlCustomStage>)>,
+    /// If set, visible frames are resampled in linear light, and output buffers must have the
+    /// resampled size. Resampling is done as groups are rendered, like the other stages. Requires
+    /// color encodings for the image and the output, and frames that cover the whole image.
+    pub resampling: Option<JxlResampling>,
+    /// If set, and the file has a gain map stored before the codestream, visible frames are
+    /// rendered for a display with this HDR headroom (log2 of the ratio between peak and SDR
+    /// white) by applying the gain map in linear light. Requires color encodings for the image
+    /// and the output.
+    pub gain_map_headroom: Option<f32>,
+    /// Captures the intermediate results of the matching render pipeline stages, for debugging.
+    pub stage_taps: Vec<JxlStageTap>,
+    /// If set, buffers are taken from this pool and given back to it once a frame is done with
+    /// them, instead of being allocated and freed for each frame.
+    pub buffer_pool: Option<JxlBufferPool>,
+    /// If set, the render pipeline runs with the most capable available instruction set that is
+    /// at most as capable as this one. Mostly useful for testing and benchmarking.
+    pub max_simd_level: Option<SimdLevel>,
+    /// Called as the sections of the frames returned to the user are decoded, e.g. to show
+    /// progress or to decide when to render intermediate results. Sections that are missing or
+    /// skipped are not reported.
+    pub progress_callback: Option<Box<dyn Fn(JxlProgressEvent)>>,
 }
 
 impl Default for JxlDecoderOptions {
@@ -58,6 +141,22 @@ impl Default for JxlDecoderOptions {
             pixel_limit: None,
             high_precision: false,
             premultiply_output: false,
+            hlg_rendering: JxlHlgRendering::default(),
+            custom_stages: vec![],
+            resampling: None,
+            gain_map_headroom: None,
+            stage_taps: vec![],
+            buffer_pool: None,
+            max_simd_level: None,
+            progress_callback: None,
         }
     }
 }
+
+impl JxlDecoderOptions {
+    /// Returns the instruction set that render pipelines run with.
+    pub(crate) fn simd_level(&self) -> SimdLevel {
+        self.max_simd_level
+            .map_or_else(SimdLevel::best, SimdLevel::best_up_to)
+    }
+}
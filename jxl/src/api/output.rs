@@ -0,0 +1,65 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use super::JxlOutputBuffer;
+
+/// Callback that receives `(x, y, num_pixels, pixels)`, see [`JxlOutputSink`].
+pub type JxlRowCallback<'a> = dyn FnMut(usize, usize, usize, &[u8]) + 'a;
+
+/// Destination for the pixels of one of the outputs of the decoder, i.e. the color channels or
+/// one of the extra channels.
+///
+/// Callbacks receive `(x, y, num_pixels, pixels)`: `pixels` contains `num_pixels` interleaved
+/// pixels of row `y` of the output, starting at column `x`, in the requested data format and with
+/// the orientation already applied. Rows are passed as soon as they are rendered, in no particular
+/// order, and the same pixels might be passed more than once (for example, when more passes of a
+/// progressive image become available); later calls supersede earlier ones.
+pub enum JxlOutputSink<'a> {
+    /// Pixels are written to a buffer that covers the whole output.
+    Buffer(JxlOutputBuffer<'a>),
+    /// Pixels are passed to a callback, which is only called from the thread that calls into the
+    /// decoder.
+    Callback(&'a mut JxlRowCallback<'a>),
+}
+
+impl<'a> JxlOutputSink<'a> {
+    pub(crate) fn reborrow<'b>(&'b mut self) -> JxlOutputSink<'b> {
+        match self {
+            JxlOutputSink::Buffer(buf) => JxlOutputSink::Buffer(JxlOutputBuffer::reborrow(buf)),
+            JxlOutputSink::Callback(callback) => JxlOutputSink::Callback(&mut **callback),
+        }
+    }
+
+    /// Returns the buffer that pixels are written to, if any.
+    pub(crate) fn buffer(&self) -> Option<&JxlOutputBuffer<'a>> {
+        match self {
+            JxlOutputSink::Buffer(buf) => Some(buf),
+            _ => None,
+        }
+    }
+
+    /// Passes `num_pixels` pixels of row `y`, starting at column `x`, to the callback.
+    pub(crate) fn call(&mut self, x: usize, y: usize, num_pixels: usize, pixels: &[u8]) {
+        match self {
+            JxlOutputSink::Buffer(_) => unreachable!("output buffers do not have a callback"),
+            JxlOutputSink::Callback(callback) => callback(x, y, num_pixels, pixels),
+        }
+    }
+}
+
+impl<'a> From<JxlOutputBuffer<'a>> for JxlOutputSink<'a> {
+    fn from(buf: JxlOutputBuffer<'a>) -> Self {
+        JxlOutputSink::Buffer(buf)
+    }
+}
+
+impl std::fmt::Debug for JxlOutputSink<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JxlOutputSink::Buffer(buf) => f.debug_tuple("Buffer").field(buf).finish(),
+            JxlOutputSink::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
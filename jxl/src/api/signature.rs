@@ -50,6 +50,35 @@ pub(crate) fn check_signature_internal(file_prefix: &[u8]) -> Result<Option<JxlS
     Ok(None)
 }
 
+/// Returns the contents of the first box of type `ty` of a complete JPEG XL container, if any.
+pub(crate) fn find_container_box<'a>(mut data: &'a [u8], ty: &[u8; 4]) -> Result<Option<&'a [u8]>> {
+    if !data.starts_with(&CONTAINER_SIGNATURE) {
+        return Ok(None);
+    }
+    data = &data[CONTAINER_SIGNATURE.len()..];
+    while !data.is_empty() {
+        let (header_size, box_size) = match *data {
+            [0, 0, 0, 1, _, _, _, _, s0, s1, s2, s3, s4, s5, s6, s7, ..] => {
+                (16, u64::from_be_bytes([s0, s1, s2, s3, s4, s5, s6, s7]))
+            }
+            [s0, s1, s2, s3, _, _, _, _, ..] => (8, u32::from_be_bytes([s0, s1, s2, s3]) as u64),
+            _ => return Err(Error::InvalidBox),
+        };
+        let content_end = if box_size == 0 {
+            data.len()
+        } else if box_size < header_size as u64 || box_size > data.len() as u64 {
+            return Err(Error::InvalidBox);
+        } else {
+            box_size as usize
+        };
+        if &data[4..8] == ty {
+            return Ok(Some(&data[header_size..content_end]));
+        }
+        data = &data[content_end..];
+    }
+    Ok(None)
+}
+
 /// Checks if the given buffer starts with a valid JPEG XL signature.
 ///
 /// # Returns
@@ -0,0 +1,102 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::sync::Arc;
+
+use crate::{image::Image, util::AtomicRefCell};
+
+/// Values of the channels produced by the render pipeline stages that match a [`JxlStageTap`],
+/// for one rendered frame.
+#[derive(Debug)]
+pub struct JxlStageCapture {
+    /// Descriptions of the matching stages, in pipeline order.
+    pub stages: Vec<String>,
+    /// For each channel of the frame (the color channels followed by the extra channels), the
+    /// output of the last matching stage that produces it, if any. Channels keep the resolution
+    /// that they have at that point of the pipeline, and are converted to `f32` if needed.
+    pub channels: Vec<Option<Image<f32>>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct CaptureState {
+    pub(crate) stages: Vec<String>,
+    // For each channel, the index of the capture stage that writes it, and the image it writes to.
+    pub(crate) channels: Vec<Option<(usize, Image<f32>)>>,
+}
+
+/// Captures the intermediate results of the render pipeline stages whose description matches
+/// `name`, for debugging.
+///
+/// Stage descriptions are their `Display` output (e.g. "EPF stage 2 with sigma scale: ...").
+/// A stage matches if its description starts with `name` when both are lowercased and reduced
+/// to their letters and digits, ignoring the word "stage": for example, `epf2` matches the last
+/// EPF stage, `gaborish` matches the Gaborish stages of all channels, and `xyb` matches the
+/// conversion from XYB.
+///
+/// Clones of a tap share their captures.
+#[derive(Clone, Debug)]
+pub struct JxlStageTap {
+    name: String,
+    pub(crate) captures: Arc<AtomicRefCell<Vec<CaptureState>>>,
+}
+
+fn normalize(description: &str) -> String {
+    description
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .map(|word| word.to_ascii_lowercase())
+        .filter(|word| word != "stage")
+        .collect()
+}
+
+impl JxlStageTap {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            captures: Arc::new(AtomicRefCell::new(vec![])),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn matches(&self, stage_description: &str) -> bool {
+        let name = normalize(&self.name);
+        !name.is_empty() && normalize(stage_description).starts_with(&name)
+    }
+
+    /// Returns the captures made so far, one for each rendered frame that has matching stages
+    /// (including frames that are only used as references for other frames), in decoding order.
+    pub fn take_captures(&self) -> Vec<JxlStageCapture> {
+        std::mem::take(&mut *self.captures.borrow_mut())
+            .into_iter()
+            .map(|capture| JxlStageCapture {
+                stages: capture.stages,
+                channels: capture
+                    .channels
+                    .into_iter()
+                    .map(|c| c.map(|(_, image)| image))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matching() {
+        let epf2 = JxlStageTap::new("epf2");
+        assert!(epf2.matches("EPF stage 2 with sigma scale: 1.5, border_sad_mul: 2"));
+        assert!(!epf2.matches("EPF stage 1 with sigma scale: 1.5, border_sad_mul: 2"));
+        let gaborish = JxlStageTap::new("Gaborish");
+        assert!(gaborish.matches("Gaborish filter for channel 1"));
+        assert!(!gaborish.matches("EPF stage 0 with sigma scale: 1.5, border_sad_mul: 2"));
+        assert!(JxlStageTap::new("xyb").matches("XYB to linear for channel [0,1,2]"));
+        assert!(!JxlStageTap::new("").matches("patches"));
+    }
+}
@@ -389,6 +389,25 @@ fn hlg_ootf_inner(exp: f32, [lr, lg, lb]: [f32; 3], [sr, sg, sb]: [&mut [f32]; 3
     }
 }
 
+/// Computes the system gamma of the HLG OOTF for a display with nominal peak luminance
+/// `intensity_display` nits, using the extended range formula of BT.2390:
+/// `1.2 * 1.111^log2(intensity_display / 1000)`. This is the formula used by libjxl.
+pub fn hlg_system_gamma(intensity_display: f32) -> f32 {
+    1.2f32 * 1.111f32.powf((intensity_display / 1e3).log2())
+}
+
+/// Computes the system gamma of the HLG OOTF for a display with nominal peak luminance
+/// `intensity_display` nits, using the variable system gamma formula of BT.2100:
+/// `1.2 + 0.42 * log10(intensity_display / 1000)`.
+///
+/// The result is further adjusted for a viewing environment with ambient luminance
+/// `surround_luminance` nits by multiplying it by `0.98^log2(surround_luminance / 5)`, as
+/// described in BT.2390; the reference viewing environment of BT.2100 has a surround of 5 nits.
+pub fn hlg_system_gamma_bt2100(intensity_display: f32, surround_luminance: f32) -> f32 {
+    let gamma = 1.2f32 + 0.42 * (intensity_display / 1e3).log10();
+    gamma * 0.98f32.powf((surround_luminance / 5.0).log2())
+}
+
 /// Converts scene-referred linear samples to display-referred linear samples using HLG OOTF.
 ///
 /// This version uses double precision arithmetic internally.
@@ -424,9 +443,11 @@ pub fn hlg_scene_to_display(
     luminance_rgb: [f32; 3],
     samples_rgb: [&mut [f32]; 3],
 ) {
-    let system_gamma = 1.2f32 * 1.111f32.powf((intensity_display / 1e3).log2());
-    let gamma_sub_one = system_gamma - 1.0;
-    hlg_ootf_inner(gamma_sub_one, luminance_rgb, samples_rgb);
+    hlg_ootf(
+        hlg_system_gamma(intensity_display),
+        luminance_rgb,
+        samples_rgb,
+    );
 }
 
 /// Converts display-referred linear samples to scene-referred linear samples using HLG inverse
@@ -438,7 +459,27 @@ pub fn hlg_display_to_scene(
     luminance_rgb: [f32; 3],
     samples_rgb: [&mut [f32]; 3],
 ) {
-    let system_gamma = 1.2f32 * 1.111f32.powf((intensity_display / 1e3).log2());
+    hlg_inverse_ootf(
+        hlg_system_gamma(intensity_display),
+        luminance_rgb,
+        samples_rgb,
+    );
+}
+
+/// Converts scene-referred linear samples to display-referred linear samples using HLG OOTF
+/// with the given system gamma. Display-referred samples are relative to the display peak
+/// luminance.
+///
+/// This version uses `fast_powf` to compute power function.
+pub fn hlg_ootf(system_gamma: f32, luminance_rgb: [f32; 3], samples_rgb: [&mut [f32]; 3]) {
+    hlg_ootf_inner(system_gamma - 1.0, luminance_rgb, samples_rgb);
+}
+
+/// Converts display-referred linear samples to scene-referred linear samples using HLG inverse
+/// OOTF with the given system gamma. Inverse of `hlg_ootf`.
+///
+/// This version uses `fast_powf` to compute power function.
+pub fn hlg_inverse_ootf(system_gamma: f32, luminance_rgb: [f32; 3], samples_rgb: [&mut [f32]; 3]) {
     let one_sub_gamma = 1.0 - system_gamma;
     hlg_ootf_inner(one_sub_gamma / system_gamma, luminance_rgb, samples_rgb);
 }
@@ -775,6 +816,48 @@ mod test {
         });
     }
 
+    #[test]
+    fn hlg_system_gamma_reference_display() {
+        // Both formulas agree on a 1000 nits display in the reference environment.
+        assert!((hlg_system_gamma(1000.0) - 1.2).abs() < 1e-6);
+        assert!((hlg_system_gamma_bt2100(1000.0, 5.0) - 1.2).abs() < 1e-6);
+        // Values from Table 5 of BT.2390.
+        assert!((hlg_system_gamma_bt2100(400.0, 5.0) - 1.033).abs() < 1e-3);
+        assert!((hlg_system_gamma_bt2100(2000.0, 5.0) - 1.326).abs() < 1e-3);
+        // Brighter surround lowers system gamma.
+        assert!(hlg_system_gamma_bt2100(1000.0, 20.0) < 1.2);
+    }
+
+    #[test]
+    fn hlg_ootf_roundtrip_arb() {
+        arbtest::arbtest(|u| {
+            // Exponents close to 0 are skipped by the OOTF, so stay away from gamma ~1.
+            let system_gamma = 1.2 + u.int_in_range(0..=255)? as f32 / 1024.0;
+            let luminance_rgb = [0.2627, 0.678, 0.0593];
+
+            let r = u.int_in_range(1u32..=(1 << 24))? as f32 / (1 << 24) as f32;
+            let g = u.int_in_range(1u32..=(1 << 24))? as f32 / (1 << 24) as f32;
+            let b = u.int_in_range(1u32..=(1 << 24))? as f32 / (1 << 24) as f32;
+
+            let (mut sr, mut sg, mut sb) = (r, g, b);
+            let samples = [
+                std::slice::from_mut(&mut sr),
+                std::slice::from_mut(&mut sg),
+                std::slice::from_mut(&mut sb),
+            ];
+            hlg_ootf(system_gamma, luminance_rgb, samples);
+            let samples = [
+                std::slice::from_mut(&mut sr),
+                std::slice::from_mut(&mut sg),
+                std::slice::from_mut(&mut sb),
+            ];
+            hlg_inverse_ootf(system_gamma, luminance_rgb, samples);
+
+            assert_all_almost_abs_eq(&[sr, sg, sb], &[r, g, b], 1e-4);
+            Ok(())
+        });
+    }
+
     #[test]
     fn scene_to_hlg_arb() {
         arbtest::arbtest(|u| {
@@ -5,6 +5,7 @@
 //
 // Originally written for jxl-oxide.
 
+use crate::api::{JxlBufferPool, pooled_vec};
 use crate::bit_reader::BitReader;
 use crate::error::{Error, Result};
 
@@ -189,7 +190,12 @@ impl AnsHistogram {
         Ok(alphabet_size)
     }
 
-    fn build_alias_map(alphabet_size: usize, log_bucket_size: usize, dist: &[u16]) -> Vec<Bucket> {
+    fn build_alias_map(
+        alphabet_size: usize,
+        log_bucket_size: usize,
+        dist: &[u16],
+        mut out: Vec<Bucket>,
+    ) -> Vec<Bucket> {
         #[derive(Debug)]
         struct WorkingBucket {
             dist: u16,
@@ -235,33 +241,34 @@ impl AnsHistogram {
         // before building alias map.
         assert!(overfull.is_empty() && underfull.is_empty());
 
-        buckets
-            .iter()
-            .enumerate()
-            .map(|(idx, bucket)| {
-                if bucket.alias_cutoff == bucket_size {
-                    Bucket {
-                        dist: bucket.dist,
-                        alias_symbol: idx as u8,
-                        alias_offset: 0,
-                        alias_cutoff: 0,
-                        alias_dist_xor: 0,
-                    }
-                } else {
-                    Bucket {
-                        dist: bucket.dist,
-                        alias_symbol: bucket.alias_symbol as u8,
-                        alias_offset: bucket.alias_offset - bucket.alias_cutoff,
-                        alias_cutoff: bucket.alias_cutoff as u8,
-                        alias_dist_xor: bucket.dist ^ buckets[bucket.alias_symbol as usize].dist,
-                    }
+        out.extend(buckets.iter().enumerate().map(|(idx, bucket)| {
+            if bucket.alias_cutoff == bucket_size {
+                Bucket {
+                    dist: bucket.dist,
+                    alias_symbol: idx as u8,
+                    alias_offset: 0,
+                    alias_cutoff: 0,
+                    alias_dist_xor: 0,
                 }
-            })
-            .collect()
+            } else {
+                Bucket {
+                    dist: bucket.dist,
+                    alias_symbol: bucket.alias_symbol as u8,
+                    alias_offset: bucket.alias_offset - bucket.alias_cutoff,
+                    alias_cutoff: bucket.alias_cutoff as u8,
+                    alias_dist_xor: bucket.dist ^ buckets[bucket.alias_symbol as usize].dist,
+                }
+            }
+        }));
+        out
     }
 
     // log_alphabet_size: 5 + u(2)
-    pub fn decode(br: &mut BitReader, log_alpha_size: usize) -> Result<Self> {
+    pub fn decode(
+        br: &mut BitReader,
+        log_alpha_size: usize,
+        pool: Option<&JxlBufferPool>,
+    ) -> Result<Self> {
         debug_assert!((5..=8).contains(&log_alpha_size));
         let table_size = (1u16 << log_alpha_size) as usize;
         // 4 <= log_bucket_size <= 7
@@ -282,18 +289,15 @@ impl AnsHistogram {
             Self::decode_dist_complex(br, &mut dist)?
         };
 
+        let mut buckets = pooled_vec(pool, table_size);
         if let Some(single_sym_idx) = dist.iter().position(|&d| d == SUM_PROBS) {
-            let buckets = dist
-                .into_iter()
-                .enumerate()
-                .map(|(i, dist)| Bucket {
-                    dist,
-                    alias_symbol: single_sym_idx as u8,
-                    alias_offset: bucket_size * i as u16,
-                    alias_cutoff: 0,
-                    alias_dist_xor: dist ^ SUM_PROBS,
-                })
-                .collect();
+            buckets.extend(dist.into_iter().enumerate().map(|(i, dist)| Bucket {
+                dist,
+                alias_symbol: single_sym_idx as u8,
+                alias_offset: bucket_size * i as u16,
+                alias_cutoff: 0,
+                alias_dist_xor: dist ^ SUM_PROBS,
+            }));
             return Ok(Self {
                 buckets,
                 log_bucket_size,
@@ -303,7 +307,7 @@ impl AnsHistogram {
         }
 
         Ok(Self {
-            buckets: Self::build_alias_map(alphabet_size, log_bucket_size, &dist),
+            buckets: Self::build_alias_map(alphabet_size, log_bucket_size, &dist, buckets),
             log_bucket_size,
             bucket_mask,
             single_symbol: None,
@@ -387,14 +391,24 @@ impl AnsHistogram {
 #[derive(Debug)]
 pub struct AnsCodes {
     histograms: Vec<AnsHistogram>,
+    // Pool that the histogram tables are given back to on drop.
+    pool: Option<JxlBufferPool>,
 }
 
 impl AnsCodes {
-    pub fn decode(num: usize, log_alpha_size: usize, br: &mut BitReader) -> Result<AnsCodes> {
+    pub fn decode(
+        num: usize,
+        log_alpha_size: usize,
+        br: &mut BitReader,
+        pool: Option<&JxlBufferPool>,
+    ) -> Result<AnsCodes> {
         let histograms = (0..num)
-            .map(|_| AnsHistogram::decode(br, log_alpha_size))
+            .map(|_| AnsHistogram::decode(br, log_alpha_size, pool))
             .collect::<Result<_>>()?;
-        Ok(Self { histograms })
+        Ok(Self {
+            histograms,
+            pool: pool.cloned(),
+        })
     }
 
     pub fn single_symbol(&self, ctx: usize) -> Option<u32> {
@@ -402,6 +416,16 @@ impl AnsCodes {
     }
 }
 
+impl Drop for AnsCodes {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            for histogram in self.histograms.drain(..) {
+                pool.recycle_vec(histogram.buckets);
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct AnsReader(u32);
 
@@ -449,21 +473,21 @@ mod test {
     fn single_symbol() {
         // Single symbol of 20
         let mut br = BitReader::new(&[0b00100101, 0b01]);
-        let histogram = AnsHistogram::decode(&mut br, 5).unwrap();
+        let histogram = AnsHistogram::decode(&mut br, 5, None).unwrap();
         validate_buckets(&histogram.buckets);
         assert_eq!(histogram.buckets[20].dist, SUM_PROBS);
         assert_eq!(histogram.single_symbol, Some(20));
 
         // Single symbol of 32 (invalid)
         let mut br = BitReader::new(&[0b00101101, 0b000]);
-        assert!(AnsHistogram::decode(&mut br, 5).is_err());
+        assert!(AnsHistogram::decode(&mut br, 5, None).is_err());
     }
 
     #[test]
     fn two_symbols() {
         // two symbols of 10 and 20, where the prob of symbol 10 is 256
         let mut br = BitReader::new(&[0b10011111, 0b10010010, 0b00000000, 0b00010]);
-        let histogram = AnsHistogram::decode(&mut br, 5).unwrap();
+        let histogram = AnsHistogram::decode(&mut br, 5, None).unwrap();
         validate_buckets(&histogram.buckets);
         assert_eq!(histogram.buckets[10].dist, 256);
         assert_eq!(histogram.buckets[20].dist, SUM_PROBS - 256);
@@ -478,7 +502,7 @@ mod test {
             let mut br = BitReader::new(&buf);
 
             loop {
-                match AnsHistogram::decode(&mut br, 8) {
+                match AnsHistogram::decode(&mut br, 8, None) {
                     Ok(histogram) => validate_buckets(&histogram.buckets),
                     Err(Error::OutOfBounds(_)) => break,
                     Err(_) => {}
@@ -5,6 +5,7 @@
 
 use jxl_macros::UnconditionalCoder;
 
+use crate::api::JxlBufferPool;
 use crate::bit_reader::BitReader;
 use crate::entropy_coding::ans::*;
 use crate::entropy_coding::context_map::*;
@@ -496,6 +497,17 @@ impl SymbolReader {
 
 impl Histograms {
     pub fn decode(num_contexts: usize, br: &mut BitReader, allow_lz77: bool) -> Result<Histograms> {
+        Self::decode_with_pool(num_contexts, br, allow_lz77, None)
+    }
+
+    /// Like [`Self::decode`], but takes the memory for the decoding tables from `pool` and gives
+    /// it back when the histograms are dropped.
+    pub fn decode_with_pool(
+        num_contexts: usize,
+        br: &mut BitReader,
+        allow_lz77: bool,
+        pool: Option<&JxlBufferPool>,
+    ) -> Result<Histograms> {
         let lz77_params = Lz77Params::read_unconditional(&(), br, &Empty {})?;
         if !allow_lz77 && lz77_params.enabled {
             return Err(Error::Lz77Disallowed);
@@ -533,6 +545,7 @@ impl Histograms {
                 num_histograms as usize,
                 log_alpha_size,
                 br,
+                pool,
             )?)
         };
 
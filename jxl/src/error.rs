@@ -139,6 +139,8 @@ pub enum Error {
     PipelineChannelTypeMismatch(String, usize, DataTypeTag, DataTypeTag),
     #[error("Invalid stage {0} after extend stage")]
     PipelineInvalidStageAfterExtend(String),
+    #[error("Invalid stage {0} after resampling stage")]
+    PipelineInvalidStageAfterResample(String),
     #[error("Channel {0} was not used in the render pipeline")]
     PipelineChannelUnused(usize),
     #[error("Trying to copy rects of different size, src: {0}x{1} dst {2}x{3}")]
@@ -257,6 +259,32 @@ pub enum Error {
     IccTableSizeExceeded(usize),
     #[error("Invalid CMS configuration: requested ICC but no CMS is configured")]
     ICCOutputNoCMS,
+    #[error("Converting CMYK data to a different color profile requires a CMS")]
+    CmykOutputNoCMS,
+    #[error("Processing in linear light requires color encodings for the image and the output")]
+    LinearNoColorEncoding,
+    #[error("Resampling is not supported for frames that do not cover the whole image")]
+    ResamplingCroppedFrame,
+    #[error("Resampling needs a border of {0} pixels, which is more than supported")]
+    ResamplingBorderTooLarge(usize),
+    #[error("Image has a CMYK color profile but no black extra channel")]
+    CmykWithoutBlackChannel,
+    #[error("CMS produced {0} output channels, expected 1, 3 or 4")]
+    InvalidCmsChannels(usize),
+    #[error("CMS returned {0} transforms, {1} were requested")]
+    InvalidCmsTransformCount(usize, usize),
+    #[error("CMS error: {0}")]
+    CmsError(String),
+    #[error("Invalid gain map")]
+    InvalidGainMap,
+    #[error("Unsupported gain map version {0}")]
+    UnsupportedGainMapVersion(u32),
+    #[error(
+        "Gain maps can only be applied in color spaces described by a color encoding, with the color channels of the image"
+    )]
+    UnsupportedGainMapColorSpace,
+    #[error("Invalid frame index")]
+    InvalidFrameIndex,
     #[error("I/O error: {0}")]
     IOError(#[from] std::io::Error),
     #[error("Wrong buffer count: {0} buffers given, {1} buffers expected")]
@@ -267,8 +295,16 @@ pub enum Error {
     InvalidOutputBufferSize(usize, usize, usize, usize, JxlColorType, JxlDataFormat),
     #[error("Attempting to save channels with different downsample amounts: {0:?} and {1:?}")]
     SaveDifferentDownsample((u8, u8), (u8, u8)),
+    #[error("Input ended before enough of the frame was received to render it")]
+    NotEnoughFrameData,
+    #[error("The sections of the frame are not stored contiguously in the file")]
+    SectionsNotContiguous,
+    #[error("No LF preview is available for the current frame")]
+    NoLfPreview,
     #[error("Image has {0} extra channels, more than the maximum of 256")]
     TooManyExtraChannels(usize),
+    #[error("Input ended before the end of the file")]
+    TruncatedInput,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
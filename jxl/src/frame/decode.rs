@@ -11,7 +11,10 @@ use super::{
     coeff_order::decode_coeff_orders,
     color_correlation_map::ColorCorrelationParams,
     group::{VarDctBuffers, decode_vardct_group},
-    modular::{FullModularImage, ModularStreamId, Tree, decode_hf_metadata, decode_vardct_lf},
+    modular::{
+        FullModularImage, ModularStreamId, Tree, decode_hf_metadata, decode_vardct_lf,
+        fill_missing_hf_metadata,
+    },
     quant_weights::DequantMatrices,
     quantizer::{LfQuantFactors, QuantizerParams},
 };
@@ -22,6 +25,7 @@ use crate::render::buffer_splitter::BufferSplitter;
 use crate::{
     GROUP_DIM,
     bit_reader::BitReader,
+    color::tf::srgb_to_linear,
     entropy_coding::decode::Histograms,
     error::Result,
     features::{noise::Noise, patches::PatchesDictionary, spline::Splines},
@@ -29,16 +33,39 @@ use crate::{
         DecoderState, Frame, HfGlobalState, HfMetadata, LfGlobalState, PassState, coeff_order,
     },
     headers::{
+        bit_depth::BitDepth,
         color_encoding::ColorSpace,
+        extra_channels::ExtraChannel,
         frame_header::{Encoding, FrameHeader},
         toc::Toc,
     },
-    image::Image,
+    image::{Image, Rect},
     render::RenderPipeline,
     util::{CeilLog2, Xorshift128Plus, tracing_wrappers::*},
 };
 use jxl_transforms::transform_map::*;
 
+/// Converts a sample value, nominally in [0, 1], to a modular sample of the given bit depth.
+fn modular_sample(bit_depth: &BitDepth, value: f32) -> i32 {
+    let bits = bit_depth.bits_per_sample();
+    if !bit_depth.floating_point_sample() {
+        return (value * ((1u64 << bits) - 1) as f32).round() as i32;
+    }
+    // Narrows the exponent and mantissa of the binary32 value, flushing values that are too
+    // small for the format to zero.
+    let exp_bits = bit_depth.exponent_bits_per_sample();
+    let mant_bits = bits - exp_bits - 1;
+    let exp_bias = (1i32 << (exp_bits - 1)) - 1;
+    let f = value.to_bits();
+    let exp = ((f >> 23) & 0xff) as i32 - 127 + exp_bias;
+    if value == 0.0 || exp <= 0 {
+        return 0;
+    }
+    let sign = (f >> 31) << (bits - 1);
+    let mantissa = (f & 0x7fffff) >> (23 - mant_bits);
+    (sign | (exp as u32) << mant_bits | mantissa) as i32
+}
+
 impl Frame {
     pub fn from_header_and_toc(
         frame_header: FrameHeader,
@@ -161,7 +188,7 @@ impl Frame {
             inv_perm[*pos as usize] = i;
         }
         let mut shuffled_ret = ret.clone();
-        for (br, pos) in ret.into_iter().zip(inv_perm.into_iter()) {
+        for (br, pos) in ret.into_iter().zip(inv_perm) {
             shuffled_ret[pos] = br;
         }
         Ok(shuffled_ret)
@@ -233,7 +260,11 @@ impl Frame {
                     * (self.color_channels + self.decoder_state.extra_channel_info().len())
                     / 16)
                 .min(1 << 22);
-            Some(Tree::read(br, size_limit)?)
+            Some(Tree::read(
+                br,
+                size_limit,
+                self.decoder_state.buffer_pool.as_ref(),
+            )?)
         } else {
             None
         };
@@ -242,6 +273,7 @@ impl Frame {
             &self.header,
             &self.decoder_state.file_header.image_metadata,
             self.modular_color_channels(),
+            &self.missing_modular_samples(&lf_quant),
             &tree,
             br,
         )?;
@@ -261,11 +293,86 @@ impl Frame {
         Ok(())
     }
 
+    /// Returns the color samples of a mid-gray pixel in the space that the frame is coded in,
+    /// which are used in place of missing data. For XYB and YCbCr, the color is neutral.
+    fn mid_gray(&self) -> [f32; 3] {
+        let image_metadata = &self.decoder_state.file_header.image_metadata;
+        let mut gray = [0.5];
+        if self.header.do_ycbcr {
+            // Chroma is centered around zero, and 128/255 is added to luma when rendering.
+            [0.0, 0.5 - 128.0 / 255.0, 0.0]
+        } else if image_metadata.xyb_encoded {
+            // Inverts the XYB stage for the linear value of sRGB mid-gray, with X = 0 and B = Y.
+            srgb_to_linear(&mut gray);
+            let intensity_scale = 255.0 / image_metadata.tone_mapping.intensity_target;
+            let transform_data = &self.decoder_state.file_header.transform_data;
+            let bias = transform_data.opsin_inverse_matrix.opsin_biases;
+            let [_, y, b] =
+                bias.map(|bias| (gray[0] / intensity_scale - bias).cbrt() + bias.cbrt());
+            [0.0, y, b]
+        } else {
+            [gray[0]; 3]
+        }
+    }
+
+    /// Returns the samples that the channels of the modular image, color channels first, are
+    /// filled with where their data is missing: mid-gray for the color channels, opaque alpha, and
+    /// zeros for other extra channels.
+    fn missing_modular_samples(&self, lf_quant: &LfQuantFactors) -> Vec<i32> {
+        let image_metadata = &self.decoder_state.file_header.image_metadata;
+        let bit_depth = &image_metadata.bit_depth;
+        let mid_gray = self.mid_gray();
+        let mut samples = match self.modular_color_channels() {
+            0 => vec![],
+            _ if image_metadata.xyb_encoded => {
+                // Modular XYB images store Y, X and B - Y, divided by the LF quantization factors.
+                let [_, y, b] =
+                    [0, 1, 2].map(|c| (mid_gray[c] * lf_quant.inv_quant_factors[c]).round() as i32);
+                vec![y, 0, b - y]
+            }
+            n => mid_gray[..n]
+                .iter()
+                .map(|v| modular_sample(bit_depth, *v))
+                .collect(),
+        };
+        samples.extend(
+            image_metadata
+                .extra_channel_info
+                .iter()
+                .map(|info| match info.ec_type {
+                    ExtraChannel::Alpha => modular_sample(bit_depth, 1.0),
+                    _ => 0,
+                }),
+        );
+        samples
+    }
+
+    /// Decodes an LF group. If `br` is `None`, the section is missing: the LF image of the group
+    /// is filled with mid-gray, its modular channels as described in
+    /// [`FullModularImage::read_stream`], and its blocks are treated as DCT8 blocks.
     #[instrument(level = "debug", skip(self, br))]
-    pub fn decode_lf_group(&mut self, group: usize, br: &mut BitReader) -> Result<()> {
-        debug!(section_size = br.total_bits_available());
+    pub fn decode_lf_group(&mut self, group: usize, mut br: Option<&mut BitReader>) -> Result<()> {
+        debug!(section_size = br.as_ref().map(|br| br.total_bits_available()));
+        if self.header.encoding == Encoding::VarDCT && !self.header.has_lf_frame() && br.is_none() {
+            let r = self.header.lf_group_rect(group);
+            let mid_gray = self.mid_gray();
+            for (c, lf) in self.lf_image.as_mut().unwrap().iter_mut().enumerate() {
+                let (hshift, vshift) = (self.header.hshift(c), self.header.vshift(c));
+                let rect = Rect {
+                    origin: (r.origin.0 >> hshift, r.origin.1 >> vshift),
+                    size: (r.size.0 >> hshift, r.size.1 >> vshift),
+                };
+                let mut lf_rect = lf.get_rect_mut(rect);
+                for y in 0..rect.size.1 {
+                    lf_rect.row(y).fill(mid_gray[c]);
+                }
+            }
+        }
         let lf_global = self.lf_global.as_mut().unwrap();
-        if self.header.encoding == Encoding::VarDCT && !self.header.has_lf_frame() {
+        if self.header.encoding == Encoding::VarDCT
+            && !self.header.has_lf_frame()
+            && let Some(br) = br.as_deref_mut()
+        {
             info!("decoding VarDCT LF with group id {}", group);
             decode_vardct_lf(
                 group,
@@ -285,29 +392,45 @@ impl Frame {
             ModularStreamId::ModularLF(group),
             &self.header,
             &lf_global.tree,
-            br,
+            br.as_deref_mut(),
         )?;
         if self.header.encoding == Encoding::VarDCT {
             info!("decoding HF metadata with group id {}", group);
             let hf_meta = self.hf_meta.as_mut().unwrap();
-            decode_hf_metadata(
-                group,
-                &self.header,
-                &self.decoder_state.file_header.image_metadata,
-                &lf_global.tree,
-                hf_meta,
-                br,
-            )?;
+            match br {
+                Some(br) => decode_hf_metadata(
+                    group,
+                    &self.header,
+                    &self.decoder_state.file_header.image_metadata,
+                    &lf_global.tree,
+                    hf_meta,
+                    br,
+                )?,
+                None => fill_missing_hf_metadata(group, &self.header, hf_meta),
+            }
         }
         Ok(())
     }
 
+    /// Decodes the HF global section. If `br` is `None`, the section is missing: default
+    /// quantization matrices are used, and no HF coefficients can be decoded.
     #[instrument(level = "debug", skip_all)]
-    pub fn decode_hf_global(&mut self, br: &mut BitReader) -> Result<()> {
-        debug!(section_size = br.total_bits_available());
+    pub fn decode_hf_global(&mut self, br: Option<&mut BitReader>) -> Result<()> {
+        debug!(section_size = br.as_ref().map(|br| br.total_bits_available()));
         if self.header.encoding == Encoding::Modular {
             return Ok(());
         }
+        let Some(br) = br else {
+            let mut dequant_matrices = DequantMatrices::all_default();
+            dequant_matrices.ensure_computed(self.hf_meta.as_ref().unwrap().used_hf_types)?;
+            self.hf_global = Some(HfGlobalState {
+                num_histograms: 0,
+                passes: vec![],
+                dequant_matrices,
+                hf_coefficients: None,
+            });
+            return Ok(());
+        };
         let lf_global = self.lf_global.as_mut().unwrap();
         let mut dequant_matrices = DequantMatrices::decode(&self.header, lf_global, br)?;
         dequant_matrices.ensure_computed(self.hf_meta.as_ref().unwrap().used_hf_types)?;
@@ -335,7 +458,12 @@ impl Frame {
                 "Deconding histograms for pass {} with {} contexts",
                 i, num_contexts
             );
-            let histograms = Histograms::decode(num_contexts, br, true)?;
+            let histograms = Histograms::decode_with_pool(
+                num_contexts,
+                br,
+                true,
+                self.decoder_state.buffer_pool.as_ref(),
+            )?;
             debug!("Found {} histograms", histograms.num_histograms());
             passes.push(PassState {
                 coeff_orders,
@@ -362,15 +490,18 @@ impl Frame {
         Ok(())
     }
 
+    /// Decodes the given pass of an HF group and passes the group to the render pipeline once it
+    /// is complete. If `br` is `None`, the section is missing, and the group is rendered from
+    /// what was decoded before.
     #[instrument(level = "debug", skip(self, br, buffer_splitter))]
     pub fn decode_hf_group(
         &mut self,
         group: usize,
         pass: usize,
-        mut br: BitReader,
+        mut br: Option<BitReader>,
         buffer_splitter: &mut BufferSplitter,
     ) -> Result<()> {
-        debug!(section_size = br.total_bits_available());
+        debug!(section_size = br.as_ref().map(|br| br.total_bits_available()));
         if self.header.has_noise() {
             // TODO(sboukortt): consider making this a dedicated stage
             let num_channels = self.header.num_extra_channels as usize + 3;
@@ -428,7 +559,9 @@ impl Frame {
         }
 
         let lf_global = self.lf_global.as_mut().unwrap();
-        if self.header.encoding == Encoding::VarDCT {
+        let last_pass = pass + 1 == self.header.passes.num_passes as usize;
+        // Without data for this pass, there is nothing to do until the pixels are needed.
+        if self.header.encoding == Encoding::VarDCT && (br.is_some() || last_pass) {
             info!("Decoding VarDCT group {group}, pass {pass}");
             let hf_global = self.hf_global.as_mut().unwrap();
             let hf_meta = self.hf_meta.as_mut().unwrap();
@@ -454,12 +587,10 @@ impl Frame {
                     .opsin_inverse_matrix
                     .quant_biases,
                 &mut pixels,
-                &mut br,
+                br.as_mut(),
                 buffers,
             )?;
-            if self.decoder_state.enable_output
-                && pass + 1 == self.header.passes.num_passes as usize
-            {
+            if self.decoder_state.enable_output && last_pass {
                 for (c, img) in pixels.into_iter().enumerate() {
                     pipeline!(
                         self,
@@ -473,7 +604,7 @@ impl Frame {
             ModularStreamId::ModularHF { group, pass },
             &self.header,
             &lf_global.tree,
-            &mut br,
+            br.as_mut(),
         )?;
         lf_global.modular_global.process_output(
             2 + pass,
@@ -318,16 +318,24 @@ pub fn decode_vardct_group(
     quant_lf: &Image<u8>,
     quant_biases: &[f32; 4],
     pixels: &mut [Image<f32>; 3],
-    br: &mut BitReader,
+    br: Option<&mut BitReader>,
     buffers: &mut VarDctBuffers,
 ) -> Result<(), Error> {
     let x_dm_multiplier = (1.0 / (1.25)).powf(frame_header.x_qm_scale as f32 - 2.0);
     let b_dm_multiplier = (1.0 / (1.25)).powf(frame_header.b_qm_scale as f32 - 2.0);
 
-    let num_histo_bits = hf_global.num_histograms.ceil_log2();
-    let histogram_index: usize = br.read(num_histo_bits as usize)? as usize;
-    debug!(?histogram_index);
-    let mut reader = SymbolReader::new(&hf_global.passes[pass].histograms, br, None)?;
+    // Without a bitstream, no coefficients are read, and the group is rendered from the
+    // coefficients of previous passes (if any) and the LF image.
+    let mut coefficient_reader = match br {
+        Some(br) => {
+            let num_histo_bits = hf_global.num_histograms.ceil_log2();
+            let histogram_index: usize = br.read(num_histo_bits as usize)? as usize;
+            debug!(?histogram_index);
+            let reader = SymbolReader::new(&hf_global.passes[pass].histograms, br, None)?;
+            Some((reader, br, histogram_index))
+        }
+        None => None,
+    };
     let block_group_rect = frame_header.block_group_rect(group);
     debug!(?block_group_rect);
     // Reset and use pooled buffers
@@ -366,7 +374,6 @@ pub fn decode_vardct_group(
     ];
     let quant_lf_rect = quant_lf.get_rect(block_group_rect);
     let block_context_map = lf_global.block_context_map.as_mut().unwrap();
-    let context_offset = histogram_index * block_context_map.num_ac_contexts();
     let coeffs = match hf_global.hf_coefficients.as_mut() {
         Some(hf_coefficients) => [
             hf_coefficients.0.row_mut(group),
@@ -474,58 +481,61 @@ pub fn decode_vardct_group(
             let num_blocks = cx * cy;
             let num_coeffs = num_blocks * BLOCK_SIZE;
             let log_num_blocks = num_blocks.ilog2() as usize;
-            let pass_info = &hf_global.passes[pass];
-            for c in [1, 0, 2] {
-                if (sbx[c] << hshift[c]) != bx || (sby[c] << vshift[c] != by) {
-                    continue;
-                }
-                trace!(
-                    "Decoding block ({},{}) channel {} with {}x{} block transform {} (shape id {})",
-                    sbx[c], sby[c], c, cx, cy, transform_id, shape_id
-                );
-                let predicted_nzeros = predict_num_nonzeros(&num_nzeros[c], sbx[c], sby[c]);
-                let block_context =
-                    block_context_map.block_context(quant_lf, raw_quant, shape_id, c);
-                let nonzero_context = block_context_map
-                    .nonzero_context(predicted_nzeros, block_context)
-                    + context_offset;
-                let mut nonzeros =
-                    reader.read_unsigned(&pass_info.histograms, br, nonzero_context) as usize;
-                trace!(
-                    "block ({},{},{c}) predicted_nzeros: {predicted_nzeros} \
-                       nzero_ctx: {nonzero_context} (offset: {context_offset}) \
-                       nzeros: {nonzeros}",
-                    sbx[c], sby[c]
-                );
-                if nonzeros + num_blocks > num_coeffs {
-                    return Err(Error::InvalidNumNonZeros(nonzeros, num_blocks));
-                }
-                for iy in 0..cy {
-                    let nzrow = num_nzeros[c].row_mut(sby[c] + iy);
-                    for ix in 0..cx {
-                        nzrow[sbx[c] + ix] = nonzeros.shrc(log_num_blocks) as u32;
+            if let Some((reader, br, histogram_index)) = coefficient_reader.as_mut() {
+                let context_offset = *histogram_index * block_context_map.num_ac_contexts();
+                let pass_info = &hf_global.passes[pass];
+                for c in [1, 0, 2] {
+                    if (sbx[c] << hshift[c]) != bx || (sby[c] << vshift[c] != by) {
+                        continue;
                     }
-                }
-                let histo_offset =
-                    block_context_map.zero_density_context_offset(block_context) + context_offset;
-                let mut prev = if nonzeros > num_coeffs / 16 { 0 } else { 1 };
-                let permutation = &pass_info.coeff_orders[shape_id * 3 + c];
-                let current_coeffs = &mut coeffs[c][coeffs_offset..coeffs_offset + num_coeffs];
-                for k in num_blocks..num_coeffs {
-                    if nonzeros == 0 {
-                        break;
+                    trace!(
+                        "Decoding block ({},{}) channel {} with {}x{} block transform {} (shape id {})",
+                        sbx[c], sby[c], c, cx, cy, transform_id, shape_id
+                    );
+                    let predicted_nzeros = predict_num_nonzeros(&num_nzeros[c], sbx[c], sby[c]);
+                    let block_context =
+                        block_context_map.block_context(quant_lf, raw_quant, shape_id, c);
+                    let nonzero_context = block_context_map
+                        .nonzero_context(predicted_nzeros, block_context)
+                        + context_offset;
+                    let mut nonzeros =
+                        reader.read_unsigned(&pass_info.histograms, br, nonzero_context) as usize;
+                    trace!(
+                        "block ({},{},{c}) predicted_nzeros: {predicted_nzeros} \
+                           nzero_ctx: {nonzero_context} (offset: {context_offset}) \
+                           nzeros: {nonzeros}",
+                        sbx[c], sby[c]
+                    );
+                    if nonzeros + num_blocks > num_coeffs {
+                        return Err(Error::InvalidNumNonZeros(nonzeros, num_blocks));
+                    }
+                    for iy in 0..cy {
+                        let nzrow = num_nzeros[c].row_mut(sby[c] + iy);
+                        for ix in 0..cx {
+                            nzrow[sbx[c] + ix] = nonzeros.shrc(log_num_blocks) as u32;
+                        }
+                    }
+                    let histo_offset = block_context_map.zero_density_context_offset(block_context)
+                        + context_offset;
+                    let mut prev = if nonzeros > num_coeffs / 16 { 0 } else { 1 };
+                    let permutation = &pass_info.coeff_orders[shape_id * 3 + c];
+                    let current_coeffs = &mut coeffs[c][coeffs_offset..coeffs_offset + num_coeffs];
+                    for k in num_blocks..num_coeffs {
+                        if nonzeros == 0 {
+                            break;
+                        }
+                        let ctx =
+                            histo_offset + zero_density_context(nonzeros, k, log_num_blocks, prev);
+                        let coeff =
+                            reader.read_signed(&pass_info.histograms, br, ctx) << shift_for_pass;
+                        prev = if coeff != 0 { 1 } else { 0 };
+                        nonzeros -= prev;
+                        let coeff_index = permutation[k] as usize;
+                        current_coeffs[coeff_index] += coeff;
+                    }
+                    if nonzeros != 0 {
+                        return Err(Error::EndOfBlockResidualNonZeros(nonzeros));
                     }
-                    let ctx =
-                        histo_offset + zero_density_context(nonzeros, k, log_num_blocks, prev);
-                    let coeff =
-                        reader.read_signed(&pass_info.histograms, br, ctx) << shift_for_pass;
-                    prev = if coeff != 0 { 1 } else { 0 };
-                    nonzeros -= prev;
-                    let coeff_index = permutation[k] as usize;
-                    current_coeffs[coeff_index] += coeff;
-                }
-                if nonzeros != 0 {
-                    return Err(Error::EndOfBlockResidualNonZeros(nonzeros));
                 }
             }
             let qblock = [
@@ -562,6 +572,8 @@ pub fn decode_vardct_group(
             coeffs_offset += num_coeffs;
         }
     }
-    reader.check_final_state(&hf_global.passes[pass].histograms, br)?;
+    if let Some((reader, br, _)) = coefficient_reader {
+        reader.check_final_state(&hf_global.passes[pass].histograms, br)?;
+    }
     Ok(())
 }
@@ -6,6 +6,10 @@
 use std::sync::Arc;
 
 use crate::{
+    api::{
+        GainMapRendering, JxlBufferPool, JxlColorProfile, JxlCustomStage, JxlCustomStageSpace,
+        JxlHlgRendering, JxlResampling, JxlStageTap, SimdLevel,
+    },
     entropy_coding::decode::Histograms,
     error::Result,
     features::{noise::Noise, patches::PatchesDictionary, spline::Splines},
@@ -125,6 +129,14 @@ pub struct DecoderState {
     pub nonvisible_frame_index: usize,
     pub high_precision: bool,
     pub premultiply_output: bool,
+    pub hlg_rendering: JxlHlgRendering,
+    pub custom_stages: Vec<(JxlCustomStageSpace, Arc<dyn JxlCustomStage>)>,
+    pub resampling: Option<JxlResampling>,
+    pub(crate) gain_map: Option<Arc<GainMapRendering>>,
+    pub stage_taps: Vec<JxlStageTap>,
+    pub buffer_pool: Option<JxlBufferPool>,
+    pub simd_level: SimdLevel,
+    pub embedded_color_profile: Option<JxlColorProfile>,
 }
 
 impl DecoderState {
@@ -144,6 +156,14 @@ impl DecoderState {
             nonvisible_frame_index: 0,
             high_precision: false,
             premultiply_output: false,
+            hlg_rendering: JxlHlgRendering::default(),
+            custom_stages: vec![],
+            resampling: None,
+            gain_map: None,
+            stage_taps: vec![],
+            buffer_pool: None,
+            simd_level: SimdLevel::best(),
+            embedded_color_profile: None,
         }
     }
 
@@ -156,6 +176,14 @@ impl DecoderState {
         self.reference_frames[i].as_ref()
     }
 
+    /// Returns the size of the image, before orientation is applied.
+    pub fn image_size(&self) -> (usize, usize) {
+        (
+            self.file_header.size.xsize() as usize,
+            self.file_header.size.ysize() as usize,
+        )
+    }
+
     #[cfg(test)]
     pub fn set_use_simple_pipeline(&mut self, u: bool) {
         self.use_simple_pipeline = u;
@@ -266,6 +294,13 @@ impl Frame {
         Ok(decoder_state)
     }
 
+    /// Ends a frame whose sections were not decoded. Unlike [`finalize`](Self::finalize), the
+    /// frame is not saved for use by later frames.
+    pub fn skip(mut self) -> Option<DecoderState> {
+        self.render_pipeline = None;
+        (!self.header.is_last).then_some(self.decoder_state)
+    }
+
     fn modular_color_channels(&self) -> usize {
         if self.header.encoding == Encoding::VarDCT {
             0
@@ -32,7 +32,7 @@ use borrowed_buffers::with_buffers;
 pub use decode::ModularStreamId;
 use decode::decode_modular_subbitstream;
 pub use predict::Predictor;
-use transforms::{TransformStepChunk, make_grids};
+use transforms::{TransformStepChunk, constant_buffer_values, make_grids};
 pub use tree::Tree;
 
 // Two rows on top, two pixels to the left, two pixels to the right.
@@ -296,14 +296,20 @@ pub struct FullModularImage {
     // In order, LfGlobal, LfGroup, HfGroup(pass 0), ..., HfGroup(last pass).
     section_buffer_indices: Vec<Vec<usize>>,
     modular_color_channels: usize,
+    // Value that each buffer is filled with when the section that codes it is missing.
+    missing_values: Vec<i32>,
 }
 
 impl FullModularImage {
+    /// Reads the modular header and global channels of the frame. `missing_samples` holds the
+    /// sample of each channel (color channels, then extra channels) that the parts of the image
+    /// whose sections are missing are rendered with.
     #[instrument(level = "debug", skip_all)]
     pub fn read(
         frame_header: &FrameHeader,
         image_metadata: &ImageMetadata,
         modular_color_channels: usize,
+        missing_samples: &[i32],
         global_tree: &Option<Tree>,
         br: &mut BitReader,
     ) -> Result<Self> {
@@ -350,6 +356,7 @@ impl FullModularImage {
                 transform_steps: vec![],
                 section_buffer_indices: vec![vec![]; 2 + frame_header.passes.num_passes as usize],
                 modular_color_channels,
+                missing_values: vec![],
             });
         }
 
@@ -358,6 +365,9 @@ impl FullModularImage {
 
         let (mut buffer_info, transform_steps) =
             transforms::apply::meta_apply_transforms(&channels, &header)?;
+        assert_eq!(missing_samples.len(), channels.len());
+        let missing_values =
+            constant_buffer_values(buffer_info.len(), missing_samples, &transform_steps);
 
         // Assign each (channel, group) pair present in the bitstream to the section in which it
         // will be decoded.
@@ -490,9 +500,14 @@ impl FullModularImage {
             transform_steps,
             section_buffer_indices,
             modular_color_channels,
+            missing_values,
         })
     }
 
+    /// Decodes the channels of `stream` from `br`. If `br` is `None`, the stream is missing from
+    /// the file, and its channels are filled with the values they have in an image of the missing
+    /// samples passed to [`read`](Self::read). Squeeze residuals are zero, so the lower
+    /// resolution channels of the image are used where they are available.
     #[allow(clippy::type_complexity)]
     #[instrument(level = "debug", skip(self, frame_header, global_tree, br), ret)]
     pub fn read_stream(
@@ -500,7 +515,7 @@ impl FullModularImage {
         stream: ModularStreamId,
         frame_header: &FrameHeader,
         global_tree: &Option<Tree>,
-        br: &mut BitReader,
+        br: Option<&mut BitReader>,
     ) -> Result<()> {
         if self.buffer_info.is_empty() {
             info!("No modular channels to decode");
@@ -516,22 +531,18 @@ impl FullModularImage {
             }
         };
 
-        with_buffers(
-            &self.buffer_info,
-            &self.section_buffer_indices[section_id],
-            grid,
-            true,
-            |bufs| {
-                decode_modular_subbitstream(
-                    bufs,
-                    stream.get_id(frame_header),
-                    None,
-                    global_tree,
-                    br,
-                )
-            },
-        )?;
-        Ok(())
+        let indices = &self.section_buffer_indices[section_id];
+        let Some(br) = br else {
+            return with_buffers(&self.buffer_info, indices, grid, false, |bufs| {
+                for (buf, i) in bufs.into_iter().zip(indices) {
+                    buf.data.fill(self.missing_values[*i]);
+                }
+                Ok(())
+            });
+        };
+        with_buffers(&self.buffer_info, indices, grid, true, |bufs| {
+            decode_modular_subbitstream(bufs, stream.get_id(frame_header), None, global_tree, br)
+        })
     }
 
     pub fn process_output(
@@ -850,3 +861,23 @@ pub fn decode_hf_metadata(
     hf_meta.used_hf_types |= used_hf_types;
     Ok(())
 }
+
+/// Fills the HF metadata of an LF group whose section is missing with DCT8 blocks, no color
+/// correlation and the lowest quantization.
+pub fn fill_missing_hf_metadata(
+    group: usize,
+    frame_header: &FrameHeader,
+    hf_meta: &mut HfMetadata,
+) {
+    let r = frame_header.lf_group_rect(group);
+    let mut transform_map_rect = hf_meta.transform_map.get_rect_mut(r);
+    let mut raw_quant_map_rect = hf_meta.raw_quant_map.get_rect_mut(r);
+    for y in 0..r.size.1 {
+        // Set highest bit to signal first block.
+        transform_map_rect
+            .row(y)
+            .fill(HfTransformType::DCT as u8 + 128);
+        raw_quant_map_rect.row(y).fill(1);
+    }
+    hf_meta.used_hf_types |= 1 << HfTransformType::DCT as u32;
+}
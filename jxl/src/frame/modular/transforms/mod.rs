@@ -41,6 +41,41 @@ pub enum RctOp {
     YCoCg = 6,
 }
 
+/// Returns the value of each buffer when the pre-transform channels, which are the first buffers,
+/// are filled with `channel_values`. Squeeze residuals are zero, and palette indices are 0.
+pub fn constant_buffer_values(
+    num_buffers: usize,
+    channel_values: &[i32],
+    transform_steps: &[TransformStep],
+) -> Vec<i32> {
+    let mut values = vec![0; num_buffers];
+    values[..channel_values.len()].copy_from_slice(channel_values);
+    // The outputs of each step are pre-transform channels or inputs of an earlier step, so their
+    // values are known when the steps are visited in order.
+    for step in transform_steps {
+        match step {
+            TransformStep::Rct {
+                buf_in,
+                buf_out,
+                op,
+                perm,
+            } => {
+                let pixel = rct::forward_rct_pixel(buf_out.map(|b| values[b]), *op, *perm);
+                for (b, v) in buf_in.iter().zip(pixel) {
+                    values[*b] = v;
+                }
+            }
+            TransformStep::Palette { .. } => {}
+            TransformStep::HSqueeze { buf_in, buf_out }
+            | TransformStep::VSqueeze { buf_in, buf_out } => {
+                values[buf_in[0]] = values[*buf_out];
+                values[buf_in[1]] = 0;
+            }
+        }
+    }
+    values
+}
+
 #[instrument(level = "trace", skip_all, ret)]
 pub fn make_grids(
     frame_header: &FrameHeader,
@@ -3,7 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use jxl_simd::{I32SimdVec, ScalarDescriptor, SimdDescriptor, shr, simd_function};
+use jxl_simd::{I32SimdVec, SimdDescriptor, shr, simd_function};
 
 use crate::{
     frame::modular::{
@@ -74,6 +74,41 @@ fn rct_row_impl<D: SimdDescriptor, const OP: u32>(d: D, rgb: [&mut [i32]; 3]) ->
     ]
 }
 
+// Scalar version of `rct_impl`. Like the vector instructions, it wraps around on overflow, which
+// can happen with float samples that are stored as their bit patterns.
+#[inline(always)]
+fn rct_row_scalar<const OP: u32>([r, g, b]: [&mut [i32]; 3]) {
+    const { assert!(OP <= 6) };
+
+    for ((r, g), b) in r.iter_mut().zip(g.iter_mut()).zip(b.iter_mut()) {
+        let (v0, v1, v2) = (*r, *g, *b);
+        (*r, *g, *b) = match OP {
+            0 => (v0, v1, v2),
+            1 => (v0, v1, v2.wrapping_add(v0)),
+            2 => (v0, v1.wrapping_add(v0), v2),
+            3 => (v0, v1.wrapping_add(v0), v2.wrapping_add(v0)),
+            4 => {
+                let avg = v0.wrapping_add(v2) >> 1;
+                (v0, v1.wrapping_add(avg), v2)
+            }
+            5 => {
+                let v2 = v0.wrapping_add(v2);
+                let avg = v0.wrapping_add(v2) >> 1;
+                (v0, v1.wrapping_add(avg), v2)
+            }
+            6 => {
+                let (y, co, cg) = (v0, v1, v2);
+                let y = y.wrapping_sub(cg >> 1);
+                let g = cg.wrapping_add(y);
+                let y = y.wrapping_sub(co >> 1);
+                let r = y.wrapping_add(co);
+                (r, g, y)
+            }
+            _ => unreachable!(),
+        };
+    }
+}
+
 #[inline(always)]
 fn rct_loop_impl<D: SimdDescriptor, const OP: u32>(
     d: D,
@@ -88,16 +123,16 @@ fn rct_loop_impl<D: SimdDescriptor, const OP: u32>(
     for pos_y in 0..h {
         let mut rgb = [&mut *r, &mut *g, &mut *b].map(|x| x.row_mut(pos_y));
 
-        rgb = rct_row_impl::<D, OP>(d, rgb);
+        if D::I32Vec::LEN > 1 {
+            rgb = rct_row_impl::<D, OP>(d, rgb);
+        }
         if D::I32Vec::LEN > 8 {
             rgb = rct_row_impl::<_, OP>(d.maybe_downgrade_256bit(), rgb);
         }
         if D::I32Vec::LEN > 4 {
             rgb = rct_row_impl::<_, OP>(d.maybe_downgrade_128bit(), rgb);
         }
-        if D::I32Vec::LEN > 1 {
-            rct_row_impl::<_, OP>(ScalarDescriptor::new().unwrap(), rgb);
-        }
+        rct_row_scalar::<OP>(rgb);
     }
 }
 
@@ -117,6 +152,35 @@ simd_function!(
     }
 );
 
+/// Returns the samples that [`do_rct_step`] turns into `pixel`.
+pub fn forward_rct_pixel(pixel: [i32; 3], op: RctOp, perm: RctPermutation) -> [i32; 3] {
+    // The sample that the transform writes to channel i ends up in channel dest[i].
+    let dest = match perm {
+        RctPermutation::Rgb => [0, 1, 2],
+        RctPermutation::Gbr => [1, 2, 0],
+        RctPermutation::Brg => [2, 0, 1],
+        RctPermutation::Rbg => [0, 2, 1],
+        RctPermutation::Grb => [1, 0, 2],
+        RctPermutation::Bgr => [2, 1, 0],
+    };
+    let [w0, w1, w2] = dest.map(|c| pixel[c]);
+    let avg = |v2: i32| w0.wrapping_add(v2) >> 1;
+    match op {
+        RctOp::Noop => [w0, w1, w2],
+        RctOp::AddFirstToThird => [w0, w1, w2.wrapping_sub(w0)],
+        RctOp::AddFirstToSecond => [w0, w1.wrapping_sub(w0), w2],
+        RctOp::AddFirstToSecondAndThird => [w0, w1.wrapping_sub(w0), w2.wrapping_sub(w0)],
+        RctOp::AddAvgToSecond => [w0, w1.wrapping_sub(avg(w2)), w2],
+        RctOp::AddFirstToThirdAndAvgToSecond => [w0, w1.wrapping_sub(avg(w2)), w2.wrapping_sub(w0)],
+        RctOp::YCoCg => {
+            let co = w0.wrapping_sub(w2);
+            let y = w2.wrapping_add(co >> 1);
+            let cg = w1.wrapping_sub(y);
+            [y.wrapping_add(cg >> 1), co, cg]
+        }
+    }
+}
+
 // Applies a RCT in-place to the given buffers.
 #[instrument(level = "debug", skip(buffers), ret)]
 pub fn do_rct_step(buffers: &mut [&mut ModularChannel], op: RctOp, perm: RctPermutation) {
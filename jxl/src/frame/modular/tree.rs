@@ -7,6 +7,7 @@ use std::fmt::Debug;
 
 use super::{Predictor, predict::WeightedPredictorState};
 use crate::{
+    api::JxlBufferPool,
     bit_reader::BitReader,
     entropy_coding::decode::Histograms,
     entropy_coding::decode::SymbolReader,
@@ -288,11 +289,15 @@ pub(super) fn predict_flat(
 }
 
 impl Tree {
-    #[instrument(level = "debug", skip(br), err)]
-    pub fn read(br: &mut BitReader, size_limit: usize) -> Result<Tree> {
+    #[instrument(level = "debug", skip(br, pool), err)]
+    pub fn read(
+        br: &mut BitReader,
+        size_limit: usize,
+        pool: Option<&JxlBufferPool>,
+    ) -> Result<Tree> {
         assert!(size_limit <= u32::MAX as usize);
         trace!(pos = br.total_bits_read());
-        let tree_histograms = Histograms::decode(NUM_TREE_CONTEXTS, br, true)?;
+        let tree_histograms = Histograms::decode_with_pool(NUM_TREE_CONTEXTS, br, true, pool)?;
         let mut tree_reader = SymbolReader::new(&tree_histograms, br, None)?;
         // TODO(veluca): consider early-exiting for trees known to be infinite.
         let mut tree: Vec<TreeNode> = vec![];
@@ -405,7 +410,7 @@ impl Tree {
             }
         }
 
-        let histograms = Histograms::decode(tree.len().div_ceil(2), br, true)?;
+        let histograms = Histograms::decode_with_pool(tree.len().div_ceil(2), br, true, pool)?;
 
         Ok(Tree {
             nodes: tree,
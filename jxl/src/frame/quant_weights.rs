@@ -931,13 +931,26 @@ impl DequantMatrices {
                 )?);
             }
         }
-        Ok(Self {
+        Ok(Self::from_encodings(encodings))
+    }
+
+    /// Returns the default quantization matrices.
+    pub fn all_default() -> Self {
+        Self::from_encodings(
+            (0..QuantTable::CARDINALITY)
+                .map(|_| QuantEncoding::Library)
+                .collect(),
+        )
+    }
+
+    fn from_encodings(encodings: Vec<QuantEncoding>) -> Self {
+        Self {
             computed_mask: 0,
             table: vec![0.0; Self::TOTAL_TABLE_SIZE],
             inv_table: vec![0.0; Self::TOTAL_TABLE_SIZE],
             table_offsets: [0; HfTransformType::CARDINALITY * 3],
             encodings,
-        })
+        }
     }
 
     pub const REQUIRED_SIZE_X: [usize; QuantTable::CARDINALITY] =
@@ -5,16 +5,23 @@
 
 use std::sync::Arc;
 
+use crate::api::GainMapRendering;
 use crate::api::JxlCms;
+use crate::api::JxlColorEncoding;
+use crate::api::JxlColorProfile;
 use crate::api::JxlColorType;
+use crate::api::JxlCustomStageSpace;
 use crate::api::JxlDataFormat;
 use crate::api::JxlOutputBuffer;
+use crate::api::JxlOutputSink;
+use crate::api::JxlTransferFunction;
 use crate::bit_reader::BitReader;
 use crate::error::{Error, Result};
 use crate::features::epf::create_sigma_image;
 use crate::headers::frame_header::Encoding;
+use crate::headers::image_metadata::ImageMetadata;
 use crate::headers::{Orientation, color_encoding::ColorSpace, extra_channels::ExtraChannel};
-use crate::image::Rect;
+use crate::image::{DataTypeTag, Rect};
 #[cfg(test)]
 use crate::render::SimpleRenderPipeline;
 use crate::render::buffer_splitter::BufferSplitter;
@@ -96,11 +103,425 @@ impl Frame {
         Ok(pipeline)
     }
 
+    /// Returns, for each channel, the integer output type that its lossless modular data can be
+    /// converted to directly, if any. This requires that no other stage processes the
+    /// channel, and that it is saved only with integer formats of the same bit depth as the image.
+    fn integer_output_channels(
+        decoder_state: &DecoderState,
+        frame_header: &FrameHeader,
+        pixel_format: &JxlPixelFormat,
+        output_color_profile: &JxlColorProfile,
+    ) -> Result<Vec<Option<DataTypeTag>>> {
+        let num_channels = frame_header.num_extra_channels as usize + 3;
+        let metadata = &decoder_state.file_header.image_metadata;
+        let rf = &frame_header.restoration_filter;
+        let renders_spot_colors = decoder_state.render_spotcolors
+            && metadata
+                .extra_channel_info
+                .iter()
+                .any(|x| x.ec_type == ExtraChannel::SpotColor);
+        let is_cmyk = decoder_state
+            .embedded_color_profile
+            .as_ref()
+            .is_some_and(|p| p.is_cmyk());
+        let converts_color = match (
+            Self::rendered_color_encoding(metadata)?,
+            output_color_profile,
+        ) {
+            (Some(source), JxlColorProfile::Simple(output)) => Self::color_conversion(
+                &source, output,
+            )
+            .is_some_and(|(tf, output_tf, change_primaries)| change_primaries || tf != output_tf),
+            _ => false,
+        };
+        let passthrough = frame_header.encoding == Encoding::Modular
+            && !metadata.xyb_encoded
+            && !metadata.bit_depth.floating_point_sample()
+            && frame_header.is_visible()
+            && frame_header.lf_level == 0
+            && !frame_header.can_be_referenced
+            && !frame_header.needs_blending()
+            && !frame_header.do_ycbcr
+            && (0..3).all(|c| frame_header.hshift(c) == 0 && frame_header.vshift(c) == 0)
+            && frame_header.upsampling == 1
+            && frame_header.ec_upsampling.iter().all(|x| *x == 1)
+            && !rf.gab
+            && rf.epf_iters == 0
+            && !frame_header.has_patches()
+            && !frame_header.has_splines()
+            && !frame_header.has_noise()
+            && !renders_spot_colors
+            && !is_cmyk
+            && !converts_color
+            && !(decoder_state.premultiply_output && pixel_format.color_type.has_alpha())
+            && decoder_state.custom_stages.is_empty()
+            && decoder_state.resampling.is_none();
+        if !passthrough {
+            return Ok(vec![None; num_channels]);
+        }
+
+        let bits = metadata.bit_depth.bits_per_sample();
+        let output_type = |df: &JxlDataFormat| match *df {
+            JxlDataFormat::U8 { bit_depth } if bit_depth as u32 == bits => Some(DataTypeTag::U8),
+            JxlDataFormat::U16 { bit_depth, .. } if bit_depth as u32 == bits => {
+                Some(DataTypeTag::U16)
+            }
+            _ => None,
+        };
+        let mut output_types = vec![vec![]; num_channels];
+        if let Some(df) = &pixel_format.color_data_format {
+            let num_color_channels = if pixel_format.color_type.is_grayscale() {
+                1
+            } else {
+                3
+            };
+            let alpha = metadata
+                .extra_channel_info
+                .iter()
+                .position(|x| x.ec_type == ExtraChannel::Alpha)
+                .filter(|_| pixel_format.color_type.has_alpha());
+            for c in (0..num_color_channels).chain(alpha.map(|a| a + 3)) {
+                output_types[c].push(output_type(df));
+            }
+        }
+        for (i, df) in pixel_format.extra_channel_format.iter().enumerate() {
+            if let Some(df) = df {
+                output_types[3 + i].push(output_type(df));
+            }
+        }
+        Ok(output_types
+            .iter()
+            .map(|types| {
+                let first = *types.first()?;
+                types.iter().all(|x| *x == first).then_some(first)?
+            })
+            .collect())
+    }
+
+    /// Adds the custom stages that run in the color space `space`, in order.
+    fn add_custom_stages<P: RenderPipeline>(
+        mut pipeline: RenderPipelineBuilder<P>,
+        decoder_state: &DecoderState,
+        space: JxlCustomStageSpace,
+        num_channels: usize,
+    ) -> Result<RenderPipelineBuilder<P>> {
+        for (_, stage) in decoder_state
+            .custom_stages
+            .iter()
+            .filter(|(s, _)| *s == space)
+        {
+            pipeline = pipeline.add_inplace_stage(CustomStage::new(stage.clone(), num_channels))?;
+        }
+        Ok(pipeline)
+    }
+
+    /// Adds the stages that apply the gain map to the linear color channels, which have the
+    /// primaries and white point of `output`. The gain map is applied in its own color space if
+    /// it has one, and in the one of the base rendition, `source`, otherwise.
+    fn add_gain_map_stages<P: RenderPipeline>(
+        mut pipeline: RenderPipelineBuilder<P>,
+        gain_map: &Arc<GainMapRendering>,
+        source: &JxlColorEncoding,
+        output: &JxlColorEncoding,
+        num_color_channels: usize,
+    ) -> Result<RenderPipelineBuilder<P>> {
+        let space = gain_map.color_encoding.as_ref().unwrap_or(source);
+        let conversion = match (space, output) {
+            (
+                JxlColorEncoding::RgbColorSpace {
+                    white_point,
+                    primaries,
+                    ..
+                },
+                JxlColorEncoding::RgbColorSpace {
+                    white_point: output_white_point,
+                    primaries: output_primaries,
+                    ..
+                },
+            ) => (white_point != output_white_point || primaries != output_primaries).then_some((
+                (primaries, white_point),
+                (output_primaries, output_white_point),
+            )),
+            (
+                JxlColorEncoding::GrayscaleColorSpace { .. },
+                JxlColorEncoding::GrayscaleColorSpace { .. },
+            ) => None,
+            _ => return Err(Error::UnsupportedGainMapColorSpace),
+        };
+        if let Some((space, output)) = conversion {
+            pipeline = pipeline
+                .add_inplace_stage(ColorMatrixStage::convert_primaries(0, output, space)?)?;
+        }
+        pipeline =
+            pipeline.add_inplace_stage(GainMapStage::new(gain_map.clone(), num_color_channels))?;
+        if let Some((space, output)) = conversion {
+            pipeline = pipeline
+                .add_inplace_stage(ColorMatrixStage::convert_primaries(0, space, output)?)?;
+        }
+        Ok(pipeline)
+    }
+
+    /// Adds the stages that run on visible frames in the output color space: custom stages,
+    /// alpha premultiplication, and conversion and saving to the output buffers.
+    /// Channels with an `integer_output` type already hold data in their output format.
+    fn add_output_stages<P: RenderPipeline>(
+        mut pipeline: RenderPipelineBuilder<P>,
+        decoder_state: &DecoderState,
+        num_extra_channels: usize,
+        pixel_format: &JxlPixelFormat,
+        integer_output: &[Option<DataTypeTag>],
+    ) -> Result<RenderPipelineBuilder<P>> {
+        let metadata = &decoder_state.file_header.image_metadata;
+        let num_color_channels = if metadata.color_encoding.color_space == ColorSpace::Gray {
+            1
+        } else {
+            3
+        };
+        // Find the alpha channel info (index and metadata) if the color type requires alpha
+        let alpha_channel_info = if pixel_format.color_type.has_alpha() {
+            metadata
+                .extra_channel_info
+                .iter()
+                .enumerate()
+                .find(|x| x.1.ec_type == ExtraChannel::Alpha)
+        } else {
+            None
+        };
+        let alpha_in_color = alpha_channel_info.map(|x| x.0 + 3);
+        // Check if the source alpha is already premultiplied (alpha_associated)
+        let source_alpha_associated =
+            alpha_channel_info.is_some_and(|(_, info)| info.alpha_associated());
+        pipeline = Self::add_custom_stages(
+            pipeline,
+            decoder_state,
+            JxlCustomStageSpace::Output,
+            num_extra_channels + 3,
+        )?;
+        // Determine if we need to fill opaque alpha:
+        // - color_type requests alpha (has_alpha() is true)
+        // - but no actual alpha channel exists in the image (alpha_in_color is None)
+        let fill_opaque_alpha = pixel_format.color_type.has_alpha() && alpha_in_color.is_none();
+
+        // Determine if we should premultiply:
+        // - premultiply_output is requested
+        // - there is an alpha channel in the output
+        // - source is not already premultiplied (to avoid double-premultiplication)
+        let should_premultiply = decoder_state.premultiply_output
+            && alpha_in_color.is_some()
+            && !source_alpha_associated;
+
+        let color_source_channels: &[usize] =
+            match (pixel_format.color_type.is_grayscale(), alpha_in_color) {
+                (true, None) => &[0],
+                (true, Some(c)) => &[0, c],
+                (false, None) => &[0, 1, 2],
+                (false, Some(c)) => &[0, 1, 2, c],
+            };
+        if let Some(df) = &pixel_format.color_data_format {
+            // Add premultiply stage if needed (before conversion to output format)
+            if should_premultiply && let Some(alpha_channel) = alpha_in_color {
+                pipeline = pipeline.add_inplace_stage(PremultiplyAlphaStage::new(
+                    0,
+                    num_color_channels,
+                    alpha_channel,
+                ))?;
+            }
+            // Add conversion stages for non-float output formats
+            let channels: Vec<_> = color_source_channels
+                .iter()
+                .copied()
+                .filter(|c| !integer_output.get(*c).is_some_and(|x| x.is_some()))
+                .collect();
+            pipeline = Self::add_conversion_stages(pipeline, &channels, *df)?;
+            pipeline = pipeline.add_save_stage(
+                color_source_channels,
+                metadata.orientation,
+                0,
+                pixel_format.color_type,
+                *df,
+                fill_opaque_alpha,
+            )?;
+        }
+        // Channels without a format do not get an API buffer.
+        let mut buffer_index = pixel_format.color_data_format.is_some() as usize;
+        for i in 0..num_extra_channels {
+            if let Some(df) = &pixel_format.extra_channel_format[i] {
+                // Add conversion stages for non-float output formats
+                if !integer_output.get(3 + i).is_some_and(|x| x.is_some()) {
+                    pipeline = Self::add_conversion_stages(pipeline, &[3 + i], *df)?;
+                }
+                pipeline = pipeline.add_save_stage(
+                    &[3 + i],
+                    metadata.orientation,
+                    buffer_index,
+                    JxlColorType::Grayscale,
+                    *df,
+                    false,
+                )?;
+                buffer_index += 1;
+            }
+        }
+        Ok(pipeline)
+    }
+
+    /// Returns whether a preview of this frame can be rendered from its LF image, once the
+    /// LF groups are decoded or, for frames that use an LF frame, right away.
+    pub fn supports_lf_preview(&self) -> bool {
+        self.lf_image.is_some()
+            && self.header.is_visible()
+            && !self.header.needs_blending()
+            && (0..3).all(|c| self.header.hshift(c) == 0 && self.header.vshift(c) == 0)
+    }
+
+    /// Renders the LF image of the frame, upsampled to the full frame size, to `api_buffers`.
+    ///
+    /// Restoration filters, patches, splines and noise are not applied. Extra channels are not
+    /// known from the LF image: alpha channels are rendered as opaque, and others as zeros.
+    pub fn render_lf_preview(
+        &mut self,
+        api_buffers: &mut [JxlOutputSink<'_>],
+        pixel_format: &JxlPixelFormat,
+        output_color_profile: &JxlColorProfile,
+        cms: Option<&dyn JxlCms>,
+    ) -> Result<()> {
+        assert!(self.supports_lf_preview());
+        let header = &self.header;
+        let decoder_state = &self.decoder_state;
+        let num_channels = header.num_extra_channels as usize + 3;
+        // LF samples cover 8x8 blocks of the frame before upsampling.
+        let log_upsampling = 3 + header.upsampling.ilog2() as usize;
+        let mut pipeline = RenderPipelineBuilder::<LowMemoryRenderPipeline>::new(
+            num_channels,
+            header.size_upsampled(),
+            log_upsampling,
+            header.log_group_dim(),
+            1,
+        )
+        .with_simd_level(decoder_state.simd_level)
+        .with_buffer_pool(decoder_state.buffer_pool.clone());
+        let transform_data = &decoder_state.file_header.transform_data;
+        for c in 0..3 {
+            pipeline = pipeline.add_inout_stage(Upsample8x::new(transform_data, c))?;
+            pipeline = match header.upsampling {
+                1 => Ok(pipeline),
+                2 => pipeline.add_inout_stage(Upsample2x::new(transform_data, c)),
+                4 => pipeline.add_inout_stage(Upsample4x::new(transform_data, c)),
+                8 => pipeline.add_inout_stage(Upsample8x::new(transform_data, c)),
+                _ => unreachable!(),
+            }?;
+        }
+        for c in 3..num_channels {
+            for _ in 0..log_upsampling {
+                pipeline = pipeline.add_inout_stage(NearestNeighbourUpsample::new(c))?;
+            }
+        }
+        let linear;
+        (pipeline, linear) = Self::add_color_transform(pipeline, decoder_state, header)?;
+        let mut pipeline = Self::build_visible_output(
+            pipeline,
+            linear,
+            decoder_state,
+            header,
+            pixel_format,
+            output_color_profile,
+            cms,
+            &[],
+        )?;
+
+        let mut buffers: Vec<_> = api_buffers.iter_mut().map(|b| Some(b.reborrow())).collect();
+        pipeline.check_buffer_sizes(&mut buffers)?;
+        let mut buffer_splitter = BufferSplitter::new(&mut buffers);
+        let lf_image = self.lf_image.as_ref().unwrap();
+        let extra_channel_info = &decoder_state.file_header.image_metadata.extra_channel_info;
+        let size = lf_image[0].size();
+        let group_dim = header.group_dim();
+        let num_groups_x = size.0.div_ceil(group_dim);
+        let num_groups = num_groups_x * size.1.div_ceil(group_dim);
+        for group in 0..num_groups {
+            let x0 = (group % num_groups_x) * group_dim;
+            let y0 = (group / num_groups_x) * group_dim;
+            let xsize = group_dim.min(size.0 - x0);
+            let ysize = group_dim.min(size.1 - y0);
+            for c in 0..num_channels {
+                let mut buf = pipeline.get_buffer::<f32>(c)?;
+                for y in 0..ysize {
+                    let row = &mut buf.row_mut(y)[..xsize];
+                    match lf_image.get(c) {
+                        Some(image) => row.copy_from_slice(&image.row(y0 + y)[x0..x0 + xsize]),
+                        None if extra_channel_info[c - 3].ec_type == ExtraChannel::Alpha => {
+                            row.fill(1.0)
+                        }
+                        None => row.fill(0.0),
+                    }
+                }
+                pipeline.set_buffer_for_group(c, group, 1, buf, &mut buffer_splitter)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the color encoding of the color channels once they are rendered, if it is
+    /// described by the codestream.
+    fn rendered_color_encoding(metadata: &ImageMetadata) -> Result<Option<JxlColorEncoding>> {
+        if metadata.color_encoding.want_icc {
+            // XYB images with an ICC profile are rendered as sRGB.
+            return Ok(metadata.xyb_encoded.then(|| {
+                JxlColorEncoding::srgb(metadata.color_encoding.color_space == ColorSpace::Gray)
+            }));
+        }
+        Ok(Some(
+            match JxlColorEncoding::from_internal(&metadata.color_encoding)? {
+                JxlColorEncoding::XYB { .. } => JxlColorEncoding::srgb(false),
+                encoding => encoding,
+            },
+        ))
+    }
+
+    /// Returns the transfer functions of `source` and `output`, and whether converting between
+    /// them requires changing primaries or white point, or `None` if the conversion is not
+    /// supported without a CMS.
+    fn color_conversion<'a>(
+        source: &'a JxlColorEncoding,
+        output: &'a JxlColorEncoding,
+    ) -> Option<(&'a JxlTransferFunction, &'a JxlTransferFunction, bool)> {
+        match (source, output) {
+            (
+                JxlColorEncoding::RgbColorSpace {
+                    white_point,
+                    primaries,
+                    transfer_function,
+                    ..
+                },
+                JxlColorEncoding::RgbColorSpace {
+                    white_point: output_white_point,
+                    primaries: output_primaries,
+                    transfer_function: output_transfer_function,
+                    ..
+                },
+            ) => Some((
+                transfer_function,
+                output_transfer_function,
+                white_point != output_white_point || primaries != output_primaries,
+            )),
+            (
+                JxlColorEncoding::GrayscaleColorSpace {
+                    transfer_function, ..
+                },
+                JxlColorEncoding::GrayscaleColorSpace {
+                    transfer_function: output_transfer_function,
+                    ..
+                },
+            ) => Some((transfer_function, output_transfer_function, false)),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
     pub fn decode_and_render_hf_groups(
         &mut self,
-        api_buffers: &mut Option<&mut [JxlOutputBuffer<'_>]>,
+        api_buffers: &mut Option<&mut [JxlOutputSink<'_>]>,
         pixel_format: &JxlPixelFormat,
-        groups: Vec<(usize, Vec<(usize, BitReader)>)>,
+        groups: Vec<(usize, Vec<(usize, Option<BitReader>)>)>,
     ) -> Result<()> {
         if self.render_pipeline.is_none() {
             assert_eq!(groups.iter().map(|x| x.1.len()).sum::<usize>(), 0);
@@ -109,7 +530,7 @@ impl Frame {
             return Ok(());
         }
 
-        let mut buffers: Vec<Option<JxlOutputBuffer>> = Vec::new();
+        let mut buffers: Vec<Option<JxlOutputSink>> = Vec::new();
 
         macro_rules! buffers_from_api {
             ($get_next: expr) => {
@@ -127,9 +548,7 @@ impl Frame {
 
         if let Some(api_buffers) = api_buffers {
             let mut api_buffers_iter = api_buffers.iter_mut();
-            buffers_from_api!(Some(JxlOutputBuffer::reborrow(
-                api_buffers_iter.next().unwrap(),
-            )));
+            buffers_from_api!(Some(api_buffers_iter.next().unwrap().reborrow()));
         } else {
             buffers_from_api!(None);
         }
@@ -145,9 +564,9 @@ impl Frame {
                     size: img.size(),
                     origin: (0, 0),
                 };
-                Some(JxlOutputBuffer::from_image_rect_mut(
+                Some(JxlOutputSink::Buffer(JxlOutputBuffer::from_image_rect_mut(
                     img.get_rect_mut(rect).into_raw(),
-                ))
+                )))
             }));
         };
 
@@ -157,9 +576,9 @@ impl Frame {
                     size: img.size(),
                     origin: (0, 0),
                 };
-                Some(JxlOutputBuffer::from_image_rect_mut(
+                Some(JxlOutputSink::Buffer(JxlOutputBuffer::from_image_rect_mut(
                     img.get_rect_mut(rect).into_raw(),
-                ))
+                )))
             }));
         };
 
@@ -213,6 +632,8 @@ impl Frame {
         lf_global: &LfGlobalState,
         epf_sigma: &Option<Arc<Image<f32>>>,
         pixel_format: &JxlPixelFormat,
+        output_color_profile: &JxlColorProfile,
+        cms: Option<&dyn JxlCms>,
     ) -> Result<Box<T>> {
         let num_channels = frame_header.num_extra_channels as usize + 3;
         let num_temp_channels = if frame_header.has_noise() { 3 } else { 0 };
@@ -223,22 +644,41 @@ impl Frame {
             frame_header.upsampling.ilog2() as usize,
             frame_header.log_group_dim(),
             frame_header.passes.num_passes as usize,
-        );
+        )
+        .with_stage_taps(&decoder_state.stage_taps)
+        .with_simd_level(decoder_state.simd_level)
+        .with_buffer_pool(decoder_state.buffer_pool.clone());
 
+        let integer_output = Self::integer_output_channels(
+            decoder_state,
+            frame_header,
+            pixel_format,
+            output_color_profile,
+        )?;
+        let add_modular_conversion = |pipeline: RenderPipelineBuilder<T>, c: usize| {
+            let bits = metadata.bit_depth.bits_per_sample() as u8;
+            match integer_output[c] {
+                Some(DataTypeTag::U8) => {
+                    pipeline.add_inout_stage(ConvertModularToU8Stage::new(c, bits))
+                }
+                Some(DataTypeTag::U16) => {
+                    pipeline.add_inout_stage(ConvertModularToU16Stage::new(c, bits))
+                }
+                _ => pipeline.add_inout_stage(ConvertModularToF32Stage::new(c, metadata.bit_depth)),
+            }
+        };
         if frame_header.encoding == Encoding::Modular {
             if decoder_state.file_header.image_metadata.xyb_encoded {
                 pipeline = pipeline
                     .add_inout_stage(ConvertModularXYBToF32Stage::new(0, &lf_global.lf_quant))?
             } else {
                 for i in 0..3 {
-                    pipeline = pipeline
-                        .add_inout_stage(ConvertModularToF32Stage::new(i, metadata.bit_depth))?;
+                    pipeline = add_modular_conversion(pipeline, i)?;
                 }
             }
         }
         for i in 3..num_channels {
-            pipeline =
-                pipeline.add_inout_stage(ConvertModularToF32Stage::new(i, metadata.bit_depth))?;
+            pipeline = add_modular_conversion(pipeline, i)?;
         }
 
         for c in 0..3 {
@@ -250,51 +690,46 @@ impl Frame {
             }
         }
 
-        let filters = &frame_header.restoration_filter;
-        if filters.gab {
-            pipeline = pipeline
-                .add_inout_stage(GaborishStage::new(
-                    0,
-                    filters.gab_x_weight1,
-                    filters.gab_x_weight2,
-                ))?
-                .add_inout_stage(GaborishStage::new(
-                    1,
-                    filters.gab_y_weight1,
-                    filters.gab_y_weight2,
-                ))?
-                .add_inout_stage(GaborishStage::new(
-                    2,
-                    filters.gab_b_weight1,
-                    filters.gab_b_weight2,
-                ))?;
-        }
-
         let rf = &frame_header.restoration_filter;
+        let mut filters = vec![];
+        if rf.gab {
+            filters.push(RestorationFilter::Gaborish([
+                GaborishStage::new(0, rf.gab_x_weight1, rf.gab_x_weight2),
+                GaborishStage::new(1, rf.gab_y_weight1, rf.gab_y_weight2),
+                GaborishStage::new(2, rf.gab_b_weight1, rf.gab_b_weight2),
+            ]));
+        }
         if rf.epf_iters >= 3 {
-            pipeline = pipeline.add_inout_stage(Epf0Stage::new(
+            filters.push(RestorationFilter::Epf0(Epf0Stage::new(
                 rf.epf_pass0_sigma_scale,
                 rf.epf_border_sad_mul,
                 rf.epf_channel_scale,
                 epf_sigma.as_ref().unwrap().clone(),
-            ))?
+            )));
         }
         if rf.epf_iters >= 1 {
-            pipeline = pipeline.add_inout_stage(Epf1Stage::new(
+            filters.push(RestorationFilter::Epf1(Epf1Stage::new(
                 1.0,
                 rf.epf_border_sad_mul,
                 rf.epf_channel_scale,
                 epf_sigma.as_ref().unwrap().clone(),
-            ))?
+            )));
         }
         if rf.epf_iters >= 2 {
-            pipeline = pipeline.add_inout_stage(Epf2Stage::new(
+            filters.push(RestorationFilter::Epf2(Epf2Stage::new(
                 rf.epf_pass2_sigma_scale,
                 rf.epf_border_sad_mul,
                 rf.epf_channel_scale,
                 epf_sigma.as_ref().unwrap().clone(),
-            ))?
+            )));
         }
+        // Stage taps need the filters to run as separate stages.
+        pipeline = add_restoration_filters(
+            pipeline,
+            filters,
+            frame_header.size(),
+            decoder_state.stage_taps.is_empty(),
+        )?;
 
         let late_ec_upsample = frame_header.upsampling > 1
             && frame_header
@@ -401,19 +836,9 @@ impl Frame {
             }
         }
 
-        let mut linear = false;
         let output_color_info = OutputColorInfo::from_header(&decoder_state.file_header)?;
-        if frame_header.do_ycbcr {
-            pipeline = pipeline.add_inplace_stage(YcbcrToRgbStage::new(0))?;
-        } else if decoder_state.file_header.image_metadata.xyb_encoded {
-            pipeline = pipeline.add_inplace_stage(XybStage::new(0, output_color_info.clone()))?;
-            if decoder_state.xyb_output_linear {
-                linear = true;
-            } else {
-                pipeline = pipeline
-                    .add_inplace_stage(FromLinearStage::new(0, output_color_info.tf.clone()))?;
-            }
-        }
+        let mut linear;
+        (pipeline, linear) = Self::add_color_transform(pipeline, decoder_state, frame_header)?;
 
         if frame_header.needs_blending() {
             if linear {
@@ -469,104 +894,216 @@ impl Frame {
         }
 
         if frame_header.is_visible() {
-            let color_space = decoder_state
-                .file_header
-                .image_metadata
-                .color_encoding
-                .color_space;
-            let num_color_channels = if color_space == ColorSpace::Gray {
-                1
+            return Self::build_visible_output(
+                pipeline,
+                linear,
+                decoder_state,
+                frame_header,
+                pixel_format,
+                output_color_profile,
+                cms,
+                &integer_output,
+            );
+        }
+        pipeline.build()
+    }
+
+    /// Adds the stages that convert the color channels from YCbCr or XYB, if needed. Returns
+    /// whether the color channels are linear afterwards.
+    fn add_color_transform<P: RenderPipeline>(
+        mut pipeline: RenderPipelineBuilder<P>,
+        decoder_state: &DecoderState,
+        frame_header: &FrameHeader,
+    ) -> Result<(RenderPipelineBuilder<P>, bool)> {
+        let mut linear = false;
+        let output_color_info = OutputColorInfo::from_header(&decoder_state.file_header)?;
+        if frame_header.do_ycbcr {
+            pipeline = pipeline.add_inplace_stage(YcbcrToRgbStage::new(0))?;
+        } else if decoder_state.file_header.image_metadata.xyb_encoded {
+            pipeline = pipeline.add_inplace_stage(XybStage::new(0, output_color_info.clone()))?;
+            if decoder_state.xyb_output_linear {
+                linear = true;
             } else {
-                3
-            };
-            // Find the alpha channel info (index and metadata) if the color type requires alpha
-            let alpha_channel_info = if pixel_format.color_type.has_alpha() {
-                decoder_state
-                    .file_header
-                    .image_metadata
+                pipeline =
+                    pipeline.add_inplace_stage(FromLinearStage::new(0, output_color_info.tf))?;
+            }
+        }
+        Ok((pipeline, linear))
+    }
+
+    /// Adds the stages that convert the color channels of a visible frame (linear if `linear` is
+    /// true) to the output color profile and write all channels to the output buffers, and
+    /// builds the pipeline.
+    #[allow(clippy::too_many_arguments)]
+    fn build_visible_output<T: RenderPipeline>(
+        mut pipeline: RenderPipelineBuilder<T>,
+        mut linear: bool,
+        decoder_state: &DecoderState,
+        frame_header: &FrameHeader,
+        pixel_format: &JxlPixelFormat,
+        output_color_profile: &JxlColorProfile,
+        cms: Option<&dyn JxlCms>,
+        integer_output: &[Option<DataTypeTag>],
+    ) -> Result<Box<T>> {
+        let num_channels = frame_header.num_extra_channels as usize + 3;
+        let metadata = &decoder_state.file_header.image_metadata;
+        let output_color_info = OutputColorInfo::from_header(&decoder_state.file_header)?;
+        let color_space = decoder_state
+            .file_header
+            .image_metadata
+            .color_encoding
+            .color_space;
+        let num_color_channels = if color_space == ColorSpace::Gray {
+            1
+        } else {
+            3
+        };
+        if pixel_format.color_type.is_grayscale() && num_color_channels == 3 {
+            return Err(Error::NotGrayscale);
+        }
+        if let Some(embedded) = decoder_state
+            .embedded_color_profile
+            .as_ref()
+            .filter(|p| p.is_cmyk() && !metadata.xyb_encoded)
+        {
+            let same_profile = matches!(
+                (embedded, output_color_profile),
+                (JxlColorProfile::Icc(a), JxlColorProfile::Icc(b)) if a == b
+            );
+            if !same_profile {
+                let cms = cms.ok_or(Error::CmykOutputNoCMS)?;
+                let black_c = metadata
                     .extra_channel_info
                     .iter()
-                    .enumerate()
-                    .find(|x| x.1.ec_type == ExtraChannel::Alpha)
-            } else {
-                None
-            };
-            let alpha_in_color = alpha_channel_info.map(|x| x.0 + 3);
-            // Check if the source alpha is already premultiplied (alpha_associated)
-            let source_alpha_associated =
-                alpha_channel_info.is_some_and(|(_, info)| info.alpha_associated());
-            if pixel_format.color_type.is_grayscale() && num_color_channels == 3 {
-                return Err(Error::NotGrayscale);
+                    .position(|x| x.ec_type == ExtraChannel::Black)
+                    .ok_or(Error::CmykWithoutBlackChannel)?;
+                pipeline = pipeline.add_inplace_stage(CmykStage::new(
+                    black_c,
+                    cms,
+                    embedded.clone(),
+                    output_color_profile.clone(),
+                    metadata.tone_mapping.intensity_target,
+                )?)?;
             }
-            if decoder_state.file_header.image_metadata.xyb_encoded
-                && decoder_state.xyb_output_linear
-                && !linear
-            {
-                pipeline = pipeline
-                    .add_inplace_stage(ToLinearStage::new(0, output_color_info.tf.clone()))?;
+        }
+        let source_encoding = Self::rendered_color_encoding(metadata)?;
+        let conversion = match (&source_encoding, output_color_profile) {
+            (Some(source), JxlColorProfile::Simple(output)) => {
+                Self::color_conversion(source, output).map(|c| (source, output, c))
             }
-            // Determine if we need to fill opaque alpha:
-            // - color_type requests alpha (has_alpha() is true)
-            // - but no actual alpha channel exists in the image (alpha_in_color is None)
-            let fill_opaque_alpha = pixel_format.color_type.has_alpha() && alpha_in_color.is_none();
-
-            // Determine if we should premultiply:
-            // - premultiply_output is requested
-            // - there is an alpha channel in the output
-            // - source is not already premultiplied (to avoid double-premultiplication)
-            let should_premultiply = decoder_state.premultiply_output
-                && alpha_in_color.is_some()
-                && !source_alpha_associated;
-
-            let color_source_channels: &[usize] =
-                match (pixel_format.color_type.is_grayscale(), alpha_in_color) {
-                    (true, None) => &[0],
-                    (true, Some(c)) => &[0, c],
-                    (false, None) => &[0, 1, 2],
-                    (false, Some(c)) => &[0, 1, 2, c],
-                };
-            if let Some(df) = &pixel_format.color_data_format {
-                // Add premultiply stage if needed (before conversion to output format)
-                if should_premultiply && let Some(alpha_channel) = alpha_in_color {
-                    pipeline = pipeline.add_inplace_stage(PremultiplyAlphaStage::new(
+            _ => None,
+        };
+        let gain_map = decoder_state
+            .gain_map
+            .as_ref()
+            .filter(|g| !g.applier.is_identity());
+        let needs_linear = decoder_state.resampling.is_some()
+            || gain_map.is_some()
+            || decoder_state
+                .custom_stages
+                .iter()
+                .any(|(space, _)| *space == JxlCustomStageSpace::Linear);
+        if let Some((source, output, (tf, output_tf, change_primaries))) = conversion {
+            // Transfer function that encodes linear samples for the output, if any.
+            let encoding_tf = if !change_primaries && tf == output_tf {
+                if needs_linear && !linear {
+                    pipeline = pipeline
+                        .add_inplace_stage(ToLinearStage::new(0, output_color_info.tf.clone()))?;
+                    linear = true;
+                }
+                Some(output_color_info.tf.clone())
+            } else {
+                let display_tf = output_color_info.display_tf(&decoder_state.hlg_rendering);
+                if linear && display_tf != output_color_info.tf {
+                    // Go through the encoded signal to re-render for a different display.
+                    pipeline = pipeline
+                        .add_inplace_stage(FromLinearStage::new(0, output_color_info.tf.clone()))?;
+                    linear = false;
+                }
+                if !linear {
+                    pipeline = pipeline.add_inplace_stage(ToLinearStage::new(0, display_tf))?;
+                    linear = true;
+                }
+                if let (
+                    JxlColorEncoding::RgbColorSpace {
+                        white_point,
+                        primaries,
+                        ..
+                    },
+                    JxlColorEncoding::RgbColorSpace {
+                        white_point: output_white_point,
+                        primaries: output_primaries,
+                        ..
+                    },
+                ) = (source, output)
+                    && change_primaries
+                {
+                    pipeline = pipeline.add_inplace_stage(ColorMatrixStage::convert_primaries(
                         0,
+                        (primaries, white_point),
+                        (output_primaries, output_white_point),
+                    )?)?;
+                }
+                if *output_tf != JxlTransferFunction::Linear {
+                    Some(OutputColorInfo::from_encoding(&decoder_state.file_header, output)?.tf)
+                } else {
+                    None
+                }
+            };
+            if linear {
+                if let Some(gain_map) = gain_map {
+                    pipeline = Self::add_gain_map_stages(
+                        pipeline,
+                        gain_map,
+                        source,
+                        output,
                         num_color_channels,
-                        alpha_channel,
-                    ))?;
+                    )?;
                 }
-                // Add conversion stages for non-float output formats
-                pipeline = Self::add_conversion_stages(pipeline, color_source_channels, *df)?;
-                pipeline = pipeline.add_save_stage(
-                    color_source_channels,
-                    metadata.orientation,
-                    0,
-                    pixel_format.color_type,
-                    *df,
-                    fill_opaque_alpha,
+                pipeline = Self::add_custom_stages(
+                    pipeline,
+                    decoder_state,
+                    JxlCustomStageSpace::Linear,
+                    num_channels,
                 )?;
-            }
-            for i in 0..frame_header.num_extra_channels as usize {
-                if let Some(df) = &pixel_format.extra_channel_format[i] {
-                    // Add conversion stages for non-float output formats
-                    pipeline = Self::add_conversion_stages(pipeline, &[3 + i], *df)?;
-                    pipeline = pipeline.add_save_stage(
-                        &[3 + i],
-                        metadata.orientation,
-                        1 + i,
-                        JxlColorType::Grayscale,
-                        *df,
-                        false,
+                if let Some(resampling) = &decoder_state.resampling {
+                    // The remaining stages run on the resampled frame.
+                    let stage = ResampleStage::new(
+                        resampling.filter,
+                        num_channels,
+                        pipeline.size(),
+                        metadata.orientation.map_size(resampling.size),
                     )?;
+                    pipeline = pipeline.add_inout_stage(stage)?;
+                }
+                if let Some(tf) = encoding_tf {
+                    pipeline = pipeline.add_inplace_stage(FromLinearStage::new(0, tf))?;
                 }
             }
+        } else if needs_linear {
+            return Err(Error::LinearNoColorEncoding);
         }
+        pipeline = Self::add_output_stages(
+            pipeline,
+            decoder_state,
+            frame_header.num_extra_channels as usize,
+            pixel_format,
+            integer_output,
+        )?;
         pipeline.build()
     }
 
+    /// Returns the peak memory usage of the render pipeline, if one was created.
+    pub fn peak_render_memory_usage(&mut self) -> Option<usize> {
+        self.render_pipeline.as_ref()?;
+        Some(pipeline!(self, p, p.peak_memory_usage()))
+    }
+
     pub fn prepare_render_pipeline(
         &mut self,
         pixel_format: &JxlPixelFormat,
-        _cms: Option<&dyn JxlCms>,
+        output_color_profile: &JxlColorProfile,
+        cms: Option<&dyn JxlCms>,
     ) -> Result<()> {
         let lf_global = self.lf_global.as_mut().unwrap();
         let epf_sigma = if self.header.restoration_filter.epf_iters > 0 {
@@ -584,6 +1121,8 @@ impl Frame {
                 lf_global,
                 &epf_sigma,
                 pixel_format,
+                output_color_profile,
+                cms,
             )? as Box<dyn std::any::Any>
         } else {
             Self::build_render_pipeline::<LowMemoryRenderPipeline>(
@@ -592,6 +1131,8 @@ impl Frame {
                 lf_global,
                 &epf_sigma,
                 pixel_format,
+                output_color_profile,
+                cms,
             )? as Box<dyn std::any::Any>
         };
         #[cfg(not(test))]
@@ -601,6 +1142,8 @@ impl Frame {
             lf_global,
             &epf_sigma,
             pixel_format,
+            output_color_profile,
+            cms,
         )?;
         self.render_pipeline = Some(render_pipeline);
         self.lf_global_was_rendered = false;
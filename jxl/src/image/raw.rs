@@ -20,16 +20,26 @@ pub struct OwnedRawImage {
 }
 
 impl OwnedRawImage {
-    pub fn new_zeroed_with_padding(
+    /// Returns the padding of an image created by [`Self::new_zeroed_with_padding`] with the
+    /// given size and requested padding.
+    pub fn actual_padding(
         byte_size: (usize, usize),
-        offset: (usize, usize),
         mut padding: (usize, usize),
-    ) -> Result<Self> {
+    ) -> (usize, usize) {
         // Since RawImageBuffer::try_allocate will round up the length of a row to a cache line,
         // might as well declare that as available padding space.
         if !(padding.0 + byte_size.0).is_multiple_of(CACHE_LINE_BYTE_SIZE) {
             padding.0 += CACHE_LINE_BYTE_SIZE - (padding.0 + byte_size.0) % CACHE_LINE_BYTE_SIZE;
         }
+        padding
+    }
+
+    pub fn new_zeroed_with_padding(
+        byte_size: (usize, usize),
+        offset: (usize, usize),
+        padding: (usize, usize),
+    ) -> Result<Self> {
+        let padding = Self::actual_padding(byte_size, padding);
         Ok(Self {
             // Safety note: the returned memory is initialized and part of a single allocation of
             // the correct length.
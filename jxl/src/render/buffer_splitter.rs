@@ -3,7 +3,13 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::{api::JxlOutputBuffer, headers::Orientation, image::Rect, util::ShiftRightCeil};
+use crate::{
+    api::{JxlOutputBuffer, JxlOutputSink},
+    error::Result,
+    headers::Orientation,
+    image::{Image, Rect},
+    util::ShiftRightCeil,
+};
 
 // Information for splitting the output buffers.
 #[derive(Debug)]
@@ -12,16 +18,98 @@ pub(super) struct SaveStageBufferInfo {
     pub(super) orientation: Orientation,
     pub(super) byte_size: usize,
     pub(super) after_extend: bool,
+    pub(super) after_resample: bool,
+}
+
+/// Rows of an output that is passed to a callback, while they are being rendered.
+struct StagingBuffer {
+    image: Image<u8>,
+    // Rect of the output (in pixels) that is currently stored at the top left of `image`.
+    rect: Option<Rect>,
+    pixel_bytes: usize,
+}
+
+impl StagingBuffer {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            image: Image::new((0, 0))?,
+            rect: None,
+            pixel_bytes: 0,
+        })
+    }
+
+    /// Returns a buffer for `rect` of the output, growing the staging image if needed.
+    fn buffer_for(&mut self, rect: Rect, pixel_bytes: usize) -> Result<JxlOutputBuffer<'_>> {
+        let byte_size = (rect.size.0 * pixel_bytes, rect.size.1);
+        let (xsize, ysize) = self.image.size();
+        if xsize < byte_size.0 || ysize < byte_size.1 {
+            self.image = Image::new((xsize.max(byte_size.0), ysize.max(byte_size.1)))?;
+        }
+        self.rect = Some(rect);
+        self.pixel_bytes = pixel_bytes;
+        let rect = Rect {
+            origin: (0, 0),
+            size: byte_size,
+        };
+        Ok(JxlOutputBuffer::from_image_rect_mut(
+            self.image.get_rect_mut(rect).into_raw(),
+        ))
+    }
+
+    /// Passes the rows that were rendered since the last call to `sink`.
+    fn flush(&mut self, sink: &mut JxlOutputSink) {
+        let Some(rect) = self.rect.take() else {
+            return;
+        };
+        for y in 0..rect.size.1 {
+            let row = &self.image.row(y)[..rect.size.0 * self.pixel_bytes];
+            sink.call(rect.origin.0, rect.origin.1 + y, rect.size.0, row);
+        }
+    }
 }
 
 /// Data structure responsible for handing out access to portions of the output buffers.
-pub struct BufferSplitter<'a, 'b>(&'a mut [Option<JxlOutputBuffer<'b>>]);
+///
+/// Outputs that are passed to callbacks are rendered into small staging buffers instead, and the
+/// rendered rows are handed to the callbacks by [`BufferSplitter::flush`].
+pub struct BufferSplitter<'a, 'b> {
+    sinks: &'a mut [Option<JxlOutputSink<'b>>],
+    staging: Vec<Option<StagingBuffer>>,
+}
 
 impl<'a, 'b> BufferSplitter<'a, 'b> {
-    pub fn new(bufs: &'a mut [Option<JxlOutputBuffer<'b>>]) -> Self {
-        Self(bufs)
+    pub fn new(sinks: &'a mut [Option<JxlOutputSink<'b>>]) -> Self {
+        let staging = sinks.iter().map(|_| None).collect();
+        Self { sinks, staging }
+    }
+
+    /// Returns a buffer for `rect` (in pixels of `pixel_bytes` bytes) of the output written to
+    /// `sink`, or `None` if that output is not needed.
+    fn buffer_for<'c>(
+        sink: &'c mut Option<JxlOutputSink<'b>>,
+        staging: &'c mut Option<StagingBuffer>,
+        rect: Rect,
+        pixel_bytes: usize,
+    ) -> Result<Option<JxlOutputBuffer<'c>>> {
+        match sink {
+            None => Ok(None),
+            Some(JxlOutputSink::Buffer(buf)) => {
+                Ok(Some(buf.rect(rect.to_byte_rect_sz(pixel_bytes))))
+            }
+            Some(_) => {
+                if staging.is_none() {
+                    *staging = Some(StagingBuffer::new()?);
+                }
+                Ok(Some(
+                    staging.as_mut().unwrap().buffer_for(rect, pixel_bytes)?,
+                ))
+            }
+        }
     }
 
+    /// Returns buffers for `rect` of the frame. Outputs that are written after a resampling stage
+    /// get buffers for `resampled.0` of the resampled image of size `resampled.1` instead.
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn get_local_buffers(
         &mut self,
         save_buffer_info: &[Option<SaveStageBufferInfo>],
@@ -30,11 +118,11 @@ impl<'a, 'b> BufferSplitter<'a, 'b> {
         frame_size: (usize, usize),
         full_image_size: (usize, usize),
         frame_origin: (isize, isize),
-    ) -> Vec<Option<JxlOutputBuffer<'_>>> {
-        let mut local_buffers = vec![];
-        let buffers = &mut *self.0;
-        local_buffers.reserve(buffers.len());
-        for _ in 0..buffers.len() {
+        resampled: Option<(Rect, (usize, usize))>,
+    ) -> Result<Vec<Option<JxlOutputBuffer<'_>>>> {
+        self.flush();
+        let mut local_buffers = Vec::with_capacity(self.sinks.len());
+        for _ in 0..self.sinks.len() {
             local_buffers.push(None::<JxlOutputBuffer>);
         }
         let rect = if !outside_current_frame {
@@ -42,19 +130,31 @@ impl<'a, 'b> BufferSplitter<'a, 'b> {
         } else {
             rect
         };
-        for (i, (info, buf)) in save_buffer_info.iter().zip(buffers.iter_mut()).enumerate() {
+        let outputs = save_buffer_info
+            .iter()
+            .zip(self.sinks.iter_mut().zip(self.staging.iter_mut()));
+        for (i, (info, (sink, staging))) in outputs.enumerate() {
             let Some(bi) = info else {
                 // We never write to this buffer.
                 continue;
             };
-            let Some(buf) = buf.as_mut() else {
+            if sink.is_none() {
                 // The buffer to write into was not provided.
                 continue;
-            };
+            }
             if outside_current_frame && !bi.after_extend {
                 // Before-extend stages do not write to rects outside the current frame.
                 continue;
             }
+            if bi.after_resample {
+                let Some((rect, size)) = resampled.filter(|(r, _)| r.size.0 != 0 && r.size.1 != 0)
+                else {
+                    continue;
+                };
+                let channel_rect = bi.orientation.display_rect(rect, size);
+                local_buffers[i] = Self::buffer_for(sink, staging, channel_rect, bi.byte_size)?;
+                continue;
+            }
             let mut channel_rect = rect.downsample(bi.downsample);
             if !outside_current_frame {
                 let frame_size = (
@@ -91,13 +191,40 @@ impl<'a, 'b> BufferSplitter<'a, 'b> {
                 continue;
             }
             let channel_rect = bi.orientation.display_rect(channel_rect, full_image_size);
-            let channel_rect = channel_rect.to_byte_rect_sz(bi.byte_size);
-            local_buffers[i] = Some(buf.rect(channel_rect));
+            local_buffers[i] = Self::buffer_for(sink, staging, channel_rect, bi.byte_size)?;
         }
-        local_buffers
+        Ok(local_buffers)
+    }
+
+    /// Returns a list of buffers in which only the output with index `index` is present, covering
+    /// all of it. `size` is the size of the output in pixels of `pixel_bytes` bytes.
+    pub fn get_full_buffers(
+        &mut self,
+        index: usize,
+        size: (usize, usize),
+        pixel_bytes: usize,
+    ) -> Result<Vec<Option<JxlOutputBuffer<'_>>>> {
+        self.flush();
+        let rect = Rect {
+            origin: (0, 0),
+            size,
+        };
+        let mut buffers: Vec<_> = self.sinks.iter().map(|_| None).collect();
+        buffers[index] = match &mut self.sinks[index] {
+            // Memory buffers are checked (and written) in full.
+            Some(JxlOutputSink::Buffer(buf)) => Some(JxlOutputBuffer::reborrow(buf)),
+            sink => Self::buffer_for(sink, &mut self.staging[index], rect, pixel_bytes)?,
+        };
+        Ok(buffers)
     }
 
-    pub fn get_full_buffers(&mut self) -> &mut [Option<JxlOutputBuffer<'b>>] {
-        &mut *self.0
+    /// Passes the rows that were rendered into the buffers most recently handed out by this
+    /// splitter to the corresponding callbacks.
+    pub fn flush(&mut self) {
+        for (sink, staging) in self.sinks.iter_mut().zip(self.staging.iter_mut()) {
+            if let (Some(sink), Some(staging)) = (sink, staging) {
+                staging.flush(sink);
+            }
+        }
     }
 }
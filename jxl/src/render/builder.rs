@@ -3,19 +3,35 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::api::{JxlColorType, JxlDataFormat};
+use crate::api::{
+    CaptureState, JxlBufferPool, JxlColorType, JxlDataFormat, JxlStageTap, SimdLevel,
+};
 use crate::error::{Error, Result};
 use crate::headers::Orientation;
+use crate::image::{DataTypeTag, Image};
 use crate::render::internal::ChannelInfo;
 use crate::render::save::SaveStage;
-use crate::util::{ShiftRightCeil, tracing_wrappers::*};
+use crate::util::{ShiftRightCeil, f16, tracing_wrappers::*};
+use jxl_simd::simd_level_dispatch;
 
-use super::internal::{RenderPipelineShared, Stage};
-use super::stages::ExtendToImageDimensionsStage;
+use super::internal::{RenderPipelineShared, RunInPlaceStage, Stage};
+use super::stages::{ExtendToImageDimensionsStage, TapStage};
 use super::{RenderPipeline, RenderPipelineInOutStage, RenderPipelineInPlaceStage};
 
+/// A stage tap, and the capture stages that were added for it.
+struct TapState {
+    tap: JxlStageTap,
+    // Index of the capture for this pipeline in the tap, once a stage has matched.
+    capture: Option<usize>,
+    // Index and channels of each capture stage.
+    stages: Vec<(usize, Vec<usize>)>,
+}
+
 pub(crate) struct RenderPipelineBuilder<Pipeline: RenderPipeline> {
     shared: RenderPipelineShared<Pipeline::Buffer>,
+    stage_taps: Vec<TapState>,
+    // Instruction set that the stages are run with.
+    simd_level: SimdLevel,
 }
 
 impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
@@ -55,11 +71,80 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
                 num_passes,
                 chunk_size,
                 extend_stage_index: None,
+                resample_stage_index: None,
+                buffer_pool: None,
             },
+            stage_taps: vec![],
+            simd_level: SimdLevel::best(),
+        }
+    }
+
+    pub(super) fn add_stage_internal(self, stage: Stage<Pipeline::Buffer>) -> Result<Self> {
+        if self.stage_taps.is_empty() || matches!(stage, Stage::Save(_)) {
+            return self.push_stage(stage);
+        }
+        let description = stage.to_string();
+        let num_channels = self.shared.channel_info[0].len();
+        let channels: Vec<_> = (0..num_channels)
+            .filter(|c| stage.uses_channel(*c))
+            .collect();
+        let ty = stage.output_type().unwrap_or(stage.input_type());
+        self.push_stage(stage)?
+            .add_tap_stages(&description, channels, ty)
+    }
+
+    /// Adds capture stages for the taps that match the stage that was just added.
+    fn add_tap_stages(
+        mut self,
+        description: &str,
+        channels: Vec<usize>,
+        ty: DataTypeTag,
+    ) -> Result<Self> {
+        if channels.is_empty() {
+            return Ok(self);
+        }
+        for i in 0..self.stage_taps.len() {
+            let state = &mut self.stage_taps[i];
+            if !state.tap.matches(description) {
+                continue;
+            }
+            let mut captures = state.tap.captures.borrow_mut();
+            let capture = *state.capture.get_or_insert_with(|| {
+                captures.push(CaptureState {
+                    stages: vec![],
+                    channels: vec![],
+                });
+                captures.len() - 1
+            });
+            captures[capture].stages.push(description.to_string());
+            drop(captures);
+            let id = self.shared.stages.len();
+            state.stages.push((id, channels.clone()));
+            let tap = state.tap.clone();
+            let channels = channels.clone();
+            macro_rules! push_tap_stage {
+                ($ty: ty) => {{
+                    let stage =
+                        self.box_inplace_stage(TapStage::<$ty>::new(tap, capture, id, channels));
+                    self.push_stage(Stage::InPlace(stage))?
+                }};
+            }
+            self = match ty {
+                DataTypeTag::U8 => push_tap_stage!(u8),
+                DataTypeTag::U16 => push_tap_stage!(u16),
+                DataTypeTag::U32 => push_tap_stage!(u32),
+                DataTypeTag::F32 => push_tap_stage!(f32),
+                DataTypeTag::I8 => push_tap_stage!(i8),
+                DataTypeTag::I16 => push_tap_stage!(i16),
+                DataTypeTag::I32 => push_tap_stage!(i32),
+                DataTypeTag::F16 => push_tap_stage!(f16),
+                DataTypeTag::F64 => push_tap_stage!(f64),
+            };
         }
+        Ok(self)
     }
 
-    pub(super) fn add_stage_internal(mut self, stage: Stage<Pipeline::Buffer>) -> Result<Self> {
+    fn push_stage(mut self, stage: Stage<Pipeline::Buffer>) -> Result<Self> {
         let input_type = stage.input_type();
         let output_type = stage.output_type();
         let shift = stage.shift();
@@ -95,14 +180,33 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
                 });
             }
         }
-        if self.shared.extend_stage_index.is_some()
-            && (shift != (0, 0) || border != (0, 0) || is_extend)
+        let resampling = stage.resampling();
+        if let Some(e) = self.shared.extend_stage_index {
+            let Stage::Extend(e) = &self.shared.stages[e] else {
+                unreachable!("extend stage is not an extend stage");
+            };
+            // Resampling needs pixels around the current frame, which are only available if the
+            // frame covers the whole image.
+            if resampling.is_some()
+                && (e.frame_origin != (0, 0) || e.image_size != self.shared.input_size)
+            {
+                return Err(Error::ResamplingCroppedFrame);
+            }
+            if shift != (0, 0) || (border != (0, 0) && resampling.is_none()) || is_extend {
+                return Err(Error::PipelineInvalidStageAfterExtend(stage.to_string()));
+            }
+        }
+        if self.shared.resample_stage_index.is_some()
+            && (shift != (0, 0) || border != (0, 0) || is_extend || resampling.is_some())
         {
-            return Err(Error::PipelineInvalidStageAfterExtend(stage.to_string()));
+            return Err(Error::PipelineInvalidStageAfterResample(stage.to_string()));
         }
         if is_extend {
             self.shared.extend_stage_index = Some(self.shared.stages.len());
         }
+        if resampling.is_some() {
+            self.shared.resample_stage_index = Some(self.shared.stages.len());
+        }
         debug!(
             new_channel_info = ?after_info,
             extend_stage_index= ?self.shared.extend_stage_index,
@@ -130,6 +234,47 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
         )
     }
 
+    /// Runs the stages with the instruction set `level`, which must be available. Must be called
+    /// before adding stages.
+    pub fn with_simd_level(mut self, level: SimdLevel) -> Self {
+        assert!(self.shared.stages.is_empty());
+        self.simd_level = level;
+        self
+    }
+
+    /// Takes buffers from `pool`, and gives them back to it when the pipeline is dropped.
+    pub fn with_buffer_pool(mut self, pool: Option<JxlBufferPool>) -> Self {
+        self.shared.buffer_pool = pool;
+        self
+    }
+
+    /// Captures the output of the stages that match `taps` (see [`JxlStageTap`]). Must be called
+    /// before adding stages.
+    pub fn with_stage_taps(mut self, taps: &[JxlStageTap]) -> Self {
+        assert!(self.shared.stages.is_empty());
+        self.stage_taps = taps
+            .iter()
+            .map(|tap| TapState {
+                tap: tap.clone(),
+                capture: None,
+                stages: vec![],
+            })
+            .collect();
+        self
+    }
+
+    /// Returns the size of the image that the next stage operates on.
+    pub fn size(&self) -> (usize, usize) {
+        self.size_before(self.shared.stages.len())
+    }
+
+    /// Returns the size of the image that stage `s` operates on.
+    fn size_before(&self, s: usize) -> (usize, usize) {
+        self.shared.stages[..s]
+            .iter()
+            .fold(self.shared.input_size, |size, stage| stage.new_size(size))
+    }
+
     #[instrument(skip_all, err)]
     pub fn add_save_stage(
         self,
@@ -147,6 +292,7 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
             color_type,
             data_format,
             fill_opaque_alpha,
+            self.simd_level,
         );
         self.add_stage_internal(Stage::Save(stage))
     }
@@ -158,12 +304,21 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
 
     #[instrument(skip_all, err)]
     pub fn add_inplace_stage<S: RenderPipelineInPlaceStage>(self, stage: S) -> Result<Self> {
-        self.add_stage_internal(Stage::InPlace(Pipeline::box_inplace_stage(stage)))
+        let stage = self.box_inplace_stage(stage);
+        self.add_stage_internal(Stage::InPlace(stage))
     }
 
     #[instrument(skip_all, err)]
     pub fn add_inout_stage<S: RenderPipelineInOutStage>(self, stage: S) -> Result<Self> {
-        self.add_stage_internal(Stage::InOut(Pipeline::box_inout_stage(stage)))
+        let stage = simd_level_dispatch!(self.simd_level, d => Pipeline::box_inout_stage(d, stage));
+        self.add_stage_internal(Stage::InOut(stage))
+    }
+
+    fn box_inplace_stage<S: RenderPipelineInPlaceStage>(
+        &self,
+        stage: S,
+    ) -> Box<dyn RunInPlaceStage<Pipeline::Buffer>> {
+        simd_level_dispatch!(self.simd_level, d => Pipeline::box_inplace_stage(d, stage))
     }
 
     #[instrument(skip_all, err)]
@@ -240,6 +395,27 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
             }
         }
 
+        // Now that the downsampling of each channel at each stage is known, allocate the images
+        // of the captures. Each channel is captured by the last matching stage that produces it.
+        for state in self.stage_taps.iter() {
+            let Some(capture) = state.capture else {
+                continue;
+            };
+            let mut channels: Vec<_> = (0..num_channels).map(|_| None).collect();
+            for (id, stage_channels) in state.stages.iter() {
+                let size = self.size_before(*id);
+                for &c in stage_channels {
+                    let downsample = self.shared.channel_info[*id][c].downsample;
+                    channels[c] =
+                        Some((*id, (size.0.shrc(downsample.0), size.1.shrc(downsample.1))));
+                }
+            }
+            state.tap.captures.borrow_mut()[capture].channels = channels
+                .into_iter()
+                .map(|c| c.map(|(id, size)| Ok((id, Image::new(size)?))).transpose())
+                .collect::<Result<_>>()?;
+        }
+
         Ok(Box::new(Pipeline::new_from_shared(self.shared)?))
     }
 }
@@ -6,12 +6,14 @@
 use std::any::Any;
 use std::fmt::Display;
 
+use crate::api::JxlBufferPool;
 use crate::error::Result;
 use crate::image::{DataTypeTag, ImageDataType};
+use jxl_simd::SimdDescriptor;
 
 use super::save::SaveStage;
 use super::stages::ExtendToImageDimensionsStage;
-use super::{RenderPipelineInOutStage, RenderPipelineInPlaceStage};
+use super::{RenderPipelineInOutStage, RenderPipelineInPlaceStage, Resampling};
 
 pub enum Stage<Buffer> {
     InPlace(Box<dyn RunInPlaceStage<Buffer>>),
@@ -43,10 +45,18 @@ impl<Buffer: 'static> Stage<Buffer> {
         }
     }
 
-    #[cfg(test)]
+    pub(super) fn resampling(&self) -> Option<Resampling> {
+        match self {
+            Stage::InOut(s) => s.resampling(),
+            _ => None,
+        }
+    }
+
+    /// Returns the size of the image after this stage, given the size before it.
     pub(super) fn new_size(&self, size: (usize, usize)) -> (usize, usize) {
         match self {
             Stage::Extend(e) => e.image_size,
+            Stage::InOut(s) => s.resampling().map_or(size, |r| r.output_size()),
             _ => size,
         }
     }
@@ -102,6 +112,8 @@ pub struct RenderPipelineShared<Buffer> {
     pub chunk_size: usize,
     pub stages: Vec<Stage<Buffer>>,
     pub extend_stage_index: Option<usize>,
+    pub resample_stage_index: Option<usize>,
+    pub buffer_pool: Option<JxlBufferPool>,
 }
 
 impl<Buffer> RenderPipelineShared<Buffer> {
@@ -173,15 +185,27 @@ pub trait RunInPlaceStage<Buffer: PipelineBuffer>: InPlaceStage {
         info: Buffer::InPlaceExtraInfo,
         buffers: &mut [&mut Buffer],
         state: Option<&mut dyn Any>,
-    );
+    ) -> Result<()>;
 }
 
-impl<T: RenderPipelineInPlaceStage> InPlaceStage for T {
+/// A stage, and the SIMD descriptor that the pipeline runs it with.
+pub struct SimdStage<D, S> {
+    pub d: D,
+    pub stage: S,
+}
+
+impl<D, S: Display> Display for SimdStage<D, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.stage.fmt(f)
+    }
+}
+
+impl<D: SimdDescriptor + 'static, T: RenderPipelineInPlaceStage> InPlaceStage for SimdStage<D, T> {
     fn init_local_state(&self) -> Result<Option<Box<dyn Any>>> {
-        self.init_local_state()
+        self.stage.init_local_state()
     }
     fn uses_channel(&self, c: usize) -> bool {
-        self.uses_channel(c)
+        self.stage.uses_channel(c)
     }
     fn ty(&self) -> DataTypeTag {
         T::Type::DATA_TYPE_ID
@@ -195,20 +219,21 @@ pub trait InOutStage: Any + Display {
     fn uses_channel(&self, c: usize) -> bool;
     fn input_type(&self) -> DataTypeTag;
     fn output_type(&self) -> DataTypeTag;
+    fn resampling(&self) -> Option<Resampling>;
 }
 
-impl<T: RenderPipelineInOutStage> InOutStage for T {
+impl<D: SimdDescriptor + 'static, T: RenderPipelineInOutStage> InOutStage for SimdStage<D, T> {
     fn init_local_state(&self) -> Result<Option<Box<dyn Any>>> {
-        self.init_local_state()
+        self.stage.init_local_state()
     }
     fn uses_channel(&self, c: usize) -> bool {
-        self.uses_channel(c)
+        self.stage.uses_channel(c)
     }
     fn shift(&self) -> (u8, u8) {
         T::SHIFT
     }
     fn border(&self) -> (u8, u8) {
-        T::BORDER
+        self.stage.resampling().map_or(T::BORDER, |r| r.border)
     }
     fn input_type(&self) -> DataTypeTag {
         T::InputT::DATA_TYPE_ID
@@ -216,6 +241,9 @@ impl<T: RenderPipelineInOutStage> InOutStage for T {
     fn output_type(&self) -> DataTypeTag {
         T::OutputT::DATA_TYPE_ID
     }
+    fn resampling(&self) -> Option<Resampling> {
+        self.stage.resampling()
+    }
 }
 
 pub trait RunInOutStage<Buffer: PipelineBuffer>: InOutStage {
@@ -9,16 +9,17 @@ use std::any::Any;
 
 use row_buffers::RowBuffer;
 
-use crate::api::JxlOutputBuffer;
-use crate::error::Result;
+use crate::api::JxlOutputSink;
+use crate::error::{Error, Result};
 use crate::image::{Image, ImageDataType, OwnedRawImage, Rect};
 use crate::render::MAX_BORDER;
 use crate::render::buffer_splitter::{BufferSplitter, SaveStageBufferInfo};
 use crate::render::internal::Stage;
-use crate::util::{ShiftRightCeil, tracing_wrappers::*};
+use crate::util::{CeilLog2, ShiftRightCeil, tracing_wrappers::*};
+use jxl_simd::SimdDescriptor;
 
-use super::RenderPipeline;
-use super::internal::{RenderPipelineShared, RunInOutStage, RunInPlaceStage};
+use super::internal::{RenderPipelineShared, RunInOutStage, RunInPlaceStage, SimdStage};
+use super::{RenderPipeline, Resampling};
 
 mod helpers;
 mod render_group;
@@ -26,6 +27,8 @@ pub(super) mod row_buffers;
 mod run_stage;
 mod save;
 
+use save::SaveState;
+
 struct InputBuffer {
     // One buffer per channel.
     data: Vec<Option<OwnedRawImage>>,
@@ -62,11 +65,56 @@ pub struct LowMemoryRenderPipeline {
     sorted_buffer_indices: Vec<Vec<(usize, usize, usize)>>,
     // For each channel, buffers that could be reused to store group data for that channel.
     scratch_channel_buffers: Vec<Vec<OwnedRawImage>>,
+    // Width (in full-resolution pixels) of the tiles that groups are split into for rendering.
+    tile_width: usize,
+    // Bytes used by row buffers, which are allocated once.
+    row_buffer_memory: usize,
+    // Bytes currently held in input buffers and scratch buffers.
+    group_data_memory: usize,
+    peak_memory: usize,
+}
+
+// Groups are rendered in tiles of at most this many bytes of row buffers, so that data stays in
+// L2 cache as it flows from one stage to the next.
+const TILE_BYTE_BUDGET: usize = 256 * 1024;
+#[cfg(test)]
+thread_local! {
+    static TILE_BYTE_BUDGET_OVERRIDE: std::cell::Cell<Option<usize>> = const {
+        std::cell::Cell::new(None)
+    };
+}
+
+/// Runs `f` with low-memory pipelines that split groups into the smallest tiles possible.
+#[cfg(test)]
+pub(crate) fn with_smallest_tiles<R>(f: impl FnOnce() -> R) -> R {
+    TILE_BYTE_BUDGET_OVERRIDE.set(Some(0));
+    let result = f();
+    TILE_BYTE_BUDGET_OVERRIDE.set(None);
+    result
+}
+
+// Smallest tile width that we split groups into. This is a multiple of the largest possible
+// channel downsampling.
+const MIN_TILE_WIDTH: usize = 64;
+
+fn raw_image_memory(image: &OwnedRawImage) -> usize {
+    let (xsize, ysize) = image.byte_size();
+    xsize * ysize
 }
 
 impl LowMemoryRenderPipeline {
-    // TODO(veluca): most of this logic will need to change to ensure better cache utilization and
-    // lower memory usage.
+    fn resampling(&self) -> Option<Resampling> {
+        self.shared
+            .resample_stage_index
+            .map(|r| self.shared.stages[r].resampling().unwrap())
+    }
+
+    fn update_peak_memory(&mut self) {
+        self.peak_memory = self
+            .peak_memory
+            .max(self.row_buffer_memory + self.group_data_memory);
+    }
+
     fn render_with_new_group(
         &mut self,
         new_group_id: usize,
@@ -142,24 +190,37 @@ impl LowMemoryRenderPipeline {
                 } else {
                     ((0, 0), self.shared.input_size)
                 };
-                let gsz = (
-                    1 << self.shared.log_group_size,
-                    1 << self.shared.log_group_size,
-                );
-                let rect_to_render = Rect {
-                    size: gsz,
-                    origin: (gsz.0 * gx, gsz.1 * gy),
-                };
-                let mut local_buffers = buffer_splitter.get_local_buffers(
-                    &self.save_buffer_info,
-                    rect_to_render,
-                    false,
-                    self.shared.input_size,
-                    size,
-                    origin,
-                );
-
-                self.render_group((gx, gy), &mut local_buffers)?;
+                let gsz = 1 << self.shared.log_group_size;
+                let (group_xsize, group_ysize) = self.shared.group_size(g);
+                for tile_x0 in (0..group_xsize).step_by(self.tile_width) {
+                    let rect_to_render = Rect {
+                        size: (self.tile_width, gsz),
+                        origin: (gsz * gx + tile_x0, gsz * gy),
+                    };
+                    let resampled = self.resampling().map(|r| {
+                        let x0 = gsz * gx + tile_x0;
+                        let xs =
+                            r.x.outputs(x0..x0 + self.tile_width.min(group_xsize - tile_x0));
+                        let ys = r.y.outputs(gsz * gy..gsz * gy + group_ysize);
+                        let rect = Rect {
+                            origin: (xs.start, ys.start),
+                            size: (xs.len(), ys.len()),
+                        };
+                        (rect, r.output_size())
+                    });
+                    let mut local_buffers = buffer_splitter.get_local_buffers(
+                        &self.save_buffer_info,
+                        rect_to_render,
+                        false,
+                        self.shared.input_size,
+                        size,
+                        origin,
+                        resampled,
+                    )?;
+
+                    self.render_group((gx, gy), tile_x0, &mut local_buffers)?;
+                    buffer_splitter.flush();
+                }
 
                 self.input_buffers[g].completed_passes = fully_ready_passes;
             }
@@ -199,6 +260,29 @@ impl LowMemoryRenderPipeline {
     }
 }
 
+impl Drop for LowMemoryRenderPipeline {
+    fn drop(&mut self) {
+        let Some(pool) = &self.shared.buffer_pool else {
+            return;
+        };
+        let group_data = self
+            .input_buffers
+            .iter_mut()
+            .flat_map(|b| b.data.iter_mut().filter_map(Option::take))
+            .chain(
+                self.scratch_channel_buffers
+                    .iter_mut()
+                    .flat_map(std::mem::take),
+            );
+        for buffer in group_data {
+            pool.recycle_image(buffer);
+        }
+        for buffer in std::mem::take(&mut self.row_buffers).into_iter().flatten() {
+            buffer.recycle(pool);
+        }
+    }
+}
+
 impl RenderPipeline for LowMemoryRenderPipeline {
     type Buffer = RowBuffer;
 
@@ -242,30 +326,136 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             }
         }
 
-        let mut initial_buffers = vec![];
-        for chan in 0..nc {
-            initial_buffers.push(RowBuffer::new(
-                shared.channel_info[0][chan].ty.unwrap(),
-                next_border_and_cur_downsample[0][chan].0 as usize,
-                0,
-                shared.chunk_size >> shared.channel_info[0][chan].downsample.0,
-            )?);
+        // Compute the amount of border pixels needed per channel, per stage.
+        let mut border_pixels = vec![(0usize, 0usize); nc];
+        let mut border_pixels_per_stage = vec![];
+        for s in shared.stages.iter().rev() {
+            let mut stage_max = (0, 0);
+            for (c, bp) in border_pixels.iter_mut().enumerate() {
+                if !s.uses_channel(c) {
+                    continue;
+                }
+                stage_max.0 = stage_max.0.max(bp.0);
+                stage_max.1 = stage_max.1.max(bp.1);
+
+                bp.0 = bp.0.shrc(s.shift().0) + s.border().0 as usize;
+                bp.1 = bp.1.shrc(s.shift().1) + s.border().1 as usize;
+            }
+            border_pixels_per_stage.push(stage_max);
         }
-        let mut row_buffers = vec![initial_buffers];
+        border_pixels_per_stage.reverse();
 
-        // Allocate buffers.
-        for (i, stage) in shared.stages.iter().enumerate() {
-            let mut stage_buffers = vec![];
-            for (next_y_border, (dsx, _)) in next_border_and_cur_downsample[i + 1].iter() {
-                stage_buffers.push(RowBuffer::new(
-                    stage.output_type().unwrap(),
-                    *next_y_border as usize,
-                    stage.shift().1 as usize,
-                    shared.chunk_size >> *dsx,
-                )?);
+        let resampling = shared
+            .resample_stage_index
+            .map(|r| shared.stages[r].resampling().unwrap());
+        if resampling.is_none() {
+            assert!(border_pixels_per_stage[0].0 <= MAX_BORDER);
+        }
+        // Rendering a group only reads data from adjacent groups.
+        for (c, bp) in border_pixels.iter().enumerate() {
+            let (dx, dy) = shared.channel_info[0][c].downsample;
+            let group_size = 1 << shared.log_group_size;
+            if bp.0 > group_size >> dx || bp.1 > group_size >> dy {
+                return Err(Error::ResamplingBorderTooLarge(bp.0.max(bp.1)));
             }
-            row_buffers.push(stage_buffers);
         }
+
+        // Rows of `tile_width` full-resolution pixels (before resampling, if `resampled` is false)
+        // have this many pixels in a channel with downsampling `dsx`.
+        let row_len = |tile_width: usize, dsx: u8, resampled: bool| match resampling {
+            Some(r) if resampled => r.x.max_outputs(tile_width),
+            _ => tile_width >> dsx,
+        };
+
+        // Type, y border, y shift, x downsampling, x border and whether the row is resampled, for
+        // each row buffer.
+        let mut buffer_params = vec![
+            (0..nc)
+                .map(|chan| {
+                    (
+                        shared.channel_info[0][chan].ty.unwrap(),
+                        next_border_and_cur_downsample[0][chan].0 as usize,
+                        0,
+                        shared.channel_info[0][chan].downsample.0,
+                        border_pixels[chan].0,
+                        false,
+                    )
+                })
+                .collect::<Vec<_>>(),
+        ];
+        for (i, stage) in shared.stages.iter().enumerate() {
+            let y_shift = match stage.resampling() {
+                // Enough rows to store all the output rows of an input row.
+                Some(r) => r.y.max_outputs(1).ceil_log2(),
+                None => stage.shift().1 as usize,
+            };
+            let x_shift = stage.shift().0 as usize;
+            let x_border = border_pixels_per_stage[i].0.shrc(x_shift) << x_shift;
+            let resampled = shared.resample_stage_index.is_some_and(|r| i >= r);
+            buffer_params.push(
+                next_border_and_cur_downsample[i + 1]
+                    .iter()
+                    .map(|(next_y_border, (dsx, _))| {
+                        (
+                            stage.output_type().unwrap(),
+                            *next_y_border as usize,
+                            y_shift,
+                            *dsx,
+                            x_border,
+                            resampled,
+                        )
+                    })
+                    .collect(),
+            );
+        }
+
+        // Halve the tile width until the row buffers of all the stages fit in the budget.
+        let tile_bytes = |tile_width: usize| -> usize {
+            buffer_params
+                .iter()
+                .flatten()
+                .map(|(ty, border, shift, dsx, x_border, resampled)| {
+                    RowBuffer::num_rows(*border, *shift)
+                        * ty.size()
+                        * (row_len(tile_width, *dsx, *resampled) + 2 * x_border)
+                })
+                .sum()
+        };
+        #[cfg(not(test))]
+        let budget = TILE_BYTE_BUDGET;
+        #[cfg(test)]
+        let budget = TILE_BYTE_BUDGET_OVERRIDE.get().unwrap_or(TILE_BYTE_BUDGET);
+        let mut tile_width = shared.chunk_size;
+        while tile_width > MIN_TILE_WIDTH
+            && tile_width.is_multiple_of(2)
+            && tile_bytes(tile_width) > budget
+        {
+            tile_width /= 2;
+        }
+        debug!(
+            "rendering groups in tiles of width {tile_width} ({} bytes of row buffers)",
+            tile_bytes(tile_width)
+        );
+
+        // Allocate buffers.
+        let row_buffers = buffer_params
+            .iter()
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|(ty, border, shift, dsx, x_border, resampled)| {
+                        RowBuffer::new_pooled(
+                            shared.buffer_pool.as_ref(),
+                            *ty,
+                            *border,
+                            *shift,
+                            *x_border,
+                            row_len(tile_width, *dsx, *resampled),
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
         // Compute information to be used to compute sub-rects for "save" stages to operate on
         // rects.
         let mut save_buffer_info = vec![];
@@ -285,6 +475,7 @@ impl RenderPipeline for LowMemoryRenderPipeline {
                         orientation: s.orientation,
                         byte_size: s.data_format.bytes_per_sample() * s.output_channels(),
                         after_extend: shared.extend_stage_index.is_some_and(|e| i > e),
+                        after_resample: shared.resample_stage_index.is_some_and(|r| i > r),
                     };
                     while save_buffer_info.len() <= s.output_buffer_index {
                         save_buffer_info.push(None);
@@ -295,27 +486,6 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             }
         }
 
-        // Compute the amount of border pixels needed per channel, per stage.
-        let mut border_pixels = vec![(0usize, 0usize); nc];
-        let mut border_pixels_per_stage = vec![];
-        for s in shared.stages.iter().rev() {
-            let mut stage_max = (0, 0);
-            for (c, bp) in border_pixels.iter_mut().enumerate() {
-                if !s.uses_channel(c) {
-                    continue;
-                }
-                stage_max.0 = stage_max.0.max(bp.0);
-                stage_max.1 = stage_max.1.max(bp.1);
-
-                bp.0 = bp.0.shrc(s.shift().0) + s.border().0 as usize;
-                bp.1 = bp.1.shrc(s.shift().1) + s.border().1 as usize;
-            }
-            border_pixels_per_stage.push(stage_max);
-        }
-        border_pixels_per_stage.reverse();
-
-        assert!(border_pixels_per_stage[0].0 <= MAX_BORDER);
-
         let downsampling_for_stage: Vec<_> = shared
             .stages
             .iter()
@@ -343,7 +513,8 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             if let Stage::Save(s) = stage {
                 if s.fill_opaque_alpha {
                     let (dx, _dy) = downsampling_for_stage[i];
-                    let row_len = shared.chunk_size >> dx;
+                    let resampled = shared.resample_stage_index.is_some_and(|r| i > r);
+                    let row_len = row_len(tile_width, dx as u8, resampled);
                     let fill_pattern = s.data_format.opaque_alpha_bytes();
                     let buf =
                         RowBuffer::new_filled(s.data_format.data_type(), row_len, &fill_pattern)?;
@@ -378,6 +549,13 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             })
             .collect();
 
+        let row_buffer_memory = row_buffers
+            .iter()
+            .flatten()
+            .chain(opaque_alpha_buffers.iter().flatten())
+            .map(RowBuffer::byte_size)
+            .sum();
+
         Ok(Self {
             input_buffers,
             stage_input_buffer_index,
@@ -390,23 +568,34 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             local_states: shared
                 .stages
                 .iter()
-                .map(|x| x.init_local_state())
+                .map(|x| match x {
+                    Stage::Save(_) => Ok(Some(Box::new(SaveState::default()) as Box<dyn Any>)),
+                    _ => x.init_local_state(),
+                })
                 .collect::<Result<_>>()?,
             shared,
             downsampling_for_stage,
             opaque_alpha_buffers,
             sorted_buffer_indices,
             scratch_channel_buffers: (0..nc).map(|_| vec![]).collect(),
+            tile_width,
+            row_buffer_memory,
+            group_data_memory: 0,
+            peak_memory: row_buffer_memory,
         })
     }
 
     #[instrument(skip_all, err)]
     fn get_buffer<T: ImageDataType>(&mut self, channel: usize) -> Result<Image<T>> {
         if let Some(b) = self.scratch_channel_buffers[channel].pop() {
+            self.group_data_memory -= raw_image_memory(&b);
             return Ok(Image::from_raw(b));
         }
         let sz = self.shared.group_size_for_channel(channel, T::DATA_TYPE_ID);
-        Image::<T>::new(sz)
+        match &self.shared.buffer_pool {
+            Some(pool) => pool.take_image(sz),
+            None => Image::<T>::new(sz),
+        }
     }
 
     fn set_buffer_for_group<T: ImageDataType>(
@@ -423,26 +612,30 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             channel,
             T::DATA_TYPE_ID,
         );
-        self.input_buffers[group_id].data[channel] = Some(buf.into_raw());
+        let buf = buf.into_raw();
+        self.group_data_memory += raw_image_memory(&buf);
+        if let Some(old) = self.input_buffers[group_id].data[channel].replace(buf) {
+            self.group_data_memory -= raw_image_memory(&old);
+        }
+        self.update_peak_memory();
         self.shared.group_chan_ready_passes[group_id][channel] += num_passes;
 
         self.render_with_new_group(group_id, buffer_splitter)
     }
 
-    fn check_buffer_sizes(&self, buffers: &mut [Option<JxlOutputBuffer>]) -> Result<()> {
+    fn check_buffer_sizes(&self, buffers: &mut [Option<JxlOutputSink>]) -> Result<()> {
         // Check that buffer sizes are correct.
         let mut size = self.shared.input_size;
         for (i, s) in self.shared.stages.iter().enumerate() {
-            match s {
-                Stage::Extend(e) => size = e.image_size,
-                Stage::Save(s) => {
-                    let (dx, dy) = self.downsampling_for_stage[i];
-                    s.check_buffer_size(
-                        (size.0 >> dx, size.1 >> dy),
-                        buffers[s.output_buffer_index].as_ref(),
-                    )?
-                }
-                _ => {}
+            size = s.new_size(size);
+            if let Stage::Save(s) = s {
+                let (dx, dy) = self.downsampling_for_stage[i];
+                s.check_buffer_size(
+                    (size.0 >> dx, size.1 >> dy),
+                    buffers[s.output_buffer_index]
+                        .as_ref()
+                        .and_then(|b| b.buffer()),
+                )?
             }
         }
         Ok(())
@@ -464,14 +657,14 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             e.frame_origin.1 + self.shared.input_size.1 as isize,
         );
         // Split the full image area in 4 strips: left and right of the frame, and above and below.
-        // We divide each part further in strips of width self.shared.chunk_size.
+        // We divide each part further in strips of width self.tile_width.
         let mut strips = vec![];
         // Above (including left and right)
         if e.frame_origin.1 > 0 {
             let xend = e.image_size.0;
             let yend = (e.frame_origin.1 as usize).min(e.image_size.1);
-            for x in (0..xend).step_by(self.shared.chunk_size) {
-                let xe = (x + self.shared.chunk_size).min(xend);
+            for x in (0..xend).step_by(self.tile_width) {
+                let xe = (x + self.tile_width).min(xend);
                 strips.push((x..xe, 0..yend));
             }
         }
@@ -480,8 +673,8 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             let ystart = frame_end.1.max(0) as usize;
             let yend = e.image_size.1;
             let xend = e.image_size.0;
-            for x in (0..xend).step_by(self.shared.chunk_size) {
-                let xe = (x + self.shared.chunk_size).min(xend);
+            for x in (0..xend).step_by(self.tile_width) {
+                let xe = (x + self.tile_width).min(xend);
                 strips.push((x..xe, ystart..yend));
             }
         }
@@ -490,8 +683,8 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             let ystart = e.frame_origin.1.max(0) as usize;
             let yend = (frame_end.1 as usize).min(e.image_size.1);
             let xend = (e.frame_origin.0 as usize).min(e.image_size.0);
-            for x in (0..xend).step_by(self.shared.chunk_size) {
-                let xe = (x + self.shared.chunk_size).min(xend);
+            for x in (0..xend).step_by(self.tile_width) {
+                let xe = (x + self.tile_width).min(xend);
                 strips.push((x..xe, ystart..yend));
             }
         }
@@ -501,8 +694,8 @@ impl RenderPipeline for LowMemoryRenderPipeline {
             let xend = e.image_size.0;
             let ystart = e.frame_origin.1.max(0) as usize;
             let yend = (frame_end.1 as usize).min(e.image_size.1);
-            for x in (xstart..xend).step_by(self.shared.chunk_size) {
-                let xe = (x + self.shared.chunk_size).min(xend);
+            for x in (xstart..xend).step_by(self.tile_width) {
+                let xe = (x + self.tile_width).min(xend);
                 strips.push((x..xe, ystart..yend));
             }
         }
@@ -522,21 +715,29 @@ impl RenderPipeline for LowMemoryRenderPipeline {
                 full_image_size,
                 full_image_size,
                 (0, 0),
-            );
+                None,
+            )?;
             self.render_outside_frame(xrange, yrange, &mut local_buffers)?;
+            buffer_splitter.flush();
         }
         Ok(())
     }
 
-    fn box_inout_stage<S: super::RenderPipelineInOutStage>(
+    fn peak_memory_usage(&self) -> usize {
+        self.peak_memory
+    }
+
+    fn box_inout_stage<D: SimdDescriptor + 'static, S: super::RenderPipelineInOutStage>(
+        d: D,
         stage: S,
     ) -> Box<dyn RunInOutStage<Self::Buffer>> {
-        Box::new(stage)
+        Box::new(SimdStage { d, stage })
     }
 
-    fn box_inplace_stage<S: super::RenderPipelineInPlaceStage>(
+    fn box_inplace_stage<D: SimdDescriptor + 'static, S: super::RenderPipelineInPlaceStage>(
+        d: D,
         stage: S,
     ) -> Box<dyn RunInPlaceStage<Self::Buffer>> {
-        Box::new(stage)
+        Box::new(SimdStage { d, stage })
     }
 }
@@ -8,7 +8,7 @@ use std::ops::Range;
 use crate::{
     api::JxlOutputBuffer,
     error::Result,
-    image::DataTypeTag,
+    image::{DataTypeTag, Rect},
     render::{
         internal::Stage,
         low_memory_pipeline::{
@@ -19,7 +19,7 @@ use crate::{
     util::{ShiftRightCeil, SmallVec, tracing_wrappers::*},
 };
 
-use super::{LowMemoryRenderPipeline, row_buffers::RowBuffer};
+use super::LowMemoryRenderPipeline;
 
 // Most images have at most 7 channels (RGBA + noise extra channels).
 // 8 gives a bit extra leeway and makes the size a power of two.
@@ -28,10 +28,11 @@ pub(super) type ChannelVec<T> = SmallVec<T, 8>;
 fn apply_x_padding(
     input_type: DataTypeTag,
     row: &mut [u8],
+    x0_offset: usize,
     to_pad: Range<isize>,
     valid_pixels: Range<isize>,
 ) {
-    let x0_offset = RowBuffer::x0_byte_offset() as isize;
+    let x0_offset = x0_offset as isize;
     let num_valid = valid_pixels.clone().count();
     let sz = input_type.size();
     match sz {
@@ -70,12 +71,19 @@ fn apply_x_padding(
 }
 
 impl LowMemoryRenderPipeline {
-    fn fill_initial_buffers(&mut self, c: usize, y: usize, y0: usize, (gx, gy): (usize, usize)) {
+    fn fill_initial_buffers(
+        &mut self,
+        c: usize,
+        y: usize,
+        y0: usize,
+        (gx, gy): (usize, usize),
+        tile_x0: usize,
+    ) {
         let ty = self.shared.channel_info[0][c]
             .ty
             .expect("Channel info should be populated at this point");
-        let gys = 1
-            << (self.shared.log_group_size - self.shared.channel_info[0][c].downsample.1 as usize);
+        let (dx, dy) = self.shared.channel_info[0][c].downsample;
+        let gys = 1 << (self.shared.log_group_size - dy as usize);
 
         let (input_y, igy) = if y < y0 {
             (y + gys - y0, gy - 1)
@@ -85,38 +93,55 @@ impl LowMemoryRenderPipeline {
             (y - y0, gy)
         };
 
+        let x0_offset = self.row_buffers[0][c].x0_byte_offset();
         let output_row = self.row_buffers[0][c].get_row_mut::<u8>(y);
-        // Both are in units of bytes.
-        let x0_offset = RowBuffer::x0_byte_offset();
-        let extrax = self.input_border_pixels[c].0 * ty.size();
+        let sz = ty.size();
+        let extrax = self.input_border_pixels[c].0;
 
         let base_gid = igy * self.shared.group_count.0 + gx;
 
+        // All the x coordinates below are in pixels, relative to the start of the current group.
+        let input_buf = self.input_buffers[base_gid].data[c].as_ref().unwrap();
+        let gxs = input_buf.byte_size().0 / sz;
+        let tile_start = tile_x0 >> dx;
+        let tile_end = (tile_x0 + self.tile_width).shrc(dx).min(gxs);
+        let start = tile_start as isize - extrax as isize;
+        let end = tile_end + extrax;
+        // Position in the output row of the pixel at x.
+        let out_pos =
+            |x: isize| (x0_offset as isize + (x - tile_start as isize) * sz as isize) as usize;
+
         // Previous group horizontally, if any.
-        if gx > 0 && extrax != 0 {
+        if start < 0 && gx > 0 {
             let input_buf = self.input_buffers[base_gid - 1].data[c].as_ref().unwrap();
             let input_row = input_buf.row(input_y);
-            output_row[x0_offset - extrax..x0_offset]
-                .copy_from_slice(&input_row[input_buf.byte_size().0 - extrax..]);
+            let num_bytes = (-start) as usize * sz;
+            output_row[out_pos(start)..out_pos(0)]
+                .copy_from_slice(&input_row[input_buf.byte_size().0 - num_bytes..]);
         }
-        let input_buf = self.input_buffers[base_gid].data[c].as_ref().unwrap();
         let input_row = input_buf.row(input_y);
-        let gxs = input_buf.byte_size().0; // bytes
-        output_row[x0_offset..x0_offset + gxs].copy_from_slice(input_row);
+        let cur_start = start.max(0) as usize;
+        let cur_end = end.min(gxs);
+        output_row[out_pos(cur_start as isize)..out_pos(cur_end as isize)]
+            .copy_from_slice(&input_row[cur_start * sz..cur_end * sz]);
         // Next group horizontally, if any.
-        if gx + 1 < self.shared.group_count.0 && extrax != 0 {
+        if end > gxs && gx + 1 < self.shared.group_count.0 {
             let input_buf = self.input_buffers[base_gid + 1].data[c].as_ref().unwrap();
             let input_row = input_buf.row(input_y);
-            let dx = self.shared.channel_info[0][c].downsample.0;
             let gid = gy * self.shared.group_count.0 + gx;
             let next_group_xsize = self.shared.group_size(gid + 1).0.shrc(dx);
-            let border_x = extrax.min(next_group_xsize * ty.size());
-            output_row[gxs + x0_offset..gxs + x0_offset + border_x]
-                .copy_from_slice(&input_row[..border_x]);
-            if border_x < extrax {
-                let pad_from = ((gxs + border_x) / ty.size()) as isize;
-                let pad_to = ((gxs + extrax) / ty.size()) as isize;
-                apply_x_padding(ty, output_row, pad_from..pad_to, 0..pad_from);
+            let border_x = (end - gxs).min(next_group_xsize);
+            output_row[out_pos(gxs as isize)..out_pos((gxs + border_x) as isize)]
+                .copy_from_slice(&input_row[..border_x * sz]);
+            if gxs + border_x < end {
+                let to_tile = |x: usize| x as isize - tile_start as isize;
+                apply_x_padding(
+                    ty,
+                    output_row,
+                    x0_offset,
+                    to_tile(gxs + border_x)..to_tile(end),
+                    to_tile(cur_start)..to_tile(gxs + border_x),
+                );
             }
         }
     }
@@ -126,11 +151,16 @@ impl LowMemoryRenderPipeline {
     pub(super) fn render_group(
         &mut self,
         (gx, gy): (usize, usize),
+        tile_x0: usize,
         buffers: &mut [Option<JxlOutputBuffer>],
     ) -> Result<()> {
         let gid = gy * self.shared.group_count.0 + gx;
-        let (xsize, num_rows) = self.shared.group_size(gid);
-        let (x0, y0) = self.shared.group_offset(gid);
+        let (group_xsize, num_rows) = self.shared.group_size(gid);
+        let (group_x0, y0) = self.shared.group_offset(gid);
+        let xsize = (group_xsize - tile_x0).min(self.tile_width);
+        let x0 = group_x0 + tile_x0;
+        let is_first_xgroup = x0 == 0;
+        let is_last_xgroup = x0 + xsize == self.shared.input_size.0;
 
         let num_channels = self.shared.num_channels();
         let mut num_extra_rows = 0;
@@ -154,7 +184,16 @@ impl LowMemoryRenderPipeline {
         let vy0 = y0.saturating_sub(num_extra_rows);
         let vy1 = y0 + num_rows + num_extra_rows;
 
+        // The resampling stage caches filtered rows of the current tile, which might have been
+        // rendered before with less data.
+        let resample_stage = self.shared.resample_stage_index;
+        if let Some(r) = resample_stage {
+            self.local_states[r] = self.shared.stages[r].init_local_state()?;
+        }
+
         for vy in vy0..vy1 {
+            // The input row that the resampling stage processed in this iteration, if any.
+            let mut resampled_row = None;
             let mut current_origin = (0, 0);
             let mut current_size = self.shared.input_size;
 
@@ -176,14 +215,20 @@ impl LowMemoryRenderPipeline {
                     continue;
                 }
                 let y = y as usize;
-                self.fill_initial_buffers(c, y, y0 >> dy, (gx, gy));
+                self.fill_initial_buffers(c, y, y0 >> dy, (gx, gy), tile_x0);
             }
-            // Step 2: go through stages one by one.
+            // Step 2: go through stages one by one, up to the resampling stage if any.
             for (i, stage) in self.shared.stages.iter().enumerate() {
+                if resample_stage.is_some_and(|r| i > r) {
+                    break;
+                }
                 let (dx, dy) = self.downsampling_for_stage[i];
                 // The logic below uses *virtual* y coordinates, so we need to convert the border
                 // amount appropriately.
-                let scaled_y_border = self.stage_output_border_pixels[i].1 << dy;
+                // The border is in output pixels of the stage, whose vertical downsampling is
+                // reduced by the stage's shift.
+                let scaled_y_border =
+                    self.stage_output_border_pixels[i].1 << (dy - stage.shift().1 as usize);
                 // I knew the reason behind this formula at some point, but now I don't.
                 let stage_vy = vy as isize - num_extra_rows as isize + scaled_y_border as isize;
                 if stage_vy % (1 << dy) != 0 {
@@ -215,13 +260,13 @@ impl LowMemoryRenderPipeline {
                                 current_row: y,
                                 group_x0: x0 >> dx,
                                 out_extra_x,
-                                is_first_xgroup: gx == 0,
-                                is_last_xgroup: gx + 1 == self.shared.group_count.0,
+                                is_first_xgroup,
+                                is_last_xgroup,
                                 image_height: shifted_ysize,
                             },
                             &mut buffers,
                             self.local_states[i].as_deref_mut(),
-                        );
+                        )?;
                     }
                     Stage::Save(s) => {
                         // Find buffers for channels that will be saved.
@@ -241,6 +286,7 @@ impl LowMemoryRenderPipeline {
                             (x0 >> dx, y0 >> dy),
                             current_size,
                             current_origin,
+                            self.local_states[i].as_deref_mut(),
                         )?;
                     }
                     Stage::Extend(s) => {
@@ -248,35 +294,47 @@ impl LowMemoryRenderPipeline {
                         current_origin = s.frame_origin;
                     }
                     Stage::InOut(s) => {
-                        let borderx = s.border().0 as usize;
+                        let borderx = s.border().0 as isize;
                         let bordery = s.border().1 as isize;
-                        // Apply x padding.
-                        if gx == 0 && borderx != 0 {
+                        // Apply x padding where the pixels that this stage reads extend beyond
+                        // the image edges. All coordinates are relative to the current tile.
+                        let in_extra_x = out_extra_x.shrc(s.shift().0) as isize;
+                        let read_start = if is_first_xgroup { 0 } else { -in_extra_x } - borderx;
+                        let read_end = shifted_xsize as isize
+                            + if is_last_xgroup { 0 } else { in_extra_x }
+                            + borderx;
+                        let image_start = -((x0 >> dx) as isize);
+                        let image_end = self.shared.input_size.0.shrc(dx) as isize + image_start;
+                        if borderx != 0 && read_start < image_start {
                             for (si, ci) in self.stage_input_buffer_index[i].iter() {
+                                let buffer = &mut self.row_buffers[*si][*ci];
+                                let x0_offset = buffer.x0_byte_offset();
                                 for iy in -bordery..=bordery {
                                     let y = mirror(y as isize + iy, shifted_ysize);
                                     apply_x_padding(
                                         s.input_type(),
-                                        self.row_buffers[*si][*ci].get_row_mut::<u8>(y),
-                                        -(borderx as isize)..0,
-                                        // Either xsize is the actual size of the image, or it is
-                                        // much larger than borderx, so this works out either way.
-                                        0..shifted_xsize as isize,
+                                        buffer.get_row_mut::<u8>(y),
+                                        x0_offset,
+                                        read_start..image_start,
+                                        image_start..image_end.min(read_end),
                                     );
                                 }
                             }
                         }
-                        if gx + 1 == self.shared.group_count.0 && borderx != 0 {
+                        if borderx != 0 && read_end > image_end {
                             for (si, ci) in self.stage_input_buffer_index[i].iter() {
+                                let buffer = &mut self.row_buffers[*si][*ci];
+                                let x0_offset = buffer.x0_byte_offset();
                                 for iy in -bordery..=bordery {
                                     let y = mirror(y as isize + iy, shifted_ysize);
                                     apply_x_padding(
                                         s.input_type(),
-                                        self.row_buffers[*si][*ci].get_row_mut::<u8>(y),
-                                        shifted_xsize as isize..(shifted_xsize + borderx) as isize,
-                                        // borderx..0 is either data from the neighbouring group or
-                                        // data that was filled in by the iteration above.
-                                        -(borderx as isize)..shifted_xsize as isize,
+                                        buffer.get_row_mut::<u8>(y),
+                                        x0_offset,
+                                        image_end..read_end,
+                                        // Pixels before image_end are either image data or data
+                                        // that was filled in by the iteration above.
+                                        read_start.max(image_start - borderx)..image_end,
                                     );
                                 }
                             }
@@ -293,17 +351,45 @@ impl LowMemoryRenderPipeline {
                                 current_row: y,
                                 group_x0: x0 >> dx,
                                 out_extra_x,
-                                is_first_xgroup: gx == 0,
-                                is_last_xgroup: gx + 1 == self.shared.group_count.0,
+                                is_first_xgroup,
+                                is_last_xgroup,
                                 image_height: shifted_ysize,
                             },
                             &input_data,
                             &mut outb[0][..],
                             self.local_states[i].as_deref_mut(),
                         );
+                        if resample_stage == Some(i) {
+                            resampled_row = Some(y);
+                        }
                     }
                 }
             }
+            // Step 3: run the stages after the resampling stage on its output rows.
+            if let Some(y) = resampled_row {
+                let r = self.shared.stages[resample_stage.unwrap()]
+                    .resampling()
+                    .unwrap();
+                let out_x = r.x.outputs(x0..x0 + xsize);
+                if out_x.is_empty() {
+                    continue;
+                }
+                let out_y = r.y.outputs(y0..y0 + num_rows);
+                let rect = Rect {
+                    origin: (out_x.start, out_y.start),
+                    size: (out_x.len(), out_y.len()),
+                };
+                for oy in r.y.outputs(y..y + 1) {
+                    self.run_stages_without_border(
+                        resample_stage.unwrap() + 1,
+                        (out_x.start, oy),
+                        out_x.len(),
+                        rect,
+                        r.output_size(),
+                        buffers,
+                    )?;
+                }
+            }
         }
         Ok(())
     }
@@ -331,79 +417,98 @@ impl LowMemoryRenderPipeline {
                 let Stage::Extend(extend) = &self.shared.stages[extend] else {
                     unreachable!("extend stage is not an extend stage");
                 };
-                let row = &mut buffer.get_row_mut(y)[RowBuffer::x0_offset::<f32>()..];
+                let x0_offset = buffer.x0_offset::<f32>();
+                let row = &mut buffer.get_row_mut(y)[x0_offset..];
                 extend.process_row_chunk((x0, y), xsize, c, row);
             }
             // Step 2: go through remaining stages one by one.
-            for (i, stage) in self.shared.stages.iter().enumerate().skip(extend + 1) {
-                assert_eq!(self.downsampling_for_stage[i], (0, 0));
+            self.run_stages_without_border(
+                extend + 1,
+                (x0, y),
+                xsize,
+                Rect {
+                    origin: (x0, y0),
+                    size: (xsize, ysize),
+                },
+                (xrange.end, yrange.end), // this is not true, but works out correctly.
+                buffers,
+            )?;
+        }
+        Ok(())
+    }
 
-                match stage {
-                    Stage::InPlace(s) => {
-                        let mut buffers = get_distinct_indices(
-                            &mut self.row_buffers,
-                            &self.sorted_buffer_indices[i],
-                        );
-                        s.run_stage_on(
-                            ExtraInfo {
-                                xsize,
-                                current_row: y,
-                                group_x0: x0,
-                                out_extra_x: 0,
-                                is_first_xgroup: false,
-                                is_last_xgroup: false,
-                                image_height: self.shared.input_size.1,
-                            },
-                            &mut buffers,
-                            self.local_states[i].as_deref_mut(),
-                        );
-                    }
-                    Stage::Save(s) => {
-                        // Find buffers for channels that will be saved.
-                        let mut input_data: ChannelVec<_> = self.stage_input_buffer_index[i]
-                            .iter()
-                            .map(|(si, ci)| &self.row_buffers[*si][*ci])
-                            .collect();
-                        // Append opaque alpha buffer if fill_opaque_alpha is set
-                        if let Some(ref alpha_buf) = self.opaque_alpha_buffers[i] {
-                            input_data.push(alpha_buf);
-                        }
-                        s.save_lowmem(
-                            &input_data,
-                            &mut *buffers,
-                            (xsize, ysize),
-                            y,
-                            (x0, y0),
-                            (xrange.end, yrange.end), // this is not true, but works out correctly.
-                            (0, 0),
-                        )?;
-                    }
-                    Stage::Extend(_) => {
-                        unreachable!("duplicate extend stage");
-                    }
-                    Stage::InOut(s) => {
-                        assert_eq!(s.border(), (0, 0));
-                        let (inb, outb) = self.row_buffers.split_at_mut(i + 1);
-                        // Prepare pointers to input and output buffers.
-                        let input_data: ChannelVec<_> = self.stage_input_buffer_index[i]
-                            .iter()
-                            .map(|(si, ci)| &inb[*si][*ci])
-                            .collect();
-                        s.run_stage_on(
-                            ExtraInfo {
-                                xsize,
-                                current_row: y,
-                                group_x0: x0,
-                                out_extra_x: 0,
-                                is_first_xgroup: false,
-                                is_last_xgroup: false,
-                                image_height: self.shared.input_size.1,
-                            },
-                            &input_data,
-                            &mut outb[0][..],
-                            self.local_states[i].as_deref_mut(),
-                        );
+    /// Runs the stages starting from `first_stage`, none of which has a border or a shift, on
+    /// row `y` of the `xsize` pixels starting at `x0`. `rect` is the area that is being rendered
+    /// of the image of size `image_size` that those stages operate on.
+    fn run_stages_without_border(
+        &mut self,
+        first_stage: usize,
+        (x0, y): (usize, usize),
+        xsize: usize,
+        rect: Rect,
+        image_size: (usize, usize),
+        buffers: &mut [Option<JxlOutputBuffer>],
+    ) -> Result<()> {
+        for (i, stage) in self.shared.stages.iter().enumerate().skip(first_stage) {
+            assert_eq!(self.downsampling_for_stage[i], (0, 0));
+            let extra_info = ExtraInfo {
+                xsize,
+                current_row: y,
+                group_x0: x0,
+                out_extra_x: 0,
+                is_first_xgroup: false,
+                is_last_xgroup: false,
+                image_height: image_size.1,
+            };
+
+            match stage {
+                Stage::InPlace(s) => {
+                    let mut buffers =
+                        get_distinct_indices(&mut self.row_buffers, &self.sorted_buffer_indices[i]);
+                    s.run_stage_on(
+                        extra_info,
+                        &mut buffers,
+                        self.local_states[i].as_deref_mut(),
+                    )?;
+                }
+                Stage::Save(s) => {
+                    // Find buffers for channels that will be saved.
+                    let mut input_data: ChannelVec<_> = self.stage_input_buffer_index[i]
+                        .iter()
+                        .map(|(si, ci)| &self.row_buffers[*si][*ci])
+                        .collect();
+                    // Append opaque alpha buffer if fill_opaque_alpha is set
+                    if let Some(ref alpha_buf) = self.opaque_alpha_buffers[i] {
+                        input_data.push(alpha_buf);
                     }
+                    s.save_lowmem(
+                        &input_data,
+                        &mut *buffers,
+                        rect.size,
+                        y,
+                        rect.origin,
+                        image_size,
+                        (0, 0),
+                        self.local_states[i].as_deref_mut(),
+                    )?;
+                }
+                Stage::Extend(_) => {
+                    unreachable!("duplicate extend stage");
+                }
+                Stage::InOut(s) => {
+                    assert_eq!(s.border(), (0, 0));
+                    let (inb, outb) = self.row_buffers.split_at_mut(i + 1);
+                    // Prepare pointers to input and output buffers.
+                    let input_data: ChannelVec<_> = self.stage_input_buffer_index[i]
+                        .iter()
+                        .map(|(si, ci)| &inb[*si][*ci])
+                        .collect();
+                    s.run_stage_on(
+                        extra_info,
+                        &input_data,
+                        &mut outb[0][..],
+                        self.local_states[i].as_deref_mut(),
+                    );
                 }
             }
         }
@@ -6,9 +6,9 @@
 use std::ops::Range;
 
 use crate::{
+    api::JxlBufferPool,
     error::Result,
     image::{DataTypeTag, ImageDataType},
-    render::MAX_BORDER,
     util::{
         CACHE_LINE_BYTE_SIZE, CacheLine, SmallVec, num_per_cache_line, slice_from_cachelines,
         slice_from_cachelines_mut,
@@ -16,11 +16,14 @@ use crate::{
 };
 
 /// Temporary storage for data rows. Note that the first pixel of the group is expected to be
-/// located *one cacheline worth of data* inside the row.
+/// located *one cacheline worth of data* inside the row, or more if the buffer was created with
+/// an x border that does not fit in one cacheline.
 pub struct RowBuffer {
     buffer: Box<[CacheLine]>,
     // Distance (in number of *cache lines*) between the start of two rows.
     row_stride: usize,
+    // Offset (in number of *cache lines*) of the first pixel of the group in each row.
+    x0_cache_lines: usize,
     // Number of rows that are actually stored.
     // TODO(veluca): consider padding this to a power of 2 and using & here. In *most* cases,
     // that's not a huge loss in memory usage (for most images, num_rows is 1/3/5/7, which would
@@ -35,28 +38,58 @@ impl RowBuffer {
         y_shift: usize,
         row_len: usize,
     ) -> Result<Self> {
-        let num_rows = (1 << y_shift) + 2 * next_y_border;
-        let num_rows = num_rows.next_power_of_two();
-        // Input offset is at *one* cacheline, and we need up to *two* cachelines on the other
-        // side as the data might exceed xsize slightly.
-        let row_stride = (row_len * data_type.size()).div_ceil(CACHE_LINE_BYTE_SIZE) + 3;
-        let mut buffer = Vec::<CacheLine>::new();
+        Self::new_pooled(None, data_type, next_y_border, y_shift, 0, row_len)
+    }
+
+    /// Like [`Self::new`], but re-uses a buffer from `pool` if possible, and leaves room for
+    /// `x_border` pixels on each side of the row.
+    pub fn new_pooled(
+        pool: Option<&JxlBufferPool>,
+        data_type: DataTypeTag,
+        next_y_border: usize,
+        y_shift: usize,
+        x_border: usize,
+        row_len: usize,
+    ) -> Result<Self> {
+        let num_rows = Self::num_rows(next_y_border, y_shift);
+        let x0_cache_lines = (x_border * data_type.size())
+            .div_ceil(CACHE_LINE_BYTE_SIZE)
+            .max(1);
+        // Input offset is at (usually) *one* cacheline, and we need one more cacheline on the
+        // other side as the data might exceed xsize slightly.
+        let row_stride =
+            (row_len * data_type.size()).div_ceil(CACHE_LINE_BYTE_SIZE) + 2 * x0_cache_lines + 1;
+        let mut buffer = match pool {
+            Some(pool) => pool.take_vec::<CacheLine>(row_stride * num_rows),
+            None => Vec::new(),
+        };
         buffer.try_reserve_exact(row_stride * num_rows)?;
         buffer.resize(row_stride * num_rows, CacheLine::default());
         let buffer = buffer.into_boxed_slice();
         Ok(Self {
             buffer,
             row_stride,
+            x0_cache_lines,
             num_rows,
         })
     }
 
+    /// Gives back the memory of this buffer to `pool`.
+    pub fn recycle(self, pool: &JxlBufferPool) {
+        pool.recycle_vec(self.buffer.into_vec());
+    }
+
+    /// Number of rows stored by a buffer created with the given parameters.
+    pub fn num_rows(next_y_border: usize, y_shift: usize) -> usize {
+        ((1 << y_shift) + 2 * next_y_border).next_power_of_two()
+    }
+
     /// Creates a new row buffer with a single row filled with a repeating pattern.
     /// Used for constant values like opaque alpha.
     pub fn new_filled(data_type: DataTypeTag, row_len: usize, fill_pattern: &[u8]) -> Result<Self> {
         let mut result = Self::new(data_type, 0, 0, row_len)?;
+        let start = result.x0_offset::<u8>();
         let row_bytes: &mut [u8] = result.get_row_mut(0);
-        let start = Self::x0_offset::<u8>();
         let end = start + row_len * fill_pattern.len();
         for (i, byte) in row_bytes[start..end].iter_mut().enumerate() {
             *byte = fill_pattern[i % fill_pattern.len()];
@@ -100,12 +133,16 @@ impl RowBuffer {
             .collect()
     }
 
-    pub const fn x0_offset<T: ImageDataType>() -> usize {
-        assert!(num_per_cache_line::<T>() >= MAX_BORDER);
-        num_per_cache_line::<T>()
+    /// Number of bytes allocated for this buffer.
+    pub fn byte_size(&self) -> usize {
+        self.buffer.len() * CACHE_LINE_BYTE_SIZE
+    }
+
+    pub fn x0_offset<T: ImageDataType>(&self) -> usize {
+        self.x0_cache_lines * num_per_cache_line::<T>()
     }
 
-    pub const fn x0_byte_offset() -> usize {
-        CACHE_LINE_BYTE_SIZE
+    pub fn x0_byte_offset(&self) -> usize {
+        self.x0_cache_lines * CACHE_LINE_BYTE_SIZE
     }
 }
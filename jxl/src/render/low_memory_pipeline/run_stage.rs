@@ -5,14 +5,16 @@
 
 use std::any::Any;
 
+use crate::error::Result;
 use crate::{
     render::{
         Channels, ChannelsMut, RunInPlaceStage,
-        internal::{PipelineBuffer, RunInOutStage},
+        internal::{InOutStage, PipelineBuffer, RunInOutStage, SimdStage},
         low_memory_pipeline::{helpers::mirror, render_group::ChannelVec},
     },
     util::{ShiftRightCeil, SmallVec, tracing_wrappers::*},
 };
+use jxl_simd::SimdDescriptor;
 
 use super::{
     super::{RenderPipelineInOutStage, RenderPipelineInPlaceStage},
@@ -36,7 +38,9 @@ impl PipelineBuffer for RowBuffer {
     type InOutExtraInfo = ExtraInfo;
 }
 
-impl<T: RenderPipelineInPlaceStage> RunInPlaceStage<RowBuffer> for T {
+impl<D: SimdDescriptor + 'static, T: RenderPipelineInPlaceStage> RunInPlaceStage<RowBuffer>
+    for SimdStage<D, T>
+{
     #[instrument(skip_all)]
     fn run_stage_on(
         &self,
@@ -51,26 +55,30 @@ impl<T: RenderPipelineInPlaceStage> RunInPlaceStage<RowBuffer> for T {
         }: ExtraInfo,
         buffers: &mut [&mut RowBuffer],
         state: Option<&mut dyn Any>,
-    ) {
-        let x0 = RowBuffer::x0_offset::<T::Type>();
+    ) -> Result<()> {
         let xpre = if is_first_xgroup { 0 } else { out_extra_x };
-        let xstart = x0 - xpre;
-        let xend = x0 + xsize + if is_last_xgroup { 0 } else { out_extra_x };
+        let xpost = if is_last_xgroup { 0 } else { out_extra_x };
         let mut rows: ChannelVec<_> = buffers
             .iter_mut()
-            .map(|x| &mut x.get_row_mut::<T::Type>(current_row)[xstart..])
+            .map(|x| {
+                let xstart = x.x0_offset::<T::Type>() - xpre;
+                &mut x.get_row_mut::<T::Type>(current_row)[xstart..]
+            })
             .collect();
 
-        self.process_row_chunk(
+        self.stage.process_row_chunk(
+            self.d,
             (group_x0 - xpre, current_row),
-            xend - xstart,
+            xpre + xsize + xpost,
             &mut rows[..],
             state,
-        );
+        )
     }
 }
 
-impl<T: RenderPipelineInOutStage> RunInOutStage<RowBuffer> for T {
+impl<D: SimdDescriptor + 'static, T: RenderPipelineInOutStage> RunInOutStage<RowBuffer>
+    for SimdStage<D, T>
+{
     #[instrument(skip_all)]
     fn run_stage_on(
         &self,
@@ -87,52 +95,56 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<RowBuffer> for T {
         output_buffers: &mut [RowBuffer],
         state: Option<&mut dyn Any>,
     ) {
-        let ibordery = Self::BORDER.1 as isize;
-        let x0 = RowBuffer::x0_offset::<T::InputT>();
+        let (iborderx, ibordery) = (self.border().0 as usize, self.border().1 as isize);
         let xpre = if is_first_xgroup {
             0
         } else {
             out_extra_x.shrc(T::SHIFT.0)
         };
-        let xstart = x0 - xpre;
-        let xend = x0
-            + xsize
-            + if is_last_xgroup {
-                0
-            } else {
-                out_extra_x.shrc(T::SHIFT.0)
-            };
+        let xpost = if is_last_xgroup {
+            0
+        } else {
+            out_extra_x.shrc(T::SHIFT.0)
+        };
 
         // Build flat input rows: all rows for all channels in one Vec
-        let input_rows_per_channel = (2 * Self::BORDER.1 + 1) as usize;
+        let input_rows_per_channel = (2 * ibordery + 1) as usize;
         let num_channels = input_buffers.len();
         let mut input_row_data = SmallVec::new();
         for x in input_buffers.iter() {
+            let xstart = x.x0_offset::<T::InputT>() - xpre - iborderx;
             for iy in -ibordery..=ibordery {
                 input_row_data.push(
                     &x.get_row::<T::InputT>(mirror(current_row as isize + iy, image_height))
-                        [xstart - Self::BORDER.0 as usize..],
+                        [xstart..],
                 );
             }
         }
         let input_rows = Channels::new(input_row_data, num_channels, input_rows_per_channel);
 
-        // Build flat output rows: all rows for all channels in one Vec
-        let output_rows_per_channel = 1 << T::SHIFT.1;
+        // Build flat output rows: all rows for all channels in one Vec. Resampling stages
+        // produce the output rows that are assigned to the current row (and have no output border,
+        // so xpre is 0 for them).
+        let output_y = match self.resampling() {
+            Some(r) => r.y.outputs(current_row..current_row + 1),
+            None => (current_row << T::SHIFT.1)..((current_row + 1) << T::SHIFT.1),
+        };
+        let output_xpre = xpre << T::SHIFT.0;
+        let output_rows_per_channel = output_y.len();
         let num_output_channels = output_buffers.len();
         let mut output_row_data = SmallVec::new();
         // optimize for the common case of a single output row per channel.
         if output_rows_per_channel == 1 {
             for x in output_buffers.iter_mut() {
-                let row = x.get_row_mut::<T::OutputT>(current_row);
-                output_row_data.push(&mut row[xstart..]);
+                // The output type might differ from the input type, and so might its x0 offset.
+                let output_xstart = x.x0_offset::<T::OutputT>() - output_xpre;
+                let row = x.get_row_mut::<T::OutputT>(output_y.start);
+                output_row_data.push(&mut row[output_xstart..]);
             }
         } else {
             for x in output_buffers.iter_mut() {
-                let rows = x.get_rows_mut::<T::OutputT>(
-                    (current_row << T::SHIFT.1)..((current_row + 1) << T::SHIFT.1),
-                    RowBuffer::x0_offset::<T::OutputT>() - (xpre << T::SHIFT.0),
-                );
+                let output_xstart = x.x0_offset::<T::OutputT>() - output_xpre;
+                let rows = x.get_rows_mut::<T::OutputT>(output_y.clone(), output_xstart);
                 output_row_data.extend_sv(rows);
             }
         }
@@ -142,9 +154,10 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<RowBuffer> for T {
             output_rows_per_channel,
         );
 
-        self.process_row_chunk(
+        self.stage.process_row_chunk(
+            self.d,
             (group_x0 - xpre, current_row),
-            xend - xstart,
+            xpre + xsize + xpost,
             &input_rows,
             &mut output_rows,
             state,
@@ -8,10 +8,10 @@
 use std::mem::MaybeUninit;
 use std::ops::Range;
 
-use jxl_simd::{F32SimdVec, SimdDescriptor, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor, simd_level_dispatch};
 
 use crate::{
-    api::{Endianness, JxlDataFormat, JxlOutputBuffer},
+    api::{Endianness, JxlDataFormat, JxlOutputBuffer, SimdLevel},
     render::low_memory_pipeline::row_buffers::RowBuffer,
 };
 
@@ -96,21 +96,19 @@ fn run_interleaved_4<D: SimdDescriptor>(
     n
 }
 
-simd_function!(
-    store_interleaved,
+#[inline(always)]
+pub(super) fn store_interleaved<D: SimdDescriptor>(
     d: D,
-    fn store_interleaved_impl(
-        inputs: &[&[f32]],
-        output: &mut [MaybeUninit<f32>]
-    ) -> usize {
-        match inputs.len() {
-            2 => run_interleaved_2(d, inputs[0], inputs[1], output),
-            3 => run_interleaved_3(d, inputs[0], inputs[1], inputs[2], output),
-            4 => run_interleaved_4(d, inputs[0], inputs[1], inputs[2], inputs[3], output),
-            _ => 0,
-        }
+    inputs: &[&[f32]],
+    output: &mut [MaybeUninit<f32>],
+) -> usize {
+    match inputs.len() {
+        2 => run_interleaved_2(d, inputs[0], inputs[1], output),
+        3 => run_interleaved_3(d, inputs[0], inputs[1], inputs[2], output),
+        4 => run_interleaved_4(d, inputs[0], inputs[1], inputs[2], inputs[3], output),
+        _ => 0,
     }
-);
+}
 
 pub(super) fn store(
     input_buf: &[&RowBuffer],
@@ -119,9 +117,10 @@ pub(super) fn store(
     output_buf: &mut JxlOutputBuffer,
     output_y: usize,
     data_format: JxlDataFormat,
+    simd_level: SimdLevel,
 ) -> usize {
-    let byte_start = xrange.start * data_format.bytes_per_sample() + RowBuffer::x0_byte_offset();
-    let byte_end = xrange.end * data_format.bytes_per_sample() + RowBuffer::x0_byte_offset();
+    let byte_start = xrange.start * data_format.bytes_per_sample();
+    let byte_end = xrange.end * data_format.bytes_per_sample();
     let is_native_endian = match data_format {
         JxlDataFormat::U8 { .. } => true,
         JxlDataFormat::F16 { endianness, .. }
@@ -138,7 +137,8 @@ pub(super) fn store(
     ) {
         (1, _, true) => {
             // We can just do a memcpy.
-            let input_buf = &input_buf[0].get_row::<u8>(input_y)[byte_start..byte_end];
+            let x0 = input_buf[0].x0_byte_offset();
+            let input_buf = &input_buf[0].get_row::<u8>(input_y)[x0 + byte_start..x0 + byte_end];
             assert_eq!(input_buf.len(), output_buf.len());
             // SAFETY: we are copying `u8`s, which have an alignment of 1, from a slice of [u8] to
             // a slice of [MaybeUninit<u8>] of the same length (as we checked just above). u8 and
@@ -167,17 +167,17 @@ pub(super) fn store(
                     )
                 };
 
-                let start_f32 = byte_start / 4;
-                let end_f32 = byte_end / 4;
-
                 let mut slices = [&[] as &[f32]; 4];
                 for (i, buf) in input_buf.iter().enumerate() {
-                    slices[i] = &buf.get_row::<f32>(input_y)[start_f32..end_f32];
+                    let x0 = buf.x0_offset::<f32>();
+                    slices[i] = &buf.get_row::<f32>(input_y)[x0 + xrange.start..x0 + xrange.end];
                 }
 
                 // Note that, by the conditions on the *_uninit methods on F32Vec, this function
                 // never writes uninitialized memory.
-                store_interleaved(&slices[..channels], output_f32)
+                simd_level_dispatch!(simd_level, d => d.call(|d| {
+                    store_interleaved(d, &slices[..channels], output_f32)
+                }))
             } else {
                 0
             }
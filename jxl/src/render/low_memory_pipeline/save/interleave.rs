@@ -0,0 +1,217 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+#![allow(unsafe_code)]
+
+use std::mem::MaybeUninit;
+use std::ops::Range;
+
+use jxl_simd::{SimdDescriptor, simd_level_dispatch};
+
+use crate::{
+    api::{Endianness, JxlDataFormat, SimdLevel},
+    image::ImageDataType,
+    render::low_memory_pipeline::row_buffers::RowBuffer,
+};
+
+use super::identity::store_interleaved;
+
+/// Number of pixels handled by each iteration of the kernels below. Working on blocks of a fixed
+/// size lets the compiler vectorize them with the target features enabled by
+/// `SimdDescriptor::call`.
+const BLOCK: usize = 16;
+
+/// Interleaves whole blocks of `BLOCK` pixels, converting each sample with `to_bytes` (which may
+/// also swap its bytes). Returns the number of pixels written.
+#[inline(always)]
+fn interleave_blocks<D: SimdDescriptor, T: Copy, const N: usize, const S: usize>(
+    _d: D,
+    rows: &[&[T]; N],
+    to_bytes: impl Fn(T) -> [u8; S],
+    out: &mut [u8],
+) -> usize {
+    let mut n = 0;
+    for (i, out) in out.chunks_exact_mut(BLOCK * N * S).enumerate() {
+        let block: [&[T]; N] = std::array::from_fn(|c| &rows[c][i * BLOCK..][..BLOCK]);
+        for x in 0..BLOCK {
+            for c in 0..N {
+                out[(x * N + c) * S..][..S].copy_from_slice(&to_bytes(block[c][x]));
+            }
+        }
+        n += BLOCK;
+    }
+    n
+}
+
+#[inline(always)]
+fn interleave_samples<T: Copy, const N: usize, const S: usize>(
+    rows: &[&[T]; N],
+    to_bytes: impl Fn(T) -> [u8; S],
+    out: &mut [u8],
+) {
+    for (x, px) in out.chunks_exact_mut(N * S).enumerate() {
+        for (c, sample) in px.chunks_exact_mut(S).enumerate() {
+            sample.copy_from_slice(&to_bytes(rows[c][x]));
+        }
+    }
+}
+
+fn interleave_channels<T: Copy, const N: usize, const S: usize>(
+    rows: &[&[T]],
+    to_bytes: impl Fn(T) -> [u8; S] + Copy,
+    simd_level: SimdLevel,
+    out: &mut [u8],
+) {
+    let rows: [&[T]; N] = std::array::from_fn(|c| rows[c]);
+    let done = simd_level_dispatch!(simd_level, d => d.call(|d| {
+        interleave_blocks(d, &rows, to_bytes, out)
+    }));
+    let rest: [&[T]; N] = std::array::from_fn(|c| &rows[c][done..]);
+    interleave_samples(&rest, to_bytes, &mut out[done * N * S..]);
+}
+
+fn interleave_typed<T: ImageDataType, const S: usize>(
+    data: &[&RowBuffer],
+    y: usize,
+    xrange: Range<usize>,
+    to_bytes: impl Fn(T) -> [u8; S] + Copy,
+    simd_level: SimdLevel,
+    out: &mut [u8],
+) {
+    let mut rows = [&[] as &[T]; 4];
+    for (row, buf) in rows.iter_mut().zip(data.iter()) {
+        let x0 = buf.x0_offset::<T>();
+        *row = &buf.get_row::<T>(y)[x0 + xrange.start..x0 + xrange.end];
+    }
+    match data.len() {
+        1 => interleave_channels::<T, 1, S>(&rows, to_bytes, simd_level, out),
+        2 => interleave_channels::<T, 2, S>(&rows, to_bytes, simd_level, out),
+        3 => interleave_channels::<T, 3, S>(&rows, to_bytes, simd_level, out),
+        4 => interleave_channels::<T, 4, S>(&rows, to_bytes, simd_level, out),
+        nc => unreachable!("invalid number of channels: {nc}"),
+    }
+}
+
+/// Interleaves native-endian `f32` samples of 2 to 4 channels with vector stores straight into
+/// `out`, if it is suitably aligned. Returns the number of pixels written.
+fn store_f32_native(
+    data: &[&RowBuffer],
+    y: usize,
+    xrange: Range<usize>,
+    simd_level: SimdLevel,
+    out: &mut [u8],
+) -> usize {
+    if !(2..=4).contains(&data.len()) || out.as_ptr().align_offset(align_of::<f32>()) != 0 {
+        return 0;
+    }
+    // SAFETY: we checked alignment above, the length is rounded down to whole `f32`s, and
+    // `MaybeUninit<f32>` has the same layout as `f32`. Initialized bytes stay initialized, as
+    // `store_interleaved` never writes uninitialized memory.
+    let out = unsafe {
+        std::slice::from_raw_parts_mut(
+            out.as_mut_ptr() as *mut MaybeUninit<f32>,
+            out.len() / size_of::<f32>(),
+        )
+    };
+    let mut rows = [&[] as &[f32]; 4];
+    for (row, buf) in rows.iter_mut().zip(data.iter()) {
+        let x0 = buf.x0_offset::<f32>();
+        *row = &buf.get_row::<f32>(y)[x0 + xrange.start..x0 + xrange.end];
+    }
+    simd_level_dispatch!(simd_level, d => d.call(|d| {
+        store_interleaved(d, &rows[..data.len()], out)
+    }))
+}
+
+/// Writes the samples in `xrange` of row `y` of each buffer in `data` to `out` as interleaved
+/// pixels, in the sample format and byte order of `data_format`.
+pub(super) fn interleave(
+    data: &[&RowBuffer],
+    y: usize,
+    xrange: Range<usize>,
+    data_format: JxlDataFormat,
+    simd_level: SimdLevel,
+    out: &mut [u8],
+) {
+    assert_eq!(
+        out.len(),
+        xrange.len() * data.len() * data_format.bytes_per_sample()
+    );
+    match data_format {
+        JxlDataFormat::U8 { .. } => {
+            interleave_typed::<u8, 1>(data, y, xrange, |v| [v], simd_level, out);
+        }
+        JxlDataFormat::U16 { endianness, .. } | JxlDataFormat::F16 { endianness, .. } => {
+            match endianness {
+                Endianness::LittleEndian => {
+                    interleave_typed(data, y, xrange, u16::to_le_bytes, simd_level, out)
+                }
+                Endianness::BigEndian => {
+                    interleave_typed(data, y, xrange, u16::to_be_bytes, simd_level, out)
+                }
+            }
+        }
+        JxlDataFormat::F32 { endianness, .. } => {
+            let done = if endianness == Endianness::native() {
+                store_f32_native(data, y, xrange.clone(), simd_level, out)
+            } else {
+                0
+            };
+            let xrange = xrange.start + done..xrange.end;
+            let out = &mut out[done * data.len() * 4..];
+            match endianness {
+                Endianness::LittleEndian => {
+                    interleave_typed(data, y, xrange, f32::to_le_bytes, simd_level, out)
+                }
+                Endianness::BigEndian => {
+                    interleave_typed(data, y, xrange, f32::to_be_bytes, simd_level, out)
+                }
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn reverse_blocks<D: SimdDescriptor, const P: usize>(_d: D, src: &[u8], dst: &mut [u8]) -> usize {
+    let mut n = 0;
+    for (d, s) in dst
+        .chunks_exact_mut(BLOCK * P)
+        .zip(src.rchunks_exact(BLOCK * P))
+    {
+        for x in 0..BLOCK {
+            d[x * P..][..P].copy_from_slice(&s[(BLOCK - 1 - x) * P..][..P]);
+        }
+        n += BLOCK;
+    }
+    n
+}
+
+fn reverse_fixed<const P: usize>(src: &[u8], dst: &mut [u8], simd_level: SimdLevel) {
+    let done =
+        simd_level_dispatch!(simd_level, d => d.call(|d| reverse_blocks::<_, P>(d, src, dst)));
+    let rest = src.len() - done * P;
+    for (d, s) in dst[done * P..]
+        .chunks_exact_mut(P)
+        .zip(src[..rest].chunks_exact(P).rev())
+    {
+        d.copy_from_slice(s);
+    }
+}
+
+/// Copies the `px_bytes`-sized pixels of `src` to `dst` in reverse order.
+pub(super) fn reverse_pixels(src: &[u8], px_bytes: usize, dst: &mut [u8], simd_level: SimdLevel) {
+    assert_eq!(src.len(), dst.len());
+    match px_bytes {
+        1 => reverse_fixed::<1>(src, dst, simd_level),
+        2 => reverse_fixed::<2>(src, dst, simd_level),
+        3 => reverse_fixed::<3>(src, dst, simd_level),
+        4 => reverse_fixed::<4>(src, dst, simd_level),
+        6 => reverse_fixed::<6>(src, dst, simd_level),
+        8 => reverse_fixed::<8>(src, dst, simd_level),
+        12 => reverse_fixed::<12>(src, dst, simd_level),
+        16 => reverse_fixed::<16>(src, dst, simd_level),
+        _ => unreachable!("invalid pixel size: {px_bytes}"),
+    }
+}
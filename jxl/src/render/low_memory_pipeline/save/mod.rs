@@ -3,18 +3,27 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::{
-    api::{Endianness, JxlDataFormat, JxlOutputBuffer},
-    error::Result,
-    headers::Orientation,
-    render::save::SaveStage,
-};
+use std::any::Any;
+
+use crate::{api::JxlOutputBuffer, error::Result, headers::Orientation, render::save::SaveStage};
 
 use super::row_buffers::RowBuffer;
 
 mod identity;
+mod interleave;
+mod transpose;
+
+use interleave::{interleave, reverse_pixels};
+use transpose::TransposeTile;
+
+/// Scratch buffers of a save stage in the low-memory pipeline.
+#[derive(Default)]
+pub(super) struct SaveState {
+    row: Vec<u8>,
+    reversed: Vec<u8>,
+    tile: Option<TransposeTile>,
+}
 
-// Placeholder slow implementation.
 impl SaveStage {
     // Takes as input only those channels that are *actually* saved.
     #[allow(clippy::too_many_arguments)]
@@ -27,6 +36,7 @@ impl SaveStage {
         group_origin: (usize, usize),
         full_image_size: (usize, usize),
         frame_origin: (isize, isize),
+        state: Option<&mut dyn Any>,
     ) -> Result<()> {
         let Some(buf) = buffers[self.output_buffer_index].as_mut() else {
             return Ok(());
@@ -67,70 +77,262 @@ impl SaveStage {
 
         let save_size = (save_end.0 - save_start.0, save_end.1 - save_start.1);
 
-        let num_fast = match self.orientation {
-            Orientation::Identity => identity::store(
-                data,
-                frame_y,
-                save_start.0..save_end.0,
-                buf,
-                relative_y,
-                self.data_format,
-            ),
-            _ => 0,
-        };
-
-        // TODO(veluca): this is very slow, implement more fast paths.
+        let state = state
+            .and_then(|s| s.downcast_mut::<SaveState>())
+            .expect("missing save state");
+        let px_bytes = data.len() * self.data_format.bytes_per_sample();
+        let row_bytes = save_size.0 * px_bytes;
+        let xrange = save_start.0..save_end.0;
 
-        macro_rules! write_pixel {
-            ($px: expr, $endianness: expr, $y: expr, $x: expr) => {
-                let px = $px;
-                let px_bytes = if $endianness == Endianness::LittleEndian {
-                    px.to_le_bytes()
+        match self.orientation {
+            Orientation::Identity | Orientation::FlipVertical => {
+                let out_y = if self.orientation == Orientation::FlipVertical {
+                    save_size.1 - 1 - relative_y
                 } else {
-                    px.to_be_bytes()
+                    relative_y
                 };
-                buf.write_bytes($y, $x, &px_bytes);
-            };
+                let num_fast = identity::store(
+                    data,
+                    frame_y,
+                    xrange.clone(),
+                    buf,
+                    out_y,
+                    self.data_format,
+                    self.simd_level,
+                );
+                if num_fast < save_size.0 {
+                    state.row.resize(row_bytes - num_fast * px_bytes, 0);
+                    interleave(
+                        data,
+                        frame_y,
+                        xrange.start + num_fast..xrange.end,
+                        self.data_format,
+                        self.simd_level,
+                        &mut state.row,
+                    );
+                    buf.write_bytes(out_y, num_fast * px_bytes, &state.row);
+                }
+            }
+            Orientation::FlipHorizontal | Orientation::Rotate180 => {
+                let out_y = if self.orientation == Orientation::Rotate180 {
+                    save_size.1 - 1 - relative_y
+                } else {
+                    relative_y
+                };
+                state.row.resize(row_bytes, 0);
+                interleave(
+                    data,
+                    frame_y,
+                    xrange,
+                    self.data_format,
+                    self.simd_level,
+                    &mut state.row,
+                );
+                state.reversed.resize(row_bytes, 0);
+                reverse_pixels(&state.row, px_bytes, &mut state.reversed, self.simd_level);
+                buf.write_bytes(out_y, 0, &state.reversed);
+            }
+            Orientation::Transpose
+            | Orientation::AntiTranspose
+            | Orientation::Rotate90Cw
+            | Orientation::Rotate90Ccw => {
+                if !state.tile.as_ref().is_some_and(|t| t.fits(row_bytes)) {
+                    state.tile = Some(TransposeTile::new(row_bytes)?);
+                }
+                let tile = state.tile.as_mut().unwrap();
+                interleave(
+                    data,
+                    frame_y,
+                    xrange,
+                    self.data_format,
+                    self.simd_level,
+                    tile.next_row(relative_y, row_bytes),
+                );
+                if tile.is_full() || relative_y + 1 == save_size.1 {
+                    tile.flush(
+                        self.orientation,
+                        px_bytes,
+                        save_size,
+                        buf,
+                        &mut state.reversed,
+                        self.simd_level,
+                    );
+                }
+            }
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use test_log::test;
+
+    use super::*;
+    use crate::api::{Endianness, JxlColorType, JxlDataFormat, SimdLevel};
+    use crate::image::ImageDataType;
 
-        for (c, d) in data.iter().enumerate() {
-            let nc = self.output_channels();
-            let (x0, y0) = self.orientation.display_pixel((0, relative_y), save_size);
-            let (x1, y1) = self.orientation.display_pixel((1, relative_y), save_size);
-            let x0 = x0 as isize;
-            let y0 = y0 as isize;
-            let dx = x1 as isize - x0;
-            let dy = y1 as isize - y0;
-            match self.data_format {
-                JxlDataFormat::U8 { .. } => {
-                    let src_row = d.get_row::<u8>(frame_y);
-                    for ix in (save_start.0..save_end.0).skip(num_fast) {
-                        let px = src_row[RowBuffer::x0_offset::<u8>() + ix];
-                        let y = (y0 + (dy * (ix - save_start.0) as isize)) as usize;
-                        let x = (x0 + (dx * (ix - save_start.0) as isize)) as usize;
-                        write_pixel!(px, Endianness::LittleEndian, y, x * nc + c);
+    const ORIENTATIONS: [Orientation; 8] = [
+        Orientation::Identity,
+        Orientation::FlipHorizontal,
+        Orientation::Rotate180,
+        Orientation::FlipVertical,
+        Orientation::Transpose,
+        Orientation::Rotate90Cw,
+        Orientation::AntiTranspose,
+        Orientation::Rotate90Ccw,
+    ];
+
+    // Saves a single group with `save_lowmem` and compares the result with a per-sample
+    // reference implementation.
+    fn check_save<T: ImageDataType, const S: usize>(
+        data_format: JxlDataFormat,
+        to_bytes: impl Fn(T) -> [u8; S],
+        group_size: (usize, usize),
+        frame_origin: (isize, isize),
+        full_image_size: (usize, usize),
+    ) -> Result<()> {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let save_start = (
+            (-frame_origin.0).max(0) as usize,
+            (-frame_origin.1).max(0) as usize,
+        );
+        let save_size = (
+            full_image_size.0.min(group_size.0 - save_start.0),
+            full_image_size.1.min(group_size.1 - save_start.1),
+        );
+        let color_types = [
+            JxlColorType::Grayscale,
+            JxlColorType::GrayscaleAlpha,
+            JxlColorType::Rgb,
+            JxlColorType::Rgba,
+        ];
+        for (nc, color_type) in color_types.into_iter().enumerate().map(|(i, c)| (i + 1, c)) {
+            let mut buffers = vec![];
+            for _ in 0..nc {
+                let mut buffer = RowBuffer::new(T::DATA_TYPE_ID, 0, 6, group_size.0)?;
+                for y in 0..group_size.1 {
+                    let x0 = buffer.x0_offset::<T>();
+                    let row = buffer.get_row_mut::<T>(y);
+                    for x in 0..group_size.0 {
+                        row[x0 + x] = T::random(&mut rng);
                     }
                 }
-                JxlDataFormat::U16 { endianness, .. } | JxlDataFormat::F16 { endianness, .. } => {
-                    let src_row = d.get_row::<u16>(frame_y);
-                    for ix in (save_start.0..save_end.0).skip(num_fast) {
-                        let px = src_row[RowBuffer::x0_offset::<u16>() + ix];
-                        let y = (y0 + (dy * (ix - save_start.0) as isize)) as usize;
-                        let x = (x0 + (dx * (ix - save_start.0) as isize)) as usize;
-                        write_pixel!(px, endianness, y, (x * nc + c) * 2);
-                    }
+                buffers.push(buffer);
+            }
+            let data: Vec<_> = buffers.iter().collect();
+            let channels: Vec<_> = (0..nc).collect();
+            let levels = SimdLevel::available();
+            for (simd_level, orientation) in
+                levels.iter().flat_map(|l| ORIENTATIONS.map(|o| (*l, o)))
+            {
+                let stage = SaveStage::new(
+                    &channels,
+                    orientation,
+                    0,
+                    color_type,
+                    data_format,
+                    false,
+                    simd_level,
+                );
+                let (out_xsize, out_ysize) = orientation.map_size(save_size);
+                let bytes_per_row = out_xsize * nc * S;
+                let mut output = vec![0u8; bytes_per_row * out_ysize];
+                let mut expected = vec![0u8; bytes_per_row * out_ysize];
+                let mut state = SaveState::default();
+                for y in 0..group_size.1 {
+                    stage.save_lowmem(
+                        &data,
+                        &mut [Some(JxlOutputBuffer::new(
+                            &mut output,
+                            out_ysize,
+                            bytes_per_row,
+                        ))],
+                        group_size,
+                        y,
+                        (0, 0),
+                        full_image_size,
+                        frame_origin,
+                        Some(&mut state),
+                    )?;
                 }
-                JxlDataFormat::F32 { endianness, .. } => {
-                    let src_row = d.get_row::<f32>(frame_y);
-                    for ix in (save_start.0..save_end.0).skip(num_fast) {
-                        let px = src_row[RowBuffer::x0_offset::<f32>() + ix];
-                        let y = (y0 + (dy * (ix - save_start.0) as isize)) as usize;
-                        let x = (x0 + (dx * (ix - save_start.0) as isize)) as usize;
-                        write_pixel!(px, endianness, y, (x * nc + c) * 4);
+                for y in 0..save_size.1 {
+                    for x in 0..save_size.0 {
+                        let (ox, oy) = orientation.display_pixel((x, y), save_size);
+                        for (c, buf) in data.iter().enumerate() {
+                            let v = buf.get_row::<T>(save_start.1 + y)
+                                [buf.x0_offset::<T>() + save_start.0 + x];
+                            let pos = oy * bytes_per_row + (ox * nc + c) * S;
+                            expected[pos..pos + S].copy_from_slice(&to_bytes(v));
+                        }
                     }
                 }
+                assert!(
+                    output == expected,
+                    "mismatch for {orientation:?}, {data_format:?}, {nc} channels, {simd_level:?}"
+                );
             }
         }
         Ok(())
     }
+
+    fn check_all_formats(
+        group_size: (usize, usize),
+        frame_origin: (isize, isize),
+        full_image_size: (usize, usize),
+    ) -> Result<()> {
+        check_save::<u8, 1>(
+            JxlDataFormat::U8 { bit_depth: 8 },
+            |v| [v],
+            group_size,
+            frame_origin,
+            full_image_size,
+        )?;
+        for endianness in [Endianness::LittleEndian, Endianness::BigEndian] {
+            let u16_bytes = |v: u16| match endianness {
+                Endianness::LittleEndian => v.to_le_bytes(),
+                Endianness::BigEndian => v.to_be_bytes(),
+            };
+            check_save::<u16, 2>(
+                JxlDataFormat::U16 {
+                    endianness,
+                    bit_depth: 16,
+                },
+                u16_bytes,
+                group_size,
+                frame_origin,
+                full_image_size,
+            )?;
+            check_save::<u16, 2>(
+                JxlDataFormat::F16 { endianness },
+                u16_bytes,
+                group_size,
+                frame_origin,
+                full_image_size,
+            )?;
+            check_save::<f32, 4>(
+                JxlDataFormat::F32 { endianness },
+                |v: f32| match endianness {
+                    Endianness::LittleEndian => v.to_le_bytes(),
+                    Endianness::BigEndian => v.to_be_bytes(),
+                },
+                group_size,
+                frame_origin,
+                full_image_size,
+            )?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn save_all_orientations_and_formats() -> Result<()> {
+        check_all_formats((37, 23), (0, 0), (37, 23))
+    }
+
+    #[test]
+    fn save_all_orientations_and_formats_cropped() -> Result<()> {
+        check_all_formats((64, 40), (-5, -3), (50, 33))
+    }
 }
@@ -0,0 +1,190 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::ops::Range;
+
+use jxl_simd::{F32SimdVec, SimdDescriptor, simd_level_dispatch};
+
+use crate::{
+    api::{JxlOutputBuffer, SimdLevel},
+    error::Result,
+    headers::Orientation,
+    image::DataTypeTag,
+    render::low_memory_pipeline::row_buffers::RowBuffer,
+};
+
+use super::interleave::reverse_pixels;
+
+const TILE_ROWS_SHIFT: usize = 4;
+/// Number of input rows that are transposed together.
+const TILE_ROWS: usize = 1 << TILE_ROWS_SHIFT;
+/// Number of output rows that are assembled at once. This is also the maximum vector length.
+const COLUMN_BLOCK: usize = 16;
+/// Size of an interleaved pixel with 4 `f32` samples.
+const MAX_PIXEL_BYTES: usize = 16;
+
+/// Transposes 4-byte pixels, treating them as `f32` bit patterns.
+#[inline(always)]
+fn transpose_u32<D: SimdDescriptor>(
+    d: D,
+    tile: &RowBuffer,
+    num_rows: usize,
+    xrange: Range<usize>,
+    columns: &mut RowBuffer,
+) {
+    let len = D::F32Vec::LEN;
+    let mut block = [0.0f32; COLUMN_BLOCK * COLUMN_BLOCK];
+    let block = &mut block[..len * len];
+    let x0 = tile.x0_offset::<f32>();
+    let y0_offset = columns.x0_offset::<f32>();
+    let mut x = xrange.start;
+    if num_rows.is_multiple_of(len) {
+        while x + len <= xrange.end {
+            for y0 in (0..num_rows).step_by(len) {
+                for (r, block_row) in block.chunks_exact_mut(len).enumerate() {
+                    block_row.copy_from_slice(&tile.get_row::<f32>(y0 + r)[x0 + x..][..len]);
+                }
+                D::F32Vec::transpose_square(d, D::F32Vec::make_array_slice_mut(block), 1);
+                for (c, block_row) in block.chunks_exact(len).enumerate() {
+                    let column = columns.get_row_mut::<f32>(x - xrange.start + c);
+                    column[y0_offset + y0..][..len].copy_from_slice(block_row);
+                }
+            }
+            x += len;
+        }
+    }
+    for x in x..xrange.end {
+        let column = columns.get_row_mut::<f32>(x - xrange.start);
+        for y in 0..num_rows {
+            column[y0_offset + y] = tile.get_row::<f32>(y)[x0 + x];
+        }
+    }
+}
+
+fn transpose_pixels<const P: usize>(
+    tile: &RowBuffer,
+    num_rows: usize,
+    xrange: Range<usize>,
+    columns: &mut RowBuffer,
+) {
+    let x0 = tile.x0_byte_offset();
+    let y0_offset = columns.x0_byte_offset();
+    for x in xrange.clone() {
+        let column = &mut columns.get_row_mut::<u8>(x - xrange.start)[y0_offset..][..num_rows * P];
+        for (y, px) in column.chunks_exact_mut(P).enumerate() {
+            px.copy_from_slice(&tile.get_row::<u8>(y)[x0 + x * P..][..P]);
+        }
+    }
+}
+
+/// Buffers rows of interleaved pixels of a group for orientations that swap the x and y axes, and
+/// writes them out as contiguous segments of output rows.
+pub(super) struct TransposeTile {
+    tile: RowBuffer,
+    columns: RowBuffer,
+    row_bytes: usize,
+    first_row: usize,
+    num_rows: usize,
+}
+
+impl TransposeTile {
+    pub(super) fn new(row_bytes: usize) -> Result<Self> {
+        Ok(Self {
+            tile: RowBuffer::new(DataTypeTag::U8, 0, TILE_ROWS_SHIFT, row_bytes)?,
+            columns: RowBuffer::new(
+                DataTypeTag::U8,
+                0,
+                COLUMN_BLOCK.trailing_zeros() as usize,
+                TILE_ROWS * MAX_PIXEL_BYTES,
+            )?,
+            row_bytes,
+            first_row: 0,
+            num_rows: 0,
+        })
+    }
+
+    /// Whether rows with `row_bytes` bytes fit in this tile.
+    pub(super) fn fits(&self, row_bytes: usize) -> bool {
+        row_bytes <= self.row_bytes
+    }
+
+    /// Returns the buffer for the (relative) row `y` of the visible area, which must directly
+    /// follow the rows that are already buffered.
+    pub(super) fn next_row(&mut self, y: usize, row_bytes: usize) -> &mut [u8] {
+        if self.num_rows == 0 {
+            self.first_row = y;
+        }
+        assert_eq!(self.first_row + self.num_rows, y);
+        assert!(self.num_rows < TILE_ROWS);
+        let x0 = self.tile.x0_byte_offset();
+        let row = &mut self.tile.get_row_mut::<u8>(self.num_rows)[x0..x0 + row_bytes];
+        self.num_rows += 1;
+        row
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.num_rows == TILE_ROWS
+    }
+
+    /// Writes all the buffered rows to `buf`, which contains the oriented visible area of size
+    /// `save_size` (in input coordinates).
+    pub(super) fn flush(
+        &mut self,
+        orientation: Orientation,
+        px_bytes: usize,
+        save_size: (usize, usize),
+        buf: &mut JxlOutputBuffer,
+        reversed: &mut Vec<u8>,
+        simd_level: SimdLevel,
+    ) {
+        let num_rows = std::mem::take(&mut self.num_rows);
+        if num_rows == 0 {
+            return;
+        }
+        let (mirror_x, mirror_y) = match orientation {
+            Orientation::Transpose => (false, false),
+            Orientation::Rotate90Cw => (false, true),
+            Orientation::AntiTranspose => (true, true),
+            Orientation::Rotate90Ccw => (true, false),
+            _ => unreachable!("orientation {orientation:?} is not transposing"),
+        };
+        // Input rows become output columns, in reverse order if the y axis is mirrored.
+        let out_x = if mirror_y {
+            save_size.1 - self.first_row - num_rows
+        } else {
+            self.first_row
+        };
+        let num_bytes = num_rows * px_bytes;
+        reversed.resize(num_bytes, 0);
+        let x0 = self.columns.x0_byte_offset();
+        for start in (0..save_size.0).step_by(COLUMN_BLOCK) {
+            let xrange = start..(start + COLUMN_BLOCK).min(save_size.0);
+            let (tile, columns) = (&self.tile, &mut self.columns);
+            match px_bytes {
+                1 => transpose_pixels::<1>(tile, num_rows, xrange.clone(), columns),
+                2 => transpose_pixels::<2>(tile, num_rows, xrange.clone(), columns),
+                3 => transpose_pixels::<3>(tile, num_rows, xrange.clone(), columns),
+                4 => simd_level_dispatch!(simd_level, d => d.call(|d| {
+                    transpose_u32(d, tile, num_rows, xrange.clone(), columns)
+                })),
+                6 => transpose_pixels::<6>(tile, num_rows, xrange.clone(), columns),
+                8 => transpose_pixels::<8>(tile, num_rows, xrange.clone(), columns),
+                12 => transpose_pixels::<12>(tile, num_rows, xrange.clone(), columns),
+                16 => transpose_pixels::<16>(tile, num_rows, xrange.clone(), columns),
+                _ => unreachable!("invalid pixel size: {px_bytes}"),
+            }
+            for x in xrange.clone() {
+                let column = &columns.get_row::<u8>(x - start)[x0..x0 + num_bytes];
+                let out_y = if mirror_x { save_size.0 - 1 - x } else { x };
+                if mirror_y {
+                    reverse_pixels(column, px_bytes, reversed, simd_level);
+                    buf.write_bytes(out_y, out_x * px_bytes, reversed);
+                } else {
+                    buf.write_bytes(out_y, out_x * px_bytes, column);
+                }
+            }
+        }
+    }
+}
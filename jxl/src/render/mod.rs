@@ -7,17 +7,19 @@ use internal::{RenderPipelineShared, RunInOutStage, RunInPlaceStage};
 use std::any::Any;
 
 use crate::{
-    api::JxlOutputBuffer,
+    api::JxlOutputSink,
     error::Result,
     image::{Image, ImageDataType},
     render::buffer_splitter::BufferSplitter,
 };
+use jxl_simd::SimdDescriptor;
 
 pub mod buffer_splitter;
 mod builder;
 mod channels;
 mod internal;
 mod low_memory_pipeline;
+mod resampling;
 mod save;
 #[cfg(test)]
 mod simple_pipeline;
@@ -39,20 +41,25 @@ pub(crate) use builder::RenderPipelineBuilder;
 pub(crate) use channels::{Channels, ChannelsMut};
 pub(crate) use low_memory_pipeline::LowMemoryRenderPipeline;
 #[cfg(test)]
+pub(crate) use low_memory_pipeline::with_smallest_tiles;
+pub(crate) use resampling::{ResampledAxis, Resampling};
+#[cfg(test)]
 pub(crate) use simple_pipeline::SimpleRenderPipeline;
 
 /// Modifies channels in-place.
 pub trait RenderPipelineInPlaceStage: Any + std::fmt::Display {
     type Type: ImageDataType;
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        // the instruction set that the pipeline was built for
+        d: D,
         position: (usize, usize),
         xsize: usize,
         // one for each channel
         row: &mut [&mut [Self::Type]],
         state: Option<&mut dyn Any>,
-    );
+    ) -> Result<()>;
 
     fn init_local_state(&self) -> Result<Option<Box<dyn Any>>> {
         Ok(None)
@@ -73,6 +80,13 @@ pub trait RenderPipelineInPlaceStage: Any + std::fmt::Display {
 ///    padding on either side.
 ///  - the output slice contains 1 << SHIFT.1 slices, each of length xsize << SHIFT.0, the
 ///    corresponding output pixels.
+///
+/// Stages that resample their input to an arbitrary size instead return the mapping between
+/// input and output samples from [`Self::resampling`], and have a SHIFT and BORDER of (0, 0).
+/// For those, the input slices cover `Resampling::border` pixels of padding, and the output
+/// slice contains the rows assigned to the input row, each with the columns assigned to the
+/// input pixels of the chunk (see [`ResampledAxis`]). Only stages without border and shift can
+/// follow them.
 pub trait RenderPipelineInOutStage: Any + std::fmt::Display {
     type InputT: ImageDataType;
     type OutputT: ImageDataType;
@@ -80,8 +94,10 @@ pub trait RenderPipelineInOutStage: Any + std::fmt::Display {
     const BORDER: (u8, u8);
     const SHIFT: (u8, u8);
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        // the instruction set that the pipeline was built for
+        d: D,
         position: (usize, usize),
         xsize: usize,
         // channel, row, column
@@ -96,10 +112,13 @@ pub trait RenderPipelineInOutStage: Any + std::fmt::Display {
     }
 
     fn uses_channel(&self, c: usize) -> bool;
+
+    fn resampling(&self) -> Option<Resampling> {
+        None
+    }
 }
 
-// TODO(veluca): find a way to reduce the generated code due to having two builders, to integrate
-// SIMD dispatch in the pipeline, and to test consistency across instruction sets in the pipeline.
+// TODO(veluca): find a way to reduce the generated code due to having two builders.
 pub(crate) trait RenderPipeline: Sized {
     type Buffer: 'static;
 
@@ -123,18 +142,23 @@ pub(crate) trait RenderPipeline: Sized {
     ) -> Result<()>;
 
     /// Checks whether the provided buffer sizes are correct.
-    fn check_buffer_sizes(&self, buffers: &mut [Option<JxlOutputBuffer>]) -> Result<()>;
+    fn check_buffer_sizes(&self, buffers: &mut [Option<JxlOutputSink>]) -> Result<()>;
 
     /// Renders any data outside the frame that would not be rendered by calls to
     /// set_buffer_for_group. Can be called multiple times - it is up to the pipeline
     /// implementation to ensure rendering only happens once.
     fn render_outside_frame(&mut self, buffer_splitter: &mut BufferSplitter) -> Result<()>;
 
-    fn box_inout_stage<S: RenderPipelineInOutStage>(
+    /// Returns the largest number of bytes that the pipeline held in its buffers at any time.
+    fn peak_memory_usage(&self) -> usize;
+
+    fn box_inout_stage<D: SimdDescriptor + 'static, S: RenderPipelineInOutStage>(
+        d: D,
         stage: S,
     ) -> Box<dyn RunInOutStage<Self::Buffer>>;
 
-    fn box_inplace_stage<S: RenderPipelineInPlaceStage>(
+    fn box_inplace_stage<D: SimdDescriptor + 'static, S: RenderPipelineInPlaceStage>(
+        d: D,
         stage: S,
     ) -> Box<dyn RunInPlaceStage<Self::Buffer>>;
 }
@@ -0,0 +1,95 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::ops::Range;
+
+/// Maps the samples of one axis of an image to the samples of the same axis of the image
+/// resampled to `output_size` samples.
+///
+/// Each output sample is assigned to the input sample that is closest to its center, and is
+/// produced when that input sample is processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResampledAxis {
+    pub input_size: usize,
+    pub output_size: usize,
+}
+
+impl ResampledAxis {
+    /// Returns the input sample that output sample `o` is assigned to.
+    pub fn input(&self, o: usize) -> usize {
+        // The center of `o` is at (o + 0.5) * input_size / output_size in input coordinates.
+        let input = (2 * o as u64 + 1) * self.input_size as u64 / (2 * self.output_size as u64);
+        input as usize
+    }
+
+    /// Returns the first output sample that is assigned to input sample `i` or later ones.
+    fn first_output(&self, i: usize) -> usize {
+        let (i, input_size, output_size) =
+            (i as u64, self.input_size as u64, self.output_size as u64);
+        // Smallest `o` such that (2o + 1) * input_size >= 2 * i * output_size.
+        let first = (2 * i * output_size).saturating_sub(input_size);
+        first.div_ceil(2 * input_size) as usize
+    }
+
+    /// Returns the output samples that are assigned to the input samples in `input`.
+    pub fn outputs(&self, input: Range<usize>) -> Range<usize> {
+        self.first_output(input.start)..self.first_output(input.end)
+    }
+
+    /// Returns the largest number of output samples that can be assigned to `n` consecutive
+    /// input samples.
+    pub fn max_outputs(&self, n: usize) -> usize {
+        (n * self.output_size).div_ceil(self.input_size)
+    }
+}
+
+/// How a [`super::RenderPipelineInOutStage`] resamples its input to an arbitrary size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resampling {
+    pub x: ResampledAxis,
+    pub y: ResampledAxis,
+    /// Number of input samples around the input sample that an output sample is assigned to
+    /// that the output sample depends on, in each direction.
+    pub border: (u8, u8),
+}
+
+impl Resampling {
+    pub fn output_size(&self) -> (usize, usize) {
+        (self.x.output_size, self.y.output_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_log::test;
+
+    use super::*;
+
+    #[test]
+    fn outputs_partition_output_samples() {
+        for (input_size, output_size) in [(100, 37), (37, 100), (1, 5), (5, 1), (64, 64), (7, 3)] {
+            let axis = ResampledAxis {
+                input_size,
+                output_size,
+            };
+            assert_eq!(axis.outputs(0..input_size), 0..output_size);
+            let mut next = 0;
+            for i in 0..input_size {
+                let outputs = axis.outputs(i..i + 1);
+                assert_eq!(outputs.start, next);
+                assert!(outputs.len() <= axis.max_outputs(1));
+                for o in outputs.clone() {
+                    assert_eq!(axis.input(o), i);
+                }
+                next = outputs.end;
+            }
+            for n in 1..input_size {
+                for i in 0..input_size - n {
+                    assert!(axis.outputs(i..i + n).len() <= axis.max_outputs(n));
+                }
+            }
+        }
+    }
+}
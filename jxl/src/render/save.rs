@@ -4,7 +4,7 @@
 // license that can be found in the LICENSE file.
 
 use crate::{
-    api::{JxlColorType, JxlDataFormat, JxlOutputBuffer},
+    api::{JxlColorType, JxlDataFormat, JxlOutputBuffer, SimdLevel},
     error::{Error, Result},
     headers::Orientation,
     image::DataTypeTag,
@@ -20,6 +20,8 @@ pub struct SaveStage {
     /// When true, fill alpha channel with opaque (1.0) values.
     /// Used when RGBA output is requested but image has no alpha channel.
     pub(super) fill_opaque_alpha: bool,
+    /// Instruction set that samples are converted and interleaved with.
+    pub(super) simd_level: SimdLevel,
 }
 
 impl SaveStage {
@@ -30,6 +32,7 @@ impl SaveStage {
         mut color_type: JxlColorType,
         data_format: JxlDataFormat,
         fill_opaque_alpha: bool,
+        simd_level: SimdLevel,
     ) -> SaveStage {
         let mut channels = channels.to_vec();
         if color_type == JxlColorType::Bgr {
@@ -47,6 +50,7 @@ impl SaveStage {
             color_type,
             data_format,
             fill_opaque_alpha,
+            simd_level,
         }
     }
 
@@ -4,16 +4,17 @@
 // license that can be found in the LICENSE file.
 
 use crate::{
-    api::JxlOutputBuffer,
+    api::JxlOutputSink,
     error::Result,
     image::{Image, ImageDataType},
     render::{buffer_splitter::BufferSplitter, internal::ChannelInfo},
     util::{ShiftRightCeil, tracing_wrappers::*},
 };
+use jxl_simd::SimdDescriptor;
 
 use super::{
     RenderPipeline, RenderPipelineInOutStage, RenderPipelineInPlaceStage,
-    internal::{RenderPipelineShared, Stage},
+    internal::{RenderPipelineShared, SimdStage, Stage},
 };
 
 mod extend;
@@ -96,7 +97,7 @@ impl SimpleRenderPipeline {
                     );
                     let repl_iter = (0..self.shared.num_channels())
                         .filter(|c| stage.uses_channel(*c))
-                        .zip(output_buf.into_iter());
+                        .zip(output_buf);
                     for (c, chan) in repl_iter {
                         output_buffers[c] = chan;
                     }
@@ -113,7 +114,7 @@ impl SimpleRenderPipeline {
                         self.shared.chunk_size,
                         &mut output_buf,
                         state.as_deref_mut(),
-                    );
+                    )?;
                 }
                 Stage::Extend(e) => {
                     e.extend_simple(
@@ -123,7 +124,15 @@ impl SimpleRenderPipeline {
                     );
                 }
                 Stage::Save(stage) => {
-                    stage.save_simple(&output_buffers, buffer_splitter.get_full_buffers())?;
+                    let size = output_buffers[stage.channels[0]].size();
+                    let mut buffers = buffer_splitter.get_full_buffers(
+                        stage.output_buffer_index,
+                        stage.orientation.map_size(size),
+                        stage.output_channels() * stage.data_format.bytes_per_sample(),
+                    )?;
+                    stage.save_simple(&output_buffers, &mut buffers)?;
+                    drop(buffers);
+                    buffer_splitter.flush();
                 }
             }
             current_buffers = output_buffers;
@@ -198,7 +207,7 @@ impl RenderPipeline for SimpleRenderPipeline {
         self.do_render(buffer_splitter)
     }
 
-    fn check_buffer_sizes(&self, _buffers: &mut [Option<JxlOutputBuffer>]) -> Result<()> {
+    fn check_buffer_sizes(&self, _buffers: &mut [Option<JxlOutputSink>]) -> Result<()> {
         // This will be checked during rendering.
         Ok(())
     }
@@ -208,15 +217,24 @@ impl RenderPipeline for SimpleRenderPipeline {
         Ok(())
     }
 
-    fn box_inout_stage<S: RenderPipelineInOutStage>(
+    fn peak_memory_usage(&self) -> usize {
+        self.input_buffers
+            .iter()
+            .map(|b| b.size().0 * b.size().1 * std::mem::size_of::<f64>())
+            .sum()
+    }
+
+    fn box_inout_stage<D: SimdDescriptor + 'static, S: RenderPipelineInOutStage>(
+        d: D,
         stage: S,
     ) -> Box<dyn super::RunInOutStage<Self::Buffer>> {
-        Box::new(stage)
+        Box::new(SimdStage { d, stage })
     }
 
-    fn box_inplace_stage<S: RenderPipelineInPlaceStage>(
+    fn box_inplace_stage<D: SimdDescriptor + 'static, S: RenderPipelineInPlaceStage>(
+        d: D,
         stage: S,
     ) -> Box<dyn super::RunInPlaceStage<Self::Buffer>> {
-        Box::new(stage)
+        Box::new(SimdStage { d, stage })
     }
 }
@@ -7,31 +7,35 @@
 
 use std::any::Any;
 
+use crate::error::Result;
 use crate::{
     image::{Image, ImageDataType},
     render::{
         RenderPipelineInOutStage, RenderPipelineInPlaceStage, RunInOutStage, RunInPlaceStage,
-        internal::PipelineBuffer,
+        internal::{InOutStage, PipelineBuffer, SimdStage},
     },
     util::{SmallVec, round_up_size_to_cache_line, tracing_wrappers::*},
 };
+use jxl_simd::SimdDescriptor;
 
 impl PipelineBuffer for Image<f64> {
     type InPlaceExtraInfo = usize;
     type InOutExtraInfo = usize;
 }
 
-impl<T: RenderPipelineInPlaceStage> RunInPlaceStage<Image<f64>> for T {
+impl<D: SimdDescriptor + 'static, T: RenderPipelineInPlaceStage> RunInPlaceStage<Image<f64>>
+    for SimdStage<D, T>
+{
     fn run_stage_on(
         &self,
         chunk_size: usize,
         buffers: &mut [&mut Image<f64>],
         mut state: Option<&mut dyn Any>,
-    ) {
+    ) -> Result<()> {
         debug!("running inplace stage '{self}' in simple pipeline");
         let numc = buffers.len();
         if numc == 0 {
-            return;
+            return Ok(());
         }
         let size = buffers[0].size();
         for b in buffers.iter() {
@@ -53,7 +57,13 @@ impl<T: RenderPipelineInPlaceStage> RunInPlaceStage<Image<f64>> for T {
                     }
                 }
                 let mut row: Vec<_> = buffer.iter_mut().map(|x| x as &mut [_]).collect();
-                self.process_row_chunk((x, y), xsize, &mut row, state.as_deref_mut());
+                self.stage.process_row_chunk(
+                    self.d,
+                    (x, y),
+                    xsize,
+                    &mut row,
+                    state.as_deref_mut(),
+                )?;
                 for c in 0..numc {
                     let out_row = buffers[c].row_mut(y);
                     for ix in 0..xsize {
@@ -62,10 +72,13 @@ impl<T: RenderPipelineInPlaceStage> RunInPlaceStage<Image<f64>> for T {
                 }
             }
         }
+        Ok(())
     }
 }
 
-impl<T: RenderPipelineInOutStage> RunInOutStage<Image<f64>> for T {
+impl<D: SimdDescriptor + 'static, T: RenderPipelineInOutStage> RunInOutStage<Image<f64>>
+    for SimdStage<D, T>
+{
     #[instrument(skip_all)]
     fn run_stage_on(
         &self,
@@ -87,15 +100,20 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<Image<f64>> for T {
             assert_eq!(input_size, input_buffers[c].size());
             assert_eq!(output_size, output_buffers[c].size());
         }
+        let resampling = self.stage.resampling();
+        let border = self.border();
         debug!(
             ?input_size,
             ?output_size,
-            SHIFT = ?Self::SHIFT,
-            BORDER = ?Self::BORDER,
+            SHIFT = ?T::SHIFT,
+            ?border,
+            ?resampling,
             numc
         );
-        assert_eq!(input_size.0, output_size.0.div_ceil(1 << Self::SHIFT.0));
-        assert_eq!(input_size.1, output_size.1.div_ceil(1 << Self::SHIFT.1));
+        if resampling.is_none() {
+            assert_eq!(input_size.0, output_size.0.div_ceil(1 << T::SHIFT.0));
+            assert_eq!(input_size.1, output_size.1.div_ceil(1 << T::SHIFT.1));
+        }
         let mut buffer_in = vec![
             vec![
                 vec![
@@ -103,24 +121,26 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<Image<f64>> for T {
                     // Double rounding make sure that we always have enough buffer for reading a whole SIMD lane.
                     round_up_size_to_cache_line::<T::OutputT>(
                         round_up_size_to_cache_line::<T::OutputT>(chunk_size)
-                            + T::BORDER.0 as usize * 2
+                            + border.0 as usize * 2
                     )
                 ];
-                T::BORDER.1 as usize * 2 + 1
-            ];
-            numc
-        ];
-        let mut buffer_out = vec![
-            vec![
-                vec![
-                    T::OutputT::default();
-                    round_up_size_to_cache_line::<T::OutputT>(chunk_size)
-                        << T::SHIFT.0
-                ];
-                1 << T::SHIFT.1
+                border.1 as usize * 2 + 1
             ];
             numc
         ];
+        // Output rows and columns produced by each input row chunk, at most.
+        let (output_xsize, output_ysize) = match resampling {
+            Some(r) => (
+                round_up_size_to_cache_line::<T::OutputT>(r.x.max_outputs(chunk_size)),
+                r.y.max_outputs(1),
+            ),
+            None => (
+                round_up_size_to_cache_line::<T::OutputT>(chunk_size) << T::SHIFT.0,
+                1 << T::SHIFT.1,
+            ),
+        };
+        let mut buffer_out =
+            vec![vec![vec![T::OutputT::default(); output_xsize]; output_ysize]; numc];
 
         let mirror = |mut v: i64, size: i64| {
             while v < 0 || v >= size {
@@ -135,8 +155,8 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<Image<f64>> for T {
         };
         for y in 0..input_size.1 {
             for x in (0..input_size.0).step_by(chunk_size) {
-                let border_x = Self::BORDER.0 as i64;
-                let border_y = Self::BORDER.1 as i64;
+                let border_x = border.0 as i64;
+                let border_y = border.1 as i64;
                 let xsize = input_size.0.min(x + chunk_size) - x;
                 let xs = xsize as i64;
                 debug!("position: {x}x{y} xsize: {xsize}");
@@ -157,6 +177,15 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<Image<f64>> for T {
                     }
                 }
 
+                // Output rows and columns that correspond to this chunk.
+                let (out_ys, out_xs) = match resampling {
+                    Some(r) => (r.y.outputs(y..y + 1), r.x.outputs(x..x + xsize)),
+                    None => (
+                        (y << T::SHIFT.1)..((y + 1) << T::SHIFT.1).min(output_size.1),
+                        (x << T::SHIFT.0)..((x + xsize) << T::SHIFT.0).min(output_size.0),
+                    ),
+                };
+
                 {
                     // Build flat input rows: all rows for all channels in one Vec
                     let num_input_channels = buffer_in.len();
@@ -175,10 +204,13 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<Image<f64>> for T {
 
                     // Build flat output rows: all rows for all channels in one Vec
                     let num_output_channels = buffer_out.len();
-                    let output_rows_per_channel = buffer_out[0].len();
+                    let output_rows_per_channel = match resampling {
+                        Some(_) => out_ys.len(),
+                        None => buffer_out[0].len(),
+                    };
                     let mut output_row_data = SmallVec::new();
                     for ch_buf in buffer_out.iter_mut() {
-                        for row in ch_buf.iter_mut() {
+                        for row in ch_buf.iter_mut().take(output_rows_per_channel) {
                             output_row_data.push(row as &mut [_]);
                         }
                     }
@@ -188,7 +220,8 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<Image<f64>> for T {
                         output_rows_per_channel,
                     );
 
-                    self.process_row_chunk(
+                    self.stage.process_row_chunk(
+                        self.d,
                         (x, y),
                         xsize,
                         &input_rows,
@@ -197,15 +230,11 @@ impl<T: RenderPipelineInOutStage> RunInOutStage<Image<f64>> for T {
                     );
                 }
 
-                let stripe_xsize =
-                    (xsize << Self::SHIFT.0).min(output_size.0 - (x << Self::SHIFT.0));
-                let stripe_ysize =
-                    (1usize << Self::SHIFT.1).min(output_size.1 - (y << Self::SHIFT.1));
                 for c in 0..numc {
-                    for iy in 0..stripe_ysize {
-                        let out_row = output_buffers[c].row_mut((y << Self::SHIFT.1) + iy);
-                        for ix in 0..stripe_xsize {
-                            out_row[(x << Self::SHIFT.0) + ix] = buffer_out[c][iy][ix].to_f64();
+                    for (iy, out_y) in out_ys.clone().enumerate() {
+                        let out_row = output_buffers[c].row_mut(out_y);
+                        for (ix, out_x) in out_xs.clone().enumerate() {
+                            out_row[out_x] = buffer_out[c][iy][ix].to_f64();
                         }
                     }
                 }
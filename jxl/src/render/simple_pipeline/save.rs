@@ -93,7 +93,10 @@ impl SaveStage {
 mod test {
     use super::*;
     use crate::{
-        api::JxlColorType, headers::Orientation, image::Rect, util::test::assert_almost_eq,
+        api::{JxlColorType, SimdLevel},
+        headers::Orientation,
+        image::Rect,
+        util::test::assert_almost_eq,
     };
     use rand::SeedableRng;
     use rand_xorshift::XorShiftRng;
@@ -108,6 +111,7 @@ mod test {
             JxlColorType::Grayscale,
             JxlDataFormat::U8 { bit_depth: 8 },
             false,
+            SimdLevel::best(),
         );
         let mut rng = XorShiftRng::seed_from_u64(0);
         let src = [Image::<f64>::new_random((128, 128), &mut rng)?];
@@ -156,6 +160,7 @@ mod test {
             JxlColorType::Grayscale,
             JxlDataFormat::f32(),
             false,
+            SimdLevel::best(),
         );
 
         let mut rng = XorShiftRng::seed_from_u64(0);
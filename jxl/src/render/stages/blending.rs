@@ -16,6 +16,7 @@ use crate::{
     render::RenderPipelineInPlaceStage,
     util::slice,
 };
+use jxl_simd::SimdDescriptor;
 
 pub struct BlendingStage {
     pub frame_origin: (isize, isize),
@@ -78,13 +79,14 @@ impl RenderPipelineInPlaceStage for BlendingStage {
         c < 3 + self.extra_channels.len()
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
-    ) {
+    ) -> Result<()> {
         let num_ec = self.extra_channels.len();
         let fg_y0 = self.frame_origin.1 + position.1 as isize;
         let mut fg_x0 = self.frame_origin.0 + position.0 as isize;
@@ -93,7 +95,7 @@ impl RenderPipelineInPlaceStage for BlendingStage {
         let mut bg_x1: isize = xsize as isize;
 
         if fg_x1 <= 0 || fg_x0 >= self.image_size.0 || fg_y0 < 0 || fg_y0 >= self.image_size.1 {
-            return;
+            return Ok(());
         }
 
         if fg_x0 < 0 {
@@ -153,6 +155,7 @@ impl RenderPipelineInPlaceStage for BlendingStage {
             &ec_blending_info,
             &self.extra_channels,
         );
+        Ok(())
     }
 }
 
@@ -4,7 +4,7 @@
 // license that can be found in the LICENSE file.
 
 use crate::render::{Channels, ChannelsMut, RenderPipelineInOutStage};
-use jxl_simd::{F32SimdVec, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor};
 
 pub struct HorizontalChromaUpsample {
     channel: usize,
@@ -27,38 +27,37 @@ impl std::fmt::Display for HorizontalChromaUpsample {
 }
 
 // SIMD horizontal chroma upsampling
-simd_function!(
-    hchroma_upsample_simd_dispatch,
-    d: D,
-    fn hchroma_upsample_simd(input: &[f32], output: &mut [f32], xsize: usize) {
-        // Precompute constants
-        let c025 = D::F32Vec::splat(d, 0.25);
-        let c075 = D::F32Vec::splat(d, 0.75);
-
-        // Use windows for input (prev, cur, next) and chunks_exact_mut for output
-        // Input has border padding so windows of size simd_width+2 work
-        // Output is 2x the size, so chunks of 2*simd_width
-        let input_iter = input.windows(D::F32Vec::LEN + 2).step_by(D::F32Vec::LEN);
-        let output_iter = output.chunks_exact_mut(2 * D::F32Vec::LEN);
-
-        for (in_win, out_chunk) in input_iter.zip(output_iter).take(xsize.div_ceil(D::F32Vec::LEN))
-        {
-            // Load: prev, cur, next
-            let prev_vec = D::F32Vec::load(d, &in_win[0..]);
-            let cur_vec = D::F32Vec::load(d, &in_win[1..]);
-            let next_vec = D::F32Vec::load(d, &in_win[2..]);
-
-            // Compute: left = 0.25 * prev + 0.75 * cur
-            let left = prev_vec.mul_add(c025, cur_vec * c075);
-
-            // Compute: right = 0.25 * next + 0.75 * cur
-            let right = next_vec.mul_add(c025, cur_vec * c075);
-
-            // Interleave and store: [left0, right0, left1, right1, ...]
-            D::F32Vec::store_interleaved_2(left, right, out_chunk);
-        }
+#[inline(always)]
+fn hchroma_upsample_simd<D: SimdDescriptor>(d: D, input: &[f32], output: &mut [f32], xsize: usize) {
+    // Precompute constants
+    let c025 = D::F32Vec::splat(d, 0.25);
+    let c075 = D::F32Vec::splat(d, 0.75);
+
+    // Use windows for input (prev, cur, next) and chunks_exact_mut for output
+    // Input has border padding so windows of size simd_width+2 work
+    // Output is 2x the size, so chunks of 2*simd_width
+    let input_iter = input.windows(D::F32Vec::LEN + 2).step_by(D::F32Vec::LEN);
+    let output_iter = output.chunks_exact_mut(2 * D::F32Vec::LEN);
+
+    for (in_win, out_chunk) in input_iter
+        .zip(output_iter)
+        .take(xsize.div_ceil(D::F32Vec::LEN))
+    {
+        // Load: prev, cur, next
+        let prev_vec = D::F32Vec::load(d, &in_win[0..]);
+        let cur_vec = D::F32Vec::load(d, &in_win[1..]);
+        let next_vec = D::F32Vec::load(d, &in_win[2..]);
+
+        // Compute: left = 0.25 * prev + 0.75 * cur
+        let left = prev_vec.mul_add(c025, cur_vec * c075);
+
+        // Compute: right = 0.25 * next + 0.75 * cur
+        let right = next_vec.mul_add(c025, cur_vec * c075);
+
+        // Interleave and store: [left0, right0, left1, right1, ...]
+        D::F32Vec::store_interleaved_2(left, right, out_chunk);
     }
-);
+}
 
 impl RenderPipelineInOutStage for HorizontalChromaUpsample {
     type InputT = f32;
@@ -70,8 +69,9 @@ impl RenderPipelineInOutStage for HorizontalChromaUpsample {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
@@ -80,7 +80,7 @@ impl RenderPipelineInOutStage for HorizontalChromaUpsample {
     ) {
         let input = &input_rows[0];
         let output = &mut output_rows[0];
-        hchroma_upsample_simd_dispatch(input[0], output[0], xsize);
+        d.call(|d| hchroma_upsample_simd(d, input[0], output[0], xsize));
     }
 }
 
@@ -101,51 +101,49 @@ impl std::fmt::Display for VerticalChromaUpsample {
 }
 
 // SIMD vertical chroma upsampling
-simd_function!(
-    vchroma_upsample_simd_dispatch,
+#[inline(always)]
+fn vchroma_upsample_simd<D: SimdDescriptor>(
     d: D,
-    fn vchroma_upsample_simd(
-        input_prev: &[f32],
-        input_cur: &[f32],
-        input_next: &[f32],
-        output_up: &mut [f32],
-        output_down: &mut [f32],
-        xsize: usize,
-    ) {
-        // Precompute constants
-        let c025 = D::F32Vec::splat(d, 0.25);
-        let c075 = D::F32Vec::splat(d, 0.75);
-
-        // Use chunks_exact for all arrays (buffers are guaranteed large enough)
-        let prev_iter = input_prev.chunks_exact(D::F32Vec::LEN);
-        let cur_iter = input_cur.chunks_exact(D::F32Vec::LEN);
-        let next_iter = input_next.chunks_exact(D::F32Vec::LEN);
-        let up_iter = output_up.chunks_exact_mut(D::F32Vec::LEN);
-        let down_iter = output_down.chunks_exact_mut(D::F32Vec::LEN);
-
-        for ((((prev_chunk, cur_chunk), next_chunk), up_chunk), down_chunk) in prev_iter
-            .zip(cur_iter)
-            .zip(next_iter)
-            .zip(up_iter)
-            .zip(down_iter)
-            .take(xsize.div_ceil(D::F32Vec::LEN))
-        {
-            let prev_vec = D::F32Vec::load(d, prev_chunk);
-            let cur_vec = D::F32Vec::load(d, cur_chunk);
-            let next_vec = D::F32Vec::load(d, next_chunk);
-
-            // Compute: up = 0.25 * prev + 0.75 * cur
-            let up = prev_vec.mul_add(c025, cur_vec * c075);
-
-            // Compute: down = 0.25 * next + 0.75 * cur
-            let down = next_vec.mul_add(c025, cur_vec * c075);
-
-            // Store results
-            up.store(up_chunk);
-            down.store(down_chunk);
-        }
+    input_prev: &[f32],
+    input_cur: &[f32],
+    input_next: &[f32],
+    output_up: &mut [f32],
+    output_down: &mut [f32],
+    xsize: usize,
+) {
+    // Precompute constants
+    let c025 = D::F32Vec::splat(d, 0.25);
+    let c075 = D::F32Vec::splat(d, 0.75);
+
+    // Use chunks_exact for all arrays (buffers are guaranteed large enough)
+    let prev_iter = input_prev.chunks_exact(D::F32Vec::LEN);
+    let cur_iter = input_cur.chunks_exact(D::F32Vec::LEN);
+    let next_iter = input_next.chunks_exact(D::F32Vec::LEN);
+    let up_iter = output_up.chunks_exact_mut(D::F32Vec::LEN);
+    let down_iter = output_down.chunks_exact_mut(D::F32Vec::LEN);
+
+    for ((((prev_chunk, cur_chunk), next_chunk), up_chunk), down_chunk) in prev_iter
+        .zip(cur_iter)
+        .zip(next_iter)
+        .zip(up_iter)
+        .zip(down_iter)
+        .take(xsize.div_ceil(D::F32Vec::LEN))
+    {
+        let prev_vec = D::F32Vec::load(d, prev_chunk);
+        let cur_vec = D::F32Vec::load(d, cur_chunk);
+        let next_vec = D::F32Vec::load(d, next_chunk);
+
+        // Compute: up = 0.25 * prev + 0.75 * cur
+        let up = prev_vec.mul_add(c025, cur_vec * c075);
+
+        // Compute: down = 0.25 * next + 0.75 * cur
+        let down = next_vec.mul_add(c025, cur_vec * c075);
+
+        // Store results
+        up.store(up_chunk);
+        down.store(down_chunk);
     }
-);
+}
 
 impl RenderPipelineInOutStage for VerticalChromaUpsample {
     type InputT = f32;
@@ -157,8 +155,9 @@ impl RenderPipelineInOutStage for VerticalChromaUpsample {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
@@ -168,14 +167,17 @@ impl RenderPipelineInOutStage for VerticalChromaUpsample {
         let input = &input_rows[0];
         let output = &mut output_rows[0];
         let (output_up, output_down) = output.split_at_mut(1);
-        vchroma_upsample_simd_dispatch(
-            input[0],
-            input[1],
-            input[2],
-            output_up[0],
-            output_down[0],
-            xsize,
-        );
+        d.call(|d| {
+            vchroma_upsample_simd(
+                d,
+                input[0],
+                input[1],
+                input[2],
+                output_up[0],
+                output_down[0],
+                xsize,
+            )
+        });
     }
 }
 
@@ -0,0 +1,321 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::api::{JxlCms, JxlCmsTransformer, JxlColorProfile};
+use crate::error::{Error, Result};
+use crate::render::RenderPipelineInPlaceStage;
+use jxl_simd::SimdDescriptor;
+
+/// Maximum number of pixels passed to the CMS in a single call.
+const MAX_PIXELS_PER_TRANSFORM: usize = 256;
+
+/// Number of transformers created for a stage, i.e. of local states that can exist at once.
+const NUM_TRANSFORMERS: usize = 2;
+
+/// Transformers that are not currently owned by a local state.
+type TransformerPool = Rc<RefCell<Vec<Box<dyn JxlCmsTransformer>>>>;
+
+/// Converts CMYK data (C, M, Y in the color channels, K in an extra channel) to the output
+/// color space using a CMS.
+pub struct CmykStage {
+    /// Index of the black channel.
+    black_c: usize,
+    /// Number of channels produced by the transform (1 for grayscale, 3 for RGB, 4 for CMYK).
+    num_output_channels: usize,
+    transformers: TransformerPool,
+}
+
+impl std::fmt::Display for CmykStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CMYK to {}-channel conversion with black channel {}",
+            self.num_output_channels, self.black_c
+        )
+    }
+}
+
+impl CmykStage {
+    pub fn new(
+        black_c_offset: usize,
+        cms: &dyn JxlCms,
+        input: JxlColorProfile,
+        output: JxlColorProfile,
+        intensity_target: f32,
+    ) -> Result<Self> {
+        let (num_output_channels, transformers) = cms.initialize_transforms(
+            NUM_TRANSFORMERS,
+            MAX_PIXELS_PER_TRANSFORM,
+            input,
+            output,
+            intensity_target,
+        )?;
+        if !matches!(num_output_channels, 1 | 3 | 4) {
+            return Err(Error::InvalidCmsChannels(num_output_channels));
+        }
+        if transformers.len() != NUM_TRANSFORMERS {
+            return Err(Error::InvalidCmsTransformCount(
+                transformers.len(),
+                NUM_TRANSFORMERS,
+            ));
+        }
+        Ok(Self {
+            black_c: 3 + black_c_offset,
+            num_output_channels,
+            transformers: Rc::new(RefCell::new(transformers)),
+        })
+    }
+}
+
+/// Scratch buffers and transformer of a thread. The transformer goes back to the pool of the
+/// stage when the state is dropped.
+struct CmykLocalState {
+    input: Vec<f32>,
+    output: Vec<f32>,
+    transformer: Option<Box<dyn JxlCmsTransformer>>,
+    pool: TransformerPool,
+}
+
+impl Drop for CmykLocalState {
+    fn drop(&mut self) {
+        if let Some(transformer) = self.transformer.take() {
+            self.pool.borrow_mut().push(transformer);
+        }
+    }
+}
+
+impl RenderPipelineInPlaceStage for CmykStage {
+    type Type = f32;
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c < 3 || c == self.black_c
+    }
+
+    fn init_local_state(&self) -> Result<Option<Box<dyn Any>>> {
+        let transformer = self.transformers.borrow_mut().pop().ok_or_else(|| {
+            Error::CmsError(format!(
+                "more than {NUM_TRANSFORMERS} CMYK transforms used at the same time"
+            ))
+        })?;
+        Ok(Some(Box::new(CmykLocalState {
+            input: vec![0.0; MAX_PIXELS_PER_TRANSFORM * 4],
+            output: vec![0.0; MAX_PIXELS_PER_TRANSFORM * self.num_output_channels],
+            transformer: Some(transformer),
+            pool: self.transformers.clone(),
+        })))
+    }
+
+    // `row` should only contain color channels and the black channel.
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        _d: D,
+        _position: (usize, usize),
+        xsize: usize,
+        row: &mut [&mut [f32]],
+        state: Option<&mut dyn Any>,
+    ) -> Result<()> {
+        let state: &mut CmykLocalState = state.unwrap().downcast_mut().unwrap();
+        let [row_c, row_m, row_y, row_k] = row else {
+            panic!(
+                "incorrect number of channels; expected 4, found {}",
+                row.len()
+            );
+        };
+        let transformer = state.transformer.as_mut().unwrap();
+        let nc = self.num_output_channels;
+        for x0 in (0..xsize).step_by(MAX_PIXELS_PER_TRANSFORM) {
+            let n = (xsize - x0).min(MAX_PIXELS_PER_TRANSFORM);
+            let input = &mut state.input[..n * 4];
+            for (i, px) in input.chunks_exact_mut(4).enumerate() {
+                px.copy_from_slice(&[row_c[x0 + i], row_m[x0 + i], row_y[x0 + i], row_k[x0 + i]]);
+            }
+            let output = &mut state.output[..n * nc];
+            transformer.do_transform(input, output)?;
+            for (i, px) in output.chunks_exact(nc).enumerate() {
+                let x = x0 + i;
+                match px {
+                    [v] => {
+                        row_c[x] = *v;
+                        row_m[x] = *v;
+                        row_y[x] = *v;
+                    }
+                    [r, g, b] => {
+                        row_c[x] = *r;
+                        row_m[x] = *g;
+                        row_y[x] = *b;
+                    }
+                    [c, m, y, k] => {
+                        row_c[x] = *c;
+                        row_m[x] = *m;
+                        row_y[x] = *y;
+                        row_k[x] = *k;
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use test_log::test;
+
+    use super::*;
+    use crate::image::Image;
+    use crate::render::test::{make_and_run_pipeline, make_and_run_simple_pipeline};
+    use crate::render::{LowMemoryRenderPipeline, RenderPipeline, SimpleRenderPipeline};
+    use crate::util::test::assert_all_almost_abs_eq;
+
+    /// Naive CMYK to RGB conversion, ignoring the actual profiles.
+    pub(crate) struct NaiveCmykCms;
+
+    struct NaiveCmykTransformer;
+
+    impl JxlCmsTransformer for NaiveCmykTransformer {
+        fn do_transform(&mut self, input: &[f32], output: &mut [f32]) -> Result<()> {
+            for (i, o) in input.chunks_exact(4).zip(output.chunks_exact_mut(3)) {
+                // 0 means maximum ink, so multiplying darkens.
+                o[0] = i[0] * i[3];
+                o[1] = i[1] * i[3];
+                o[2] = i[2] * i[3];
+            }
+            Ok(())
+        }
+
+        fn do_transform_inplace(&mut self, inout: &mut [f32]) -> Result<()> {
+            for px in inout.chunks_exact_mut(4) {
+                let k = px[3];
+                for v in &mut px[..3] {
+                    *v *= k;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl JxlCms for NaiveCmykCms {
+        fn initialize_transforms(
+            &self,
+            n: usize,
+            _max_pixels_per_transform: usize,
+            _input: JxlColorProfile,
+            _output: JxlColorProfile,
+            _intensity_target: f32,
+        ) -> Result<(usize, Vec<Box<dyn JxlCmsTransformer>>)> {
+            Ok((
+                3,
+                (0..n)
+                    .map(|_| Box::new(NaiveCmykTransformer) as Box<dyn JxlCmsTransformer>)
+                    .collect(),
+            ))
+        }
+    }
+
+    fn naive_stage() -> CmykStage {
+        CmykStage::new(
+            0,
+            &NaiveCmykCms,
+            JxlColorProfile::Icc(vec![]),
+            JxlColorProfile::Icc(vec![]),
+            255.0,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn consistency() -> Result<()> {
+        crate::render::test::test_stage_consistency(naive_stage, (500, 500), 4)
+    }
+
+    struct FailingTransformer;
+
+    impl JxlCmsTransformer for FailingTransformer {
+        fn do_transform(&mut self, _input: &[f32], _output: &mut [f32]) -> Result<()> {
+            Err(Error::CmsError("transform failed".to_string()))
+        }
+
+        fn do_transform_inplace(&mut self, _inout: &mut [f32]) -> Result<()> {
+            Err(Error::CmsError("transform failed".to_string()))
+        }
+    }
+
+    struct FailingCms;
+
+    impl JxlCms for FailingCms {
+        fn initialize_transforms(
+            &self,
+            n: usize,
+            _max_pixels_per_transform: usize,
+            _input: JxlColorProfile,
+            _output: JxlColorProfile,
+            _intensity_target: f32,
+        ) -> Result<(usize, Vec<Box<dyn JxlCmsTransformer>>)> {
+            Ok((
+                3,
+                (0..n)
+                    .map(|_| Box::new(FailingTransformer) as Box<dyn JxlCmsTransformer>)
+                    .collect(),
+            ))
+        }
+    }
+
+    fn check_transform_error<P: RenderPipeline>() -> Result<()> {
+        let input: Vec<_> = (0..4)
+            .map(|_| Image::new_with_value((64, 64), 0.5))
+            .collect::<Result<_>>()?;
+        let result = make_and_run_pipeline::<P>(
+            |p| {
+                p.add_inplace_stage(CmykStage::new(
+                    0,
+                    &FailingCms,
+                    JxlColorProfile::Icc(vec![]),
+                    JxlColorProfile::Icc(vec![]),
+                    255.0,
+                )?)
+            },
+            &input,
+            64,
+        );
+        assert!(matches!(result, Err(Error::CmsError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn transform_errors_are_returned() -> Result<()> {
+        check_transform_error::<SimpleRenderPipeline>()?;
+        check_transform_error::<LowMemoryRenderPipeline>()
+    }
+
+    #[test]
+    fn cmyk_to_rgb() -> Result<()> {
+        let xsize = 600;
+        let mut input = vec![];
+        for c in 0..4 {
+            let mut img = Image::new((xsize, 1))?;
+            for (x, v) in img.row_mut(0).iter_mut().enumerate() {
+                *v = ((x * (c + 1)) % 7) as f32 / 6.0;
+            }
+            input.push(img);
+        }
+        let output = make_and_run_simple_pipeline(naive_stage(), &input, (xsize, 1), 0, 256)?;
+        for c in 0..3 {
+            let expected: Vec<f32> = input[c]
+                .row(0)
+                .iter()
+                .zip(input[3].row(0))
+                .map(|(v, k)| v * k)
+                .collect();
+            assert_all_almost_abs_eq(output[c].row(0), &expected, 1e-6);
+        }
+        assert_all_almost_abs_eq(output[3].row(0), input[3].row(0), 0.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::api::{JxlPrimaries, JxlWhitePoint, primaries_conversion_matrix};
+use crate::error::Result;
+use crate::render::RenderPipelineInPlaceStage;
+use jxl_simd::{F32SimdVec, SimdDescriptor};
+
+/// Multiply linear color samples by a 3x3 matrix, e.g. to convert between primaries.
+pub struct ColorMatrixStage {
+    first_channel: usize,
+    matrix: [f32; 9],
+}
+
+impl ColorMatrixStage {
+    pub fn new(first_channel: usize, matrix: [[f32; 3]; 3]) -> Self {
+        Self {
+            first_channel,
+            matrix: matrix.as_flattened().try_into().unwrap(),
+        }
+    }
+
+    /// Converts linear samples from the `from` primaries and white point to the `to` ones.
+    pub fn convert_primaries(
+        first_channel: usize,
+        from: (&JxlPrimaries, &JxlWhitePoint),
+        to: (&JxlPrimaries, &JxlWhitePoint),
+    ) -> Result<Self> {
+        let matrix = primaries_conversion_matrix(from, to)?;
+        Ok(Self::new(
+            first_channel,
+            matrix.map(|row| row.map(|x| x as f32)),
+        ))
+    }
+}
+
+impl std::fmt::Display for ColorMatrixStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let channel = self.first_channel;
+        write!(
+            f,
+            "color matrix {:?} for channel [{},{},{}]",
+            self.matrix,
+            channel,
+            channel + 1,
+            channel + 2
+        )
+    }
+}
+
+#[inline(always)]
+fn color_matrix_process<D: SimdDescriptor>(
+    d: D,
+    matrix: &[f32; 9],
+    xsize: usize,
+    row_r: &mut [f32],
+    row_g: &mut [f32],
+    row_b: &mut [f32],
+) {
+    let mat = matrix.map(|x| D::F32Vec::splat(d, x));
+    for idx in (0..xsize).step_by(D::F32Vec::LEN) {
+        let r = D::F32Vec::load(d, &row_r[idx..]);
+        let g = D::F32Vec::load(d, &row_g[idx..]);
+        let b = D::F32Vec::load(d, &row_b[idx..]);
+        let out_r = mat[0].mul_add(r, mat[1].mul_add(g, mat[2] * b));
+        let out_g = mat[3].mul_add(r, mat[4].mul_add(g, mat[5] * b));
+        let out_b = mat[6].mul_add(r, mat[7].mul_add(g, mat[8] * b));
+        out_r.store(&mut row_r[idx..]);
+        out_g.store(&mut row_g[idx..]);
+        out_b.store(&mut row_b[idx..]);
+    }
+}
+
+impl RenderPipelineInPlaceStage for ColorMatrixStage {
+    type Type = f32;
+
+    fn uses_channel(&self, c: usize) -> bool {
+        (self.first_channel..self.first_channel + 3).contains(&c)
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        d: D,
+        _position: (usize, usize),
+        xsize: usize,
+        row: &mut [&mut [f32]],
+        _state: Option<&mut dyn std::any::Any>,
+    ) -> Result<()> {
+        let [row_r, row_g, row_b] = row else {
+            panic!(
+                "incorrect number of channels; expected 3, found {}",
+                row.len()
+            );
+        };
+        d.call(|d| color_matrix_process(d, &self.matrix, xsize, row_r, row_g, row_b));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_log::test;
+
+    use super::*;
+    use crate::image::Image;
+    use crate::render::test::make_and_run_simple_pipeline;
+    use crate::util::test::assert_all_almost_abs_eq;
+
+    #[test]
+    fn consistency() -> Result<()> {
+        crate::render::test::test_stage_consistency(
+            || {
+                ColorMatrixStage::convert_primaries(
+                    0,
+                    (&JxlPrimaries::SRGB, &JxlWhitePoint::D65),
+                    (&JxlPrimaries::P3, &JxlWhitePoint::DCI),
+                )
+                .unwrap()
+            },
+            (500, 500),
+            3,
+        )
+    }
+
+    #[test]
+    fn srgb_to_bt2100() -> Result<()> {
+        let mut input_r = Image::new((4, 1))?;
+        let mut input_g = Image::new((4, 1))?;
+        let mut input_b = Image::new((4, 1))?;
+        input_r.row_mut(0).copy_from_slice(&[1.0, 0.0, 0.0, 0.5]);
+        input_g.row_mut(0).copy_from_slice(&[0.0, 1.0, 0.0, 0.5]);
+        input_b.row_mut(0).copy_from_slice(&[0.0, 0.0, 1.0, 0.5]);
+
+        let stage = ColorMatrixStage::convert_primaries(
+            0,
+            (&JxlPrimaries::SRGB, &JxlWhitePoint::D65),
+            (&JxlPrimaries::BT2100, &JxlWhitePoint::D65),
+        )?;
+        let output =
+            make_and_run_simple_pipeline(stage, &[input_r, input_g, input_b], (4, 1), 0, 256)?;
+
+        // BT.709 to BT.2020 matrix from ITU-R BT.2087; grey stays grey.
+        assert_all_almost_abs_eq(output[0].row(0), &[0.6274, 0.3293, 0.0433, 0.5], 1e-3);
+        assert_all_almost_abs_eq(output[1].row(0), &[0.0691, 0.9195, 0.0114, 0.5], 1e-3);
+        assert_all_almost_abs_eq(output[2].row(0), &[0.0164, 0.0880, 0.8956, 0.5], 1e-3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn white_point_adaptation() -> Result<()> {
+        let mut input_r = Image::new((1, 1))?;
+        let mut input_g = Image::new((1, 1))?;
+        let mut input_b = Image::new((1, 1))?;
+        input_r.row_mut(0)[0] = 0.8;
+        input_g.row_mut(0)[0] = 0.8;
+        input_b.row_mut(0)[0] = 0.8;
+
+        // White in DCI-P3 maps to white in P3 D65: chromatic adaptation preserves whites.
+        let stage = ColorMatrixStage::convert_primaries(
+            0,
+            (&JxlPrimaries::P3, &JxlWhitePoint::DCI),
+            (&JxlPrimaries::P3, &JxlWhitePoint::D65),
+        )?;
+        let output =
+            make_and_run_simple_pipeline(stage, &[input_r, input_g, input_b], (1, 1), 0, 256)?;
+        for c in output.iter() {
+            assert_all_almost_abs_eq(c.row(0), &[0.8], 1e-4);
+        }
+        Ok(())
+    }
+}
@@ -8,7 +8,7 @@ use crate::{
     headers::bit_depth::BitDepth,
     render::{Channels, ChannelsMut, RenderPipelineInOutStage},
 };
-use jxl_simd::{F32SimdVec, simd_function};
+use jxl_simd::{F32SimdVec, I32SimdVec, SimdDescriptor, SimdMask};
 
 pub struct ConvertU8F32Stage {
     channel: usize,
@@ -36,8 +36,9 @@ impl RenderPipelineInOutStage for ConvertU8F32Stage {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<u8>,
@@ -87,8 +88,9 @@ impl RenderPipelineInOutStage for ConvertModularXYBToF32Stage {
         (self.first_channel..self.first_channel + 3).contains(&c)
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<i32>,
@@ -137,61 +139,72 @@ impl std::fmt::Display for ConvertModularToF32Stage {
 
 // Converts custom [bits]-bit float (with [exp_bits] exponent bits) stored as
 // int back to binary32 float.
-// TODO(sboukortt): SIMD
-fn int_to_float(input: &[i32], output: &mut [f32], bit_depth: &BitDepth) {
-    assert_eq!(input.len(), output.len());
-    let bits = bit_depth.bits_per_sample();
-    let exp_bits = bit_depth.exponent_bits_per_sample();
-    if bits == 32 {
-        assert_eq!(exp_bits, 8);
-        for (&in_val, out_val) in input.iter().zip(output) {
-            *out_val = f32::from_bits(in_val as u32);
-        }
-        return;
-    }
-    let exp_bias = (1 << (exp_bits - 1)) - 1;
-    let sign_shift = bits - 1;
+#[inline(always)]
+fn int_to_float_simd<D: SimdDescriptor>(
+    d: D,
+    input: &[i32],
+    output: &mut [f32],
+    bits: u32,
+    exp_bits: u32,
+    xsize: usize,
+) {
     let mant_bits = bits - exp_bits - 1;
-    let mant_shift = 23 - mant_bits;
-    for (&in_val, out_val) in input.iter().zip(output) {
-        let mut f = in_val as u32;
-        let signbit = (f >> sign_shift) != 0;
-        f &= (1 << sign_shift) - 1;
-        if f == 0 {
-            *out_val = if signbit { -0.0 } else { 0.0 };
-            continue;
-        }
-        let mut exp = (f >> mant_bits) as i32;
-        let mut mantissa = f & ((1 << mant_bits) - 1);
-        if exp == (1 << exp_bits) - 1 {
-            // NaN or infinity
-            f = if signbit { 0x80000000 } else { 0 };
-            f |= 0b11111111 << 23;
-            f |= mantissa << mant_shift;
-            *out_val = f32::from_bits(f);
-            continue;
-        }
-        mantissa <<= mant_shift;
-        // Try to normalize only if there is space for maneuver.
-        if exp == 0 && exp_bits < 8 {
-            // subnormal number
-            while (mantissa & 0x800000) == 0 {
-                mantissa <<= 1;
-                exp -= 1;
-            }
-            exp += 1;
-            // remove leading 1 because it is implicit now
-            mantissa &= 0x7fffff;
-        }
-        exp -= exp_bias;
-        // broke up the arbitrary float into its parts, now reassemble into
-        // binary32
-        exp += 127;
-        assert!(exp >= 0);
-        f = if signbit { 0x80000000 } else { 0 };
-        f |= (exp as u32) << 23;
-        f |= mantissa;
-        *out_val = f32::from_bits(f);
+    let exp_bias = (1 << (exp_bits - 1)) - 1;
+    // Bits above the exponent and the mantissa are the sign.
+    let magnitude_bits = ((1u64 << (bits - 1)) - 1) as u32;
+    let exp_mask = ((1 << exp_bits) - 1) << mant_bits;
+
+    let magnitude_mask = D::I32Vec::splat(d, magnitude_bits as i32);
+    let sign_mask = D::I32Vec::splat(d, !magnitude_bits as i32);
+    let exp_mask = D::I32Vec::splat(d, exp_mask);
+    // Multiplying by 2^(23 - mant_bits) moves exponent and mantissa to their binary32
+    // positions.
+    let mant_mul = D::I32Vec::splat(d, 1 << (23 - mant_bits));
+    // Multiplying by 2^(127 - exp_bias) then corrects the exponent bias, for both normal
+    // and subnormal numbers.
+    let bias_mul = D::F32Vec::splat(d, f32::from_bits((254 - exp_bias) << 23));
+    let inf = D::I32Vec::splat(d, 0x7f800000);
+    let zero = D::I32Vec::splat(d, 0);
+    let sign = D::I32Vec::splat(d, i32::MIN);
+
+    // Process SIMD vectors using div_ceil (buffers are padded)
+    for (input_chunk, output_chunk) in input
+        .chunks_exact(D::I32Vec::LEN)
+        .zip(output.chunks_exact_mut(D::F32Vec::LEN))
+        .take(xsize.div_ceil(D::F32Vec::LEN))
+    {
+        let val = D::I32Vec::load(d, input_chunk);
+        let magnitude = val & magnitude_mask;
+        let shifted = magnitude * mant_mul;
+        let finite = (shifted.bitcast_to_f32() * bias_mul).bitcast_to_i32();
+        // NaN or infinity
+        let result = (magnitude & exp_mask)
+            .eq(exp_mask)
+            .if_then_else_i32(shifted | inf, finite);
+        let result = (val & sign_mask).eq_zero().if_then_else_i32(zero, sign) | result;
+        result.bitcast_to_f32().store(output_chunk);
+    }
+}
+
+// SIMD conversion of integers with [bits] bits to floats in [0, 1].
+#[inline(always)]
+fn int_to_unit_float_simd<D: SimdDescriptor>(
+    d: D,
+    input: &[i32],
+    output: &mut [f32],
+    bits: u32,
+    xsize: usize,
+) {
+    let scale = D::F32Vec::splat(d, 1.0 / ((1u64 << bits) - 1) as f32);
+
+    // Process SIMD vectors using div_ceil (buffers are padded)
+    for (input_chunk, output_chunk) in input
+        .chunks_exact(D::I32Vec::LEN)
+        .zip(output.chunks_exact_mut(D::F32Vec::LEN))
+        .take(xsize.div_ceil(D::F32Vec::LEN))
+    {
+        let val = D::I32Vec::load(d, input_chunk).as_f32();
+        (val * scale).store(output_chunk);
     }
 }
 
@@ -205,26 +218,124 @@ impl RenderPipelineInOutStage for ConvertModularToF32Stage {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<i32>,
         output_rows: &mut ChannelsMut<f32>,
         _state: Option<&mut dyn std::any::Any>,
     ) {
-        let input = &input_rows[0];
+        let input = input_rows[0][0];
+        let output = &mut output_rows[0][0];
         if self.bit_depth.floating_point_sample() {
-            int_to_float(
-                &input[0][..xsize],
-                &mut output_rows[0][0][..xsize],
-                &self.bit_depth,
-            );
+            let bits = self.bit_depth.bits_per_sample();
+            let exp_bits = self.bit_depth.exponent_bits_per_sample();
+            d.call(|d| int_to_float_simd(d, input, output, bits, exp_bits, xsize));
         } else {
-            let scale = 1.0 / ((1u64 << self.bit_depth.bits_per_sample()) - 1) as f32;
-            for i in 0..xsize {
-                output_rows[0][0][i] = input[0][i] as f32 * scale;
-            }
+            let bits = self.bit_depth.bits_per_sample();
+            d.call(|d| int_to_unit_float_simd(d, input, output, bits, xsize));
+        }
+    }
+}
+
+/// Stage that converts lossless modular integer data directly to u8 values with the same bit
+/// depth, skipping the conversion to f32.
+pub struct ConvertModularToU8Stage {
+    channel: usize,
+    bit_depth: u8,
+}
+
+impl ConvertModularToU8Stage {
+    pub fn new(channel: usize, bit_depth: u8) -> ConvertModularToU8Stage {
+        ConvertModularToU8Stage { channel, bit_depth }
+    }
+}
+
+impl std::fmt::Display for ConvertModularToU8Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "convert modular data to U8 in channel {} with bit depth {}",
+            self.channel, self.bit_depth
+        )
+    }
+}
+
+impl RenderPipelineInOutStage for ConvertModularToU8Stage {
+    type InputT = i32;
+    type OutputT = u8;
+    const SHIFT: (u8, u8) = (0, 0);
+    const BORDER: (u8, u8) = (0, 0);
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c == self.channel
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        _d: D,
+        _position: (usize, usize),
+        xsize: usize,
+        input_rows: &Channels<i32>,
+        output_rows: &mut ChannelsMut<u8>,
+        _state: Option<&mut dyn std::any::Any>,
+    ) {
+        let max = (1 << self.bit_depth) - 1;
+        let input = &input_rows[0][0][..xsize];
+        for (out, v) in output_rows[0][0][..xsize].iter_mut().zip(input) {
+            *out = (*v).clamp(0, max) as u8;
+        }
+    }
+}
+
+/// Stage that converts lossless modular integer data directly to u16 values with the same bit
+/// depth, skipping the conversion to f32.
+pub struct ConvertModularToU16Stage {
+    channel: usize,
+    bit_depth: u8,
+}
+
+impl ConvertModularToU16Stage {
+    pub fn new(channel: usize, bit_depth: u8) -> ConvertModularToU16Stage {
+        ConvertModularToU16Stage { channel, bit_depth }
+    }
+}
+
+impl std::fmt::Display for ConvertModularToU16Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "convert modular data to U16 in channel {} with bit depth {}",
+            self.channel, self.bit_depth
+        )
+    }
+}
+
+impl RenderPipelineInOutStage for ConvertModularToU16Stage {
+    type InputT = i32;
+    type OutputT = u16;
+    const SHIFT: (u8, u8) = (0, 0);
+    const BORDER: (u8, u8) = (0, 0);
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c == self.channel
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        _d: D,
+        _position: (usize, usize),
+        xsize: usize,
+        input_rows: &Channels<i32>,
+        output_rows: &mut ChannelsMut<u16>,
+        _state: Option<&mut dyn std::any::Any>,
+    ) {
+        let max = (1 << self.bit_depth) - 1;
+        let input = &input_rows[0][0][..xsize];
+        for (out, v) in output_rows[0][0][..xsize].iter_mut().zip(input) {
+            *out = (*v).clamp(0, max) as u16;
         }
     }
 }
@@ -252,29 +363,32 @@ impl std::fmt::Display for ConvertF32ToU8Stage {
 }
 
 // SIMD F32 to U8 conversion
-simd_function!(
-    f32_to_u8_simd_dispatch,
+#[inline(always)]
+fn f32_to_u8_simd<D: SimdDescriptor>(
     d: D,
-    fn f32_to_u8_simd(input: &[f32], output: &mut [u8], max: f32, xsize: usize) {
-        let simd_width = D::F32Vec::LEN;
-        let zero = D::F32Vec::splat(d, 0.0);
-        let one = D::F32Vec::splat(d, 1.0);
-        let scale = D::F32Vec::splat(d, max);
-
-        // Process SIMD vectors using div_ceil (buffers are padded)
-        for (input_chunk, output_chunk) in input
-            .chunks_exact(simd_width)
-            .zip(output.chunks_exact_mut(simd_width))
-            .take(xsize.div_ceil(simd_width))
-        {
-            let val = D::F32Vec::load(d, input_chunk);
-            // Clamp to [0, 1] and scale
-            let clamped = val.max(zero).min(one);
-            let scaled = clamped * scale;
-            scaled.round_store_u8(output_chunk);
-        }
+    input: &[f32],
+    output: &mut [u8],
+    max: f32,
+    xsize: usize,
+) {
+    let simd_width = D::F32Vec::LEN;
+    let zero = D::F32Vec::splat(d, 0.0);
+    let one = D::F32Vec::splat(d, 1.0);
+    let scale = D::F32Vec::splat(d, max);
+
+    // Process SIMD vectors using div_ceil (buffers are padded)
+    for (input_chunk, output_chunk) in input
+        .chunks_exact(simd_width)
+        .zip(output.chunks_exact_mut(simd_width))
+        .take(xsize.div_ceil(simd_width))
+    {
+        let val = D::F32Vec::load(d, input_chunk);
+        // Clamp to [0, 1] and scale
+        let clamped = val.max(zero).min(one);
+        let scaled = clamped * scale;
+        scaled.round_store_u8(output_chunk);
     }
-);
+}
 
 impl RenderPipelineInOutStage for ConvertF32ToU8Stage {
     type InputT = f32;
@@ -286,8 +400,9 @@ impl RenderPipelineInOutStage for ConvertF32ToU8Stage {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
@@ -297,7 +412,7 @@ impl RenderPipelineInOutStage for ConvertF32ToU8Stage {
         let input = input_rows[0][0];
         let output = &mut output_rows[0][0];
         let max = ((1u32 << self.bit_depth) - 1) as f32;
-        f32_to_u8_simd_dispatch(input, output, max, xsize);
+        d.call(|d| f32_to_u8_simd(d, input, output, max, xsize));
     }
 }
 
@@ -324,29 +439,32 @@ impl std::fmt::Display for ConvertF32ToU16Stage {
 }
 
 // SIMD F32 to U16 conversion
-simd_function!(
-    f32_to_u16_simd_dispatch,
+#[inline(always)]
+fn f32_to_u16_simd<D: SimdDescriptor>(
     d: D,
-    fn f32_to_u16_simd(input: &[f32], output: &mut [u16], max: f32, xsize: usize) {
-        let simd_width = D::F32Vec::LEN;
-        let zero = D::F32Vec::splat(d, 0.0);
-        let one = D::F32Vec::splat(d, 1.0);
-        let scale = D::F32Vec::splat(d, max);
-
-        // Process SIMD vectors using div_ceil (buffers are padded)
-        for (input_chunk, output_chunk) in input
-            .chunks_exact(simd_width)
-            .zip(output.chunks_exact_mut(simd_width))
-            .take(xsize.div_ceil(simd_width))
-        {
-            let val = D::F32Vec::load(d, input_chunk);
-            // Clamp to [0, 1] and scale
-            let clamped = val.max(zero).min(one);
-            let scaled = clamped * scale;
-            scaled.round_store_u16(output_chunk);
-        }
+    input: &[f32],
+    output: &mut [u16],
+    max: f32,
+    xsize: usize,
+) {
+    let simd_width = D::F32Vec::LEN;
+    let zero = D::F32Vec::splat(d, 0.0);
+    let one = D::F32Vec::splat(d, 1.0);
+    let scale = D::F32Vec::splat(d, max);
+
+    // Process SIMD vectors using div_ceil (buffers are padded)
+    for (input_chunk, output_chunk) in input
+        .chunks_exact(simd_width)
+        .zip(output.chunks_exact_mut(simd_width))
+        .take(xsize.div_ceil(simd_width))
+    {
+        let val = D::F32Vec::load(d, input_chunk);
+        // Clamp to [0, 1] and scale
+        let clamped = val.max(zero).min(one);
+        let scaled = clamped * scale;
+        scaled.round_store_u16(output_chunk);
     }
-);
+}
 
 impl RenderPipelineInOutStage for ConvertF32ToU16Stage {
     type InputT = f32;
@@ -358,8 +476,9 @@ impl RenderPipelineInOutStage for ConvertF32ToU16Stage {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
@@ -369,7 +488,7 @@ impl RenderPipelineInOutStage for ConvertF32ToU16Stage {
         let input = input_rows[0][0];
         let output = &mut output_rows[0][0];
         let max = ((1u32 << self.bit_depth) - 1) as f32;
-        f32_to_u16_simd_dispatch(input, output, max, xsize);
+        d.call(|d| f32_to_u16_simd(d, input, output, max, xsize));
     }
 }
 
@@ -400,8 +519,9 @@ impl RenderPipelineInOutStage for ConvertF32ToF16Stage {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
@@ -419,13 +539,147 @@ impl RenderPipelineInOutStage for ConvertF32ToF16Stage {
 mod test {
     use super::*;
     use crate::error::Result;
+    use crate::image::Image;
+    use crate::render::test::make_and_run_simple_pipeline;
+    use crate::util::round_up_size_to_cache_line;
+    use jxl_simd::{SimdDescriptor, test_all_instruction_sets};
     use test_log::test;
 
+    // Sample-by-sample conversion of custom floats, used as a reference.
+    fn int_to_float_reference(in_val: i32, bits: u32, exp_bits: u32) -> f32 {
+        if bits == 32 {
+            return f32::from_bits(in_val as u32);
+        }
+        let exp_bias = (1 << (exp_bits - 1)) - 1;
+        let sign_shift = bits - 1;
+        let mant_bits = bits - exp_bits - 1;
+        let mant_shift = 23 - mant_bits;
+        let mut f = in_val as u32;
+        let signbit = (f >> sign_shift) != 0;
+        f &= (1 << sign_shift) - 1;
+        if f == 0 {
+            return if signbit { -0.0 } else { 0.0 };
+        }
+        let mut exp = (f >> mant_bits) as i32;
+        let mut mantissa = f & ((1 << mant_bits) - 1);
+        if exp == (1 << exp_bits) - 1 {
+            // NaN or infinity
+            f = if signbit { 0x80000000 } else { 0 };
+            f |= 0b11111111 << 23;
+            f |= mantissa << mant_shift;
+            return f32::from_bits(f);
+        }
+        mantissa <<= mant_shift;
+        // Try to normalize only if there is space for maneuver.
+        if exp == 0 && exp_bits < 8 {
+            // subnormal number
+            while (mantissa & 0x800000) == 0 {
+                mantissa <<= 1;
+                exp -= 1;
+            }
+            exp += 1;
+            // remove leading 1 because it is implicit now
+            mantissa &= 0x7fffff;
+        }
+        exp -= exp_bias;
+        // broke up the arbitrary float into its parts, now reassemble into
+        // binary32
+        exp += 127;
+        assert!(exp >= 0);
+        f = if signbit { 0x80000000 } else { 0 };
+        f |= (exp as u32) << 23;
+        f |= mantissa;
+        f32::from_bits(f)
+    }
+
+    fn int_to_float_matches_reference<D: SimdDescriptor>(d: D) {
+        let xsize = 4096;
+        let mut input = vec![0; round_up_size_to_cache_line::<i32>(xsize)];
+        let mut output = vec![0.0; input.len()];
+        for exp_bits in 2..=8 {
+            for mant_bits in 2..=23 {
+                let bits = exp_bits + mant_bits + 1;
+                // Small values, including subnormals, and values spread over the whole range,
+                // including infinities and NaNs.
+                let mask = ((1u64 << bits) - 1) as u32;
+                for (i, v) in input[..xsize].iter_mut().enumerate() {
+                    let i = i as u32;
+                    let bits = if i.is_multiple_of(2) {
+                        i / 2
+                    } else {
+                        i.wrapping_mul(0x9e3779b9)
+                    };
+                    *v = (bits & mask) as i32;
+                }
+                int_to_float_simd(d, &input, &mut output, bits, exp_bits, xsize);
+                for (&v, &out) in input.iter().zip(output.iter()).take(xsize) {
+                    let expected = int_to_float_reference(v, bits, exp_bits);
+                    assert_eq!(
+                        out.to_bits(),
+                        expected.to_bits(),
+                        "E{exp_bits}M{mant_bits} value {v:#x}: {out} vs {expected}",
+                    );
+                }
+            }
+        }
+    }
+
+    test_all_instruction_sets!(int_to_float_matches_reference);
+
+    #[test]
+    fn modular_to_f32_consistency() -> Result<()> {
+        crate::render::test::test_stage_consistency(
+            || ConvertModularToF32Stage::new(0, BitDepth::integer_samples(12)),
+            (500, 500),
+            1,
+        )
+    }
+
     #[test]
     fn u8_consistency() -> Result<()> {
         crate::render::test::test_stage_consistency(|| ConvertU8F32Stage::new(0), (500, 500), 1)
     }
 
+    #[test]
+    fn modular_to_u8_matches_f32_path() -> Result<()> {
+        let mut input = Image::new((256, 1))?;
+        for (i, v) in input.row_mut(0).iter_mut().enumerate() {
+            *v = i as i32 - 8;
+        }
+        let direct = make_and_run_simple_pipeline(
+            ConvertModularToU8Stage::new(0, 7),
+            std::slice::from_ref(&input),
+            (256, 1),
+            0,
+            256,
+        )?;
+        let as_f32 = make_and_run_simple_pipeline(
+            ConvertModularToF32Stage::new(0, BitDepth::integer_samples(7)),
+            &[input],
+            (256, 1),
+            0,
+            256,
+        )?;
+        let via_f32 = make_and_run_simple_pipeline(
+            ConvertF32ToU8Stage::new(0, 7),
+            &as_f32,
+            (256, 1),
+            0,
+            256,
+        )?;
+        assert_eq!(direct[0].row(0), via_f32[0].row(0));
+        Ok(())
+    }
+
+    #[test]
+    fn modular_to_u16_consistency() -> Result<()> {
+        crate::render::test::test_stage_consistency(
+            || ConvertModularToU16Stage::new(0, 16),
+            (500, 500),
+            1,
+        )
+    }
+
     #[test]
     fn f32_to_u8_consistency() -> Result<()> {
         crate::render::test::test_stage_consistency(
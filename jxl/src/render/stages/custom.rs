@@ -0,0 +1,110 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{any::Any, sync::Arc};
+
+use crate::error::Result;
+use crate::{api::JxlCustomStage, render::RenderPipelineInPlaceStage};
+use jxl_simd::SimdDescriptor;
+
+/// Runs a user-provided [`JxlCustomStage`] on the first `num_channels` channels.
+pub struct CustomStage {
+    stage: Arc<dyn JxlCustomStage>,
+    num_channels: usize,
+}
+
+impl CustomStage {
+    pub fn new(stage: Arc<dyn JxlCustomStage>, num_channels: usize) -> Self {
+        Self {
+            stage,
+            num_channels,
+        }
+    }
+}
+
+impl std::fmt::Display for CustomStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let channels: Vec<_> = (0..self.num_channels)
+            .filter(|c| self.stage.uses_channel(*c))
+            .collect();
+        write!(f, "custom stage for channels {channels:?}")
+    }
+}
+
+impl RenderPipelineInPlaceStage for CustomStage {
+    type Type = f32;
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c < self.num_channels && self.stage.uses_channel(c)
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        _d: D,
+        position: (usize, usize),
+        xsize: usize,
+        row: &mut [&mut [f32]],
+        _state: Option<&mut dyn Any>,
+    ) -> Result<()> {
+        self.stage.process_rows(position, xsize, row);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use test_log::test;
+
+    use super::*;
+    use crate::error::Result;
+    use crate::image::Image;
+    use crate::render::test::make_and_run_simple_pipeline;
+    use crate::util::test::assert_all_almost_abs_eq;
+
+    struct Scale(f32);
+
+    impl JxlCustomStage for Scale {
+        fn uses_channel(&self, c: usize) -> bool {
+            c != 1
+        }
+
+        fn process_rows(&self, _position: (usize, usize), xsize: usize, rows: &mut [&mut [f32]]) {
+            for row in rows.iter_mut() {
+                for v in row[..xsize].iter_mut() {
+                    *v *= self.0;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scale_used_channels() -> Result<()> {
+        let input = [
+            Image::new_with_value((65, 3), 1.0)?,
+            Image::new_with_value((65, 3), 1.0)?,
+            Image::new_with_value((65, 3), 1.0)?,
+            Image::new_with_value((65, 3), 1.0)?,
+        ];
+        let stage = CustomStage::new(Arc::new(Scale(0.5)), 3);
+        let output = make_and_run_simple_pipeline(stage, &input, (65, 3), 0, 256)?;
+        for (c, expected) in [0.5, 1.0, 0.5, 1.0].into_iter().enumerate() {
+            for y in 0..3 {
+                assert_all_almost_abs_eq(output[c].row(y), &[expected; 65], 0.0);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn consistency() -> Result<()> {
+        crate::render::test::test_stage_consistency(
+            || CustomStage::new(Arc::new(Scale(0.25)), 4),
+            (500, 500),
+            4,
+        )
+    }
+}
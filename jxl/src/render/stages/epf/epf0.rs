@@ -14,7 +14,7 @@ use crate::{
     },
 };
 
-use jxl_simd::{F32SimdVec, SimdMask, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor, SimdMask};
 
 /// 5x5 plus-shaped kernel with 5 SADs per pixel (3x3 plus-shaped). So this makes this filter a 7x7 filter.
 pub struct Epf0Stage {
@@ -52,10 +52,9 @@ impl Epf0Stage {
     }
 }
 
-simd_function!(
-    epf0_process_row_chunk_dispatch,
+#[inline(always)]
+fn epf0_process_row_chunk_simd<D: SimdDescriptor>(
     d: D,
-    fn epf0_process_row_chunk_simd(
     stage: &Epf0Stage,
     pos: (usize, usize),
     xsize: usize,
@@ -191,25 +190,25 @@ simd_function!(
         for (input_c, output_c) in input_rows.iter().zip(output_rows.iter_mut()) {
             let mut out = D::F32Vec::load(d, &input_c[3][3 + x..]);
             for (row_idx, col_idx, sad_idx) in [
-                (5, 3+x, 11),
-                (4, 4+x, 10),
-                (4, 3+x, 9),
-                (4, 2+x, 8),
-                (3, 5+x, 7),
-                (3, 4+x, 6),
-                (3, 2+x, 5),
-                (3, 1+x, 4),
-                (2, 4+x, 3),
-                (2, 3+x, 2),
-                (2, 2+x, 1),
-                (1, 3+x, 0),
+                (5, 3 + x, 11),
+                (4, 4 + x, 10),
+                (4, 3 + x, 9),
+                (4, 2 + x, 8),
+                (3, 5 + x, 7),
+                (3, 4 + x, 6),
+                (3, 2 + x, 5),
+                (3, 1 + x, 4),
+                (2, 4 + x, 3),
+                (2, 3 + x, 2),
+                (2, 2 + x, 1),
+                (1, 3 + x, 0),
             ] {
                 out = D::F32Vec::load(d, &input_c[row_idx][col_idx..]).mul_add(sads[sad_idx], out);
             }
             (out * inv_w).store(&mut output_c[0][x..]);
         }
     }
-});
+}
 
 impl RenderPipelineInOutStage for Epf0Stage {
     type InputT = f32;
@@ -221,14 +220,17 @@ impl RenderPipelineInOutStage for Epf0Stage {
         c < 3
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         (xpos, ypos): (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
         output_rows: &mut ChannelsMut<f32>,
         _state: Option<&mut dyn std::any::Any>,
     ) {
-        epf0_process_row_chunk_dispatch(self, (xpos, ypos), xsize, input_rows, output_rows);
+        d.call(|d| {
+            epf0_process_row_chunk_simd(d, self, (xpos, ypos), xsize, input_rows, output_rows)
+        });
     }
 }
@@ -14,7 +14,7 @@ use crate::{
     },
 };
 
-use jxl_simd::{F32SimdVec, SimdMask, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor, SimdMask};
 
 /// 3x3 plus-shaped kernel with 5 SADs per pixel (3x3 plus-shaped). So this makes this filter a 5x5 filter.
 pub struct Epf1Stage {
@@ -52,10 +52,9 @@ impl Epf1Stage {
     }
 }
 
-simd_function!(
-epf1_process_row_chunk_dispatch,
-d: D,
-fn epf1_process_row_chunk(
+#[inline(always)]
+fn epf1_process_row_chunk<D: SimdDescriptor>(
+    d: D,
     stage: &Epf1Stage,
     pos: (usize, usize),
     xsize: usize,
@@ -134,18 +133,15 @@ fn epf1_process_row_chunk(
         let inv_w = D::F32Vec::splat(d, 1.0) / w;
         for (input_c, output_c) in input_rows.iter().zip(output_rows.iter_mut()) {
             let mut out = D::F32Vec::load(d, &input_c[2][2 + x..]);
-            for (row_idx, col_idx, sad_idx) in [
-                (3, 2+x, 3),
-                (2, 3+x, 2),
-                (2, 1+x, 1),
-                (1, 2+x, 0),
-            ] {
+            for (row_idx, col_idx, sad_idx) in
+                [(3, 2 + x, 3), (2, 3 + x, 2), (2, 1 + x, 1), (1, 2 + x, 0)]
+            {
                 out = D::F32Vec::load(d, &input_c[row_idx][col_idx..]).mul_add(sads[sad_idx], out);
             }
             (out * inv_w).store(&mut output_c[0][x..]);
         }
     }
-});
+}
 
 impl RenderPipelineInOutStage for Epf1Stage {
     type InputT = f32;
@@ -157,14 +153,15 @@ impl RenderPipelineInOutStage for Epf1Stage {
         c < 3
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         (xpos, ypos): (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
         output_rows: &mut ChannelsMut<f32>,
         _state: Option<&mut dyn std::any::Any>,
     ) {
-        epf1_process_row_chunk_dispatch(self, (xpos, ypos), xsize, input_rows, output_rows);
+        d.call(|d| epf1_process_row_chunk(d, self, (xpos, ypos), xsize, input_rows, output_rows));
     }
 }
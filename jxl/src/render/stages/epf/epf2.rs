@@ -14,7 +14,7 @@ use crate::{
     },
 };
 
-use jxl_simd::{F32SimdVec, SimdMask, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor, SimdMask};
 
 /// 3x3 plus-shaped kernel with 1 SAD per pixel. So this makes this filter a 3x3 filter.
 pub struct Epf2Stage {
@@ -52,10 +52,9 @@ impl Epf2Stage {
     }
 }
 
-simd_function!(
-epf2_process_row_chunk_dispatch,
-d: D,
-fn epf2_process_row_chunk(
+#[inline(always)]
+fn epf2_process_row_chunk<D: SimdDescriptor>(
+    d: D,
     stage: &Epf2Stage,
     pos: (usize, usize),
     xsize: usize,
@@ -63,7 +62,12 @@ fn epf2_process_row_chunk(
     output_rows: &mut ChannelsMut<f32>,
 ) {
     let (xpos, ypos) = pos;
-    assert_eq!(input_rows.len(), 3, "Expected 3 channels, got {}", input_rows.len());
+    assert_eq!(
+        input_rows.len(),
+        3,
+        "Expected 3 channels, got {}",
+        input_rows.len()
+    );
     let (input_x, input_y, input_b) = (&input_rows[0], &input_rows[1], &input_rows[2]);
     let (output_x, output_y, output_b) = output_rows.split_first_3_mut();
 
@@ -125,7 +129,7 @@ fn epf2_process_row_chunk(
         (y_acc * inv_w).store(&mut output_y[0][x..]);
         (b_acc * inv_w).store(&mut output_b[0][x..]);
     }
-});
+}
 
 impl RenderPipelineInOutStage for Epf2Stage {
     type InputT = f32;
@@ -137,14 +141,15 @@ impl RenderPipelineInOutStage for Epf2Stage {
         c < 3
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         (xpos, ypos): (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
         output_rows: &mut ChannelsMut<f32>,
         _state: Option<&mut dyn std::any::Any>,
     ) {
-        epf2_process_row_chunk_dispatch(self, (xpos, ypos), xsize, input_rows, output_rows);
+        d.call(|d| epf2_process_row_chunk(d, self, (xpos, ypos), xsize, input_rows, output_rows));
     }
 }
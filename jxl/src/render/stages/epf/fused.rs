@@ -0,0 +1,352 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::any::Any;
+
+use crate::{
+    error::Result,
+    render::{
+        Channels, ChannelsMut, RenderPipeline, RenderPipelineBuilder, RenderPipelineInOutStage,
+        stages::GaborishStage,
+    },
+    util::SmallVec,
+};
+use jxl_simd::SimdDescriptor;
+
+use super::{Epf0Stage, Epf1Stage, Epf2Stage};
+
+/// One of the filters run by a [`RestorationFilterStage`].
+pub enum RestorationFilter {
+    /// Gaborish, with one stage for each color channel.
+    Gaborish([GaborishStage; 3]),
+    Epf0(Epf0Stage),
+    Epf1(Epf1Stage),
+    Epf2(Epf2Stage),
+}
+
+impl RestorationFilter {
+    fn border(&self) -> usize {
+        let border = match self {
+            RestorationFilter::Gaborish(_) => GaborishStage::BORDER,
+            RestorationFilter::Epf0(_) => Epf0Stage::BORDER,
+            RestorationFilter::Epf1(_) => Epf1Stage::BORDER,
+            RestorationFilter::Epf2(_) => Epf2Stage::BORDER,
+        };
+        border.1 as usize
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        d: D,
+        position: (usize, usize),
+        xsize: usize,
+        input_rows: &Channels<f32>,
+        output_rows: &mut ChannelsMut<f32>,
+    ) {
+        match self {
+            RestorationFilter::Gaborish(stages) => {
+                for (c, stage) in stages.iter().enumerate() {
+                    let mut input = SmallVec::new();
+                    input.extend(input_rows[c].iter().copied());
+                    let input = Channels::new(input, 1, input_rows[c].len());
+                    let mut output = SmallVec::new();
+                    output.push(&mut *output_rows[c][0]);
+                    let mut output = ChannelsMut::new(output, 1, 1);
+                    stage.process_row_chunk(d, position, xsize, &input, &mut output, None);
+                }
+            }
+            RestorationFilter::Epf0(stage) => {
+                stage.process_row_chunk(d, position, xsize, input_rows, output_rows, None)
+            }
+            RestorationFilter::Epf1(stage) => {
+                stage.process_row_chunk(d, position, xsize, input_rows, output_rows, None)
+            }
+            RestorationFilter::Epf2(stage) => {
+                stage.process_row_chunk(d, position, xsize, input_rows, output_rows, None)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for RestorationFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestorationFilter::Gaborish(_) => write!(f, "Gaborish"),
+            RestorationFilter::Epf0(stage) => write!(f, "{stage}"),
+            RestorationFilter::Epf1(stage) => write!(f, "{stage}"),
+            RestorationFilter::Epf2(stage) => write!(f, "{stage}"),
+        }
+    }
+}
+
+/// Runs Gaborish and the EPF iterations on the color channels as a single stage, with a total
+/// border of `BORDER` pixels.
+///
+/// The output of each filter but the last is kept in a small cache of rows that only covers the
+/// current row chunk, and that is re-used for the next row of the same chunk. This avoids going
+/// through the row buffers of the pipeline, and gives exactly the same results as running the
+/// filters as separate stages: the intermediate results are mirrored at the image edges the
+/// same way that the pipeline mirrors the input of each stage.
+pub struct RestorationFilterStage<const BORDER: u8> {
+    filters: Vec<RestorationFilter>,
+    // Size of the color channels.
+    image_size: (usize, usize),
+}
+
+impl<const BORDER: u8> RestorationFilterStage<BORDER> {
+    pub fn new(filters: Vec<RestorationFilter>, image_size: (usize, usize)) -> Self {
+        assert!(!filters.is_empty());
+        assert_eq!(
+            filters.iter().map(RestorationFilter::border).sum::<usize>(),
+            BORDER as usize
+        );
+        Self {
+            filters,
+            image_size,
+        }
+    }
+}
+
+impl<const BORDER: u8> std::fmt::Display for RestorationFilterStage<BORDER> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fused restoration filters: ")?;
+        for (i, filter) in self.filters.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{filter}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds stages that run `filters`, in order, to `pipeline`. If `fuse` is true and there is more
+/// than one filter, they are run by a single [`RestorationFilterStage`].
+pub(crate) fn add_restoration_filters<P: RenderPipeline>(
+    pipeline: RenderPipelineBuilder<P>,
+    filters: Vec<RestorationFilter>,
+    image_size: (usize, usize),
+    fuse: bool,
+) -> Result<RenderPipelineBuilder<P>> {
+    if !fuse || filters.len() < 2 {
+        return filters
+            .into_iter()
+            .try_fold(pipeline, |pipeline, filter| match filter {
+                RestorationFilter::Gaborish([x, y, b]) => pipeline
+                    .add_inout_stage(x)?
+                    .add_inout_stage(y)?
+                    .add_inout_stage(b),
+                RestorationFilter::Epf0(stage) => pipeline.add_inout_stage(stage),
+                RestorationFilter::Epf1(stage) => pipeline.add_inout_stage(stage),
+                RestorationFilter::Epf2(stage) => pipeline.add_inout_stage(stage),
+            });
+    }
+    macro_rules! add_fused {
+        ($($border:literal),*) => {
+            match filters.iter().map(RestorationFilter::border).sum::<usize>() {
+                $($border => pipeline.add_inout_stage(
+                    RestorationFilterStage::<$border>::new(filters, image_size)
+                ),)*
+                border => unreachable!("invalid restoration filter border {border}"),
+            }
+        };
+    }
+    add_fused!(2, 3, 4, 5, 6, 7)
+}
+
+// Extra space at the end of cached rows, as filters process whole SIMD vectors.
+const ROW_PADDING: usize = 16;
+
+/// Mirror-reflects `v` to fit in a [0; size) range.
+fn mirror(mut v: isize, size: usize) -> usize {
+    loop {
+        if v < 0 {
+            v = -v - 1;
+        } else if v >= size as isize {
+            v = size as isize * 2 - v - 1;
+        } else {
+            return v as usize;
+        }
+    }
+}
+
+// Output rows of one of the filters, for the columns of the current chunk plus `border` columns
+// on each side.
+struct FilterRows {
+    border: usize,
+    // For each slot, the image row that it holds, if any.
+    row_ids: Vec<Option<usize>>,
+    // One row per channel for each slot.
+    rows: Vec<Vec<f32>>,
+}
+
+impl FilterRows {
+    fn row(&self, c: usize, y: usize) -> &[f32] {
+        let slot = y % self.row_ids.len();
+        debug_assert_eq!(self.row_ids[slot], Some(y));
+        &self.rows[slot * 3 + c]
+    }
+}
+
+struct RestorationFilterState {
+    // Position and size of the chunk that the cached rows belong to, and last processed row.
+    chunk: Option<(usize, usize, usize)>,
+    filter_rows: Vec<FilterRows>,
+    // Padded copies of the input rows that are too short for the first filter.
+    padded_input: Vec<Vec<f32>>,
+}
+
+impl<const BORDER: u8> RenderPipelineInOutStage for RestorationFilterStage<BORDER> {
+    type InputT = f32;
+    type OutputT = f32;
+    const SHIFT: (u8, u8) = (0, 0);
+    const BORDER: (u8, u8) = (BORDER, BORDER);
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c < 3
+    }
+
+    fn init_local_state(&self) -> Result<Option<Box<dyn Any>>> {
+        let mut border = BORDER as usize;
+        let filter_rows = self.filters[..self.filters.len() - 1]
+            .iter()
+            .map(|filter| {
+                border -= filter.border();
+                // All the rows that the next filter reads have distinct slots.
+                let num_slots = 2 * border + 1;
+                FilterRows {
+                    border,
+                    row_ids: vec![None; num_slots],
+                    rows: vec![vec![]; num_slots * 3],
+                }
+            })
+            .collect();
+        let padded_input = vec![vec![]; 3 * (2 * self.filters[0].border() + 1)];
+        Ok(Some(Box::new(RestorationFilterState {
+            chunk: None,
+            filter_rows,
+            padded_input,
+        })))
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        d: D,
+        (x0, y): (usize, usize),
+        xsize: usize,
+        input_rows: &Channels<f32>,
+        output_rows: &mut ChannelsMut<f32>,
+        state: Option<&mut dyn Any>,
+    ) {
+        let state: &mut RestorationFilterState = state.unwrap().downcast_mut().unwrap();
+        let (width, height) = self.image_size;
+
+        // Cached rows can only be re-used when rendering the next row of the same chunk, as
+        // the input might have changed in any other case.
+        if y == 0 || state.chunk != Some((x0, xsize, y - 1)) {
+            for filter_rows in state.filter_rows.iter_mut() {
+                filter_rows.row_ids.fill(None);
+                let row_len = xsize + 2 * filter_rows.border + ROW_PADDING;
+                for row in filter_rows.rows.iter_mut() {
+                    row.resize(row_len, 0.0);
+                }
+            }
+        }
+        state.chunk = Some((x0, xsize, y));
+
+        for i in 0..state.filter_rows.len() {
+            let (previous, current) = state.filter_rows.split_at_mut(i);
+            let current = &mut current[0];
+            let filter = &self.filters[i];
+            let filter_border = filter.border() as isize;
+            let border = current.border;
+            // Only compute pixels inside the image; the others are mirrored from them.
+            let xstart = x0.saturating_sub(border);
+            let xend = (x0 + xsize + border).min(width);
+            // Index of column `xstart` in the cached rows of this filter and, since borders
+            // add up, in the (cached or input) rows that the filter reads.
+            let offset = xstart + border - x0;
+            for vy in y as isize - border as isize..=(y + border) as isize {
+                let out_y = mirror(vy, height);
+                let slot = out_y % current.row_ids.len();
+                if current.row_ids[slot] == Some(out_y) {
+                    continue;
+                }
+                // The pipeline only pads input rows for the vectors of the final output, and the
+                // first filter produces more columns than that: rows that are too short are
+                // copied to padded rows.
+                let input_len = xend - xstart + 2 * filter_border as usize + ROW_PADDING;
+                let mut padded_rows = state.padded_input.iter_mut();
+                let mut input = SmallVec::new();
+                for c in 0..3 {
+                    for iy in -filter_border..=filter_border {
+                        let in_y = out_y as isize + iy;
+                        let row = match previous.last() {
+                            Some(rows) => &rows.row(c, mirror(in_y, height))[offset..],
+                            None => {
+                                // Input rows are already mirrored by the pipeline.
+                                let row = &input_rows[c]
+                                    [(in_y - y as isize + BORDER as isize) as usize][offset..];
+                                if row.len() < input_len {
+                                    let padded = padded_rows.next().unwrap();
+                                    padded.clear();
+                                    padded.extend_from_slice(row);
+                                    padded.resize(input_len, 0.0);
+                                    &padded[..]
+                                } else {
+                                    row
+                                }
+                            }
+                        };
+                        input.push(row);
+                    }
+                }
+                let input = Channels::new(input, 3, 2 * filter_border as usize + 1);
+                {
+                    let mut output = SmallVec::new();
+                    output.extend(
+                        current.rows[slot * 3..slot * 3 + 3]
+                            .iter_mut()
+                            .map(|row| &mut row[offset..]),
+                    );
+                    let mut output = ChannelsMut::new(output, 3, 1);
+                    filter.process_row_chunk(
+                        d,
+                        (xstart, out_y),
+                        xend - xstart,
+                        &input,
+                        &mut output,
+                    );
+                }
+
+                // Columns are relative to the first cached column.
+                let out_of_image = (0..offset).chain(xend + border - x0..xsize + 2 * border);
+                for row in current.rows[slot * 3..slot * 3 + 3].iter_mut() {
+                    for x in out_of_image.clone() {
+                        let from = mirror(x as isize + x0 as isize - border as isize, width);
+                        row[x] = row[from + border - x0];
+                    }
+                }
+                current.row_ids[slot] = Some(out_y);
+            }
+        }
+
+        let filter = self.filters.last().unwrap();
+        let filter_border = filter.border() as isize;
+        match state.filter_rows.last() {
+            None => filter.process_row_chunk(d, (x0, y), xsize, input_rows, output_rows),
+            Some(rows) => {
+                let mut input = SmallVec::new();
+                for c in 0..3 {
+                    for iy in -filter_border..=filter_border {
+                        input.push(rows.row(c, mirror(y as isize + iy, height)));
+                    }
+                }
+                let input = Channels::new(input, 3, 2 * filter_border as usize + 1);
+                filter.process_row_chunk(d, (x0, y), xsize, &input, output_rows);
+            }
+        }
+    }
+}
@@ -7,10 +7,13 @@ mod common;
 mod epf0;
 mod epf1;
 mod epf2;
+mod fused;
 
 pub use epf0::Epf0Stage;
 pub use epf1::Epf1Stage;
 pub use epf2::Epf2Stage;
+pub(crate) use fused::add_restoration_filters;
+pub use fused::{RestorationFilter, RestorationFilterStage};
 
 #[cfg(test)]
 mod test;
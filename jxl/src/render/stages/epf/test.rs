@@ -9,7 +9,14 @@ use rand::SeedableRng;
 use test_log::test;
 
 use super::*;
-use crate::{error::Result, image::Image};
+use crate::{
+    error::Result,
+    image::Image,
+    render::{
+        LowMemoryRenderPipeline, RenderPipeline, SimpleRenderPipeline, stages::GaborishStage,
+        test::make_and_run_pipeline, with_smallest_tiles,
+    },
+};
 
 #[test]
 fn epf0_consistency() -> Result<()> {
@@ -43,3 +50,91 @@ fn epf2_consistency() -> Result<()> {
         4,
     )
 }
+
+fn make_filters(
+    gaborish: bool,
+    epf_iters: usize,
+    sigma: &Arc<Image<f32>>,
+) -> Vec<RestorationFilter> {
+    let mut filters = vec![];
+    if gaborish {
+        filters.push(RestorationFilter::Gaborish([
+            GaborishStage::new(0, 0.115169525, 0.061248592),
+            GaborishStage::new(1, 0.1, 0.05),
+            GaborishStage::new(2, 0.12, 0.07),
+        ]));
+    }
+    if epf_iters >= 3 {
+        filters.push(RestorationFilter::Epf0(Epf0Stage::new(
+            0.9,
+            2.3 / 3.0,
+            [40.0, 5.0, 3.5],
+            sigma.clone(),
+        )));
+    }
+    if epf_iters >= 1 {
+        filters.push(RestorationFilter::Epf1(Epf1Stage::new(
+            1.0,
+            2.3 / 3.0,
+            [40.0, 5.0, 3.5],
+            sigma.clone(),
+        )));
+    }
+    if epf_iters >= 2 {
+        filters.push(RestorationFilter::Epf2(Epf2Stage::new(
+            6.5,
+            2.3 / 3.0,
+            [40.0, 5.0, 3.5],
+            sigma.clone(),
+        )));
+    }
+    filters
+}
+
+fn check_fused_matches_separate<P: RenderPipeline>(chunk_size: usize) -> Result<()> {
+    let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(0);
+    let sigma = Arc::new(Image::new_random((128, 128), &mut rng)?);
+    for size in [(300, 267), (517, 9), (6, 300), (1, 1)] {
+        let images = (0..4)
+            .map(|_| Image::new_random(size, &mut rng))
+            .collect::<Result<Vec<_>>>()?;
+        for (gaborish, epf_iters) in [(true, 1), (true, 2), (true, 3), (false, 2), (false, 3)] {
+            let run = |fuse| {
+                make_and_run_pipeline::<P>(
+                    |pipeline| {
+                        add_restoration_filters(
+                            pipeline,
+                            make_filters(gaborish, epf_iters, &sigma),
+                            size,
+                            fuse,
+                        )
+                    },
+                    &images,
+                    chunk_size,
+                )
+            };
+            for (separate, fused) in run(false)?.iter().zip(run(true)?.iter()) {
+                for y in 0..size.1 {
+                    assert_eq!(
+                        separate.row(y),
+                        fused.row(y),
+                        "size {size:?}, gaborish {gaborish}, epf iters {epf_iters}, row {y}"
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn fused_matches_separate_simple() -> Result<()> {
+    check_fused_matches_separate::<SimpleRenderPipeline>(256)?;
+    check_fused_matches_separate::<SimpleRenderPipeline>(37)
+}
+
+#[test]
+fn fused_matches_separate_low_memory() -> Result<()> {
+    check_fused_matches_separate::<LowMemoryRenderPipeline>(256)?;
+    with_smallest_tiles(|| check_fused_matches_separate::<LowMemoryRenderPipeline>(256))
+}
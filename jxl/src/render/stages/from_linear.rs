@@ -4,9 +4,10 @@
 // license that can be found in the LICENSE file.
 
 use crate::color::tf;
+use crate::error::Result;
 use crate::headers::color_encoding::CustomTransferFunction;
 use crate::render::RenderPipelineInPlaceStage;
-use jxl_simd::{F32SimdVec, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor};
 
 /// Apply transfer function to display-referred linear color samples.
 #[derive(Debug)]
@@ -31,10 +32,7 @@ impl FromLinearStage {
     }
 
     pub fn hlg(first_channel: usize, intensity_target: f32, luminance_rgb: [f32; 3]) -> Self {
-        let tf = TransferFunction::Hlg {
-            intensity_target,
-            luminance_rgb,
-        };
+        let tf = TransferFunction::hlg(intensity_target, luminance_rgb);
         Self::new(first_channel, tf)
     }
 }
@@ -53,10 +51,13 @@ impl std::fmt::Display for FromLinearStage {
     }
 }
 
-simd_function!(
-from_linear_process_dispatch,
-d: D,
-fn from_linear_process(tf: &TransferFunction, xsize: usize, row: &mut [&mut [f32]]) {
+#[inline(always)]
+fn from_linear_process<D: SimdDescriptor>(
+    d: D,
+    tf: &TransferFunction,
+    xsize: usize,
+    row: &mut [&mut [f32]],
+) {
     let [row_r, row_g, row_b] = row else {
         panic!(
             "incorrect number of channels; expected 3, found {}",
@@ -83,13 +84,23 @@ fn from_linear_process(tf: &TransferFunction, xsize: usize, row: &mut [&mut [f32
         TransferFunction::Hlg {
             intensity_target,
             luminance_rgb,
+            system_gamma,
+            linear_white,
         } => {
+            if linear_white != intensity_target {
+                let scale = linear_white / intensity_target;
+                for row in [&mut *row_r, &mut *row_g, &mut *row_b] {
+                    for v in row[..xsize].iter_mut() {
+                        *v *= scale;
+                    }
+                }
+            }
             let rows = [
                 &mut row_r[..xsize],
                 &mut row_g[..xsize],
                 &mut row_b[..xsize],
             ];
-            tf::hlg_display_to_scene(intensity_target, luminance_rgb, rows);
+            tf::hlg_inverse_ootf(system_gamma, luminance_rgb, rows);
 
             tf::scene_to_hlg(&mut row_r[..xsize]);
             tf::scene_to_hlg(&mut row_g[..xsize]);
@@ -97,8 +108,8 @@ fn from_linear_process(tf: &TransferFunction, xsize: usize, row: &mut [&mut [f32
         }
         TransferFunction::Gamma(g) => {
             for row in row {
-                for values in row[..xsize.next_multiple_of(D::F32Vec::LEN)]
-                    .chunks_exact_mut(D::F32Vec::LEN)
+                for values in
+                    row[..xsize.next_multiple_of(D::F32Vec::LEN)].chunks_exact_mut(D::F32Vec::LEN)
                 {
                     let v = D::F32Vec::load(d, values);
                     crate::util::fast_powf_simd(d, v.abs(), D::F32Vec::splat(d, g))
@@ -108,7 +119,7 @@ fn from_linear_process(tf: &TransferFunction, xsize: usize, row: &mut [&mut [f32
             }
         }
     }
-});
+}
 
 impl RenderPipelineInPlaceStage for FromLinearStage {
     type Type = f32;
@@ -117,18 +128,20 @@ impl RenderPipelineInPlaceStage for FromLinearStage {
         (self.first_channel..self.first_channel + 3).contains(&c)
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
-    ) {
-        from_linear_process_dispatch(&self.tf, xsize, row)
+    ) -> Result<()> {
+        d.call(|d| from_linear_process(d, &self.tf, xsize, row));
+        Ok(())
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum TransferFunction {
     Bt709,
     Srgb,
@@ -136,13 +149,31 @@ pub enum TransferFunction {
         intensity_target: f32,
     },
     Hlg {
+        /// Nominal peak luminance of the display, in nits.
         intensity_target: f32,
         luminance_rgb: [f32; 3],
+        /// System gamma of the HLG OOTF.
+        system_gamma: f32,
+        /// Luminance, in nits, represented by a linear sample value of 1.0.
+        linear_white: f32,
     },
     /// Inverse gamma in range `(0, 1]`
     Gamma(f32),
 }
 
+impl TransferFunction {
+    /// HLG transfer function for a display with peak luminance `intensity_target`, with the
+    /// system gamma used by libjxl and linear samples relative to the display peak luminance.
+    pub fn hlg(intensity_target: f32, luminance_rgb: [f32; 3]) -> Self {
+        Self::Hlg {
+            intensity_target,
+            luminance_rgb,
+            system_gamma: tf::hlg_system_gamma(intensity_target),
+            linear_white: intensity_target,
+        }
+    }
+}
+
 impl TryFrom<CustomTransferFunction> for TransferFunction {
     type Error = ();
 
@@ -241,6 +272,30 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn hlg_reference_white() -> Result<()> {
+        let input_r = Image::new_with_value((1, 1), 1.0)?;
+        let input_g = Image::new_with_value((1, 1), 1.0)?;
+        let input_b = Image::new_with_value((1, 1), 1.0)?;
+
+        // Linear 1.0 is 203 nits, i.e. reference white on a 1000-nit display.
+        let tf = TransferFunction::Hlg {
+            intensity_target: 1000.0,
+            luminance_rgb: LUMINANCE_BT2020,
+            system_gamma: 1.2,
+            linear_white: 203.0,
+        };
+        let stage = FromLinearStage::new(0, tf);
+        let output =
+            make_and_run_simple_pipeline(stage, &[input_r, input_g, input_b], (1, 1), 0, 256)?;
+
+        assert_all_almost_abs_eq(output[0].row(0), &[0.75], 1e-3);
+        assert_all_almost_abs_eq(output[1].row(0), &[0.75], 1e-3);
+        assert_all_almost_abs_eq(output[2].row(0), &[0.75], 1e-3);
+
+        Ok(())
+    }
+
     #[test]
     fn sdr_white_pq() -> Result<()> {
         let intensity_target = 1000f32;
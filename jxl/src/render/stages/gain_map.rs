@@ -0,0 +1,54 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{any::Any, sync::Arc};
+
+use crate::error::Result;
+use crate::{api::GainMapRendering, render::RenderPipelineInPlaceStage};
+use jxl_simd::SimdDescriptor;
+
+/// Applies a gain map to the linear color channels, to render the image for a display with more
+/// or less HDR headroom than the base rendition.
+pub struct GainMapStage {
+    gain_map: Arc<GainMapRendering>,
+    num_color_channels: usize,
+}
+
+impl GainMapStage {
+    pub(crate) fn new(gain_map: Arc<GainMapRendering>, num_color_channels: usize) -> Self {
+        Self {
+            gain_map,
+            num_color_channels,
+        }
+    }
+}
+
+impl std::fmt::Display for GainMapStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "gain map for channels 0..{}", self.num_color_channels)
+    }
+}
+
+impl RenderPipelineInPlaceStage for GainMapStage {
+    type Type = f32;
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c < self.num_color_channels
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        _d: D,
+        position: (usize, usize),
+        xsize: usize,
+        row: &mut [&mut [f32]],
+        _state: Option<&mut dyn Any>,
+    ) -> Result<()> {
+        self.gain_map
+            .applier
+            .apply_rows(&self.gain_map.image, position, xsize, row);
+        Ok(())
+    }
+}
@@ -5,36 +5,49 @@
 
 mod blending;
 mod chroma_upsample;
+mod cmyk;
+mod color_matrix;
 mod convert;
+mod custom;
 mod epf;
 mod extend;
 mod from_linear;
 mod gaborish;
+mod gain_map;
+mod nearest_neighbor;
 mod noise;
 mod patches;
 mod premultiply_alpha;
+mod resample;
 mod splines;
 mod spot;
+mod tap;
 mod to_linear;
 mod upsample;
 mod xyb;
 mod ycbcr;
 
-#[cfg(test)]
-mod nearest_neighbor;
-
 pub use blending::*;
 pub use chroma_upsample::*;
+#[cfg(test)]
+pub(crate) use cmyk::test::NaiveCmykCms;
+pub use cmyk::*;
+pub use color_matrix::*;
 pub use convert::*;
+pub use custom::*;
 pub use epf::*;
 pub use extend::*;
 pub use from_linear::*;
 pub use gaborish::*;
+pub use gain_map::*;
+pub use nearest_neighbor::*;
 pub use noise::*;
 pub use patches::*;
 pub use premultiply_alpha::*;
+pub use resample::*;
 pub use splines::*;
 pub use spot::*;
+pub use tap::*;
 pub use to_linear::{ToLinearStage, TransferFunction as ToLinearTransferFunction};
 pub use upsample::*;
 pub use xyb::*;
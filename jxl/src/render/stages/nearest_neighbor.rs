@@ -4,6 +4,7 @@
 // license that can be found in the LICENSE file.
 
 use crate::render::{Channels, ChannelsMut, RenderPipelineInOutStage};
+use jxl_simd::SimdDescriptor;
 pub struct NearestNeighbourUpsample {
     channel: usize,
 }
@@ -34,8 +35,9 @@ impl RenderPipelineInOutStage for NearestNeighbourUpsample {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
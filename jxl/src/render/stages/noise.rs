@@ -5,12 +5,13 @@
 
 #![allow(clippy::needless_range_loop)]
 
+use crate::error::Result;
 use crate::{
     features::noise::Noise,
     frame::color_correlation_map::ColorCorrelationParams,
     render::{Channels, ChannelsMut, RenderPipelineInOutStage, RenderPipelineInPlaceStage},
 };
-use jxl_simd::{F32SimdVec, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor};
 
 pub struct ConvolveNoiseStage {
     channel: usize,
@@ -29,55 +30,57 @@ impl std::fmt::Display for ConvolveNoiseStage {
 }
 
 // SIMD noise convolution (5x5 kernel)
-simd_function!(
-    convolve_noise_simd_dispatch,
+#[inline(always)]
+fn convolve_noise_simd<D: SimdDescriptor>(
     d: D,
-    fn convolve_noise_simd(input: &[&[f32]], output: &mut [f32], xsize: usize) {
-        // Precompute constants
-        let c016 = D::F32Vec::splat(d, 0.16);
-        let cn384 = D::F32Vec::splat(d, -3.84);
+    input: &[&[f32]],
+    output: &mut [f32],
+    xsize: usize,
+) {
+    // Precompute constants
+    let c016 = D::F32Vec::splat(d, 0.16);
+    let cn384 = D::F32Vec::splat(d, -3.84);
 
-        // Windows of size LEN+4 from each row (for offsets 0..5), stepping by LEN
-        let iter0 = input[0].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
-        let iter1 = input[1].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
-        let iter2 = input[2].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
-        let iter3 = input[3].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
-        let iter4 = input[4].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
-        let out_iter = output.chunks_exact_mut(D::F32Vec::LEN);
+    // Windows of size LEN+4 from each row (for offsets 0..5), stepping by LEN
+    let iter0 = input[0].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
+    let iter1 = input[1].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
+    let iter2 = input[2].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
+    let iter3 = input[3].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
+    let iter4 = input[4].windows(D::F32Vec::LEN + 4).step_by(D::F32Vec::LEN);
+    let out_iter = output.chunks_exact_mut(D::F32Vec::LEN);
 
-        for ((((w0, w1), w2), w3), (w4, out)) in iter0
-            .zip(iter1)
-            .zip(iter2)
-            .zip(iter3)
-            .zip(iter4.zip(out_iter))
-            .take(xsize.div_ceil(D::F32Vec::LEN))
-        {
-            // Load center pixel (row 2, offset +2)
-            let p00 = D::F32Vec::load(d, &w2[2..]);
+    for ((((w0, w1), w2), w3), (w4, out)) in iter0
+        .zip(iter1)
+        .zip(iter2)
+        .zip(iter3)
+        .zip(iter4.zip(out_iter))
+        .take(xsize.div_ceil(D::F32Vec::LEN))
+    {
+        // Load center pixel (row 2, offset +2)
+        let p00 = D::F32Vec::load(d, &w2[2..]);
 
-            // Accumulate surrounding pixels
-            let mut others = D::F32Vec::splat(d, 0.0);
+        // Accumulate surrounding pixels
+        let mut others = D::F32Vec::splat(d, 0.0);
 
-            // Add all 5 offsets for rows 0, 1, 3, 4
-            for i in 0..5 {
-                others += D::F32Vec::load(d, &w0[i..]);
-                others += D::F32Vec::load(d, &w1[i..]);
-                others += D::F32Vec::load(d, &w3[i..]);
-                others += D::F32Vec::load(d, &w4[i..]);
-            }
+        // Add all 5 offsets for rows 0, 1, 3, 4
+        for i in 0..5 {
+            others += D::F32Vec::load(d, &w0[i..]);
+            others += D::F32Vec::load(d, &w1[i..]);
+            others += D::F32Vec::load(d, &w3[i..]);
+            others += D::F32Vec::load(d, &w4[i..]);
+        }
 
-            // Add row 2 neighbors (skip center at offset 2)
-            others += D::F32Vec::load(d, &w2[0..]);
-            others += D::F32Vec::load(d, &w2[1..]);
-            others += D::F32Vec::load(d, &w2[3..]);
-            others += D::F32Vec::load(d, &w2[4..]);
+        // Add row 2 neighbors (skip center at offset 2)
+        others += D::F32Vec::load(d, &w2[0..]);
+        others += D::F32Vec::load(d, &w2[1..]);
+        others += D::F32Vec::load(d, &w2[3..]);
+        others += D::F32Vec::load(d, &w2[4..]);
 
-            // Compute: others * 0.16 + center * -3.84
-            let result = others.mul_add(c016, p00 * cn384);
-            result.store(out);
-        }
+        // Compute: others * 0.16 + center * -3.84
+        let result = others.mul_add(c016, p00 * cn384);
+        result.store(out);
     }
-);
+}
 
 impl RenderPipelineInOutStage for ConvolveNoiseStage {
     type InputT = f32;
@@ -89,8 +92,9 @@ impl RenderPipelineInOutStage for ConvolveNoiseStage {
         c == self.channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
@@ -98,7 +102,7 @@ impl RenderPipelineInOutStage for ConvolveNoiseStage {
         _state: Option<&mut dyn std::any::Any>,
     ) {
         let input = &input_rows[0];
-        convolve_noise_simd_dispatch(input, output_rows[0][0], xsize);
+        d.call(|d| convolve_noise_simd(d, input, output_rows[0][0], xsize));
     }
 }
 
@@ -143,13 +147,14 @@ impl RenderPipelineInPlaceStage for AddNoiseStage {
         c < 3 || (c >= self.first_channel && c < self.first_channel + 3)
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         _position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
-    ) {
+    ) -> Result<()> {
         let norm_const = 0.22;
         let ytox = self.color_correlation.y_to_x_lf();
         let ytob = self.color_correlation.y_to_b_lf();
@@ -177,6 +182,7 @@ impl RenderPipelineInPlaceStage for AddNoiseStage {
             row[1][x] += rg_noise;
             row[2][x] += ytob * rg_noise;
         }
+        Ok(())
     }
 }
 
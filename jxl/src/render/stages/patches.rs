@@ -5,11 +5,13 @@
 
 use std::{any::Any, sync::Arc};
 
+use crate::error::Result;
 use crate::{
     features::patches::PatchesDictionary, frame::ReferenceFrame,
     headers::extra_channels::ExtraChannelInfo, render::RenderPipelineInPlaceStage,
     util::NewWithCapacity as _,
 };
+use jxl_simd::SimdDescriptor;
 
 pub struct PatchesStage {
     pub patches: Arc<PatchesDictionary>,
@@ -30,13 +32,14 @@ impl RenderPipelineInPlaceStage for PatchesStage {
         c < 3 + self.extra_channels.len()
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         state: Option<&mut dyn Any>,
-    ) {
+    ) -> Result<()> {
         let state: &mut Vec<usize> = state.unwrap().downcast_mut().unwrap();
         self.patches.add_one_row(
             row,
@@ -46,9 +49,10 @@ impl RenderPipelineInPlaceStage for PatchesStage {
             &self.decoder_state[..],
             state,
         );
+        Ok(())
     }
 
-    fn init_local_state(&self) -> crate::error::Result<Option<Box<dyn Any>>> {
+    fn init_local_state(&self) -> Result<Option<Box<dyn Any>>> {
         let patches_for_row_result = Vec::<usize>::new_with_capacity(self.patches.positions.len())?;
         Ok(Some(Box::new(patches_for_row_result) as Box<dyn Any>))
     }
@@ -3,8 +3,9 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::error::Result;
 use crate::render::RenderPipelineInPlaceStage;
-use jxl_simd::{F32SimdVec, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor};
 
 /// Premultiply color channels by alpha.
 /// This multiplies RGB values by the alpha channel value.
@@ -44,22 +45,27 @@ impl PremultiplyAlphaStage {
 }
 
 // SIMD premultiply: color = color * alpha
-simd_function!(
-    premultiply_rows_simd_dispatch,
+#[inline(always)]
+fn premultiply_rows_simd<D: SimdDescriptor>(
     d: D,
-    fn premultiply_rows_simd(color_rows: &mut [&mut [f32]], alpha_row: &[f32], xsize: usize) {
-        for color_row in color_rows.iter_mut() {
-            let iter_color = color_row.chunks_exact_mut(D::F32Vec::LEN);
-            let iter_alpha = alpha_row.chunks_exact(D::F32Vec::LEN);
-            for (color_chunk, alpha_chunk) in iter_color.zip(iter_alpha).take(xsize.div_ceil(D::F32Vec::LEN)) {
-                let color_vec = D::F32Vec::load(d, color_chunk);
-                let alpha_vec = D::F32Vec::load(d, alpha_chunk);
-                let result = color_vec * alpha_vec;
-                result.store(color_chunk);
-            }
+    color_rows: &mut [&mut [f32]],
+    alpha_row: &[f32],
+    xsize: usize,
+) {
+    for color_row in color_rows.iter_mut() {
+        let iter_color = color_row.chunks_exact_mut(D::F32Vec::LEN);
+        let iter_alpha = alpha_row.chunks_exact(D::F32Vec::LEN);
+        for (color_chunk, alpha_chunk) in iter_color
+            .zip(iter_alpha)
+            .take(xsize.div_ceil(D::F32Vec::LEN))
+        {
+            let color_vec = D::F32Vec::load(d, color_chunk);
+            let alpha_vec = D::F32Vec::load(d, alpha_chunk);
+            let result = color_vec * alpha_vec;
+            result.store(color_chunk);
         }
     }
-);
+}
 
 impl RenderPipelineInPlaceStage for PremultiplyAlphaStage {
     type Type = f32;
@@ -69,25 +75,27 @@ impl RenderPipelineInPlaceStage for PremultiplyAlphaStage {
             || c == self.alpha_channel
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
-    ) {
+    ) -> Result<()> {
         // The row slice contains only the channels we said we use.
         // The last channel is alpha (since alpha_channel > color channels).
         let num_channels = row.len();
         if num_channels < 2 {
-            return;
+            return Ok(());
         }
 
         // Alpha is the last channel in the row slice
         let (color_rows, alpha_row) = row.split_at_mut(num_channels - 1);
         let alpha_row = &alpha_row[0][..];
 
-        premultiply_rows_simd_dispatch(color_rows, alpha_row, xsize);
+        d.call(|d| premultiply_rows_simd(d, color_rows, alpha_row, xsize));
+        Ok(())
     }
 }
 
@@ -0,0 +1,473 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{any::Any, ops::Range};
+
+use crate::{
+    api::JxlResamplingFilter,
+    error::{Error, Result},
+    render::{Channels, ChannelsMut, RenderPipelineInOutStage, ResampledAxis, Resampling},
+    util::SmallVec,
+};
+use jxl_simd::{F32SimdVec, SimdDescriptor};
+
+impl JxlResamplingFilter {
+    fn radius(&self) -> f64 {
+        match self {
+            JxlResamplingFilter::Lanczos3 => 3.0,
+            JxlResamplingFilter::Mitchell => 2.0,
+        }
+    }
+
+    fn kernel(&self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            JxlResamplingFilter::Lanczos3 => {
+                if x < 1e-8 {
+                    1.0
+                } else if x < 3.0 {
+                    let px = std::f64::consts::PI * x;
+                    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+            JxlResamplingFilter::Mitchell => {
+                // Mitchell-Netravali filter with B = C = 1/3.
+                const B: f64 = 1.0 / 3.0;
+                const C: f64 = 1.0 / 3.0;
+                let x2 = x * x;
+                let x3 = x2 * x;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * B - 6.0 * C) * x3
+                        + (-18.0 + 12.0 * B + 6.0 * C) * x2
+                        + (6.0 - 2.0 * B))
+                        / 6.0
+                } else if x < 2.0 {
+                    ((-B - 6.0 * C) * x3
+                        + (6.0 * B + 30.0 * C) * x2
+                        + (-12.0 * B - 48.0 * C) * x
+                        + (8.0 * B + 24.0 * C))
+                        / 6.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// Filter weights for resampling along one axis.
+struct AxisWeights {
+    axis: ResampledAxis,
+    // For each output sample, the first input sample that contributes to it.
+    first_input: Vec<usize>,
+    // The weights of output sample `o` are `weights[offsets[o]..offsets[o + 1]]`.
+    offsets: Vec<usize>,
+    weights: Vec<f32>,
+}
+
+impl AxisWeights {
+    fn new(filter: JxlResamplingFilter, input_size: usize, output_size: usize) -> Self {
+        let scale = input_size as f64 / output_size as f64;
+        // When downsampling, the filter is stretched to cover all the input samples.
+        let stretch = scale.max(1.0);
+        let support = filter.radius() * stretch;
+        let mut first_input = Vec::with_capacity(output_size);
+        let mut offsets = Vec::with_capacity(output_size + 1);
+        let mut weights = vec![];
+        offsets.push(0);
+        for o in 0..output_size {
+            let center = (o as f64 + 0.5) * scale - 0.5;
+            let start = ((center - support).ceil().max(0.0) as usize).min(input_size - 1);
+            let end =
+                (((center + support).floor() + 1.0).max(0.0) as usize).clamp(start + 1, input_size);
+            let taps: Vec<f64> = (start..end)
+                .map(|i| filter.kernel((i as f64 - center) / stretch))
+                .collect();
+            let sum: f64 = taps.iter().sum();
+            if sum.abs() < 1e-6 {
+                // Should not happen with the supported filters; fall back to the nearest sample.
+                let nearest = (center.round().max(0.0) as usize).clamp(start, end - 1);
+                weights.extend((start..end).map(|i| if i == nearest { 1.0 } else { 0.0 }));
+            } else {
+                weights.extend(taps.iter().map(|w| (w / sum) as f32));
+            }
+            first_input.push(start);
+            offsets.push(weights.len());
+        }
+        Self {
+            axis: ResampledAxis {
+                input_size,
+                output_size,
+            },
+            first_input,
+            offsets,
+            weights,
+        }
+    }
+
+    /// Returns the first input sample that contributes to output sample `o`, and the weights of
+    /// the contributing samples.
+    fn taps(&self, o: usize) -> (usize, &[f32]) {
+        (
+            self.first_input[o],
+            &self.weights[self.offsets[o]..self.offsets[o + 1]],
+        )
+    }
+
+    /// Returns how far from the input sample that an output sample is assigned to the input
+    /// samples that contribute to it can be.
+    fn border(&self) -> usize {
+        (0..self.axis.output_size)
+            .map(|o| {
+                let (first, taps) = self.taps(o);
+                let input = self.axis.input(o);
+                input
+                    .abs_diff(first)
+                    .max(input.abs_diff(first + taps.len() - 1))
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Resamples the first `num_channels` channels to a different size with a separable filter.
+///
+/// Each input row is filtered horizontally once, and kept in a ring of rows until all the output
+/// rows that depend on it have been filtered vertically.
+pub struct ResampleStage {
+    num_channels: usize,
+    x_weights: AxisWeights,
+    y_weights: AxisWeights,
+    resampling: Resampling,
+}
+
+impl ResampleStage {
+    pub fn new(
+        filter: JxlResamplingFilter,
+        num_channels: usize,
+        input_size: (usize, usize),
+        output_size: (usize, usize),
+    ) -> Result<Self> {
+        if output_size.0 == 0 || output_size.1 == 0 {
+            return Err(Error::InvalidImageSize(output_size.0, output_size.1));
+        }
+        let x_weights = AxisWeights::new(filter, input_size.0, output_size.0);
+        let y_weights = AxisWeights::new(filter, input_size.1, output_size.1);
+        let border = x_weights.border().max(y_weights.border());
+        if border > u8::MAX as usize {
+            return Err(Error::ResamplingBorderTooLarge(border));
+        }
+        let resampling = Resampling {
+            x: x_weights.axis,
+            y: y_weights.axis,
+            border: (x_weights.border() as u8, y_weights.border() as u8),
+        };
+        Ok(Self {
+            num_channels,
+            x_weights,
+            y_weights,
+            resampling,
+        })
+    }
+}
+
+impl std::fmt::Display for ResampleStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (xsize, ysize) = self.resampling.output_size();
+        write!(
+            f,
+            "resample channels 0-{} to {xsize}x{ysize}",
+            self.num_channels - 1,
+        )
+    }
+}
+
+/// Horizontally filtered input rows of the chunk of columns `xrange`.
+struct FilteredRows {
+    xrange: Range<usize>,
+    // The input row stored in each slot of the ring, if any.
+    ys: Vec<Option<usize>>,
+    // Slot, channel, column.
+    rows: Vec<Vec<Vec<f32>>>,
+}
+
+#[inline(always)]
+fn filter_vertically<D: SimdDescriptor>(
+    d: D,
+    weights: &[f32],
+    rows: &[&[f32]],
+    xsize: usize,
+    out: &mut [f32],
+) {
+    // The rows are padded to a whole number of vectors, so the last, partial vector of each row
+    // is computed in the same way as the others, and does not depend on where the chunk ends.
+    for (i, out) in out[..xsize].chunks_mut(D::F32Vec::LEN).enumerate() {
+        let x = i * D::F32Vec::LEN;
+        let mut sum = D::F32Vec::zero(d);
+        for (w, row) in weights.iter().zip(rows) {
+            sum = D::F32Vec::splat(d, *w).mul_add(D::F32Vec::load(d, &row[x..]), sum);
+        }
+        if out.len() == D::F32Vec::LEN {
+            sum.store(out);
+        } else {
+            let mut tail = [0.0; 16];
+            sum.store(&mut tail);
+            out.copy_from_slice(&tail[..out.len()]);
+        }
+    }
+}
+
+impl RenderPipelineInOutStage for ResampleStage {
+    type InputT = f32;
+    type OutputT = f32;
+    const SHIFT: (u8, u8) = (0, 0);
+    const BORDER: (u8, u8) = (0, 0);
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c < self.num_channels
+    }
+
+    fn resampling(&self) -> Option<Resampling> {
+        Some(self.resampling)
+    }
+
+    fn init_local_state(&self) -> Result<Option<Box<dyn Any>>> {
+        let slots = 2 * self.resampling.border.1 as usize + 1;
+        Ok(Some(Box::new(FilteredRows {
+            xrange: 0..0,
+            ys: vec![None; slots],
+            rows: vec![vec![vec![]; self.num_channels]; slots],
+        })))
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        d: D,
+        position: (usize, usize),
+        xsize: usize,
+        input_rows: &Channels<f32>,
+        output_rows: &mut ChannelsMut<f32>,
+        state: Option<&mut dyn Any>,
+    ) {
+        let (x0, y) = position;
+        let out_ys = self.resampling.y.outputs(y..y + 1);
+        if out_ys.is_empty() {
+            return;
+        }
+        let out_xs = self.resampling.x.outputs(x0..x0 + xsize);
+        let (border_x, border_y) = (
+            self.resampling.border.0 as usize,
+            self.resampling.border.1 as usize,
+        );
+        let state: &mut FilteredRows = state.unwrap().downcast_mut().unwrap();
+        if state.xrange != (x0..x0 + xsize) {
+            state.xrange = x0..x0 + xsize;
+            state.ys.fill(None);
+        }
+        let slots = state.ys.len();
+        let row_len = out_xs.len().next_multiple_of(D::F32Vec::LEN);
+        for (k, out_y) in out_ys.enumerate() {
+            let (first_y, y_taps) = self.y_weights.taps(out_y);
+            let in_ys = first_y..first_y + y_taps.len();
+            for iy in in_ys.clone() {
+                let slot = iy % slots;
+                if state.ys[slot] == Some(iy) {
+                    continue;
+                }
+                for (c, filtered) in state.rows[slot].iter_mut().enumerate() {
+                    let input = input_rows[c][iy + border_y - y];
+                    filtered.resize(row_len, 0.0);
+                    for (out, out_x) in filtered.iter_mut().zip(out_xs.clone()) {
+                        let (first_x, x_taps) = self.x_weights.taps(out_x);
+                        let input = &input[first_x + border_x - x0..][..x_taps.len()];
+                        *out = x_taps.iter().zip(input).map(|(w, v)| w * v).sum();
+                    }
+                }
+                state.ys[slot] = Some(iy);
+            }
+            for c in 0..self.num_channels {
+                let rows: SmallVec<&[f32], 16> = in_ys
+                    .clone()
+                    .map(|iy| &state.rows[iy % slots][c][..])
+                    .collect();
+                let out = &mut output_rows[c][k];
+                d.call(|d| filter_vertically(d, y_taps, &rows, out_xs.len(), out));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use test_log::test;
+
+    use super::*;
+    use crate::error::Result;
+    use crate::image::Image;
+    use crate::render::{
+        LowMemoryRenderPipeline, RenderPipeline, SimpleRenderPipeline, test::make_and_run_pipeline,
+        with_smallest_tiles,
+    };
+    use crate::util::test::assert_almost_abs_eq;
+
+    const SIZES: [((usize, usize), (usize, usize)); 5] = [
+        ((300, 200), (123, 457)),
+        ((517, 9), (1000, 20)),
+        ((70, 33), (70, 33)),
+        ((1, 1), (5, 3)),
+        ((260, 300), (1, 2)),
+    ];
+
+    /// Resamples `image` by computing each output pixel directly from the filter weights.
+    fn resample_naive(stage: &ResampleStage, image: &Image<f32>) -> Result<Image<f32>> {
+        let mut output = Image::new(stage.resampling.output_size())?;
+        let (xsize, ysize) = output.size();
+        for out_y in 0..ysize {
+            let (first_y, y_taps) = stage.y_weights.taps(out_y);
+            for out_x in 0..xsize {
+                let (first_x, x_taps) = stage.x_weights.taps(out_x);
+                let mut sum = 0.0;
+                for (iy, wy) in y_taps.iter().enumerate() {
+                    let row = &image.row(first_y + iy)[first_x..];
+                    sum += wy * x_taps.iter().zip(row).map(|(w, v)| w * v).sum::<f32>();
+                }
+                output.row_mut(out_y)[out_x] = sum;
+            }
+        }
+        Ok(output)
+    }
+
+    #[test]
+    fn weights_are_normalized() {
+        for filter in [JxlResamplingFilter::Lanczos3, JxlResamplingFilter::Mitchell] {
+            for (input, output) in [(100, 37), (37, 100), (1, 5), (5, 1), (64, 64)] {
+                let weights = AxisWeights::new(filter, input, output);
+                let border = weights.border();
+                for o in 0..output {
+                    let (first, taps) = weights.taps(o);
+                    let sum: f32 = taps.iter().sum();
+                    assert_almost_abs_eq(sum, 1.0, 1e-5);
+                    let assigned = weights.axis.input(o);
+                    assert!(first + border >= assigned);
+                    assert!(first + taps.len() <= assigned + border + 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn consistency() -> Result<()> {
+        for filter in [JxlResamplingFilter::Lanczos3, JxlResamplingFilter::Mitchell] {
+            crate::render::test::test_stage_consistency(
+                || ResampleStage::new(filter, 1, (500, 300), (317, 611)).unwrap(),
+                (500, 300),
+                1,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn check_matches_naive<P: RenderPipeline>(chunk_size: usize) -> Result<()> {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(0);
+        for filter in [JxlResamplingFilter::Lanczos3, JxlResamplingFilter::Mitchell] {
+            for (input_size, output_size) in SIZES {
+                let images = (0..2)
+                    .map(|_| Image::new_random(input_size, &mut rng))
+                    .collect::<Result<Vec<_>>>()?;
+                let stage = ResampleStage::new(filter, 2, input_size, output_size)?;
+                let expected = images
+                    .iter()
+                    .map(|image| resample_naive(&stage, image))
+                    .collect::<Result<Vec<_>>>()?;
+                let output = make_and_run_pipeline::<P>(
+                    |pipeline| pipeline.add_inout_stage(stage),
+                    &images,
+                    chunk_size,
+                )?;
+                for (output, expected) in output.iter().zip(expected.iter()) {
+                    assert_eq!(output.size(), output_size);
+                    for y in 0..output_size.1 {
+                        for x in 0..output_size.0 {
+                            assert_almost_abs_eq(output.row(y)[x], expected.row(y)[x], 1e-5);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn matches_naive_simple() -> Result<()> {
+        check_matches_naive::<SimpleRenderPipeline>(256)?;
+        check_matches_naive::<SimpleRenderPipeline>(37)
+    }
+
+    #[test]
+    fn matches_naive_low_memory() -> Result<()> {
+        check_matches_naive::<LowMemoryRenderPipeline>(256)?;
+        with_smallest_tiles(|| check_matches_naive::<LowMemoryRenderPipeline>(256))
+    }
+
+    #[test]
+    fn identity_size() -> Result<()> {
+        let size = (70, 33);
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(0);
+        let input = Image::<f32>::new_random(size, &mut rng)?;
+        let output = make_and_run_pipeline::<LowMemoryRenderPipeline>(
+            |pipeline| {
+                pipeline.add_inout_stage(ResampleStage::new(
+                    JxlResamplingFilter::Lanczos3,
+                    1,
+                    size,
+                    size,
+                )?)
+            },
+            std::slice::from_ref(&input),
+            256,
+        )?;
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                assert_almost_abs_eq(output[0].row(y)[x], input.row(y)[x], 1e-5);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn constant_image() -> Result<()> {
+        for filter in [JxlResamplingFilter::Lanczos3, JxlResamplingFilter::Mitchell] {
+            let (input_size, output_size) = ((300, 200), (123, 457));
+            let images = [
+                Image::new_with_value(input_size, 0.25)?,
+                Image::new_with_value(input_size, 0.75)?,
+            ];
+            let output = make_and_run_pipeline::<LowMemoryRenderPipeline>(
+                |pipeline| {
+                    pipeline.add_inout_stage(ResampleStage::new(
+                        filter,
+                        2,
+                        input_size,
+                        output_size,
+                    )?)
+                },
+                &images,
+                256,
+            )?;
+            for (c, expected) in [0.25, 0.75].into_iter().enumerate() {
+                assert_eq!(output[c].size(), output_size);
+                for y in 0..output_size.1 {
+                    for x in 0..output_size.0 {
+                        assert_almost_abs_eq(output[c].row(y)[x], expected, 1e-4);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
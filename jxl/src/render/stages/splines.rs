@@ -7,6 +7,7 @@ use crate::{
     error::Result, features::spline::Splines, frame::color_correlation_map::ColorCorrelationParams,
     render::RenderPipelineInPlaceStage,
 };
+use jxl_simd::SimdDescriptor;
 
 pub struct SplinesStage {
     splines: Splines,
@@ -42,14 +43,16 @@ impl RenderPipelineInPlaceStage for SplinesStage {
         c < 3
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
-    ) {
+    ) -> Result<()> {
         self.splines.draw_segments(row, position, xsize);
+        Ok(())
     }
 }
 
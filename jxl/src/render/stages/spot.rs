@@ -3,7 +3,9 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::error::Result;
 use crate::render::RenderPipelineInPlaceStage;
+use jxl_simd::SimdDescriptor;
 
 /// Render spot color
 pub struct SpotColorStage {
@@ -37,13 +39,14 @@ impl RenderPipelineInPlaceStage for SpotColorStage {
     }
 
     // `row` should only contain color channels and the spot channel.
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        _d: D,
         _position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
-    ) {
+    ) -> Result<()> {
         let [row_r, row_g, row_b, row_s] = row else {
             panic!(
                 "incorrect number of channels; expected 4, found {}",
@@ -64,6 +67,7 @@ impl RenderPipelineInPlaceStage for SpotColorStage {
             row_g[idx] = mix * self.spot_color[1] + (1.0 - mix) * row_g[idx];
             row_b[idx] = mix * self.spot_color[2] + (1.0 - mix) * row_b[idx];
         }
+        Ok(())
     }
 }
 
@@ -0,0 +1,81 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{any::Any, marker::PhantomData};
+
+use crate::error::Result;
+use crate::{api::JxlStageTap, image::ImageDataType, render::RenderPipelineInPlaceStage};
+use jxl_simd::SimdDescriptor;
+
+/// Copies the channels produced by a stage that matches a [`JxlStageTap`] to the capture with
+/// index `capture` of the tap, without modifying them.
+pub struct TapStage<T: ImageDataType> {
+    tap: JxlStageTap,
+    capture: usize,
+    // Index of this stage in the pipeline; channels are only written by the stage that owns them
+    // in the capture.
+    id: usize,
+    channels: Vec<usize>,
+    _ph: PhantomData<T>,
+}
+
+impl<T: ImageDataType> TapStage<T> {
+    pub fn new(tap: JxlStageTap, capture: usize, id: usize, channels: Vec<usize>) -> Self {
+        Self {
+            tap,
+            capture,
+            id,
+            channels,
+            _ph: PhantomData,
+        }
+    }
+}
+
+impl<T: ImageDataType> std::fmt::Display for TapStage<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "capture channels {:?} for tap '{}'",
+            self.channels,
+            self.tap.name()
+        )
+    }
+}
+
+impl<T: ImageDataType> RenderPipelineInPlaceStage for TapStage<T> {
+    type Type = T;
+
+    fn uses_channel(&self, c: usize) -> bool {
+        self.channels.contains(&c)
+    }
+
+    fn process_row_chunk<D: SimdDescriptor>(
+        &self,
+        _d: D,
+        position: (usize, usize),
+        xsize: usize,
+        row: &mut [&mut [T]],
+        _state: Option<&mut dyn Any>,
+    ) -> Result<()> {
+        let mut captures = self.tap.captures.borrow_mut();
+        let capture = &mut captures[self.capture];
+        // `channels` is sorted, so rows are in the same order.
+        for (&c, row) in self.channels.iter().zip(row.iter()) {
+            let Some((owner, image)) = &mut capture.channels[c] else {
+                continue;
+            };
+            let (width, height) = image.size();
+            if *owner != self.id || position.1 >= height || position.0 >= width {
+                continue;
+            }
+            let xsize = xsize.min(width - position.0);
+            let out = &mut image.row_mut(position.1)[position.0..position.0 + xsize];
+            for (out, v) in out.iter_mut().zip(row[..xsize].iter()) {
+                *out = v.to_f64() as f32;
+            }
+        }
+        Ok(())
+    }
+}
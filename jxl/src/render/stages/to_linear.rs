@@ -4,10 +4,11 @@
 // license that can be found in the LICENSE file.
 
 use crate::color::tf;
+use crate::error::Result;
 use crate::headers::color_encoding::CustomTransferFunction;
 use crate::render::RenderPipelineInPlaceStage;
 use crate::render::stages::from_linear;
-use jxl_simd::{F32SimdVec, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor};
 
 /// Convert encoded non-linear color samples to display-referred linear color samples.
 #[derive(Debug)]
@@ -35,10 +36,7 @@ impl ToLinearStage {
 
     #[allow(unused, reason = "tirr-c: remove once we use this!")]
     pub fn hlg(first_channel: usize, intensity_target: f32, luminance_rgb: [f32; 3]) -> Self {
-        let tf = TransferFunction::Hlg {
-            intensity_target,
-            luminance_rgb,
-        };
+        let tf = TransferFunction::hlg(intensity_target, luminance_rgb);
         Self::new(first_channel, tf)
     }
 }
@@ -57,10 +55,13 @@ impl std::fmt::Display for ToLinearStage {
     }
 }
 
-simd_function!(
-to_linear_process_dispatch,
-d: D,
-fn to_linear_process(tf: &TransferFunction, xsize: usize, row: &mut [&mut [f32]]) {
+#[inline(always)]
+fn to_linear_process<D: SimdDescriptor>(
+    d: D,
+    tf: &TransferFunction,
+    xsize: usize,
+    row: &mut [&mut [f32]],
+) {
     let [row_r, row_g, row_b] = row else {
         panic!(
             "incorrect number of channels; expected 3, found {}",
@@ -87,6 +88,8 @@ fn to_linear_process(tf: &TransferFunction, xsize: usize, row: &mut [&mut [f32]]
         TransferFunction::Hlg {
             intensity_target,
             luminance_rgb,
+            system_gamma,
+            linear_white,
         } => {
             tf::hlg_to_scene(&mut row_r[..xsize]);
             tf::hlg_to_scene(&mut row_g[..xsize]);
@@ -97,7 +100,16 @@ fn to_linear_process(tf: &TransferFunction, xsize: usize, row: &mut [&mut [f32]]
                 &mut row_g[..xsize],
                 &mut row_b[..xsize],
             ];
-            tf::hlg_scene_to_display(intensity_target, luminance_rgb, rows);
+            tf::hlg_ootf(system_gamma, luminance_rgb, rows);
+
+            if linear_white != intensity_target {
+                let scale = intensity_target / linear_white;
+                for row in [row_r, row_g, row_b] {
+                    for v in row[..xsize].iter_mut() {
+                        *v *= scale;
+                    }
+                }
+            }
         }
         TransferFunction::Gamma(g) => {
             for row in row {
@@ -112,7 +124,7 @@ fn to_linear_process(tf: &TransferFunction, xsize: usize, row: &mut [&mut [f32]]
             }
         }
     }
-});
+}
 
 impl RenderPipelineInPlaceStage for ToLinearStage {
     type Type = f32;
@@ -121,14 +133,16 @@ impl RenderPipelineInPlaceStage for ToLinearStage {
         (self.first_channel..self.first_channel + 3).contains(&c)
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
-    ) {
-        to_linear_process_dispatch(&self.tf, xsize, row)
+    ) -> Result<()> {
+        d.call(|d| to_linear_process(d, &self.tf, xsize, row));
+        Ok(())
     }
 }
 
@@ -211,6 +225,30 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn hlg_reference_white() -> Result<()> {
+        let input_r = Image::new_with_value((1, 1), 0.75)?;
+        let input_g = Image::new_with_value((1, 1), 0.75)?;
+        let input_b = Image::new_with_value((1, 1), 0.75)?;
+
+        // 75% HLG is reference white, 203 nits on a 1000-nit display.
+        let tf = TransferFunction::Hlg {
+            intensity_target: 1000.0,
+            luminance_rgb: LUMINANCE_BT2020,
+            system_gamma: 1.2,
+            linear_white: 203.0,
+        };
+        let stage = ToLinearStage::new(0, tf);
+        let output =
+            make_and_run_simple_pipeline(stage, &[input_r, input_g, input_b], (1, 1), 0, 256)?;
+
+        assert_all_almost_abs_eq(output[0].row(0), &[1.0], 5e-3);
+        assert_all_almost_abs_eq(output[1].row(0), &[1.0], 5e-3);
+        assert_all_almost_abs_eq(output[2].row(0), &[1.0], 5e-3);
+
+        Ok(())
+    }
+
     #[test]
     fn sdr_white_pq() -> Result<()> {
         let intensity_target = 1000f32;
@@ -12,7 +12,7 @@ use crate::{
     headers::CustomTransformData,
     render::{Channels, ChannelsMut, RenderPipelineInOutStage},
 };
-use jxl_simd::{F32SimdVec, simd_function};
+use jxl_simd::{F32SimdVec, SimdDescriptor};
 
 pub struct Upsample<const N: usize, const SHIFT: u8> {
     // Precomputed flattened kernels for SIMD optimization
@@ -213,185 +213,221 @@ macro_rules! kernel_conv {
 }
 
 // 2x upsampling SIMD implementation - single dispatch with integrated minmax
-simd_function!(
-    upsample_2x_simd_dispatch,
+#[inline(always)]
+fn upsample_2x_simd<D: SimdDescriptor>(
     d: D,
-    fn upsample_2x_simd(
-        input: &[&[f32]],
-        xsize: usize,
-        flat_kernels: &[[f32; 25]],
-        col_min: &mut [f32],
-        col_max: &mut [f32],
-        mins: &mut [f32],
-        maxs: &mut [f32],
-        output: &mut [&mut [f32]],
-    ) {
-        // Compute min/max using shared helper
-        compute_minmax(d, input, xsize, col_min, col_max, mins, maxs);
-
-        let r0 = input[0];
-        let r1 = input[1];
-        let r2 = input[2];
-        let r3 = input[3];
-        let r4 = input[4];
-
-        // Pre-broadcast kernel weights
-        // flat_kernels layout: kernel[oy][ox] -> flat_kernels[oy * 2 + ox]
-        let mut kernel_vecs = [[D::F32Vec::splat(d, 0.0); 25]; 4];
-        for idx in 0..4 {
-            let k = &flat_kernels[idx];
-            for i in 0..25 {
-                kernel_vecs[idx][i] = D::F32Vec::splat(d, k[i]);
-            }
-        }
+    input: &[&[f32]],
+    xsize: usize,
+    flat_kernels: &[[f32; 25]],
+    col_min: &mut [f32],
+    col_max: &mut [f32],
+    mins: &mut [f32],
+    maxs: &mut [f32],
+    output: &mut [&mut [f32]],
+) {
+    // Compute min/max using shared helper
+    compute_minmax(d, input, xsize, col_min, col_max, mins, maxs);
+
+    let r0 = input[0];
+    let r1 = input[1];
+    let r2 = input[2];
+    let r3 = input[3];
+    let r4 = input[4];
 
-        // Process using iterators for mins/maxs, manual indexing for output
-        let mins_iter = mins.chunks_exact(D::F32Vec::LEN);
-        let maxs_iter = maxs.chunks_exact(D::F32Vec::LEN);
-
-        for ((mins_chunk, maxs_chunk), x) in mins_iter
-            .zip(maxs_iter)
-            .zip((0..xsize).step_by(D::F32Vec::LEN))
-            .take(xsize.div_ceil(D::F32Vec::LEN))
-        {
-            let minval = D::F32Vec::load(d, mins_chunk);
-            let maxval = D::F32Vec::load(d, maxs_chunk);
-            let out_x = x * 2;
-
-            // Row 0
-            let r0_0 = kernel_conv!(d, kernel_vecs[0], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-            let r0_1 = kernel_conv!(d, kernel_vecs[1], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-            D::F32Vec::store_interleaved_2(r0_0, r0_1, &mut output[0][out_x..]);
-
-            // Row 1
-            let r1_0 = kernel_conv!(d, kernel_vecs[2], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-            let r1_1 = kernel_conv!(d, kernel_vecs[3], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-            D::F32Vec::store_interleaved_2(r1_0, r1_1, &mut output[1][out_x..]);
+    // Pre-broadcast kernel weights
+    // flat_kernels layout: kernel[oy][ox] -> flat_kernels[oy * 2 + ox]
+    let mut kernel_vecs = [[D::F32Vec::splat(d, 0.0); 25]; 4];
+    for idx in 0..4 {
+        let k = &flat_kernels[idx];
+        for i in 0..25 {
+            kernel_vecs[idx][i] = D::F32Vec::splat(d, k[i]);
         }
     }
-);
+
+    // Process using iterators for mins/maxs, manual indexing for output
+    let mins_iter = mins.chunks_exact(D::F32Vec::LEN);
+    let maxs_iter = maxs.chunks_exact(D::F32Vec::LEN);
+
+    for ((mins_chunk, maxs_chunk), x) in mins_iter
+        .zip(maxs_iter)
+        .zip((0..xsize).step_by(D::F32Vec::LEN))
+        .take(xsize.div_ceil(D::F32Vec::LEN))
+    {
+        let minval = D::F32Vec::load(d, mins_chunk);
+        let maxval = D::F32Vec::load(d, maxs_chunk);
+        let out_x = x * 2;
+
+        // Row 0
+        let r0_0 = kernel_conv!(d, kernel_vecs[0], r0, r1, r2, r3, r4, x)
+            .max(minval)
+            .min(maxval);
+        let r0_1 = kernel_conv!(d, kernel_vecs[1], r0, r1, r2, r3, r4, x)
+            .max(minval)
+            .min(maxval);
+        D::F32Vec::store_interleaved_2(r0_0, r0_1, &mut output[0][out_x..]);
+
+        // Row 1
+        let r1_0 = kernel_conv!(d, kernel_vecs[2], r0, r1, r2, r3, r4, x)
+            .max(minval)
+            .min(maxval);
+        let r1_1 = kernel_conv!(d, kernel_vecs[3], r0, r1, r2, r3, r4, x)
+            .max(minval)
+            .min(maxval);
+        D::F32Vec::store_interleaved_2(r1_0, r1_1, &mut output[1][out_x..]);
+    }
+}
 
 // 4x upsampling SIMD implementation - single dispatch with integrated minmax
-simd_function!(
-    upsample_4x_simd_dispatch,
+#[inline(always)]
+fn upsample_4x_simd<D: SimdDescriptor>(
     d: D,
-    fn upsample_4x_simd(
-        input: &[&[f32]],
-        xsize: usize,
-        flat_kernels: &[[f32; 25]],
-        col_min: &mut [f32],
-        col_max: &mut [f32],
-        mins: &mut [f32],
-        maxs: &mut [f32],
-        output: &mut [&mut [f32]],
-    ) {
-        // Compute min/max using shared helper
-        compute_minmax(d, input, xsize, col_min, col_max, mins, maxs);
-
-        let r0 = input[0];
-        let r1 = input[1];
-        let r2 = input[2];
-        let r3 = input[3];
-        let r4 = input[4];
-
-        // Pre-broadcast kernel weights
-        // flat_kernels layout: kernel[oy][ox] -> flat_kernels[oy * 4 + ox]
-        let mut kernel_vecs = [[D::F32Vec::splat(d, 0.0); 25]; 16];
-        for idx in 0..16 {
-            let k = &flat_kernels[idx];
-            for i in 0..25 {
-                kernel_vecs[idx][i] = D::F32Vec::splat(d, k[i]);
-            }
+    input: &[&[f32]],
+    xsize: usize,
+    flat_kernels: &[[f32; 25]],
+    col_min: &mut [f32],
+    col_max: &mut [f32],
+    mins: &mut [f32],
+    maxs: &mut [f32],
+    output: &mut [&mut [f32]],
+) {
+    // Compute min/max using shared helper
+    compute_minmax(d, input, xsize, col_min, col_max, mins, maxs);
+
+    let r0 = input[0];
+    let r1 = input[1];
+    let r2 = input[2];
+    let r3 = input[3];
+    let r4 = input[4];
+
+    // Pre-broadcast kernel weights
+    // flat_kernels layout: kernel[oy][ox] -> flat_kernels[oy * 4 + ox]
+    let mut kernel_vecs = [[D::F32Vec::splat(d, 0.0); 25]; 16];
+    for idx in 0..16 {
+        let k = &flat_kernels[idx];
+        for i in 0..25 {
+            kernel_vecs[idx][i] = D::F32Vec::splat(d, k[i]);
         }
+    }
 
-        // Process using iterators for mins/maxs, manual indexing for output
-        let mins_iter = mins.chunks_exact(D::F32Vec::LEN);
-        let maxs_iter = maxs.chunks_exact(D::F32Vec::LEN);
-
-        for ((mins_chunk, maxs_chunk), x) in mins_iter
-            .zip(maxs_iter)
-            .zip((0..xsize).step_by(D::F32Vec::LEN))
-            .take(xsize.div_ceil(D::F32Vec::LEN))
-        {
-            let minval = D::F32Vec::load(d, mins_chunk);
-            let maxval = D::F32Vec::load(d, maxs_chunk);
-            let out_x = x * 4;
-
-            // Process all 4 output rows using a loop
-            for oy in 0..4 {
-                let base = oy * 4;
-                let v0 = kernel_conv!(d, kernel_vecs[base], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v1 = kernel_conv!(d, kernel_vecs[base + 1], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v2 = kernel_conv!(d, kernel_vecs[base + 2], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v3 = kernel_conv!(d, kernel_vecs[base + 3], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                D::F32Vec::store_interleaved_4(v0, v1, v2, v3, &mut output[oy][out_x..]);
-            }
+    // Process using iterators for mins/maxs, manual indexing for output
+    let mins_iter = mins.chunks_exact(D::F32Vec::LEN);
+    let maxs_iter = maxs.chunks_exact(D::F32Vec::LEN);
+
+    for ((mins_chunk, maxs_chunk), x) in mins_iter
+        .zip(maxs_iter)
+        .zip((0..xsize).step_by(D::F32Vec::LEN))
+        .take(xsize.div_ceil(D::F32Vec::LEN))
+    {
+        let minval = D::F32Vec::load(d, mins_chunk);
+        let maxval = D::F32Vec::load(d, maxs_chunk);
+        let out_x = x * 4;
+
+        // Process all 4 output rows using a loop
+        for oy in 0..4 {
+            let base = oy * 4;
+            let v0 = kernel_conv!(d, kernel_vecs[base], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v1 = kernel_conv!(d, kernel_vecs[base + 1], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v2 = kernel_conv!(d, kernel_vecs[base + 2], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v3 = kernel_conv!(d, kernel_vecs[base + 3], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            D::F32Vec::store_interleaved_4(v0, v1, v2, v3, &mut output[oy][out_x..]);
         }
     }
-);
+}
 
 // 8x upsampling SIMD implementation - single dispatch with integrated minmax
-simd_function!(
-    upsample_8x_simd_dispatch,
+#[inline(always)]
+fn upsample_8x_simd<D: SimdDescriptor>(
     d: D,
-    fn upsample_8x_simd(
-        input: &[&[f32]],
-        xsize: usize,
-        flat_kernels: &[[f32; 25]],
-        col_min: &mut [f32],
-        col_max: &mut [f32],
-        mins: &mut [f32],
-        maxs: &mut [f32],
-        output: &mut [&mut [f32]],
-    ) {
-        // Compute min/max using shared helper
-        compute_minmax(d, input, xsize, col_min, col_max, mins, maxs);
-
-        let r0 = input[0];
-        let r1 = input[1];
-        let r2 = input[2];
-        let r3 = input[3];
-        let r4 = input[4];
-
-        // Pre-broadcast kernel weights
-        // flat_kernels layout: kernel[oy][ox] -> flat_kernels[oy * 8 + ox]
-        let mut kernel_vecs = [[D::F32Vec::splat(d, 0.0); 25]; 64];
-        for idx in 0..64 {
-            let k = &flat_kernels[idx];
-            for i in 0..25 {
-                kernel_vecs[idx][i] = D::F32Vec::splat(d, k[i]);
-            }
+    input: &[&[f32]],
+    xsize: usize,
+    flat_kernels: &[[f32; 25]],
+    col_min: &mut [f32],
+    col_max: &mut [f32],
+    mins: &mut [f32],
+    maxs: &mut [f32],
+    output: &mut [&mut [f32]],
+) {
+    // Compute min/max using shared helper
+    compute_minmax(d, input, xsize, col_min, col_max, mins, maxs);
+
+    let r0 = input[0];
+    let r1 = input[1];
+    let r2 = input[2];
+    let r3 = input[3];
+    let r4 = input[4];
+
+    // Pre-broadcast kernel weights
+    // flat_kernels layout: kernel[oy][ox] -> flat_kernels[oy * 8 + ox]
+    let mut kernel_vecs = [[D::F32Vec::splat(d, 0.0); 25]; 64];
+    for idx in 0..64 {
+        let k = &flat_kernels[idx];
+        for i in 0..25 {
+            kernel_vecs[idx][i] = D::F32Vec::splat(d, k[i]);
         }
+    }
 
-        // Process using iterators for mins/maxs, manual indexing for output
-        let mins_iter = mins.chunks_exact(D::F32Vec::LEN);
-        let maxs_iter = maxs.chunks_exact(D::F32Vec::LEN);
-
-        for ((mins_chunk, maxs_chunk), x) in mins_iter
-            .zip(maxs_iter)
-            .zip((0..xsize).step_by(D::F32Vec::LEN))
-            .take(xsize.div_ceil(D::F32Vec::LEN))
-        {
-            let minval = D::F32Vec::load(d, mins_chunk);
-            let maxval = D::F32Vec::load(d, maxs_chunk);
-            let out_x = x * 8;
-
-            // Process all 8 output rows using a loop
-            for oy in 0..8 {
-                let base = oy * 8;
-                let v0 = kernel_conv!(d, kernel_vecs[base], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v1 = kernel_conv!(d, kernel_vecs[base + 1], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v2 = kernel_conv!(d, kernel_vecs[base + 2], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v3 = kernel_conv!(d, kernel_vecs[base + 3], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v4 = kernel_conv!(d, kernel_vecs[base + 4], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v5 = kernel_conv!(d, kernel_vecs[base + 5], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v6 = kernel_conv!(d, kernel_vecs[base + 6], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                let v7 = kernel_conv!(d, kernel_vecs[base + 7], r0, r1, r2, r3, r4, x).max(minval).min(maxval);
-                D::F32Vec::store_interleaved_8(v0, v1, v2, v3, v4, v5, v6, v7, &mut output[oy][out_x..]);
-            }
+    // Process using iterators for mins/maxs, manual indexing for output
+    let mins_iter = mins.chunks_exact(D::F32Vec::LEN);
+    let maxs_iter = maxs.chunks_exact(D::F32Vec::LEN);
+
+    for ((mins_chunk, maxs_chunk), x) in mins_iter
+        .zip(maxs_iter)
+        .zip((0..xsize).step_by(D::F32Vec::LEN))
+        .take(xsize.div_ceil(D::F32Vec::LEN))
+    {
+        let minval = D::F32Vec::load(d, mins_chunk);
+        let maxval = D::F32Vec::load(d, maxs_chunk);
+        let out_x = x * 8;
+
+        // Process all 8 output rows using a loop
+        for oy in 0..8 {
+            let base = oy * 8;
+            let v0 = kernel_conv!(d, kernel_vecs[base], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v1 = kernel_conv!(d, kernel_vecs[base + 1], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v2 = kernel_conv!(d, kernel_vecs[base + 2], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v3 = kernel_conv!(d, kernel_vecs[base + 3], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v4 = kernel_conv!(d, kernel_vecs[base + 4], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v5 = kernel_conv!(d, kernel_vecs[base + 5], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v6 = kernel_conv!(d, kernel_vecs[base + 6], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            let v7 = kernel_conv!(d, kernel_vecs[base + 7], r0, r1, r2, r3, r4, x)
+                .max(minval)
+                .min(maxval);
+            D::F32Vec::store_interleaved_8(
+                v0,
+                v1,
+                v2,
+                v3,
+                v4,
+                v5,
+                v6,
+                v7,
+                &mut output[oy][out_x..],
+            );
         }
     }
-);
+}
 
 impl<const N: usize, const SHIFT: u8> RenderPipelineInOutStage for Upsample<N, SHIFT> {
     type InputT = f32;
@@ -409,8 +445,9 @@ impl<const N: usize, const SHIFT: u8> RenderPipelineInOutStage for Upsample<N, S
 
     /// Processes a chunk of a row, applying NxN upsampling using a 5x5 kernel.
     /// Each input value expands into a NxN region in the output, based on neighboring inputs.
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         input_rows: &Channels<f32>,
@@ -425,40 +462,49 @@ impl<const N: usize, const SHIFT: u8> RenderPipelineInOutStage for Upsample<N, S
         // Each dispatch function computes min/max and performs upsampling in one call
         match N {
             2 => {
-                upsample_2x_simd_dispatch(
-                    input,
-                    xsize,
-                    self.flat_kernels.as_slice(),
-                    &mut state.col_min,
-                    &mut state.col_max,
-                    &mut state.mins,
-                    &mut state.maxs,
-                    &mut output_rows[0],
-                );
+                d.call(|d| {
+                    upsample_2x_simd(
+                        d,
+                        input,
+                        xsize,
+                        self.flat_kernels.as_slice(),
+                        &mut state.col_min,
+                        &mut state.col_max,
+                        &mut state.mins,
+                        &mut state.maxs,
+                        &mut output_rows[0],
+                    )
+                });
             }
             4 => {
-                upsample_4x_simd_dispatch(
-                    input,
-                    xsize,
-                    self.flat_kernels.as_slice(),
-                    &mut state.col_min,
-                    &mut state.col_max,
-                    &mut state.mins,
-                    &mut state.maxs,
-                    &mut output_rows[0],
-                );
+                d.call(|d| {
+                    upsample_4x_simd(
+                        d,
+                        input,
+                        xsize,
+                        self.flat_kernels.as_slice(),
+                        &mut state.col_min,
+                        &mut state.col_max,
+                        &mut state.mins,
+                        &mut state.maxs,
+                        &mut output_rows[0],
+                    )
+                });
             }
             8 => {
-                upsample_8x_simd_dispatch(
-                    input,
-                    xsize,
-                    self.flat_kernels.as_slice(),
-                    &mut state.col_min,
-                    &mut state.col_max,
-                    &mut state.mins,
-                    &mut state.maxs,
-                    &mut output_rows[0],
-                );
+                d.call(|d| {
+                    upsample_8x_simd(
+                        d,
+                        input,
+                        xsize,
+                        self.flat_kernels.as_slice(),
+                        &mut state.col_min,
+                        &mut state.col_max,
+                        &mut state.mins,
+                        &mut state.maxs,
+                        &mut output_rows[0],
+                    )
+                });
             }
             _ => unreachable!(),
         }
@@ -4,15 +4,16 @@
 // license that can be found in the LICENSE file.
 
 use crate::api::{
-    JxlColorEncoding, JxlPrimaries, JxlTransferFunction, JxlWhitePoint, adapt_to_xyz_d50,
-    primaries_to_xyz, primaries_to_xyz_d50,
+    JxlColorEncoding, JxlHlgRendering, JxlHlgSystemGamma, JxlPrimaries, JxlTransferFunction,
+    JxlWhitePoint, primaries_conversion_matrix, primaries_to_xyz,
 };
+use crate::color::tf;
 use crate::error::Result;
 use crate::headers::{FileHeader, OpsinInverseMatrix};
 use crate::render::RenderPipelineInPlaceStage;
 use crate::render::stages::from_linear;
-use crate::util::{Matrix3x3, inv_3x3_matrix, mul_3x3_matrix};
-use jxl_simd::{F32SimdVec, simd_function};
+use crate::util::{Matrix3x3, mul_3x3_matrix};
+use jxl_simd::{F32SimdVec, SimdDescriptor};
 
 const SRGB_LUMINANCES: [f32; 3] = [0.2126, 0.7152, 0.0722];
 
@@ -62,24 +63,33 @@ impl OutputColorInfo {
     }
 
     pub fn from_header(header: &FileHeader) -> Result<Self> {
+        if header.image_metadata.color_encoding.want_icc {
+            return Self::from_encoding(header, &JxlColorEncoding::srgb(false));
+        }
+        let desired_colorspace =
+            JxlColorEncoding::from_internal(&header.image_metadata.color_encoding)?;
+        Self::from_encoding(header, &desired_colorspace)
+    }
+
+    /// Returns the information needed to render the image described by `header` in the
+    /// given color encoding. XYB encodings are rendered as sRGB.
+    pub fn from_encoding(
+        header: &FileHeader,
+        desired_colorspace: &JxlColorEncoding,
+    ) -> Result<Self> {
         let srgb_output = OutputColorInfo {
             luminances: SRGB_LUMINANCES,
             intensity_target: header.image_metadata.tone_mapping.intensity_target,
             opsin: header.transform_data.opsin_inverse_matrix.clone(),
             tf: from_linear::TransferFunction::Srgb,
         };
-        if header.image_metadata.color_encoding.want_icc {
-            return Ok(srgb_output);
-        }
 
         let tf;
         let mut inverse_matrix = Self::opsin_matrix_to_matrix3x3(
             header.transform_data.opsin_inverse_matrix.inverse_matrix,
         );
         let mut luminances = SRGB_LUMINANCES;
-        let desired_colorspace =
-            JxlColorEncoding::from_internal(&header.image_metadata.color_encoding)?;
-        match &desired_colorspace {
+        match desired_colorspace {
             JxlColorEncoding::XYB { .. } => {
                 return Ok(srgb_output);
             }
@@ -91,18 +101,14 @@ impl OutputColorInfo {
             } => {
                 tf = transfer_function;
                 if *primaries != JxlPrimaries::SRGB || *white_point != JxlWhitePoint::D65 {
-                    let [r, g, b] = JxlPrimaries::SRGB.to_xy_coords();
-                    let w = JxlWhitePoint::D65.to_xy_coords();
-                    let srgb_to_xyzd50 =
-                        primaries_to_xyz_d50(r.0, r.1, g.0, g.1, b.0, b.1, w.0, w.1)?;
                     let [r, g, b] = primaries.to_xy_coords();
                     let w = white_point.to_xy_coords();
                     let original_to_xyz = primaries_to_xyz(r.0, r.1, g.0, g.1, b.0, b.1, w.0, w.1)?;
                     luminances = original_to_xyz[1].map(|lum| lum as f32);
-                    let adapt_to_d50 = adapt_to_xyz_d50(w.0, w.1)?;
-                    let original_to_xyzd50 = mul_3x3_matrix(&adapt_to_d50, &original_to_xyz);
-                    let xyzd50_to_original = inv_3x3_matrix(&original_to_xyzd50)?;
-                    let srgb_to_original = mul_3x3_matrix(&xyzd50_to_original, &srgb_to_xyzd50);
+                    let srgb_to_original = primaries_conversion_matrix(
+                        (&JxlPrimaries::SRGB, &JxlWhitePoint::D65),
+                        (primaries, white_point),
+                    )?;
                     inverse_matrix = mul_3x3_matrix(&srgb_to_original, &inverse_matrix);
                 }
             }
@@ -123,10 +129,9 @@ impl OutputColorInfo {
         let intensity_target = header.image_metadata.tone_mapping.intensity_target;
         let from_linear_tf = match tf {
             JxlTransferFunction::PQ => from_linear::TransferFunction::Pq { intensity_target },
-            JxlTransferFunction::HLG => from_linear::TransferFunction::Hlg {
-                intensity_target,
-                luminance_rgb: luminances,
-            },
+            JxlTransferFunction::HLG => {
+                from_linear::TransferFunction::hlg(intensity_target, luminances)
+            }
             JxlTransferFunction::BT709 => from_linear::TransferFunction::Bt709,
             JxlTransferFunction::Linear => from_linear::TransferFunction::Gamma(1.0),
             JxlTransferFunction::SRGB => from_linear::TransferFunction::Srgb,
@@ -140,6 +145,31 @@ impl OutputColorInfo {
             tf: from_linear_tf,
         })
     }
+
+    /// Returns the transfer function to use when converting to display-referred linear output.
+    /// This is `self.tf`, except for HLG, where the OOTF is applied for the display described
+    /// by `hlg_rendering`.
+    pub fn display_tf(&self, hlg_rendering: &JxlHlgRendering) -> from_linear::TransferFunction {
+        let from_linear::TransferFunction::Hlg { luminance_rgb, .. } = self.tf else {
+            return self.tf.clone();
+        };
+        let intensity_target = hlg_rendering
+            .display_peak_luminance
+            .unwrap_or(self.intensity_target);
+        let system_gamma = match hlg_rendering.system_gamma {
+            JxlHlgSystemGamma::Extended => tf::hlg_system_gamma(intensity_target),
+            JxlHlgSystemGamma::Bt2100 { surround_luminance } => {
+                tf::hlg_system_gamma_bt2100(intensity_target, surround_luminance)
+            }
+            JxlHlgSystemGamma::Fixed(gamma) => gamma,
+        };
+        from_linear::TransferFunction::Hlg {
+            intensity_target,
+            luminance_rgb,
+            system_gamma,
+            linear_white: hlg_rendering.reference_white.unwrap_or(intensity_target),
+        }
+    }
 }
 
 /// Convert XYB to linear RGB with appropriate primaries, where 1.0 corresponds to `intensity_target` nits.
@@ -170,60 +200,58 @@ impl std::fmt::Display for XybStage {
     }
 }
 
-simd_function!(
-    xyb_process_dispatch,
+#[inline(always)]
+fn xyb_process<D: SimdDescriptor>(
     d: D,
-    fn xyb_process(
-        opsin: &OpsinInverseMatrix,
-        intensity_target: f32,
-        xsize: usize,
-        row_x: &mut [f32],
-        row_y: &mut [f32],
-        row_b: &mut [f32],
-    ) {
-        let OpsinInverseMatrix {
-            inverse_matrix: mat,
-            opsin_biases: bias,
-            ..
-        } = opsin;
-        // TODO(veluca): consider computing the cbrt in advance.
-        let bias_cbrt = bias.map(|x| D::F32Vec::splat(d, x.cbrt()));
-        let intensity_scale = 255.0 / intensity_target;
-        let scaled_bias = bias.map(|x| D::F32Vec::splat(d, x * intensity_scale));
-        let mat = mat.map(|x| D::F32Vec::splat(d, x));
-        let intensity_scale = D::F32Vec::splat(d, intensity_scale);
-
-        for idx in (0..xsize).step_by(D::F32Vec::LEN) {
-            let x = D::F32Vec::load(d, &row_x[idx..]);
-            let y = D::F32Vec::load(d, &row_y[idx..]);
-            let b = D::F32Vec::load(d, &row_b[idx..]);
-
-            // Mix and apply bias
-            let l = y + x - bias_cbrt[0];
-            let m = y - x - bias_cbrt[1];
-            let s = b - bias_cbrt[2];
-
-            // Apply biased inverse gamma and scale (1.0 corresponds to `intensity_target` nits)
-            let l2 = l * l;
-            let m2 = m * m;
-            let s2 = s * s;
-            let scaled_l = l * intensity_scale;
-            let scaled_m = m * intensity_scale;
-            let scaled_s = s * intensity_scale;
-            let l = l2.mul_add(scaled_l, scaled_bias[0]);
-            let m = m2.mul_add(scaled_m, scaled_bias[1]);
-            let s = s2.mul_add(scaled_s, scaled_bias[2]);
-
-            // Apply opsin inverse matrix (linear LMS to linear sRGB)
-            let r = mat[0].mul_add(l, mat[1].mul_add(m, mat[2] * s));
-            let g = mat[3].mul_add(l, mat[4].mul_add(m, mat[5] * s));
-            let b = mat[6].mul_add(l, mat[7].mul_add(m, mat[8] * s));
-            r.store(&mut row_x[idx..]);
-            g.store(&mut row_y[idx..]);
-            b.store(&mut row_b[idx..]);
-        }
+    opsin: &OpsinInverseMatrix,
+    intensity_target: f32,
+    xsize: usize,
+    row_x: &mut [f32],
+    row_y: &mut [f32],
+    row_b: &mut [f32],
+) {
+    let OpsinInverseMatrix {
+        inverse_matrix: mat,
+        opsin_biases: bias,
+        ..
+    } = opsin;
+    // TODO(veluca): consider computing the cbrt in advance.
+    let bias_cbrt = bias.map(|x| D::F32Vec::splat(d, x.cbrt()));
+    let intensity_scale = 255.0 / intensity_target;
+    let scaled_bias = bias.map(|x| D::F32Vec::splat(d, x * intensity_scale));
+    let mat = mat.map(|x| D::F32Vec::splat(d, x));
+    let intensity_scale = D::F32Vec::splat(d, intensity_scale);
+
+    for idx in (0..xsize).step_by(D::F32Vec::LEN) {
+        let x = D::F32Vec::load(d, &row_x[idx..]);
+        let y = D::F32Vec::load(d, &row_y[idx..]);
+        let b = D::F32Vec::load(d, &row_b[idx..]);
+
+        // Mix and apply bias
+        let l = y + x - bias_cbrt[0];
+        let m = y - x - bias_cbrt[1];
+        let s = b - bias_cbrt[2];
+
+        // Apply biased inverse gamma and scale (1.0 corresponds to `intensity_target` nits)
+        let l2 = l * l;
+        let m2 = m * m;
+        let s2 = s * s;
+        let scaled_l = l * intensity_scale;
+        let scaled_m = m * intensity_scale;
+        let scaled_s = s * intensity_scale;
+        let l = l2.mul_add(scaled_l, scaled_bias[0]);
+        let m = m2.mul_add(scaled_m, scaled_bias[1]);
+        let s = s2.mul_add(scaled_s, scaled_bias[2]);
+
+        // Apply opsin inverse matrix (linear LMS to linear sRGB)
+        let r = mat[0].mul_add(l, mat[1].mul_add(m, mat[2] * s));
+        let g = mat[3].mul_add(l, mat[4].mul_add(m, mat[5] * s));
+        let b = mat[6].mul_add(l, mat[7].mul_add(m, mat[8] * s));
+        r.store(&mut row_x[idx..]);
+        g.store(&mut row_y[idx..]);
+        b.store(&mut row_b[idx..]);
     }
-);
+}
 
 impl RenderPipelineInPlaceStage for XybStage {
     type Type = f32;
@@ -232,13 +260,14 @@ impl RenderPipelineInPlaceStage for XybStage {
         (self.first_channel..self.first_channel + 3).contains(&c)
     }
 
-    fn process_row_chunk(
+    fn process_row_chunk<D: SimdDescriptor>(
         &self,
+        d: D,
         _position: (usize, usize),
         xsize: usize,
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
-    ) {
+    ) -> Result<()> {
         let [row_x, row_y, row_b] = row else {
             panic!(
                 "incorrect number of channels; expected 3, found {}",
@@ -246,14 +275,18 @@ impl RenderPipelineInPlaceStage for XybStage {
             );
         };
 
-        xyb_process_dispatch(
-            &self.output_color_info.opsin,
-            self.output_color_info.intensity_target,
-            xsize,
-            row_x,
-            row_y,
-            row_b,
-        );
+        d.call(|d| {
+            xyb_process(
+                d,
+                &self.output_color_info.opsin,
+                self.output_color_info.intensity_target,
+                xsize,
+                row_x,
+                row_y,
+                row_b,
+            )
+        });
+        Ok(())
     }
 }
 
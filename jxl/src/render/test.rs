@@ -4,7 +4,7 @@
 // license that can be found in the LICENSE file.
 
 use crate::{
-    api::{Endianness, JxlColorType, JxlDataFormat, JxlOutputBuffer},
+    api::{Endianness, JxlColorType, JxlDataFormat, JxlOutputBuffer, JxlOutputSink},
     error::Result,
     headers::Orientation,
     image::{DataTypeTag, Image, ImageDataType, Rect},
@@ -15,6 +15,7 @@ use crate::{
         tracing_wrappers::{instrument, trace},
     },
 };
+use jxl_simd::{SimdLevel, simd_level_dispatch};
 use rand::SeedableRng;
 
 use super::{
@@ -40,7 +41,9 @@ impl<T: RenderPipelineInOutStage> RenderPipelineTestableStage<()> for T {
     type InputT = T::InputT;
     type OutputT = T::OutputT;
     fn into_stage(self) -> Stage<Image<f64>> {
-        Stage::InOut(Box::new(self))
+        Stage::InOut(simd_level_dispatch!(SimdLevel::best(), d => {
+            SimpleRenderPipeline::box_inout_stage(d, self)
+        }))
     }
 }
 
@@ -50,7 +53,9 @@ impl<T: RenderPipelineInPlaceStage> RenderPipelineTestableStage<Empty> for T {
     type InputT = T::Type;
     type OutputT = T::Type;
     fn into_stage(self) -> Stage<Image<f64>> {
-        Stage::InPlace(Box::new(self))
+        Stage::InPlace(simd_level_dispatch!(SimdLevel::best(), d => {
+            SimpleRenderPipeline::box_inplace_stage(d, self)
+        }))
     }
 }
 
@@ -143,13 +148,13 @@ fn make_and_run_simple_pipeline_impl<InputT: ImageDataType, OutputT: ImageDataTy
         .iter_mut()
         .map(|x| {
             let size = x.size();
-            Some(JxlOutputBuffer::from_image_rect_mut(
+            Some(JxlOutputSink::Buffer(JxlOutputBuffer::from_image_rect_mut(
                 x.get_rect_mut(Rect {
                     size,
                     origin: (0, 0),
                 })
                 .into_raw(),
-            ))
+            )))
         })
         .collect();
 
@@ -240,3 +245,67 @@ pub(super) fn test_stage_consistency<S: RenderPipelineTestableStage<V>, V>(
     });
     Ok(())
 }
+
+/// Runs a pipeline of type `P` with the stages added by `add_stages` on `input_images` (one per
+/// channel), and returns the resulting channels.
+pub(super) fn make_and_run_pipeline<P: RenderPipeline>(
+    add_stages: impl FnOnce(RenderPipelineBuilder<P>) -> Result<RenderPipelineBuilder<P>>,
+    input_images: &[Image<f32>],
+    chunk_size: usize,
+) -> Result<Vec<Image<f32>>> {
+    const LOG_GROUP_SIZE: usize = 8;
+    let image_size = input_images[0].size();
+    let mut pipeline = add_stages(RenderPipelineBuilder::<P>::new_with_chunk_size(
+        input_images.len(),
+        image_size,
+        0,
+        LOG_GROUP_SIZE,
+        1,
+        chunk_size,
+    ))?;
+    let output_size = pipeline.size();
+    for i in 0..input_images.len() {
+        pipeline = pipeline.add_save_stage(
+            &[i],
+            Orientation::Identity,
+            i,
+            JxlColorType::Grayscale,
+            JxlDataFormat::f32(),
+            false,
+        )?;
+    }
+    let mut pipeline = pipeline.build()?;
+
+    let mut outputs = (0..input_images.len())
+        .map(|_| Image::<f32>::new(output_size))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut buf_ptrs: Vec<_> = outputs
+        .iter_mut()
+        .map(|x| {
+            let size = x.size();
+            Some(JxlOutputSink::Buffer(JxlOutputBuffer::from_image_rect_mut(
+                x.get_rect_mut(Rect {
+                    size,
+                    origin: (0, 0),
+                })
+                .into_raw(),
+            )))
+        })
+        .collect();
+    let mut buffer_splitter = BufferSplitter::new(&mut buf_ptrs);
+
+    let num_groups = image_size.0.shrc(LOG_GROUP_SIZE) * image_size.1.shrc(LOG_GROUP_SIZE);
+    for g in 0..num_groups {
+        for (c, image) in input_images.iter().enumerate() {
+            pipeline.set_buffer_for_group(
+                c,
+                g,
+                1,
+                extract_group_rect(image, g, (LOG_GROUP_SIZE, LOG_GROUP_SIZE))?,
+                &mut buffer_splitter,
+            )?;
+        }
+    }
+
+    Ok(outputs)
+}
@@ -52,7 +52,7 @@ fn decode_benches(c: &mut Criterion) {
             |b, bytes| {
                 b.iter(|| {
                     let mut input = bytes.as_slice();
-                    decode_frames(&mut input, JxlDecoderOptions::default()).unwrap();
+                    decode_frames(&mut input, JxlDecoderOptions::default(), false, false).unwrap();
                 })
             },
         );
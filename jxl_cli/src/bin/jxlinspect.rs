@@ -6,18 +6,16 @@
 use clap::{Arg, Command};
 use color_eyre::eyre::{Result, eyre};
 use jxl::api::{
-    JxlBitDepth, JxlColorEncoding, JxlColorProfile, JxlDecoder, JxlDecoderOptions, JxlOutputBuffer,
-    ProcessingResult,
+    JxlBitDepth, JxlColorEncoding, JxlColorProfile, JxlDecoder, JxlDecoderOptions,
+    JxlSeekableReader, ProcessingResult,
 };
 use jxl::headers::extra_channels::ExtraChannel;
-use jxl::image::{Image, Rect};
 use std::fs::File;
-use std::io::BufReader;
 use std::path::Path;
 
 fn parse_jxl(path: &Path) -> Result<()> {
     let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
+    let mut reader = JxlSeekableReader::new(file)?;
 
     let options = JxlDecoderOptions::default();
     let initialized_decoder = JxlDecoder::<jxl::api::states::Initialized>::new(options);
@@ -114,56 +112,28 @@ fn parse_jxl(path: &Path) -> Result<()> {
     }
 
     if let Some(animation) = info.animation {
-        let pixel_format = decoder_with_image_info.current_pixel_format().clone();
-        let num_channels: usize = pixel_format.color_type.samples_per_pixel();
+        // Scanning the frames consumes the decoder, so the frame index is found first.
+        let frame_index = decoder_with_image_info
+            .find_frame_index(&mut reader)?
+            .cloned();
+        let frames = match decoder_with_image_info.scan_frames(&mut reader)? {
+            ProcessingResult::Complete { result } => result,
+            ProcessingResult::NeedsMoreInput { .. } => {
+                return Err(eyre!("Source file {:?} truncated", path));
+            }
+        };
         let mut num_frames = 0;
         let mut total_seconds = 0.0;
 
-        loop {
-            let decoder_with_frame_info = match decoder_with_image_info.process(&mut reader)? {
-                ProcessingResult::Complete { result } => result,
-                ProcessingResult::NeedsMoreInput { .. } => {
-                    return Err(eyre!("Source file {:?} truncated", path));
-                }
-            };
-
-            let duration = decoder_with_frame_info.frame_header().duration.unwrap();
+        for frame in frames.iter().filter(|frame| frame.is_visible) {
+            let duration = frame.header.duration.unwrap();
             total_seconds += duration;
-            println!("Frame {}, duration {}ms", num_frames, duration);
-
-            let mut outputs = vec![Image::<f32>::new((
-                info.size.0 * num_channels,
-                info.size.1,
-            ))?];
-
-            for _ in 0..info.extra_channels.len() {
-                outputs.push(Image::<f32>::new(info.size)?);
+            print!("Frame {}, duration {}ms", num_frames, duration);
+            if let Some(timecode) = frame.header.timecode {
+                print!(", timecode {}", timecode);
             }
-
-            let mut output_bufs: Vec<JxlOutputBuffer<'_>> = outputs
-                .iter_mut()
-                .map(|x| {
-                    let rect = Rect {
-                        size: x.size(),
-                        origin: (0, 0),
-                    };
-                    JxlOutputBuffer::from_image_rect_mut(x.get_rect_mut(rect).into_raw())
-                })
-                .collect();
-
-            decoder_with_image_info =
-                match decoder_with_frame_info.process(&mut reader, &mut output_bufs)? {
-                    ProcessingResult::Complete { result } => result,
-                    ProcessingResult::NeedsMoreInput { .. } => {
-                        return Err(eyre!("Source file {:?} truncated", path));
-                    }
-                };
-
+            println!();
             num_frames += 1;
-
-            if !decoder_with_image_info.has_more_frames() {
-                break;
-            }
         }
 
         print!(
@@ -183,6 +153,18 @@ fn parse_jxl(path: &Path) -> Result<()> {
                 animation.num_loops as f64 * total_seconds
             );
         }
+
+        if let Some(frame_index) = frame_index {
+            println!("Frame index: {} keyframes", frame_index.frames.len());
+            for keyframe in &frame_index.frames {
+                println!(
+                    "Keyframe {}: codestream offset {}, start {}ms",
+                    keyframe.frame_number,
+                    keyframe.codestream_offset,
+                    keyframe.start_ticks as f64 * frame_index.tick_duration()
+                );
+            }
+        }
     }
     Ok(())
 }
@@ -0,0 +1,169 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use jxl::api::{JxlCms, JxlCmsTransformer, JxlColorProfile};
+use jxl::error::{Error, Result};
+use lcms2::{ColorSpaceSignature, Flags, Intent, PixelFormat, Profile, Transform};
+
+/// A [`JxlCms`] backed by Little CMS.
+pub struct Lcms2Cms;
+
+#[derive(Clone, Copy)]
+struct ChannelLayout {
+    num_channels: usize,
+    is_cmyk: bool,
+    pixel_format: PixelFormat,
+}
+
+fn open_profile(profile: &JxlColorProfile) -> Result<(Profile, ChannelLayout)> {
+    let icc = profile
+        .try_as_icc()
+        .ok_or_else(|| Error::CmsError(format!("cannot create an ICC profile for {profile}")))?;
+    let profile = Profile::new_icc(&icc).map_err(|e| Error::CmsError(e.to_string()))?;
+    let layout = match profile.color_space() {
+        ColorSpaceSignature::GrayData => ChannelLayout {
+            num_channels: 1,
+            is_cmyk: false,
+            pixel_format: PixelFormat::GRAY_FLT,
+        },
+        ColorSpaceSignature::RgbData => ChannelLayout {
+            num_channels: 3,
+            is_cmyk: false,
+            pixel_format: PixelFormat::RGB_FLT,
+        },
+        ColorSpaceSignature::CmykData => ChannelLayout {
+            num_channels: 4,
+            is_cmyk: true,
+            pixel_format: PixelFormat::CMYK_FLT,
+        },
+        cs => {
+            return Err(Error::CmsError(format!(
+                "unsupported ICC color space {cs:?}"
+            )));
+        }
+    };
+    Ok((profile, layout))
+}
+
+struct Lcms2Transformer {
+    transform: Transform<u8, u8>,
+    input: ChannelLayout,
+    output: ChannelLayout,
+    input_buf: Vec<u8>,
+    output_buf: Vec<u8>,
+}
+
+impl JxlCmsTransformer for Lcms2Transformer {
+    fn do_transform(&mut self, input: &[f32], output: &mut [f32]) -> Result<()> {
+        let num_pixels = input.len() / self.input.num_channels;
+        if output.len() != num_pixels * self.output.num_channels {
+            return Err(Error::CmsError(format!(
+                "output buffer has {} samples, expected {}",
+                output.len(),
+                num_pixels * self.output.num_channels
+            )));
+        }
+        // Little CMS represents floating point CMYK as 0 (no ink) to 100 (maximum ink).
+        self.input_buf.clear();
+        for &v in input {
+            let v = if self.input.is_cmyk {
+                (1.0 - v) * 100.0
+            } else {
+                v
+            };
+            self.input_buf.extend_from_slice(&v.to_ne_bytes());
+        }
+        self.output_buf.resize(output.len() * 4, 0);
+        self.transform
+            .transform_pixels(&self.input_buf, &mut self.output_buf);
+        for (o, bytes) in output.iter_mut().zip(self.output_buf.chunks_exact(4)) {
+            let v = f32::from_ne_bytes(bytes.try_into().unwrap());
+            *o = if self.output.is_cmyk {
+                1.0 - v / 100.0
+            } else {
+                v
+            };
+        }
+        Ok(())
+    }
+
+    fn do_transform_inplace(&mut self, inout: &mut [f32]) -> Result<()> {
+        let input = inout.to_vec();
+        self.do_transform(&input, inout)
+    }
+}
+
+impl JxlCms for Lcms2Cms {
+    fn initialize_transforms(
+        &self,
+        n: usize,
+        max_pixels_per_transform: usize,
+        input: JxlColorProfile,
+        output: JxlColorProfile,
+        _intensity_target: f32,
+    ) -> Result<(usize, Vec<Box<dyn JxlCmsTransformer>>)> {
+        let (input_profile, input_layout) = open_profile(&input)?;
+        let (output_profile, output_layout) = open_profile(&output)?;
+        let transformers = (0..n)
+            .map(|_| -> Result<Box<dyn JxlCmsTransformer>> {
+                let transform = Transform::new_flags(
+                    &input_profile,
+                    input_layout.pixel_format,
+                    &output_profile,
+                    output_layout.pixel_format,
+                    Intent::RelativeColorimetric,
+                    Flags::BLACKPOINT_COMPENSATION | Flags::HIGHRES_PRECALC,
+                )
+                .map_err(|e| Error::CmsError(e.to_string()))?;
+                Ok(Box::new(Lcms2Transformer {
+                    transform,
+                    input_buf: Vec::with_capacity(
+                        max_pixels_per_transform * input_layout.num_channels * 4,
+                    ),
+                    output_buf: Vec::with_capacity(
+                        max_pixels_per_transform * output_layout.num_channels * 4,
+                    ),
+                    input: input_layout,
+                    output: output_layout,
+                }))
+            })
+            .collect::<Result<_>>()?;
+        Ok((output_layout.num_channels, transformers))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Lcms2Cms;
+    use crate::dec::decode_header;
+    use jxl::api::{JxlCms, JxlColorEncoding, JxlColorProfile, JxlDecoderOptions};
+    use jxl::error::Result;
+
+    #[test]
+    fn cmyk_to_srgb() -> Result<()> {
+        let file = std::fs::read("../jxl/resources/test/conformance_test_images/cmyk_layers.jxl")?;
+        let decoder = decode_header(&mut file.as_slice(), JxlDecoderOptions::default()).unwrap();
+        let cmyk = decoder.embedded_color_profile().clone();
+        assert!(cmyk.is_cmyk());
+        let (num_channels, mut transformers) = Lcms2Cms.initialize_transforms(
+            1,
+            2,
+            cmyk,
+            JxlColorProfile::Simple(JxlColorEncoding::srgb(false)),
+            255.0,
+        )?;
+        assert_eq!(num_channels, 3);
+        let mut rgb = [0.0; 6];
+        // No ink, then maximum black ink.
+        transformers[0].do_transform(&[1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0], &mut rgb)?;
+        for v in &rgb[..3] {
+            assert!(*v > 0.95, "{rgb:?}");
+        }
+        for v in &rgb[3..] {
+            assert!(*v < 0.3, "{rgb:?}");
+        }
+        Ok(())
+    }
+}
@@ -9,8 +9,10 @@ use color_eyre::eyre::{Result, eyre};
 use jxl::{
     api::{
         JxlAnimation, JxlBitDepth, JxlBitstreamInput, JxlColorProfile, JxlColorType, JxlDecoder,
-        JxlDecoderOptions, JxlOutputBuffer, ProcessingResult, states::WithImageInfo,
+        JxlDecoderOptions, JxlFrameHeader, JxlOutputBuffer, ProcessingResult,
+        states::WithImageInfo,
     },
+    headers::extra_channels::ExtraChannel,
     image::{Image, ImageDataType, Rect},
 };
 
@@ -26,7 +28,11 @@ pub struct DecodeOutput<T: ImageDataType> {
     pub original_bit_depth: JxlBitDepth,
     pub output_profile: JxlColorProfile,
     pub embedded_profile: JxlColorProfile,
+    /// Luminance, in nits, of a sample value of 1.0, if the output is linear.
+    pub linear_output_luminance: Option<f32>,
     pub jxl_animation: Option<JxlAnimation>,
+    /// Whether the input ended in the middle of the last frame, which was filled in.
+    pub partial: bool,
 }
 
 pub fn decode_header<In: JxlBitstreamInput>(
@@ -41,16 +47,48 @@ pub fn decode_header<In: JxlBitstreamInput>(
     }
 }
 
+/// Reads the headers of the remaining frames, skipping over their pixel data.
+pub fn frame_headers<In: JxlBitstreamInput>(
+    input: &mut In,
+    mut decoder: JxlDecoder<WithImageInfo>,
+) -> Result<Vec<JxlFrameHeader>> {
+    let mut headers = vec![];
+    while decoder.has_more_frames() {
+        let frame_decoder = match decoder.process(input)? {
+            ProcessingResult::Complete { result } => result,
+            ProcessingResult::NeedsMoreInput { .. } => return Err(eyre!("Source file truncated")),
+        };
+        headers.push(frame_decoder.frame_header());
+        decoder = match frame_decoder.skip_frame(input)? {
+            ProcessingResult::Complete { result } => result,
+            ProcessingResult::NeedsMoreInput { .. } => return Err(eyre!("Source file truncated")),
+        };
+    }
+    Ok(headers)
+}
+
 /// Decode a JXL image from any input that implements JxlBitstreamInput.
 /// This works with both byte slices (`&mut &[u8]`) and buffered readers (`&mut BufReader<File>`).
+/// If `linear_output` is set, images described by a color encoding are decoded with a linear
+/// transfer function. If `allow_partial` is set, a frame that is cut off by the end of the input
+/// is rendered from the data that is available instead of failing.
 pub fn decode_frames<In: JxlBitstreamInput>(
     input: &mut In,
     decoder_options: JxlDecoderOptions,
+    linear_output: bool,
+    allow_partial: bool,
 ) -> Result<(DecodeOutput<f32>, Duration)> {
     let start = Instant::now();
 
     let mut decoder_with_image_info = decode_header(input, decoder_options)?;
 
+    if linear_output
+        && let JxlColorProfile::Simple(encoding) = decoder_with_image_info.output_color_profile()
+    {
+        let linear_profile = JxlColorProfile::Simple(encoding.with_linear_tf());
+        decoder_with_image_info.set_output_color_profile(linear_profile)?;
+    }
+
     let info = decoder_with_image_info.basic_info();
     let embedded_profile = decoder_with_image_info.embedded_color_profile().clone();
     let output_profile = decoder_with_image_info.output_color_profile().clone();
@@ -61,11 +99,31 @@ pub fn decode_frames<In: JxlBitstreamInput>(
         original_bit_depth: info.bit_depth.clone(),
         output_profile,
         embedded_profile,
+        linear_output_luminance: decoder_with_image_info.linear_output_luminance(),
         jxl_animation: info.animation.clone(),
+        partial: false,
     };
 
-    let extra_channels = info.extra_channels.len();
-    let pixel_format = decoder_with_image_info.current_pixel_format().clone();
+    let extra_channel_info = info.extra_channels.clone();
+    let mut pixel_format = decoder_with_image_info.current_pixel_format().clone();
+    // The black channel is consumed when converting CMYK to a different color space.
+    if image_data.embedded_profile.is_cmyk() && !image_data.output_profile.is_cmyk() {
+        for (format, info) in pixel_format
+            .extra_channel_format
+            .iter_mut()
+            .zip(extra_channel_info.iter())
+        {
+            if info.ec_type == ExtraChannel::Black {
+                *format = None;
+            }
+        }
+        decoder_with_image_info.set_pixel_format(pixel_format.clone());
+    }
+    let extra_channels = pixel_format
+        .extra_channel_format
+        .iter()
+        .filter(|f| f.is_some())
+        .count();
     let color_type = pixel_format.color_type;
     // TODO(zond): This is the way the API works right now, let's improve it when the API is cleverer.
     let samples_per_pixel = if color_type == JxlColorType::Grayscale {
@@ -103,9 +161,17 @@ pub fn decode_frames<In: JxlBitstreamInput>(
             })
             .collect();
 
-        decoder_with_image_info = match decoder_with_frame_info.process(input, &mut output_bufs)? {
-            ProcessingResult::Complete { result } => result,
-            ProcessingResult::NeedsMoreInput { .. } => return Err(eyre!("Source file truncated")),
+        decoder_with_image_info = if allow_partial {
+            let finished = decoder_with_frame_info.finish_input(input, &mut output_bufs)?;
+            image_data.partial = finished.partial;
+            finished.decoder
+        } else {
+            match decoder_with_frame_info.process(input, &mut output_bufs)? {
+                ProcessingResult::Complete { result } => result,
+                ProcessingResult::NeedsMoreInput { .. } => {
+                    return Err(eyre!("Source file truncated"));
+                }
+            }
         };
 
         image_data.frames.push(ImageFrame {
@@ -142,9 +142,60 @@ mod jxl_exr {
         let channels = AnyChannels::sort(channels);
         let mut image = Image::from_channels((width, height), channels);
         image.attributes.chromaticities = chromaticities;
-        // TODO(sboukortt): intensity_target -> whiteLuminance
+        image.layer_data.attributes.white_luminance = image_data.linear_output_luminance;
 
         image.write().to_buffered(writer)?;
         Ok(())
     }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::dec::ImageFrame;
+        use jxl::api::{JxlBitDepth, JxlColorType, JxlPrimaries, JxlWhitePoint};
+        use jxl::headers::color_encoding::RenderingIntent;
+        use std::io::Cursor;
+
+        #[test]
+        fn writes_color_metadata() -> Result<()> {
+            let profile = JxlColorProfile::Simple(JxlColorEncoding::RgbColorSpace {
+                white_point: JxlWhitePoint::D65,
+                primaries: JxlPrimaries::BT2100,
+                transfer_function: JxlTransferFunction::Linear,
+                rendering_intent: RenderingIntent::Relative,
+            });
+            let image_data = DecodeOutput {
+                size: (2, 1),
+                frames: vec![ImageFrame {
+                    channels: (0..3)
+                        .map(|_| jxl::image::Image::new_with_value((2, 1), 0.5))
+                        .collect::<Result<_, _>>()?,
+                    duration: 0.0,
+                    color_type: JxlColorType::Rgb,
+                }],
+                original_bit_depth: JxlBitDepth::Float {
+                    bits_per_sample: 16,
+                    exponent_bits_per_sample: 5,
+                },
+                output_profile: profile.clone(),
+                embedded_profile: profile,
+                linear_output_luminance: Some(4000.0),
+                jxl_animation: None,
+                partial: false,
+            };
+            let mut exr = Cursor::new(Vec::new());
+            to_exr(&image_data, 16, &mut exr)?;
+
+            exr.set_position(0);
+            let meta = exr::meta::MetaData::read_from_buffered(exr, false)?;
+            let header = &meta.headers[0];
+            let chromaticities = header.shared_attributes.chromaticities.unwrap();
+            assert_eq!(chromaticities.red, Vec2(0.708, 0.292));
+            assert_eq!(chromaticities.green, Vec2(0.170, 0.797));
+            assert_eq!(chromaticities.blue, Vec2(0.131, 0.046));
+            assert_eq!(chromaticities.white, Vec2(0.3127, 0.329));
+            assert_eq!(header.own_attributes.white_luminance, Some(4000.0));
+            Ok(())
+        }
+    }
 }
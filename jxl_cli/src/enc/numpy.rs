@@ -5,15 +5,10 @@
 
 use crate::dec::DecodeOutput;
 use jxl::error::{Error, Result};
+use jxl::image::Image;
 use std::io::Write;
 
-fn numpy_header<Writer: Write>(
-    xsize: usize,
-    ysize: usize,
-    num_channels: usize,
-    num_frames: usize,
-    writer: &mut Writer,
-) -> Result<()> {
+fn numpy_header<Writer: Write>(shape: &[usize], writer: &mut Writer) -> Result<()> {
     // The magic string and version for .npy files (Version 1.0)
     let magic_string: [u8; 8] = [0x93, b'N', b'U', b'M', b'P', b'Y', 0x01, 0x00];
 
@@ -21,9 +16,10 @@ fn numpy_header<Writer: Write>(
     // Note the trailing comma in the tuple and the space before the closing brace, and the newline.
     //
     // The dtype '<f4' signifies little-endian 32-bit float.
+    let shape: Vec<_> = shape.iter().map(|s| s.to_string()).collect();
     let mut header_dict_str = format!(
-        "{{'descr': '<f4', 'fortran_order': False, 'shape': \
-	 ({num_frames}, {ysize}, {xsize}, {num_channels}), }}"
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}), }}",
+        shape.join(", ")
     );
     // https://github.com/numpy/numpy/blob/main/doc/neps/nep-0001-npy-format.rst:
     // "terminated by a newline ('n') and padded with spaces ('x20') to make the total length of the magic string + 4 + HEADER_LEN be evenly divisible by 16 for alignment purposes"
@@ -93,9 +89,30 @@ pub fn to_numpy<Writer: Write>(image_data: &DecodeOutput<f32>, writer: &mut Writ
     let num_frames = image_data.frames.len();
     let num_channels = image_data.frames[0].channels.len();
 
-    numpy_header(width, height, num_channels, num_frames, writer)?;
+    numpy_header(&[num_frames, height, width, num_channels], writer)?;
     // Consistent channel sizes are checked inside the call to `to_numpy_bytes`.
     numpy_bytes(image_data, num_channels, writer)?;
 
     Ok(())
 }
+
+/// Writes `planes`, which must all have the same size, in .npy format, without clamping.
+/// The shape of the NumPy array will be (num_planes, height, width).
+pub fn planes_to_numpy<Writer: Write>(planes: &[&Image<f32>], writer: &mut Writer) -> Result<()> {
+    let Some(first) = planes.first() else {
+        return Err(Error::NoFrames);
+    };
+    let (width, height) = first.size();
+    for plane in planes {
+        assert_eq!(plane.size(), (width, height));
+    }
+    numpy_header(&[planes.len(), height, width], writer)?;
+    for plane in planes {
+        for y in 0..height {
+            for v in plane.row(y) {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -3,5 +3,6 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+pub mod cms;
 pub mod dec;
 pub mod enc;
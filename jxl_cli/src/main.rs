@@ -5,12 +5,12 @@
 
 use clap::Parser;
 use color_eyre::eyre::{Result, WrapErr, eyre};
-use jxl::api::{JxlColorType, JxlDecoderOptions};
+use jxl::api::{JxlColorType, JxlDecoderOptions, JxlHlgRendering, JxlSeekableReader, JxlStageTap};
 use jxl::image::Image;
-use jxl_cli::{dec, enc};
+use jxl_cli::{cms, dec, enc};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Seek, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::{fs, mem};
 
@@ -57,13 +57,69 @@ fn save_image(
         .wrap_err_with(|| format!("Failed to write decoded image to {:?}", &output_filename))
 }
 
+/// Returns `path` with `suffix` appended to the file stem.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Writes the captures of `tap` to `path`, adding the frame index to the file name if there is
+/// more than one, and the channel index if the captured channels have different sizes.
+fn save_stage_captures(tap: &JxlStageTap, path: &Path) -> Result<()> {
+    let captures = tap.take_captures();
+    if captures.is_empty() {
+        println!("No render stage matches {:?}", tap.name());
+    }
+    for (i, capture) in captures.iter().enumerate() {
+        let path = if captures.len() > 1 {
+            with_suffix(path, &format!("-{i}"))
+        } else {
+            path.to_path_buf()
+        };
+        let planes: Vec<_> = capture
+            .channels
+            .iter()
+            .enumerate()
+            .filter_map(|(c, plane)| Some((c, plane.as_ref()?)))
+            .collect();
+        let size = planes[0].1.size();
+        let files: Vec<(PathBuf, Vec<&Image<f32>>)> =
+            if planes.iter().all(|(_, plane)| plane.size() == size) {
+                vec![(path, planes.iter().map(|(_, plane)| *plane).collect())]
+            } else {
+                planes
+                    .iter()
+                    .map(|(c, plane)| (with_suffix(&path, &format!("-c{c}")), vec![*plane]))
+                    .collect()
+            };
+        for (path, planes) in files {
+            let mut writer = BufWriter::new(File::create(&path)?);
+            enc::numpy::planes_to_numpy(&planes, &mut writer)?;
+            writer
+                .flush()
+                .wrap_err_with(|| format!("Failed to write stage output to {:?}", path))?;
+            println!(
+                "Wrote output of {} to {:?}",
+                capture.stages.join(", "),
+                path
+            );
+        }
+    }
+    Ok(())
+}
+
 #[derive(Parser)]
 struct Opt {
     /// Input JXL file
     input: PathBuf,
 
     /// Output image file, should end in .ppm, .pgm, .png or .npy
-    #[clap(required_unless_present_any = ["speedtest", "info"])]
+    #[clap(required_unless_present_any = ["speedtest", "info", "dump_stage"])]
     output: Option<PathBuf>,
 
     /// Print measured decoding speed..
@@ -94,9 +150,26 @@ struct Opt {
     #[clap(long, short, action)]
     info: bool,
 
+    /// If the file is truncated, render the last frame from the data that is available
+    #[clap(long, action)]
+    allow_partial: bool,
+
     /// Use high precision mode for decoding
     #[clap(long)]
     high_precision: bool,
+
+    /// Peak luminance, in nits, of the display to render HLG images for (linear output only)
+    #[clap(long)]
+    hlg_display_peak: Option<f32>,
+
+    /// Luminance, in nits, that maps to 1.0 when rendering HLG images (linear output only)
+    #[clap(long)]
+    hlg_reference_white: Option<f32>,
+
+    /// Write the channels produced by the render stages matching NAME (e.g. epf2, gaborish, xyb)
+    /// to PATH as a .npy array of shape (channels, height, width). Can be repeated.
+    #[clap(long, value_name = "NAME=PATH")]
+    dump_stage: Vec<String>,
 }
 
 // Extract RGB channels from interleaved RGB buffer
@@ -139,19 +212,39 @@ fn main() -> Result<()> {
         Some(path) => (path.ends_with(".npy"), path.ends_with(".exr")),
         None => (false, false),
     };
+    let stage_dumps = opt
+        .dump_stage
+        .iter()
+        .map(|arg| {
+            let (name, path) = arg
+                .split_once('=')
+                .ok_or_else(|| eyre!("Invalid --dump-stage {:?}, expected NAME=PATH", arg))?;
+            Ok((JxlStageTap::new(name), PathBuf::from(path)))
+        })
+        .collect::<Result<Vec<_>>>()?;
     let high_precision = opt.high_precision;
+    let hlg_rendering = JxlHlgRendering {
+        display_peak_luminance: opt.hlg_display_peak,
+        reference_white: opt.hlg_reference_white,
+        ..Default::default()
+    };
     let options = |skip_preview: bool| {
         let mut options = JxlDecoderOptions::default();
         options.xyb_output_linear = numpy_output || exr_output;
         options.render_spot_colors = !numpy_output;
         options.skip_preview = skip_preview;
         options.high_precision = high_precision;
+        options.hlg_rendering = hlg_rendering.clone();
+        options.stage_taps = stage_dumps.iter().map(|(tap, _)| tap.clone()).collect();
+        if !numpy_output {
+            options.cms = Some(Box::new(cms::Lcms2Cms));
+        }
         options
     };
 
     // Handle --info flag: print image info and exit
     if opt.info {
-        let mut reader = BufReader::new(&mut file);
+        let mut reader = JxlSeekableReader::new(&mut file)?;
         let decoder = dec::decode_header(&mut reader, options(true))?;
         let info = decoder.basic_info();
         println!("Image size: {}x{}", info.size.0, info.size.1);
@@ -169,6 +262,22 @@ fn main() -> Result<()> {
             );
         }
         println!("Extra channels: {}", info.extra_channels.len());
+        if info
+            .animation
+            .as_ref()
+            .is_some_and(|anim| anim.have_timecodes)
+        {
+            let mut start = 0.0;
+            for (index, header) in dec::frame_headers(&mut reader, decoder)?.iter().enumerate() {
+                let duration = header.duration.unwrap_or(0.0);
+                print!("Frame {index}: start {start}ms, duration {duration}ms");
+                if let Some(timecode) = header.timecode {
+                    print!(", timecode {timecode}");
+                }
+                println!();
+                start += duration;
+            }
+        }
         return Ok(());
     }
 
@@ -195,9 +304,17 @@ fn main() -> Result<()> {
         file.read_to_end(&mut input_bytes)?;
         (0..reps)
             .try_fold(None, |_, _| -> Result<Option<dec::DecodeOutput<f32>>> {
+                // Only keep the stage outputs of the last repetition.
+                for (tap, _) in stage_dumps.iter() {
+                    tap.take_captures();
+                }
                 let mut input = input_bytes.as_slice();
-                let (mut iteration_image_data, iteration_duration) =
-                    dec::decode_frames(&mut input, options(skip_preview))?;
+                let (mut iteration_image_data, iteration_duration) = dec::decode_frames(
+                    &mut input,
+                    options(skip_preview),
+                    exr_output,
+                    opt.allow_partial,
+                )?;
                 duration_sum += iteration_duration;
                 // When extracting preview, only keep the first frame (the preview)
                 if opt.preview {
@@ -217,8 +334,13 @@ fn main() -> Result<()> {
             .unwrap()
     } else {
         // For single decode, stream from file
-        let mut reader = BufReader::new(file);
-        let (mut image_data, duration) = dec::decode_frames(&mut reader, options(skip_preview))?;
+        let mut reader = JxlSeekableReader::new(file)?;
+        let (mut image_data, duration) = dec::decode_frames(
+            &mut reader,
+            options(skip_preview),
+            exr_output,
+            opt.allow_partial,
+        )?;
         duration_sum = duration;
         // When extracting preview, only keep the first frame (the preview)
         if opt.preview {
@@ -236,6 +358,14 @@ fn main() -> Result<()> {
         image_data
     };
 
+    for (tap, path) in stage_dumps.iter() {
+        save_stage_captures(tap, path)?;
+    }
+
+    if image_data.partial {
+        println!("Source file truncated, the last frame is incomplete");
+    }
+
     let data_icc_result = save_icc(
         image_data.output_profile.as_icc().as_slice(),
         opt.icc_out.as_ref(),
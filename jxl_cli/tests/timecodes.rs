@@ -0,0 +1,51 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::process::Command;
+
+const TIMECODES_FILE: &str = "../jxl/resources/test/timecodes.jxl";
+
+fn run(binary: &str, args: &[&str]) -> String {
+    let output = Command::new(binary).args(args).output().unwrap();
+    assert!(output.status.success(), "{binary} {args:?}: {output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_info_timecodes() {
+    let output = run(env!("CARGO_BIN_EXE_jxl_cli"), &["--info", TIMECODES_FILE]);
+    let frames: Vec<_> = output
+        .lines()
+        .filter(|line| line.starts_with("Frame "))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            "Frame 0: start 0ms, duration 3000ms, timecode 10:00:00:00",
+            "Frame 1: start 3000ms, duration 1000ms, timecode 10:00:03:00",
+            "Frame 2: start 4000ms, duration 3000ms, timecode 10:00:04:00",
+            "Frame 3: start 7000ms, duration 1000ms, timecode 10:00:07:00",
+        ]
+    );
+}
+
+#[test]
+fn test_jxlinspect_timecodes() {
+    let output = run(env!("CARGO_BIN_EXE_jxlinspect"), &[TIMECODES_FILE]);
+    let frames: Vec<_> = output
+        .lines()
+        .filter(|line| line.starts_with("Frame "))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            "Frame 0, duration 3000ms, timecode 10:00:00:00",
+            "Frame 1, duration 1000ms, timecode 10:00:03:00",
+            "Frame 2, duration 3000ms, timecode 10:00:04:00",
+            "Frame 3, duration 1000ms, timecode 10:00:07:00",
+        ]
+    );
+    assert!(output.contains("with (potentially) individual timecodes"));
+}
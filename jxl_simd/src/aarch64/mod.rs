@@ -31,6 +31,26 @@ macro_rules! simd_function {
     };
 }
 
+/// Evaluates `$body` with `$d` bound to the descriptor of the instruction set `$level`, or to
+/// the scalar descriptor if that instruction set is not available.
+#[macro_export]
+macro_rules! simd_level_dispatch {
+    ($level:expr, $d:ident => $body:expr) => {{
+        // The label is unused if no instruction set is enabled.
+        #[allow(unused_labels)]
+        let result = 'dispatch: {
+            #[allow(unused)]
+            use $crate::SimdDescriptor;
+            #[allow(unused)]
+            let level: $crate::SimdLevel = $level;
+            $crate::simd_level_dispatch_neon!(level, 'dispatch, $d => $body);
+            let $d = $crate::ScalarDescriptor::new().unwrap();
+            $body
+        };
+        result
+    }};
+}
+
 #[cfg(feature = "neon")]
 #[doc(hidden)]
 #[macro_export]
@@ -58,6 +78,26 @@ macro_rules! simd_function_body_neon {
     ($($ignore:tt)*) => {};
 }
 
+#[cfg(feature = "neon")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! simd_level_dispatch_neon {
+    ($level:ident, $label:lifetime, $d:ident => $body:expr) => {
+        if $level == $crate::SimdLevel::Neon
+            && let Some($d) = $crate::NeonDescriptor::new()
+        {
+            break $label $body;
+        }
+    };
+}
+
+#[cfg(not(feature = "neon"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! simd_level_dispatch_neon {
+    ($($ignore:tt)*) => {};
+}
+
 #[macro_export]
 macro_rules! test_all_instruction_sets {
     (
@@ -0,0 +1,103 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+#[allow(unused)]
+use crate::SimdDescriptor;
+
+/// An instruction set that SIMD code can be dispatched to, with [`simd_level_dispatch`].
+///
+/// [`simd_level_dispatch`]: crate::simd_level_dispatch
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SimdLevel {
+    Scalar,
+    Sse42,
+    Avx,
+    Avx512,
+    Neon,
+}
+
+impl SimdLevel {
+    /// All the instruction sets, from the least to the most capable on each architecture.
+    pub const ALL: [SimdLevel; 5] = [
+        SimdLevel::Scalar,
+        SimdLevel::Sse42,
+        SimdLevel::Neon,
+        SimdLevel::Avx,
+        SimdLevel::Avx512,
+    ];
+
+    // Instruction sets with a higher rank can run all code for instruction sets with a lower
+    // rank on the same architecture.
+    fn rank(self) -> u8 {
+        match self {
+            SimdLevel::Scalar => 0,
+            SimdLevel::Sse42 | SimdLevel::Neon => 1,
+            SimdLevel::Avx => 2,
+            SimdLevel::Avx512 => 3,
+        }
+    }
+
+    /// Returns true if this instruction set was enabled at compile time and is supported by the
+    /// current CPU.
+    pub fn is_available(self) -> bool {
+        match self {
+            SimdLevel::Scalar => true,
+            #[cfg(all(target_arch = "x86_64", feature = "sse42"))]
+            SimdLevel::Sse42 => crate::Sse42Descriptor::new().is_some(),
+            #[cfg(all(target_arch = "x86_64", feature = "avx"))]
+            SimdLevel::Avx => crate::AvxDescriptor::new().is_some(),
+            #[cfg(all(target_arch = "x86_64", feature = "avx512"))]
+            SimdLevel::Avx512 => crate::Avx512Descriptor::new().is_some(),
+            #[cfg(all(target_arch = "aarch64", feature = "neon"))]
+            SimdLevel::Neon => crate::NeonDescriptor::new().is_some(),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    /// Returns all the available instruction sets, from the least to the most capable.
+    pub fn available() -> Vec<SimdLevel> {
+        Self::ALL.into_iter().filter(|l| l.is_available()).collect()
+    }
+
+    /// Returns the most capable available instruction set.
+    pub fn best() -> SimdLevel {
+        *Self::available().last().unwrap()
+    }
+
+    /// Returns the most capable available instruction set that is at most as capable as this one.
+    pub fn best_up_to(self) -> SimdLevel {
+        *Self::available()
+            .iter()
+            .rfind(|l| l.rank() <= self.rank())
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simd_level_dispatch;
+
+    #[test]
+    fn best_up_to() {
+        assert_eq!(SimdLevel::Scalar.best_up_to(), SimdLevel::Scalar);
+        assert_eq!(SimdLevel::Avx512.best_up_to(), SimdLevel::best());
+        for level in SimdLevel::available() {
+            assert_eq!(level.best_up_to(), level);
+        }
+    }
+
+    #[test]
+    fn dispatch_uses_level() {
+        fn level_of<D: SimdDescriptor>(_d: D) -> &'static str {
+            std::any::type_name::<D>().rsplit("::").next().unwrap()
+        }
+        for level in SimdLevel::available() {
+            let name = simd_level_dispatch!(level, d => level_of(d));
+            assert_eq!(name, format!("{level:?}Descriptor"));
+        }
+    }
+}
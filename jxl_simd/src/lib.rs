@@ -20,6 +20,7 @@ mod x86_64;
 #[cfg(target_arch = "aarch64")]
 mod aarch64;
 
+mod level;
 mod scalar;
 
 #[cfg(all(target_arch = "x86_64", feature = "avx"))]
@@ -32,6 +33,7 @@ pub use x86_64::sse42::Sse42Descriptor;
 #[cfg(all(target_arch = "aarch64", feature = "neon"))]
 pub use aarch64::neon::NeonDescriptor;
 
+pub use level::SimdLevel;
 pub use scalar::ScalarDescriptor;
 
 pub trait SimdDescriptor: Sized + Copy + Debug + Send + Sync {
@@ -375,7 +377,9 @@ pub(crate) use impl_f32_array_interface;
 mod test {
     use arbtest::arbitrary::Unstructured;
 
-    use crate::{F32SimdVec, ScalarDescriptor, SimdDescriptor, test_all_instruction_sets};
+    use crate::{
+        F32SimdVec, I32SimdVec, ScalarDescriptor, SimdDescriptor, test_all_instruction_sets,
+    };
 
     enum Distribution {
         Floats,
@@ -631,6 +635,18 @@ mod test {
     }
     test_all_instruction_sets!(test_neg);
 
+    fn test_i32_mul<D: SimdDescriptor>(d: D) {
+        let len = D::I32Vec::LEN;
+        let a: Vec<i32> = (0..len as i32).map(|i| (i + 1) * -12345).collect();
+        let b: Vec<i32> = (0..len as i32).map(|i| 1 << (i + 10)).collect();
+        let mut output = vec![0; len];
+        (D::I32Vec::load(d, &a) * D::I32Vec::load(d, &b)).store(&mut output);
+        for (i, ((out, a), b)) in output.iter().zip(a).zip(b).enumerate() {
+            assert_eq!(*out, a.wrapping_mul(b), "mismatch at index {i}");
+        }
+    }
+    test_all_instruction_sets!(test_i32_mul);
+
     fn test_transpose_square<D: SimdDescriptor>(d: D) {
         // Test square matrix transpose
         let len = D::F32Vec::LEN;
@@ -333,6 +333,18 @@ macro_rules! simd_function {
     };
 }
 
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+#[macro_export]
+macro_rules! simd_level_dispatch {
+    ($level:expr, $d:ident => $body:expr) => {{
+        #[allow(unused)]
+        use $crate::SimdDescriptor;
+        let _: $crate::SimdLevel = $level;
+        let $d = $crate::ScalarDescriptor::new().unwrap();
+        $body
+    }};
+}
+
 #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
 #[macro_export]
 macro_rules! test_all_instruction_sets {
@@ -37,6 +37,28 @@ macro_rules! simd_function {
     };
 }
 
+/// Evaluates `$body` with `$d` bound to the descriptor of the instruction set `$level`, or to
+/// the scalar descriptor if that instruction set is not available.
+#[macro_export]
+macro_rules! simd_level_dispatch {
+    ($level:expr, $d:ident => $body:expr) => {{
+        // The label is unused if no instruction set is enabled.
+        #[allow(unused_labels)]
+        let result = 'dispatch: {
+            #[allow(unused)]
+            use $crate::SimdDescriptor;
+            #[allow(unused)]
+            let level: $crate::SimdLevel = $level;
+            $crate::simd_level_dispatch_avx512!(level, 'dispatch, $d => $body);
+            $crate::simd_level_dispatch_avx!(level, 'dispatch, $d => $body);
+            $crate::simd_level_dispatch_sse42!(level, 'dispatch, $d => $body);
+            let $d = $crate::ScalarDescriptor::new().unwrap();
+            $body
+        };
+        result
+    }};
+}
+
 #[cfg(feature = "sse42")]
 #[doc(hidden)]
 #[macro_export]
@@ -118,6 +140,66 @@ macro_rules! simd_function_body_avx512 {
     ($($ignore:tt)*) => {};
 }
 
+#[cfg(feature = "sse42")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! simd_level_dispatch_sse42 {
+    ($level:ident, $label:lifetime, $d:ident => $body:expr) => {
+        if $level == $crate::SimdLevel::Sse42
+            && let Some($d) = $crate::Sse42Descriptor::new()
+        {
+            break $label $body;
+        }
+    };
+}
+
+#[cfg(not(feature = "sse42"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! simd_level_dispatch_sse42 {
+    ($($ignore:tt)*) => {};
+}
+
+#[cfg(feature = "avx")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! simd_level_dispatch_avx {
+    ($level:ident, $label:lifetime, $d:ident => $body:expr) => {
+        if $level == $crate::SimdLevel::Avx
+            && let Some($d) = $crate::AvxDescriptor::new()
+        {
+            break $label $body;
+        }
+    };
+}
+
+#[cfg(not(feature = "avx"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! simd_level_dispatch_avx {
+    ($($ignore:tt)*) => {};
+}
+
+#[cfg(feature = "avx512")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! simd_level_dispatch_avx512 {
+    ($level:ident, $label:lifetime, $d:ident => $body:expr) => {
+        if $level == $crate::SimdLevel::Avx512
+            && let Some($d) = $crate::Avx512Descriptor::new()
+        {
+            break $label $body;
+        }
+    };
+}
+
+#[cfg(not(feature = "avx512"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! simd_level_dispatch_avx512 {
+    ($($ignore:tt)*) => {};
+}
+
 #[macro_export]
 macro_rules! test_all_instruction_sets {
     (